@@ -0,0 +1,110 @@
+use std::{any::Any, fmt::Debug};
+
+use crate::{error::FreightError, execution_engine::ExecutionEngine, value::Value, TypeSystem};
+
+/// A value passed across the [DynExecutionEngine] boundary
+pub type DynValue = Box<dyn DynValueObject>;
+
+/// A type which can be carried by a [DynValue], implemented for every `'static` debuggable type
+pub trait DynValueObject: Any + Debug {
+    fn as_any(&self) -> &dyn Any;
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any + Debug> DynValueObject for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl dyn DynValueObject {
+    /// Borrow the carried value if it is a `T`
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.as_any().downcast_ref()
+    }
+
+    /// Take the carried value if it is a `T`
+    pub fn downcast<T: Any>(self: Box<Self>) -> Result<T, DynValue> {
+        if (*self).as_any().is::<T>() {
+            Ok(*self.into_any().downcast().unwrap())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// Converts a language's values to and from the representation shared between frontends
+pub trait ValueBridge: Value {
+    fn to_dyn(&self) -> DynValue;
+
+    /// Convert a shared value into this language's value, returning it unchanged if it has no equivalent
+    fn from_dyn(value: DynValue) -> Result<Self, DynValue>;
+}
+
+/// An object safe view of an [ExecutionEngine], so hosts can drive engines
+/// for different type systems without being generic over them
+pub trait DynExecutionEngine {
+    /// See [ExecutionEngine::run]
+    fn run(&mut self) -> Result<DynValue, FreightError>;
+
+    /// See [ExecutionEngine::run_function]
+    fn call_by_index(
+        &mut self,
+        location: usize,
+        args: Vec<DynValue>,
+    ) -> Result<DynValue, FreightError>;
+
+    /// See [ExecutionEngine::get_global]
+    fn get_global(&self, addr: usize) -> Option<DynValue>;
+
+    /// See [ExecutionEngine::set_global]
+    fn set_global(&mut self, addr: usize, value: DynValue) -> Result<(), FreightError>;
+}
+
+fn from_dyn<V: ValueBridge>(value: DynValue) -> Result<V, FreightError> {
+    V::from_dyn(value).map_err(|value| FreightError::IncompatibleDynValue {
+        value: format!("{value:?}"),
+    })
+}
+
+impl<TS: TypeSystem> DynExecutionEngine for ExecutionEngine<TS>
+where
+    TS::Value: ValueBridge,
+{
+    fn run(&mut self) -> Result<DynValue, FreightError> {
+        ExecutionEngine::run(self).map(|value| value.to_dyn())
+    }
+
+    fn call_by_index(
+        &mut self,
+        location: usize,
+        args: Vec<DynValue>,
+    ) -> Result<DynValue, FreightError> {
+        let args = args.into_iter().map(from_dyn).collect::<Result<_, _>>()?;
+        self.run_function(location, args)
+            .map(|value| value.to_dyn())
+    }
+
+    fn get_global(&self, addr: usize) -> Option<DynValue> {
+        ExecutionEngine::get_global(self, addr).map(|value| value.to_dyn())
+    }
+
+    fn set_global(&mut self, addr: usize, value: DynValue) -> Result<(), FreightError> {
+        ExecutionEngine::set_global(self, addr, from_dyn(value)?)
+    }
+}
+
+impl<TS: TypeSystem> ExecutionEngine<TS>
+where
+    TS::Value: ValueBridge,
+{
+    /// Erase the type system of this engine
+    pub fn into_dyn(self) -> Box<dyn DynExecutionEngine> {
+        Box::new(self)
+    }
+}
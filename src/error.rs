@@ -1,42 +1,594 @@
 use std::{error::Error, fmt::Display};
 
-use crate::{execution_engine::ExecutionEngine, TypeSystem};
+use crate::{
+    execution_engine::ExecutionEngine,
+    expression::{DebugInfo, VariableType},
+    function::ArgCount,
+    TypeSystem,
+};
+
+/// The kind of address reported by [FreightError::InvalidAddress]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AddressKind {
+    Global,
+    Stack,
+    Captured,
+    Function,
+    Constant,
+}
+
+impl Display for AddressKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Global => "global",
+            Self::Stack => "stack slot",
+            Self::Captured => "captured slot",
+            Self::Function => "function",
+            Self::Constant => "pooled constant",
+        })
+    }
+}
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum FreightError {
     InvalidInvocationTarget,
+    NotCallable {
+        type_name: String,
+    },
     IncorrectArgumentCount {
         expected_min: usize,
         expected_max: Option<usize>,
         actual: usize,
+        /// The location of the function, if the call went through a [FunctionRef](crate::function::FunctionRef)
+        function: Option<usize>,
+        /// The name of the function, if it has one
+        name: Option<String>,
     },
     Return {
         target: usize,
     },
+    Continue {
+        target: usize,
+    },
+    MissingEntryPoint,
+    GlobalOutOfRange {
+        address: usize,
+        num_globals: usize,
+    },
+    OutOfFuel {
+        consumed: u64,
+    },
+    StackOverflow {
+        depth: usize,
+    },
+    Interrupted,
+    DuplicateFunctionName {
+        name: String,
+    },
+    UnknownFunctionName {
+        name: String,
+    },
+    SnapshotMismatch {
+        expected_functions: usize,
+        actual_functions: usize,
+        expected_globals: usize,
+        actual_globals: usize,
+    },
+    StackSlotOutOfRange {
+        slot: usize,
+        size: usize,
+    },
+    CaptureSlotOutOfRange {
+        slot: usize,
+        size: usize,
+    },
+    IncorrectCaptureCount {
+        expected: usize,
+        actual: usize,
+    },
+    InvalidFunctionLocation {
+        location: usize,
+    },
+    FunctionTableFinalized,
+    UnhandledReturnTarget {
+        target: usize,
+    },
+    Cancelled,
+    IncompatibleDynValue {
+        value: String,
+    },
+    InvalidAddress {
+        function: usize,
+        kind: AddressKind,
+        address: usize,
+    },
+    MemoryLimitExceeded {
+        limit: usize,
+        used: usize,
+    },
+    NotIterable,
+    NoMatchingArm,
+    LazyValuesUnsupported,
+    ReturnFromThunk {
+        target: usize,
+    },
+    RecursiveForce,
+    TailCall,
+    NotSpreadable,
+    SpreadOutsideCall,
+    UninitializedRead {
+        variable: VariableType,
+    },
+    ReturnTargetOutOfScope {
+        function: usize,
+        target: usize,
+    },
+    DestructureMismatch {
+        expected: usize,
+        actual: usize,
+    },
+    NotDestructurable,
+    NotIndexable {
+        type_name: String,
+    },
+    IndexOutOfRange {
+        index: String,
+    },
+    TypeMismatch {
+        expected: String,
+        actual: String,
+    },
+    ContinueOutOfScope {
+        function: usize,
+        target: usize,
+    },
+    OperatorFailed {
+        operator: String,
+        message: String,
+    },
+    ComparisonChainMismatch {
+        operands: usize,
+        operators: usize,
+    },
+    UnknownOperatorId {
+        kind: String,
+        id: u32,
+    },
+    InitializerFailed {
+        initializer: String,
+        message: String,
+    },
+    ListLengthMismatch {
+        left: usize,
+        right: usize,
+    },
+    NotABinaryOperation,
+    UnknownField {
+        field: String,
+    },
+    DuplicateField {
+        field: String,
+    },
+    MixedAssociativity {
+        left: String,
+        right: String,
+    },
+    MalformedInfix {
+        message: String,
+    },
+    NotAnOptionalArgument {
+        arg: usize,
+        min: usize,
+        max: usize,
+    },
+    UnknownArgument {
+        function: String,
+        name: String,
+    },
+    DuplicateArgument {
+        function: String,
+        name: String,
+    },
+    MissingArgument {
+        function: String,
+        arg: String,
+    },
+    VariadicFunctionsDisabled,
+    OptionalArgumentsNotComposable {
+        function: String,
+    },
+    TooManyBoundArguments {
+        function: String,
+        bound: usize,
+        max: usize,
+    },
+    UnallocatedVariables {
+        addresses: Vec<usize>,
+        stack_size: usize,
+    },
+    UnfilledFunction {
+        function: String,
+    },
+    NotReserved {
+        function: usize,
+    },
+    ReservationMismatch {
+        function: usize,
+        message: String,
+    },
+    NotExportable {
+        function: String,
+        message: String,
+    },
+    YieldOutsideResumable,
+    ResumedAfterFinish,
+    WithContext(Box<ErrorContext>),
+}
+
+/// The most steps kept in the trace of an error, see [FreightError::trace]
+pub const MAX_TRACE_LEN: usize = 16;
+
+/// Information attached to an error as it propagates out of expressions
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ErrorContext {
+    pub error: FreightError,
+    /// The innermost [Expression::Spanned](crate::expression::Expression::Spanned)
+    /// the error was raised in
+    pub location: Option<DebugInfo>,
+    /// The expressions the error propagated out of, innermost first
+    pub trace: Vec<String>,
+}
+
+impl FreightError {
+    /// The error itself, without any context attached to it
+    pub fn root(&self) -> &FreightError {
+        match self {
+            Self::WithContext(context) => &context.error,
+            error => error,
+        }
+    }
+
+    /// The source location the error was raised at, if it was raised inside
+    /// [Expression::Spanned](crate::expression::Expression::Spanned)
+    pub fn location(&self) -> Option<&DebugInfo> {
+        match self {
+            Self::WithContext(context) => context.location.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Where this error propagated from, innermost first, such as the operand or call
+    /// argument it was raised in. Only the innermost [MAX_TRACE_LEN] steps are kept.
+    pub fn trace(&self) -> &[String] {
+        match self {
+            Self::WithContext(context) => &context.trace,
+            _ => &[],
+        }
+    }
+
+    /// Attach a location to this error unless it already has a more specific one
+    pub(crate) fn with_location(self, location: &DebugInfo) -> Self {
+        self.with_context(|context| {
+            context.location.get_or_insert_with(|| location.clone());
+        })
+    }
+
+    /// Add a step to the trace of this error, unless the trace is full
+    pub(crate) fn with_trace(self, step: impl FnOnce() -> String) -> Self {
+        self.with_context(|context| {
+            if context.trace.len() < MAX_TRACE_LEN {
+                context.trace.push(step());
+            }
+        })
+    }
+
+    /// Returns, continues and tail calls are control flow rather than failures, so they
+    /// never get context
+    fn with_context(self, f: impl FnOnce(&mut ErrorContext)) -> Self {
+        let mut context = match self {
+            Self::Return { .. } | Self::Continue { .. } | Self::TailCall => return self,
+            Self::WithContext(context) => context,
+            error => ErrorContext {
+                error,
+                location: None,
+                trace: Vec::new(),
+            }
+            .into(),
+        };
+        f(&mut context);
+        Self::WithContext(context)
+    }
+
+    /// Whether an [Expression::TryCatch](crate::expression::Expression::TryCatch) may handle
+    /// this error. Returns, continues, tail calls and aborts requested by the host always
+    /// propagate.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(
+            self.root(),
+            Self::Return { .. }
+                | Self::Continue { .. }
+                | Self::TailCall
+                | Self::Interrupted
+                | Self::Cancelled
+                | Self::OutOfFuel { .. }
+        )
+    }
 }
 
 impl Display for FreightError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::InvalidInvocationTarget => f.write_str("Cannot invoke non-function values"),
+            Self::NotCallable { type_name } => {
+                write!(f, "Cannot invoke a value of type {type_name}")
+            }
             Self::IncorrectArgumentCount {
                 expected_min,
                 expected_max,
                 actual,
+                function,
+                name,
             } => {
-                let Some(expected_max) = expected_max else {
-                    return write!(f, "Expected between {expected_min} and INFINITY arguments, got {actual}");
-                };
-
-                if expected_min == expected_max {
-                    write!(f, "Expected {expected_min} arguments, got {actual}")
-                } else {
-                    write!(f, "Expected between {expected_min} and {expected_max} arguments, got {actual}")
+                match (name, function) {
+                    (Some(name), _) => write!(f, "Function {name} expected ")?,
+                    (None, Some(function)) => write!(f, "Function #{function} expected ")?,
+                    (None, None) => f.write_str("Expected ")?,
                 }
+                let expected = match expected_max {
+                    Some(max) => ArgCount::range(*expected_min, *max),
+                    None => ArgCount::at_least(*expected_min),
+                };
+                write!(f, "{expected}, got {actual}")
             }
             Self::Return { target } => {
                 write!(f, "Could not return to target {target}")
             }
+            Self::Continue { target } => {
+                write!(f, "Could not continue loop {target}")
+            }
+            Self::MissingEntryPoint => f.write_str("No entry point has been set"),
+            Self::GlobalOutOfRange {
+                address,
+                num_globals,
+            } => {
+                write!(
+                    f,
+                    "Global {address} is out of range, only {num_globals} globals exist"
+                )
+            }
+            Self::OutOfFuel { consumed } => {
+                write!(f, "Ran out of fuel after {consumed} evaluations")
+            }
+            Self::StackOverflow { depth } => {
+                write!(f, "Stack overflow, exceeded maximum call depth of {depth}")
+            }
+            Self::Interrupted => f.write_str("Execution was interrupted"),
+            Self::DuplicateFunctionName { name } => {
+                write!(f, "A function named {name} is already registered")
+            }
+            Self::UnknownFunctionName { name } => {
+                write!(f, "No function named {name} is registered")
+            }
+            Self::SnapshotMismatch {
+                expected_functions,
+                actual_functions,
+                expected_globals,
+                actual_globals,
+            } => {
+                write!(
+                    f,
+                    "Snapshot has {expected_functions} functions and {expected_globals} globals, \
+                    engine has {actual_functions} functions and {actual_globals} globals"
+                )
+            }
+            Self::StackSlotOutOfRange { slot, size } => {
+                write!(
+                    f,
+                    "Stack slot {slot} is out of range for a frame of size {size}"
+                )
+            }
+            Self::CaptureSlotOutOfRange { slot, size } => {
+                write!(
+                    f,
+                    "Captured slot {slot} is out of range, only {size} values are captured"
+                )
+            }
+            Self::IncorrectCaptureCount { expected, actual } => {
+                write!(f, "Expected {expected} captured values, got {actual}")
+            }
+            Self::InvalidFunctionLocation { location } => {
+                write!(f, "No function is registered at location {location}")
+            }
+            Self::FunctionTableFinalized => {
+                f.write_str("Cannot register functions after the function table is finalized")
+            }
+            Self::UnhandledReturnTarget { target } => {
+                write!(
+                    f,
+                    "Return to target {target} was not handled by any return target"
+                )
+            }
+            Self::Cancelled => f.write_str("Execution was cancelled by the host"),
+            Self::IncompatibleDynValue { value } => {
+                write!(f, "{value} cannot be converted to a value of this engine")
+            }
+            Self::InvalidAddress {
+                function,
+                kind,
+                address,
+            } => {
+                write!(
+                    f,
+                    "Function {function} references {kind} {address}, which does not exist"
+                )
+            }
+            Self::MemoryLimitExceeded { limit, used } => {
+                write!(
+                    f,
+                    "Memory limit of {limit} bytes exceeded, {used} bytes used"
+                )
+            }
+            Self::NotIterable => f.write_str("Cannot iterate over non-iterable values"),
+            Self::NoMatchingArm => {
+                f.write_str("No match arm matched the value and there is no default")
+            }
+            Self::LazyValuesUnsupported => {
+                f.write_str("This type system does not support lazy values")
+            }
+            Self::ReturnFromThunk { target } => {
+                write!(
+                    f,
+                    "Cannot return to target {target} from inside a lazy value"
+                )
+            }
+            Self::RecursiveForce => f.write_str("A lazy value depends on its own result"),
+            Self::TailCall => f.write_str("A tail call was made outside of a function"),
+            Self::NotSpreadable => f.write_str("Only lists can be spread into call arguments"),
+            Self::SpreadOutsideCall => {
+                f.write_str("Spread expressions are only allowed as call arguments")
+            }
+            Self::UninitializedRead { variable } => {
+                write!(f, "{variable} was read before anything was assigned to it")
+            }
+            Self::ReturnTargetOutOfScope { function, target } => {
+                write!(
+                    f,
+                    "Function {function} returns to target {target}, which does not enclose it"
+                )
+            }
+            Self::DestructureMismatch { expected, actual } => {
+                write!(f, "Expected {expected} values to destructure, got {actual}")
+            }
+            Self::NotDestructurable => f.write_str("Only lists can be destructured"),
+            Self::NotIndexable { type_name } => {
+                write!(f, "Values of type {type_name} can't be indexed")
+            }
+            Self::IndexOutOfRange { index } => write!(f, "Index {index} is out of range"),
+            Self::TypeMismatch { expected, actual } => {
+                write!(f, "Expected a value of type {expected}, got {actual}")
+            }
+            Self::ContinueOutOfScope { function, target } => {
+                write!(
+                    f,
+                    "Function {function} continues loop {target}, which does not enclose it"
+                )
+            }
+            Self::OperatorFailed { operator, message } => {
+                write!(f, "Operator {operator} failed: {message}")
+            }
+            Self::ComparisonChainMismatch {
+                operands,
+                operators,
+            } => {
+                write!(
+                    f,
+                    "A comparison chain of {operands} operands needs one fewer operator, got {operators}"
+                )
+            }
+            Self::UnknownOperatorId { kind, id } => write!(f, "Unknown {kind} operator id {id}"),
+            Self::InitializerFailed {
+                initializer,
+                message,
+            } => write!(f, "Initializer {initializer} failed: {message}"),
+            Self::ListLengthMismatch { left, right } => {
+                write!(f, "Can't pair up lists of lengths {left} and {right}")
+            }
+            Self::NotABinaryOperation => {
+                f.write_str("Expected a single binary operation to apply element-wise")
+            }
+            Self::UnknownField { field } => write!(f, "Unknown field {field}"),
+            Self::DuplicateField { field } => write!(f, "Field {field} is given more than once"),
+            Self::MixedAssociativity { left, right } => write!(
+                f,
+                "Operators {left} and {right} have the same precedence but different associativity"
+            ),
+            Self::MalformedInfix { message } => write!(f, "Malformed infix expression: {message}"),
+            Self::NotAnOptionalArgument { arg, min, max } if min >= max => write!(
+                f,
+                "Argument {arg} can't have a default, since no arguments are optional"
+            ),
+            Self::NotAnOptionalArgument { arg, min, max } => write!(
+                f,
+                "Argument {arg} can't have a default, only arguments {min} to {} are optional",
+                max - 1
+            ),
+            Self::UnknownArgument { function, name } => {
+                write!(f, "Function {function} has no argument named {name}")
+            }
+            Self::DuplicateArgument { function, name } => write!(
+                f,
+                "Argument {name} of function {function} is given more than once"
+            ),
+            Self::MissingArgument { function, arg } => {
+                write!(f, "Function {function} is missing argument {arg}")
+            }
+            Self::VariadicFunctionsDisabled => {
+                f.write_str("Calling variadic functions requires the variadic_functions feature")
+            }
+            Self::OptionalArgumentsNotComposable { function } => {
+                write!(
+                    f,
+                    "Cannot compose function {function}, whose optional arguments would lose their defaults"
+                )
+            }
+            Self::TooManyBoundArguments {
+                function,
+                bound,
+                max,
+            } => {
+                write!(
+                    f,
+                    "Cannot bind {bound} arguments to function {function}, which takes at most {max}"
+                )
+            }
+            Self::UnallocatedVariables {
+                addresses,
+                stack_size,
+            } => {
+                let addresses: Vec<_> = addresses.iter().map(usize::to_string).collect();
+                write!(
+                    f,
+                    "Stack addresses {} are used but were never created, with {stack_size} slots allocated",
+                    addresses.join(", ")
+                )
+            }
+            Self::UnfilledFunction { function } => {
+                write!(
+                    f,
+                    "Function {function} was reserved but its body was never filled"
+                )
+            }
+            Self::NotReserved { function } => {
+                write!(
+                    f,
+                    "Function #{function} is not a reserved function awaiting a body"
+                )
+            }
+            Self::ReservationMismatch { function, message } => {
+                write!(f, "Body for reserved function #{function} {message}")
+            }
+            Self::NotExportable { function, message } => {
+                write!(f, "Function {function} can't be exported since {message}")
+            }
+            Self::YieldOutsideResumable => f.write_str(
+                "Yield is only allowed in the body of a resumable call, outside of call arguments",
+            ),
+            Self::ResumedAfterFinish => {
+                f.write_str("A resumable call can't be resumed after it has finished")
+            }
+            Self::WithContext(context) => {
+                write!(f, "{}", context.error)?;
+                if let Some(location) = &context.location {
+                    write!(f, " at {location}")?;
+                }
+                for step in &context.trace {
+                    write!(f, "\n    in {step}")?;
+                }
+                Ok(())
+            }
         }
     }
 }
@@ -1,42 +1,136 @@
-use self::stack::StackPool;
-#[cfg(feature = "variadic_functions")]
+use self::evaluator::{ContinuationPool, StepPool};
+use self::hooks::CallHooks;
+use self::interrupt::{Cancellation, CancellationCallback, InterruptHandle};
+#[cfg(feature = "profiling")]
+use self::profile::ProfileData;
+use self::stack::{StackPool, StackSlice};
+use self::stats::ExecutionStats;
 use crate::function::ArgCount;
+use crate::function::Function;
 use crate::{
     error::FreightError,
-    expression::{Expression, VariableType},
-    function::{FunctionRef, FunctionType, FunctionWriter},
-    operators::{BinaryOperator, Initializer, UnaryOperator},
+    expression::{DebugInfo, Expression, ExpressionArena, NativeFunction, NodeId},
+    function::{
+        validate_addresses, validate_arena_addresses, validate_function, FunctionInfo, FunctionRef,
+        FunctionType, FunctionWriter, StackLayout,
+    },
+    optimize::{eliminate_tail_calls, inline_calls},
     slice_pool::{IntoExactSizeIterator, RcSlicePool},
+    sync::{PoolCell, Shared},
     value::Value,
     TypeSystem,
 };
-use crate::{error::OrReturn, function::Function};
-use std::cell::UnsafeCell;
-use std::rc::Rc;
+use std::collections::HashMap;
+use std::fmt::Debug;
 
+pub mod builder;
+mod evaluator;
+#[cfg(feature = "serde")]
+pub mod export;
+pub mod hooks;
+pub mod interrupt;
+#[cfg(feature = "profiling")]
+pub mod profile;
+pub mod scope;
+pub mod snapshot;
 pub mod stack;
+pub mod stats;
+
+pub use self::evaluator::{Step, Suspended};
 
 pub type Stack<'a, T> = &'a mut [T];
 
+/// The default limit on nested function calls, see [ExecutionEngine::set_max_call_depth]
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
 pub struct ExecutionEngine<TS: TypeSystem> {
     pub(crate) num_globals: usize,
     pub(crate) globals: Vec<TS::Value>,
-    pub(crate) functions: UnsafeCell<Vec<Function<TS>>>,
+    pub(crate) global_seeds: Vec<Option<TS::Value>>,
+    pub(crate) functions: Vec<Shared<Function<TS>>>,
+    pub(crate) native_count: usize,
+    pub(crate) function_names: HashMap<String, usize>,
+    pub(crate) finalized: bool,
     pub(crate) next_return_target: usize,
     pub(crate) return_value: TS::Value,
-    pub stack: Rc<UnsafeCell<StackPool<TS::Value>>>,
-    pub rc_pool: Rc<UnsafeCell<RcSlicePool<TS::Value>>>,
+    pub(crate) tail_call: Option<FunctionRef<TS>>,
+    pub(crate) tail_call_args: Vec<TS::Value>,
+    pub(crate) entry_point: Option<FunctionRef<TS>>,
+    pub(crate) fuel: Option<u64>,
+    pub(crate) fuel_consumed: u64,
+    pub(crate) memory_limit: Option<usize>,
+    pub(crate) memory_used: usize,
+    pub(crate) call_depth: usize,
+    pub(crate) max_call_depth: usize,
+    pub(crate) strict_reads: bool,
+    pub(crate) error_traces: bool,
+    pub(crate) type_assertions: bool,
+    pub(crate) constants: Vec<TS::Value>,
+    pub(crate) pool_constants: bool,
+    pub(crate) inline_threshold: Option<usize>,
+    pub(crate) tail_call_elimination: bool,
+    pub(crate) interrupt: Option<InterruptHandle>,
+    pub(crate) cancellation: Option<Cancellation>,
+    pub(crate) hooks: Option<Box<dyn CallHooks<TS>>>,
+    pub(crate) stats: ExecutionStats,
+    pub(crate) continuations: ContinuationPool<TS>,
+    pub(crate) arena_steps: StepPool<TS>,
+    #[cfg(feature = "profiling")]
+    pub(crate) profile: ProfileData<TS>,
+    pub stack: Shared<PoolCell<StackPool<TS::Value>>>,
+    pub rc_pool: Shared<PoolCell<RcSlicePool<TS::Value>>>,
     pub context: TS::GlobalContext,
 }
 
+impl<TS: TypeSystem> Debug for ExecutionEngine<TS> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExecutionEngine")
+            .field("num_globals", &self.num_globals)
+            .field("globals", &self.globals)
+            .field("function_count", &self.functions.len())
+            .field("function_names", &self.function_names)
+            .field("entry_point", &self.entry_point)
+            .field("context", &self.context)
+            .finish_non_exhaustive()
+    }
+}
+
 impl<TS: TypeSystem> ExecutionEngine<TS> {
     pub fn new(context: TS::GlobalContext) -> Self {
         Self {
             num_globals: 0,
             globals: vec![],
-            functions: vec![].into(),
+            global_seeds: vec![],
+            functions: vec![],
+            native_count: 0,
+            function_names: HashMap::new(),
+            finalized: false,
             next_return_target: 0,
             return_value: Default::default(),
+            tail_call: None,
+            tail_call_args: vec![],
+            entry_point: None,
+            fuel: None,
+            fuel_consumed: 0,
+            memory_limit: None,
+            memory_used: 0,
+            call_depth: 0,
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            strict_reads: false,
+            error_traces: false,
+            type_assertions: true,
+            constants: vec![],
+            pool_constants: false,
+            inline_threshold: None,
+            tail_call_elimination: true,
+            interrupt: None,
+            cancellation: None,
+            hooks: None,
+            stats: Default::default(),
+            continuations: Default::default(),
+            arena_steps: Default::default(),
+            #[cfg(feature = "profiling")]
+            profile: Default::default(),
             stack: Default::default(),
             context,
             rc_pool: Default::default(),
@@ -50,23 +144,328 @@ impl<TS: TypeSystem> ExecutionEngine<TS> {
         Self::new(Default::default())
     }
 
+    /// Get a function from the function table. The returned handle stays valid
+    /// even if more functions are registered while it is in use.
     #[inline]
-    pub fn get_function<'a>(&self, id: usize) -> &'a Function<TS> {
-        unsafe { &(*self.functions.get())[id] }
+    pub fn get_function(&self, id: usize) -> Shared<Function<TS>> {
+        self.functions[id].clone()
+    }
+
+    /// Get a function from the function table, if the location is valid
+    pub fn try_get_function(&self, location: usize) -> Option<Shared<Function<TS>>> {
+        self.functions.get(location).cloned()
+    }
+
+    /// The name of the function at a location, if it has one
+    pub fn function_name(&self, location: usize) -> Option<&str> {
+        self.functions.get(location)?.name()
+    }
+
+    /// Where the function at a location is defined, if its writer was given a source
+    pub fn function_source(&self, location: usize) -> Option<&DebugInfo> {
+        self.functions.get(location)?.source()
     }
 
+    /// Create a reference to the function at a location, if the location is valid
+    pub fn function_ref(&self, location: usize) -> Option<FunctionRef<TS>> {
+        self.functions
+            .get(location)
+            .map(|func| func.to_ref(location))
+    }
+
+    /// Describe the function a reference calls, looking through bound arguments
+    pub fn describe_function(&self, func: &FunctionRef<TS>) -> FunctionInfo {
+        let (captures, bound) = match &func.function_type {
+            FunctionType::CapturingDef(captures, _) => (captures.len(), 0),
+            FunctionType::CapturingRef(captures) => (captures.len(), 0),
+            FunctionType::Bound { inner, args } => {
+                let inner = self.describe_function(inner);
+                (inner.captures, inner.bound + args.len())
+            }
+            FunctionType::Static | FunctionType::Native(_) => (0, 0),
+        };
+        let native = matches!(func.function_type, FunctionType::Native(_));
+        let function = self.functions.get(func.location).filter(|_| !native);
+        FunctionInfo {
+            arg_count: func.arg_count,
+            variadic: matches!(func.arg_count, ArgCount::Variadic { .. }),
+            captures,
+            bound,
+            stack_size: func.stack_size,
+            location: func.location,
+            native,
+            name: func
+                .name()
+                .or_else(|| function.and_then(|f| f.name()))
+                .map(str::to_owned),
+            source: function.and_then(|f| f.source()).cloned(),
+        }
+    }
+
+    /// Describe the function held by a value, if it holds one
+    pub fn describe_value(&self, value: &TS::Value) -> Option<FunctionInfo> {
+        value
+            .cast_to_function()
+            .map(|func| self.describe_function(func))
+    }
+
+    /// Add a function to the function table, validating its addresses unless
+    /// [FunctionWriter::disable_validation] was called
     pub fn register_function(
         &mut self,
         func: FunctionWriter<TS>,
         return_target: usize,
+    ) -> Result<FunctionRef<TS>, FreightError> {
+        if self.finalized {
+            return Err(FreightError::FunctionTableFinalized);
+        }
+        let function = self.build_function(func, return_target)?;
+        let func_ref = function.to_ref(self.functions.len());
+        self.functions.push(Shared::new(function));
+        Ok(func_ref)
+    }
+
+    /// Check the body of a function being registered or filled, run the passes enabled
+    /// on the engine over it and build it
+    fn build_function(
+        &mut self,
+        mut func: FunctionWriter<TS>,
+        return_target: usize,
+    ) -> Result<Function<TS>, FreightError> {
+        if func.allocated_only {
+            func.check_variables()?;
+        }
+        if func.resolved_captures {
+            func.check_captures()?;
+        }
+        if func.validate {
+            func.validate()?;
+        }
+        if self.pool_constants {
+            self.pool_constants_in(&mut func.expressions);
+        }
+        if let Some(max_nodes) = self.inline_threshold {
+            for expr in &mut func.expressions {
+                inline_calls(expr, &self.functions, max_nodes);
+            }
+        }
+        let eliminated = self.tail_call_elimination
+            && eliminate_tail_calls(&mut func.expressions, return_target) > 0;
+        let mut function = func.build(return_target);
+        function.eliminated_tail_calls = eliminated;
+        Ok(function)
+    }
+
+    /// Allocate a location for a function whose body is given later with
+    /// [ExecutionEngine::fill_function], so functions can call each other before all of
+    /// them are written. Every slot of the frame is allocated, and calling the function
+    /// before it's filled fails with [FreightError::UnfilledFunction].
+    pub fn reserve_function(
+        &mut self,
+        arg_count: ArgCount,
+        stack_size: usize,
+    ) -> Result<FunctionRef<TS>, FreightError> {
+        if self.finalized {
+            return Err(FreightError::FunctionTableFinalized);
+        }
+        let mut func = FunctionWriter::new(arg_count).build(0);
+        func.stack_size = stack_size.max(arg_count.stack_size());
+        func.reserved = true;
+        let func_ref = func.to_ref(self.functions.len());
+        self.functions.push(Shared::new(func));
+        Ok(func_ref)
+    }
+
+    /// Give the body of a function from [ExecutionEngine::reserve_function]. The writer
+    /// must take the reserved arguments, be static, and fit in the reserved stack size.
+    /// References created when reserving stay valid, so the body keeps the reserved layout.
+    pub fn fill_function(
+        &mut self,
+        location: usize,
+        mut func: FunctionWriter<TS>,
+        return_target: usize,
+    ) -> Result<FunctionRef<TS>, FreightError> {
+        if self.finalized {
+            return Err(FreightError::FunctionTableFinalized);
+        }
+        let Some(reserved) = self.functions.get(location).filter(|f| f.reserved) else {
+            return Err(FreightError::NotReserved { function: location });
+        };
+        let mismatch = |message: String| FreightError::ReservationMismatch {
+            function: location,
+            message,
+        };
+        if func.args != reserved.arg_count {
+            return Err(mismatch(format!(
+                "takes {} instead of {}",
+                func.args, reserved.arg_count
+            )));
+        }
+        if !matches!(func.function_type, FunctionType::Static) {
+            return Err(mismatch("captures variables".into()));
+        }
+        let stack_size = func.args.stack_size() + func.variable_count;
+        if stack_size > reserved.stack_size {
+            return Err(mismatch(format!(
+                "needs {stack_size} stack slots but {} were reserved",
+                reserved.stack_size
+            )));
+        }
+        let reserved_stack_size = reserved.stack_size;
+        func.layout = StackLayout::all_alloc();
+        let mut function = self.build_function(func, return_target)?;
+        function.stack_size = reserved_stack_size;
+        let func_ref = function.to_ref(location);
+        self.functions[location] = Shared::new(function);
+        Ok(func_ref)
+    }
+
+    /// Replace every [Expression::RawValue] with an [Expression::PooledValue], adding
+    /// constants to the pool unless an equal one is already there
+    fn pool_constants_in(&mut self, expressions: &mut [Expression<TS>]) {
+        for expr in expressions {
+            expr.visit_post_order_mut(|expr| {
+                let Expression::RawValue(value) = expr else {
+                    return;
+                };
+                let index = match self.constants.iter().position(|c| c.constant_eq(value)) {
+                    Some(index) => index,
+                    None => {
+                        self.constants.push(std::mem::take(value).into_ref());
+                        self.constants.len() - 1
+                    }
+                };
+                *expr = Expression::PooledValue(index);
+            });
+        }
+    }
+
+    /// Freeze the function table, after which registering functions is an error.
+    /// Functions are individually reference counted, so handles from [ExecutionEngine::get_function]
+    /// stay valid whether or not the table is finalized.
+    pub fn finalize(&mut self) {
+        self.finalized = true;
+        self.functions.shrink_to_fit();
+    }
+
+    /// Whether [ExecutionEngine::finalize] has been called
+    pub fn is_finalized(&self) -> bool {
+        self.finalized
+    }
+
+    /// Check every registered function for global, stack, captured and function addresses
+    /// which don't exist, reporting the first as [FreightError::InvalidAddress], and for
+    /// returns to targets which don't enclose them, reported as
+    /// [FreightError::ReturnTargetOutOfScope]
+    pub fn validate(&self) -> Result<(), FreightError> {
+        self.functions
+            .iter()
+            .enumerate()
+            .try_for_each(|(location, func)| {
+                validate_function(
+                    func,
+                    location,
+                    self.num_globals,
+                    self.functions.len(),
+                    self.constants.len(),
+                )
+            })
+    }
+
+    /// Register a function which can later be looked up by name. The function is
+    /// given the name too, unless it was already named with [FunctionWriter::set_name].
+    pub fn register_function_named(
+        &mut self,
+        name: impl Into<String>,
+        mut func: FunctionWriter<TS>,
+        return_target: usize,
+    ) -> Result<FunctionRef<TS>, FreightError> {
+        let name = name.into();
+        if self.function_names.contains_key(&name) {
+            return Err(FreightError::DuplicateFunctionName { name });
+        }
+        if func.name.is_none() {
+            func.set_name(name.as_str());
+        }
+        let func_ref = self.register_function(func, return_target)?;
+        self.function_names.insert(name, func_ref.location);
+        Ok(func_ref)
+    }
+
+    /// Create a reference to a native function with an id no other native registered
+    /// with this engine has, so references to different natives never compare equal.
+    /// It can be stored and passed around like any other function value.
+    pub fn register_native(
+        &mut self,
+        func: NativeFunction<TS>,
+        arg_count: ArgCount,
     ) -> FunctionRef<TS> {
-        unsafe {
-            let functions = &mut *self.functions.get();
-            let func_ref = func.to_ref(functions.len());
-            let func = func.build(return_target);
-            functions.push(func);
-            func_ref
+        self.native_count += 1;
+        FunctionRef::new_native(self.native_count - 1, func, arg_count)
+    }
+
+    /// Register a function passing its arguments to `inner` and the result to `outer`,
+    /// which has to accept a single argument. The composed function takes the same
+    /// arguments as `inner`, which can't have optional ones since they would be passed
+    /// along uninitialized rather than getting their defaults.
+    pub fn compose(
+        &mut self,
+        outer: &FunctionRef<TS>,
+        inner: &FunctionRef<TS>,
+    ) -> Result<FunctionRef<TS>, FreightError> {
+        for func in [outer, inner] {
+            if let FunctionType::CapturingDef(..) = func.function_type {
+                return Err(FreightError::InvalidInvocationTarget);
+            }
         }
+        if !outer.arg_count.contains(1) {
+            return Err(FreightError::IncorrectArgumentCount {
+                expected_min: outer.arg_count.min(),
+                expected_max: outer.arg_count.max(),
+                actual: 1,
+                function: Some(outer.location),
+                name: outer.name().map(str::to_owned),
+            });
+        }
+        let args = inner.arg_count;
+        if args.min() != args.max_capped() {
+            return Err(FreightError::OptionalArgumentsNotComposable {
+                function: inner.to_string(),
+            });
+        }
+        let mut forwarded: Vec<_> = (0..args.min()).map(Expression::stack).collect();
+        if let ArgCount::Variadic { .. } = args {
+            forwarded.push(Expression::Spread(Expression::stack(args.min()).into()));
+        }
+        let mut func = FunctionWriter::new(args);
+        // Arguments are only read once, so they don't need to be allocated
+        func.layout = StackLayout::no_alloc();
+        func.set_name(format!("{outer} . {inner}"));
+        func.evaluate_expression(Expression::StaticFunctionCall(
+            outer.clone(),
+            vec![Expression::StaticFunctionCall(inner.clone(), forwarded)],
+        ));
+        let return_target = self.create_return_target();
+        self.register_function(func, return_target)
+    }
+
+    /// Get a reference to a function registered with [ExecutionEngine::register_function_named]
+    pub fn get_function_by_name(&self, name: &str) -> Option<FunctionRef<TS>> {
+        self.function_names
+            .get(name)
+            .and_then(|location| self.function_ref(*location))
+    }
+
+    /// Invoke a function registered with [ExecutionEngine::register_function_named]
+    pub fn call_by_name(
+        &mut self,
+        name: &str,
+        args: impl IntoExactSizeIterator<Item = TS::Value>,
+    ) -> Result<TS::Value, FreightError> {
+        let Some(func) = self.get_function_by_name(name) else {
+            return Err(FreightError::UnknownFunctionName { name: name.into() });
+        };
+        self.call(&func, args)
     }
 
     pub fn create_return_target(&mut self) -> usize {
@@ -75,14 +474,350 @@ impl<TS: TypeSystem> ExecutionEngine<TS> {
     }
 
     pub fn create_global(&mut self) -> usize {
+        self.num_globals += 1;
         self.globals.push(Value::uninitialized_reference());
-        self.globals.len() - 1
+        self.global_seeds.push(None);
+        self.num_globals - 1
     }
 
+    /// Reinitialize every global, restoring values set through [ExecutionEngine::set_global]
     pub fn reset_globals(&mut self) {
-        self.globals = vec![Value::uninitialized_reference(); self.num_globals];
+        self.global_seeds.resize(self.num_globals, None);
+        self.globals = self
+            .global_seeds
+            .iter()
+            .map(|seed| match seed {
+                Some(value) => value.deep_clone().into_ref(),
+                None => Value::uninitialized_reference(),
+            })
+            .collect();
+    }
+
+    /// Get the current value of a global
+    pub fn get_global(&self, addr: usize) -> Option<&TS::Value> {
+        self.globals.get(addr)
+    }
+
+    /// Set the value of a global, which will be retained when the engine is reset
+    pub fn set_global(&mut self, addr: usize, value: TS::Value) -> Result<(), FreightError> {
+        *self.global_mut(addr)? = value.clone().into_ref();
+        self.global_seeds[addr] = Some(value);
+        Ok(())
+    }
+
+    /// Iterate over the current values of all globals
+    pub fn globals(&self) -> impl ExactSizeIterator<Item = &TS::Value> {
+        self.globals.iter()
+    }
+
+    /// Restore the engine to a clean state so the entry point can be run again
+    pub fn reset(&mut self) {
+        self.reset_globals();
+        self.return_value = Default::default();
+    }
+
+    /// Run a closure with mutable access to the global context
+    pub fn with_context<R>(&mut self, f: impl FnOnce(&mut TS::GlobalContext) -> R) -> R {
+        f(&mut self.context)
+    }
+
+    /// Replace the global context, returning the previous one
+    pub fn swap_context(&mut self, context: TS::GlobalContext) -> TS::GlobalContext {
+        std::mem::replace(&mut self.context, context)
+    }
+
+    /// Set the function that will be invoked by [ExecutionEngine::run]
+    pub fn set_entry_point(&mut self, func: FunctionRef<TS>) {
+        self.entry_point = Some(func);
+    }
+
+    /// Invoke a function against the live globals without resetting the engine,
+    /// so functions registered after a previous run can build on its state
+    pub fn run_incremental(&mut self, func: &FunctionRef<TS>) -> Result<TS::Value, FreightError> {
+        self.return_value = Default::default();
+        self.call(func, [])
+    }
+
+    /// Invoke any registered function as an entry point, initializing the globals if
+    /// they haven't been already
+    pub fn run_function(
+        &mut self,
+        location: usize,
+        args: Vec<TS::Value>,
+    ) -> Result<TS::Value, FreightError> {
+        let Some(func) = self.function_ref(location) else {
+            return Err(FreightError::InvalidFunctionLocation { location });
+        };
+        if self.globals.len() != self.num_globals {
+            self.reset();
+        }
+        self.call(&func, args)
+    }
+
+    /// Reset the engine and invoke the entry point with no arguments
+    pub fn run(&mut self) -> Result<TS::Value, FreightError> {
+        self.run_with_args(vec![])
+    }
+
+    /// Reset the engine and invoke the entry point with host-supplied arguments
+    pub fn run_with_args(&mut self, args: Vec<TS::Value>) -> Result<TS::Value, FreightError> {
+        let Some(entry_point) = self.entry_point.clone() else {
+            return Err(FreightError::MissingEntryPoint);
+        };
+        self.reset();
+        self.call(&entry_point, args)
+    }
+
+    /// Limit how many expressions and calls can be evaluated before failing with
+    /// [FreightError::OutOfFuel], or `None` to remove the limit
+    pub fn set_fuel(&mut self, fuel: Option<u64>) {
+        self.fuel = fuel;
+        self.fuel_consumed = 0;
+    }
+
+    /// The remaining fuel, if fuel metering is enabled
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Limit the total size in bytes of values created by initializers and variadic calls
+    /// before failing with [FreightError::MemoryLimitExceeded], or `None` to remove the limit.
+    /// Memory is never credited back when values are dropped.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+        self.memory_used = 0;
+    }
+
+    /// The number of bytes charged against the memory limit so far
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    /// Limit how deeply function calls can be nested before failing with
+    /// [FreightError::StackOverflow] instead of overflowing the native stack.
+    /// Each nested call consumes native stack, so hosts evaluating on small threads should lower this.
+    pub fn set_max_call_depth(&mut self, max_call_depth: usize) {
+        self.max_call_depth = max_call_depth;
+    }
+
+    /// The maximum number of nested function calls
+    pub fn max_call_depth(&self) -> usize {
+        self.max_call_depth
+    }
+
+    /// Fail with [FreightError::UninitializedRead] when a variable is read before anything
+    /// is assigned to it, instead of reading the uninitialized value.
+    /// This relies on [Value::is_uninitialized], so it has no effect for type systems
+    /// which don't implement it.
+    pub fn set_strict_reads(&mut self, strict: bool) {
+        self.strict_reads = strict;
+    }
+
+    /// Whether reads of uninitialized variables fail
+    pub fn strict_reads(&self) -> bool {
+        self.strict_reads
+    }
+
+    /// Check [Expression::TypeAssert] expressions, which is on by default. When disabled,
+    /// type assertions evaluate to their value without checking its type.
+    pub fn set_type_assertions(&mut self, check: bool) {
+        self.type_assertions = check;
+    }
+
+    /// Whether [Expression::TypeAssert] expressions are checked
+    pub fn type_assertions(&self) -> bool {
+        self.type_assertions
+    }
+
+    /// Record the expressions an error propagates out of, see [FreightError::trace].
+    /// Traces are only built once an error happens, so this costs nothing on success.
+    pub fn set_error_traces(&mut self, traces: bool) {
+        self.error_traces = traces;
+    }
+
+    /// Store the constants of functions registered from now on in a pool shared by every
+    /// function, deduplicating them with [Value::constant_eq]. Pooled constants are stored
+    /// as references, so evaluating one shares it instead of copying it, which also means
+    /// a value mutated through a reference changes the constant everywhere it's used.
+    pub fn set_constant_pooling(&mut self, pooling: bool) {
+        self.pool_constants = pooling;
+    }
+
+    /// Whether constants of newly registered functions are pooled
+    pub fn constant_pooling(&self) -> bool {
+        self.pool_constants
+    }
+
+    /// Inline static calls in functions registered from now on to previously registered
+    /// functions whose body is a single operator tree of at most `max_nodes` nodes reading
+    /// each argument once, in order. Inlined calls skip the call overhead entirely, so
+    /// they aren't seen by call hooks, the profiler or the call counts in the stats.
+    /// `None`, the default, disables inlining.
+    pub fn set_inline_threshold(&mut self, max_nodes: Option<usize>) {
+        self.inline_threshold = max_nodes;
+    }
+
+    /// The largest function body that is inlined, if inlining is enabled
+    pub fn inline_threshold(&self) -> Option<usize> {
+        self.inline_threshold
+    }
+
+    /// Turn static calls in tail position of functions registered from now on into
+    /// [Expression::TailCall]s, so recursion through them runs in constant native stack.
+    /// Unlike explicit tail calls, they still count towards the maximum call depth and
+    /// are reported to call hooks as if they were made normally, though the profiled time
+    /// of a function excludes the callees it tail calls. Enabled by default.
+    pub fn set_tail_call_elimination(&mut self, enabled: bool) {
+        self.tail_call_elimination = enabled;
+    }
+
+    /// Whether calls in tail position are turned into tail calls
+    pub fn tail_call_elimination(&self) -> bool {
+        self.tail_call_elimination
+    }
+
+    /// The constants pooled so far, indexed by [Expression::PooledValue]
+    pub fn constants(&self) -> &[TS::Value] {
+        &self.constants
+    }
+
+    /// Whether errors record the expressions they propagate out of
+    pub fn error_traces(&self) -> bool {
+        self.error_traces
+    }
+
+    /// Get a handle which can be used to interrupt this engine from another thread
+    pub fn interrupt_handle(&mut self) -> InterruptHandle {
+        self.interrupt.get_or_insert_with(Default::default).clone()
+    }
+
+    /// Poll `is_cancelled` once every `poll_interval` expression evaluations, failing with
+    /// [FreightError::Cancelled] as soon as it returns true
+    pub fn set_cancellation(&mut self, poll_interval: u32, is_cancelled: CancellationCallback) {
+        self.cancellation = Some(Cancellation::new(poll_interval, is_cancelled));
+    }
+
+    /// Remove the cancellation callback
+    pub fn clear_cancellation(&mut self) {
+        self.cancellation = None;
+    }
+
+    /// Install hooks to be invoked around every function call
+    pub fn set_hooks(&mut self, hooks: impl CallHooks<TS> + 'static) {
+        self.hooks = Some(Box::new(hooks));
+    }
+
+    /// Remove the installed hooks, returning them if there were any
+    pub fn clear_hooks(&mut self) -> Option<Box<dyn CallHooks<TS>>> {
+        self.hooks.take()
+    }
+
+    /// Counts of expressions evaluated, calls made and peak stack usage since the
+    /// engine was created or [ExecutionEngine::clear_stats] was called
+    pub fn stats(&self) -> ExecutionStats {
+        let (peak_stack, (frame_pool_hits, frame_pool_misses)) = self
+            .stack
+            .with(|stack| (stack.peak(), stack.spare_frames_used()));
+        ExecutionStats {
+            peak_stack,
+            frame_pool_hits,
+            frame_pool_misses,
+            ..self.stats
+        }
+    }
+
+    /// Reset all execution statistics to zero
+    pub fn clear_stats(&mut self) {
+        self.stats = Default::default();
+        self.stack.with(|stack| {
+            stack.reset_peak();
+            stack.reset_spare_frame_counts();
+        });
+    }
+
+    /// Call counts and timings for every function called so far
+    #[cfg(feature = "profiling")]
+    pub fn profile(&self) -> &ProfileData<TS> {
+        &self.profile
+    }
+
+    /// Discard all recorded profiling data
+    #[cfg(feature = "profiling")]
+    pub fn reset_profile(&mut self) {
+        self.profile.reset();
+    }
+
+    #[inline]
+    fn check_interrupt(&self) -> Result<(), FreightError> {
+        match &self.interrupt {
+            Some(handle) if handle.take() => Err(FreightError::Interrupted),
+            _ => Ok(()),
+        }
     }
 
+    #[inline]
+    fn check_cancellation(&mut self) -> Result<(), FreightError> {
+        if let Some(cancellation) = &mut self.cancellation {
+            if cancellation.poll() {
+                return Err(FreightError::Cancelled);
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn consume_fuel(&mut self) -> Result<(), FreightError> {
+        if let Some(fuel) = &mut self.fuel {
+            if *fuel == 0 {
+                return Err(FreightError::OutOfFuel {
+                    consumed: self.fuel_consumed,
+                });
+            }
+            *fuel -= 1;
+            self.fuel_consumed += 1;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn charge_memory(&mut self, value: &TS::Value) -> Result<(), FreightError> {
+        if let Some(limit) = self.memory_limit {
+            self.memory_used += value.approx_size();
+            if self.memory_used > limit {
+                return Err(FreightError::MemoryLimitExceeded {
+                    limit,
+                    used: self.memory_used,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn global(&self, addr: usize) -> Result<&TS::Value, FreightError> {
+        self.globals
+            .get(addr)
+            .ok_or(FreightError::GlobalOutOfRange {
+                address: addr,
+                num_globals: self.num_globals,
+            })
+    }
+
+    #[inline]
+    fn global_mut(&mut self, addr: usize) -> Result<&mut TS::Value, FreightError> {
+        let num_globals = self.num_globals;
+        self.globals
+            .get_mut(addr)
+            .ok_or(FreightError::GlobalOutOfRange {
+                address: addr,
+                num_globals,
+            })
+    }
+
+    /// Invoke a function with the given arguments.
+    /// This is re-entrant, so native functions may call it on the engine they are given.
+    /// A `Return` can't cross the host boundary, so one which isn't caught within the
+    /// nested call fails with [FreightError::UnhandledReturnTarget].
     #[inline]
     pub fn call(
         &mut self,
@@ -92,39 +827,302 @@ impl<TS: TypeSystem> ExecutionEngine<TS> {
         let mut iter = args.into_exact_size_iter();
         let arg_count = iter.len();
         self.call_internal(func, |_| Ok(iter.next().unwrap()), arg_count)
+            .map_err(Self::unhandled_return)
+    }
+
+    /// Invoke a function with positional arguments followed by arguments given by the
+    /// names from [FunctionWriter::set_arg_names]. Optional arguments which aren't given
+    /// get their defaults, and every required argument must be given exactly once.
+    pub fn call_named(
+        &mut self,
+        func: &FunctionRef<TS>,
+        positional: Vec<TS::Value>,
+        named: Vec<(TS::FieldId, TS::Value)>,
+    ) -> Result<TS::Value, FreightError> {
+        self.call_named_internal(func, positional, named)
+            .map_err(Self::unhandled_return)
+    }
+
+    pub(crate) fn call_named_internal(
+        &mut self,
+        func: &FunctionRef<TS>,
+        mut positional: Vec<TS::Value>,
+        named: Vec<(TS::FieldId, TS::Value)>,
+    ) -> Result<TS::Value, FreightError> {
+        if let FunctionType::Bound { inner, args: bound } = &func.function_type {
+            positional.splice(0..0, bound.iter().map(Value::clone_unaliased));
+            return self.call_named_internal(inner, positional, named);
+        }
+        let function = match func.function_type {
+            FunctionType::Native(_) => None,
+            _ => self.try_get_function(func.location),
+        };
+        let names = function.as_deref().map_or(&[][..], |f| &f.arg_names[..]);
+        let positions = func.arg_count.max_capped();
+        // Positional arguments past the last position go to the variadic list, where the
+        // argument count is checked as usual
+        let mut slots: Vec<_> = (0..positions).map(|_| None).collect();
+        let mut rest = Vec::new();
+        for (i, value) in positional.into_iter().enumerate() {
+            match slots.get_mut(i) {
+                Some(slot) => *slot = Some(value),
+                None => rest.push(value),
+            }
+        }
+        for (name, value) in named {
+            let position = names.iter().position(|n| *n == name);
+            let Some(slot) = position.and_then(|i| slots.get_mut(i)) else {
+                return Err(FreightError::UnknownArgument {
+                    function: func.to_string(),
+                    name: format!("{name:?}"),
+                });
+            };
+            if slot.is_some() {
+                return Err(FreightError::DuplicateArgument {
+                    function: func.to_string(),
+                    name: format!("{name:?}"),
+                });
+            }
+            *slot = Some(value);
+        }
+        if let Some(missing) = (0..func.arg_count.min()).find(|i| slots[*i].is_none()) {
+            return Err(FreightError::MissingArgument {
+                function: func.to_string(),
+                arg: names
+                    .get(missing)
+                    .map_or_else(|| missing.to_string(), |name| format!("{name:?}")),
+            });
+        }
+        drop(function);
+        let passed = slots.iter().rposition(Option::is_some).map_or(0, |i| i + 1);
+        let holes: Vec<_> = (0..passed).filter(|i| slots[*i].is_none()).collect();
+        let arg_count = passed + rest.len();
+        let mut values = slots
+            .into_iter()
+            .take(passed)
+            .map(|slot| slot.unwrap_or_default())
+            .chain(rest);
+        self.call_with_holes(func, |_| Ok(values.next().unwrap()), arg_count, &holes)
+    }
+
+    /// Convert a return which escaped to the host into a distinct error, since no
+    /// return target can catch it anymore
+    fn unhandled_return(err: FreightError) -> FreightError {
+        match err {
+            FreightError::Return { target } => FreightError::UnhandledReturnTarget { target },
+            err => err,
+        }
+    }
+
+    /// The value of the most recent `Return`, including one which escaped as
+    /// [FreightError::UnhandledReturnTarget]
+    pub fn last_return_value(&self) -> &TS::Value {
+        &self.return_value
+    }
+
+    /// Invoke a capturing function definition with captured values supplied by the host
+    pub fn call_with_captures(
+        &mut self,
+        func: &FunctionRef<TS>,
+        args: impl IntoExactSizeIterator<Item = TS::Value>,
+        captures: Vec<TS::Value>,
+    ) -> Result<TS::Value, FreightError> {
+        let expected = match &func.function_type {
+            FunctionType::CapturingDef(defs, _) => defs.len(),
+            FunctionType::Static => 0,
+            _ => return Err(FreightError::InvalidInvocationTarget),
+        };
+        if captures.len() != expected {
+            return Err(FreightError::IncorrectCaptureCount {
+                expected,
+                actual: captures.len(),
+            });
+        }
+        let mut func = func.clone();
+        func.function_type =
+            FunctionType::CapturingRef(RcSlicePool::from_pool(self.rc_pool.clone(), captures));
+        self.call(&func, args)
     }
 
     pub(crate) fn call_internal(
+        &mut self,
+        func: &FunctionRef<TS>,
+        args: impl FnMut(&mut ExecutionEngine<TS>) -> Result<TS::Value, FreightError>,
+        arg_count: usize,
+    ) -> Result<TS::Value, FreightError> {
+        self.call_with_holes(func, args, arg_count, &[])
+    }
+
+    /// Call a function, leaving the arguments at the indices in `holes` to their defaults
+    /// like arguments after `arg_count`. The values given for holes are ignored.
+    fn call_with_holes(
+        &mut self,
+        func: &FunctionRef<TS>,
+        args: impl FnMut(&mut ExecutionEngine<TS>) -> Result<TS::Value, FreightError>,
+        arg_count: usize,
+        holes: &[usize],
+    ) -> Result<TS::Value, FreightError> {
+        self.consume_fuel()?;
+        self.check_interrupt()?;
+        if self.call_depth >= self.max_call_depth {
+            return Err(FreightError::StackOverflow {
+                depth: self.call_depth,
+            });
+        }
+        self.call_depth += 1;
+        self.stats.function_calls += 1;
+        let result = self.call_frame(func, args, arg_count, holes);
+        self.call_depth -= 1;
+        result
+    }
+
+    fn call_frame(
         &mut self,
         func: &FunctionRef<TS>,
         mut args: impl FnMut(&mut ExecutionEngine<TS>) -> Result<TS::Value, FreightError>,
         arg_count: usize,
+        holes: &[usize],
     ) -> Result<TS::Value, FreightError> {
+        if let FunctionType::Bound { inner, args: bound } = &func.function_type {
+            let mut bound_args = bound.iter();
+            let holes: Vec<_> = holes.iter().map(|hole| hole + bound.len()).collect();
+            // Passed as a trait object so nested bound functions don't nest closure types
+            let spliced: &mut dyn FnMut(&mut Self) -> Result<TS::Value, FreightError> =
+                &mut |engine| match bound_args.next() {
+                    Some(value) => Ok(value.clone_unaliased()),
+                    None => args(engine),
+                };
+            return self.call_frame(inner, spliced, arg_count + bound.len(), &holes);
+        }
+        let mut stack = self.prepare_frame(func, args, arg_count, holes)?;
+        let result = self.run_frame(func, &mut stack);
+        drop(stack);
+        self.run_tail_calls(Some(func), result)
+    }
+
+    /// Make the calls requested by [Expression::TailCall] in place of the frame which
+    /// requested them, so tail recursion doesn't grow the native stack. Tail calls made
+    /// from the body of `caller` and the functions it tail calls are made as if they were
+    /// static calls when those bodies had them [eliminated](Self::set_tail_call_elimination),
+    /// counting towards the call depth and reported to hooks once they return.
+    pub(crate) fn run_tail_calls(
+        &mut self,
+        caller: Option<&FunctionRef<TS>>,
+        mut result: Result<TS::Value, FreightError>,
+    ) -> Result<TS::Value, FreightError> {
+        let track = self.hooks.is_some();
+        let mut requester = caller.filter(|_| track).cloned();
+        let mut implicit = caller.is_some_and(|func| self.eliminates_tail_calls(func));
+        let mut depth = 0;
+        // The frames ended by implicit tail calls, kept only for hooks
+        let mut elided = Vec::new();
+        while let Err(FreightError::TailCall) = result {
+            let mut func = self
+                .tail_call
+                .take()
+                .expect("A tail call is requested before it is signalled");
+            while let FunctionType::Bound { inner, args: bound } = &func.function_type {
+                let bound = bound.iter().map(Value::clone_unaliased);
+                self.tail_call_args.splice(0..0, bound);
+                func = FunctionRef::clone(inner);
+            }
+            if implicit {
+                if self.call_depth >= self.max_call_depth {
+                    result = Err(FreightError::StackOverflow {
+                        depth: self.call_depth,
+                    });
+                    break;
+                }
+                self.call_depth += 1;
+                depth += 1;
+                elided.extend(requester.take());
+            } else {
+                self.stats.tail_calls += 1;
+            }
+            implicit = self.eliminates_tail_calls(&func);
+            if track {
+                requester = Some(func.clone());
+            }
+            result = self.tail_call_frame(&func);
+        }
+        self.call_depth -= depth;
+        if let (Some(hooks), Ok(value)) = (&mut self.hooks, &result) {
+            for func in elided.iter().rev() {
+                hooks.on_return(func, value);
+            }
+        }
+        result
+    }
+
+    /// Whether tail calls requested by the body of `func` were turned from static calls
+    /// by [ExecutionEngine::set_tail_call_elimination]
+    fn eliminates_tail_calls(&self, func: &FunctionRef<TS>) -> bool {
+        matches!(
+            func.function_type,
+            FunctionType::Static | FunctionType::CapturingRef(_)
+        ) && self
+            .functions
+            .get(func.location)
+            .is_some_and(|func| func.eliminated_tail_calls)
+    }
+
+    /// Run a function requested by a tail call with the arguments it was given
+    fn tail_call_frame(&mut self, func: &FunctionRef<TS>) -> Result<TS::Value, FreightError> {
+        self.consume_fuel()?;
+        self.check_interrupt()?;
+        self.stats.function_calls += 1;
+
+        let mut args = std::mem::take(&mut self.tail_call_args);
+        let arg_count = args.len();
+        let mut iter = args.drain(..);
+        let stack = self.prepare_frame(func, |_| Ok(iter.next().unwrap()), arg_count, &[]);
+        drop(iter);
+        // Keep the buffer so the next tail call doesn't allocate
+        self.tail_call_args = args;
+        let mut stack = stack?;
+        self.run_frame(func, &mut stack)
+    }
+
+    /// Allocate a frame for a function and bind its arguments
+    fn prepare_frame(
+        &mut self,
+        func: &FunctionRef<TS>,
+        mut args: impl FnMut(&mut ExecutionEngine<TS>) -> Result<TS::Value, FreightError>,
+        arg_count: usize,
+        holes: &[usize],
+    ) -> Result<StackSlice<'static, TS::Value>, FreightError> {
         let mut stack = StackPool::request(self.stack.clone(), func.stack_size);
-        if !func.arg_count.valid_arg_count(arg_count) {
+        #[cfg(not(feature = "variadic_functions"))]
+        if let ArgCount::Variadic { .. } = func.arg_count {
+            return Err(FreightError::VariadicFunctionsDisabled);
+        }
+        if !func.arg_count.contains(arg_count) {
             return Err(FreightError::IncorrectArgumentCount {
                 expected_min: func.arg_count.min(),
                 expected_max: func.arg_count.max(),
                 actual: arg_count,
+                function: Some(func.location),
+                name: func.name().map(str::to_owned),
             });
         }
         let mut arg_num = 0;
         let max = func.arg_count.max_capped().min(arg_count);
         while arg_num < max {
-            let mut value = args(self)?;
-            if func.layout.is_alloc(arg_num) {
-                value = value.into_ref();
+            // Arguments are owned temporaries, so they're moved into the frame rather than
+            // cloned. Variable reads produce a `dupe_ref`, which keeps aliasing the variable.
+            let value = args(self)?;
+            stack[arg_num] = if func.layout.is_alloc(arg_num) {
+                value.into_ref()
             } else {
-                value = value.clone();
-            }
-            stack[arg_num] = value;
+                value
+            };
             arg_num += 1;
         }
         for (i, arg) in (arg_num..).zip(stack[arg_num..].iter_mut()) {
             if func.layout.is_alloc(i) {
                 *arg = Value::uninitialized_reference();
             } else {
-                *arg = Default::default();
+                *arg = Value::uninitialized_value();
             }
         }
 
@@ -134,129 +1132,159 @@ impl<TS: TypeSystem> ExecutionEngine<TS> {
             for _ in arg_num..arg_count {
                 vargs.push(args(self)?);
             }
-            stack[func.arg_count.max_capped()] = Value::gen_list(vargs);
+            let list = Value::gen_list(vargs);
+            self.charge_memory(&list)?;
+            let slot = func.arg_count.max_capped();
+            // The list slot is allocated like any other, so closures can capture it by reference
+            stack[slot] = if func.layout.is_alloc(slot) {
+                list.into_ref()
+            } else {
+                list
+            };
+        }
+        if arg_num < func.arg_count.max_capped() || !holes.is_empty() {
+            self.fill_defaults(func, &mut stack, arg_num, holes)?;
         }
+        Ok(stack)
+    }
 
-        if let FunctionType::Native(func) = &func.function_type {
-            return func(self, &mut stack);
+    fn run_frame(
+        &mut self,
+        func: &FunctionRef<TS>,
+        stack: &mut [TS::Value],
+    ) -> Result<TS::Value, FreightError> {
+        if let Some(hooks) = &mut self.hooks {
+            hooks.on_call(func, &stack[..func.arg_count.stack_size()]);
         }
-        let function = self.get_function(func.location);
 
-        match &func.function_type {
-            FunctionType::CapturingRef(captures) => function.call(self, &mut stack, captures),
-            FunctionType::Static => function.call(self, &mut stack, &[]),
-            FunctionType::CapturingDef(_) => Err(FreightError::InvalidInvocationTarget),
-            FunctionType::Native(_) => unreachable!("Native function already handled"),
+        let result = match &func.function_type {
+            FunctionType::Native(native) => {
+                self.stats.native_calls += 1;
+                native(self, stack)
+            }
+            FunctionType::CapturingRef(captures) => {
+                self.call_function(func.location, stack, captures)
+            }
+            FunctionType::Static => self.call_function(func.location, stack, &[]),
+            // Bound functions are replaced by what they're bound to before their frame
+            FunctionType::CapturingDef(..) | FunctionType::Bound { .. } => {
+                Err(FreightError::InvalidInvocationTarget)
+            }
+        };
+
+        if let (Some(hooks), Ok(value)) = (&mut self.hooks, &result) {
+            hooks.on_return(func, value);
+        }
+        result
+    }
+
+    #[inline]
+    fn call_function(
+        &mut self,
+        location: usize,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<TS::Value, FreightError> {
+        #[cfg(feature = "profiling")]
+        let start = std::time::Instant::now();
+        let func = self.get_function(location);
+        if func.reserved {
+            let function = match func.name() {
+                Some(name) => name.to_owned(),
+                None => format!("#{location}"),
+            };
+            return Err(FreightError::UnfilledFunction { function });
         }
+        let result = func.call(self, stack, captured);
+        #[cfg(feature = "profiling")]
+        self.profile.record(location, func.name(), start.elapsed());
+        result
     }
 
     #[inline]
     pub fn evaluate(&mut self, expr: &Expression<TS>) -> Result<TS::Value, FreightError> {
-        self.evaluate_internal(expr, &mut [], &[])
+        let result = self.evaluate_internal(expr, &mut [], &[]);
+        self.run_tail_calls(None, result)
+            .map_err(Self::unhandled_return)
     }
 
-    pub(crate) fn evaluate_internal(
+    /// Evaluate a standalone expression against the live globals, with `stack_slots`
+    /// temporaries available as stack variables. A `Return` to any target ends the
+    /// evaluation with the returned value.
+    pub fn eval(
         &mut self,
         expr: &Expression<TS>,
-        stack: &mut [TS::Value],
-        captured: &[TS::Value],
+        stack_slots: usize,
     ) -> Result<TS::Value, FreightError> {
-        let result = match expr {
-            Expression::RawValue(v) => v.clone(),
-            Expression::Variable(var) => match var {
-                VariableType::Captured(addr) => captured[*addr].dupe_ref(),
-                VariableType::Stack(addr) => stack[*addr].dupe_ref(),
-                VariableType::Global(addr) => self.globals[*addr].dupe_ref(),
-            },
-            Expression::BinaryOpEval(op, operands) => {
-                let [l, r] = &**operands;
-                let l = self.evaluate_internal(l, stack, captured)?;
-                let r = self.evaluate_internal(r, stack, captured)?;
-                op.apply_2(&l, &r)
-            }
-            Expression::UnaryOpEval(op, v) => {
-                let v = self.evaluate_internal(v, stack, captured)?;
-                op.apply_1(&v)
-            }
-            Expression::StaticFunctionCall(func, args) => {
-                let mut args = args.iter();
-                let arg_count = args.len();
-                self.call_internal(
-                    func,
-                    |e| e.evaluate_internal(args.next().unwrap(), stack, captured),
-                    arg_count,
-                )?
-            }
-            Expression::DynamicFunctionCall(func, args) => {
-                let func: TS::Value = self.evaluate_internal(func, stack, captured)?;
-                let Some(func): Option<&FunctionRef<TS>> = func.cast_to_function() else {
-                    return Err(FreightError::InvalidInvocationTarget);
-                };
-                let mut iter = args.iter();
-                let arg_count = iter.len();
-                self.call_internal(
-                    func,
-                    |e| e.evaluate_internal(iter.next().unwrap(), stack, captured),
-                    arg_count,
-                )?
-            }
-            Expression::FunctionCapture(func) => {
-                let FunctionType::CapturingDef(capture) = &func.function_type else {
-                return Err(FreightError::InvalidInvocationTarget);
-            };
-                let mut func = func.clone();
-                let captures_iter = capture.iter().map(|var| match var {
-                    VariableType::Captured(addr) => captured[*addr].dupe_ref(),
-                    VariableType::Stack(addr) => stack[*addr].dupe_ref(),
-                    VariableType::Global(addr) => self.globals[*addr].dupe_ref(),
-                });
+        validate_addresses(std::slice::from_ref(expr), stack_slots, 0)?;
+        let mut stack = StackPool::request(self.stack.clone(), stack_slots);
+        for slot in stack.iter_mut() {
+            *slot = Value::uninitialized_reference();
+        }
+        let result = self.evaluate_internal(expr, &mut stack, &[]);
+        drop(stack);
+        match self.run_tail_calls(None, result) {
+            Err(FreightError::Return { .. }) => Ok(std::mem::take(&mut self.return_value)),
+            result => result,
+        }
+    }
 
-                func.function_type = FunctionType::CapturingRef(RcSlicePool::from_pool(
-                    self.rc_pool.clone(),
-                    captures_iter,
-                ));
-                func.into()
-            }
-            Expression::AssignStack(addr, expr) => {
-                let val = self.evaluate_internal(expr, stack, captured)?;
-                stack[*addr].assign(val);
-                Default::default()
-            }
-            Expression::NativeFunctionCall(func, args) => {
-                let mut collected = StackPool::request(self.stack.clone(), args.len());
-                for (i, arg) in args.iter().enumerate() {
-                    collected[i] = self.evaluate_internal(arg, stack, captured)?.clone();
-                }
+    /// Like [ExecutionEngine::eval], but evaluating the tree rooted at `root` in an arena
+    pub fn eval_arena(
+        &mut self,
+        arena: &ExpressionArena<TS>,
+        root: NodeId,
+        stack_slots: usize,
+    ) -> Result<TS::Value, FreightError> {
+        validate_arena_addresses(arena, stack_slots, 0)?;
+        let mut stack = StackPool::request(self.stack.clone(), stack_slots);
+        for slot in stack.iter_mut() {
+            *slot = Value::uninitialized_reference();
+        }
+        let result = self.evaluate_arena(arena, root, &mut stack, &[]);
+        drop(stack);
+        match self.run_tail_calls(None, result) {
+            Err(FreightError::Return { .. }) => Ok(std::mem::take(&mut self.return_value)),
+            result => result,
+        }
+    }
 
-                func(self, &mut collected)?
-            }
-            Expression::AssignGlobal(addr, expr) => {
-                let val = self.evaluate_internal(expr, stack, captured)?;
-                self.globals[*addr].assign(val);
-                Default::default()
-            }
-            Expression::AssignDynamic(args) => {
-                let [target, value] = &**args;
-                let mut target = self.evaluate_internal(target, stack, captured)?.dupe_ref();
-                let value = self.evaluate_internal(value, stack, captured)?;
-                target.assign(value);
-                Default::default()
-            }
-            Expression::Initialize(init, args) => {
-                let mut collected = Vec::with_capacity(args.len());
-                for arg in args {
-                    collected.push(self.evaluate_internal(arg, stack, captured)?);
+    /// Apply a binary operator to the elements of two lists pairwise, building the result
+    /// with [Value::gen_list]. A value which isn't a [list](Value::list_elements) is paired
+    /// with every element of the other one, and two of them are just passed to the
+    /// operator. Lists of different lengths fail with [FreightError::ListLengthMismatch].
+    #[cfg(feature = "variadic_functions")]
+    pub fn map_binary(
+        &mut self,
+        op: &TS::BinaryOp,
+        a: &TS::Value,
+        b: &TS::Value,
+    ) -> Result<TS::Value, FreightError> {
+        let elements = match (a.list_elements(), b.list_elements()) {
+            (Some(a), Some(b)) => {
+                if a.len() != b.len() {
+                    return Err(FreightError::ListLengthMismatch {
+                        left: a.len(),
+                        right: b.len(),
+                    });
                 }
-                init.initialize(collected, self)
-            }
-            Expression::ReturnTarget(target, expr) => self
-                .evaluate_internal(&**expr, stack, captured)
-                .or_return(*target, self)?,
-            Expression::Return(target, expr) => {
-                self.return_value = self.evaluate_internal(&**expr, stack, captured)?;
-                return Err(FreightError::Return { target: *target });
+                a.iter()
+                    .zip(&b)
+                    .map(|(a, b)| self.apply_binary(op, a, b))
+                    .collect::<Result<Vec<_>, _>>()?
             }
+            (Some(a), None) => a
+                .iter()
+                .map(|a| self.apply_binary(op, a, b))
+                .collect::<Result<_, _>>()?,
+            (None, Some(b)) => b
+                .iter()
+                .map(|b| self.apply_binary(op, a, b))
+                .collect::<Result<_, _>>()?,
+            (None, None) => return self.apply_binary(op, a, b),
         };
-        Ok(result)
+        let list = TS::Value::gen_list(elements);
+        self.charge_memory(&list)?;
+        Ok(list)
     }
 }
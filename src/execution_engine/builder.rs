@@ -0,0 +1,99 @@
+use super::{stack::StackPool, ExecutionEngine};
+use crate::{
+    error::FreightError,
+    function::{FunctionRef, FunctionType, FunctionWriter},
+    sync::{PoolCell, Shared},
+    TypeSystem,
+};
+
+/// Collects functions and globals and assembles them into a validated [ExecutionEngine]
+pub struct EngineBuilder<TS: TypeSystem> {
+    functions: Vec<(FunctionWriter<TS>, usize)>,
+    num_globals: usize,
+    next_return_target: usize,
+    entry: Option<FunctionRef<TS>>,
+    stack_capacity: Option<usize>,
+}
+
+impl<TS: TypeSystem> EngineBuilder<TS> {
+    pub fn new() -> Self {
+        Self {
+            functions: vec![],
+            num_globals: 0,
+            next_return_target: 0,
+            entry: None,
+            stack_capacity: None,
+        }
+    }
+
+    /// Add a function, returning a reference which is valid in the built engine
+    pub fn add_function(
+        &mut self,
+        func: FunctionWriter<TS>,
+        return_target: usize,
+    ) -> FunctionRef<TS> {
+        let func_ref = func.to_ref(self.functions.len());
+        self.functions.push((func, return_target));
+        func_ref
+    }
+
+    /// Set the function that will be invoked by [ExecutionEngine::run]
+    pub fn set_entry(&mut self, func: FunctionRef<TS>) {
+        self.entry = Some(func);
+    }
+
+    pub fn create_global(&mut self) -> usize {
+        self.num_globals += 1;
+        self.num_globals - 1
+    }
+
+    pub fn create_return_target(&mut self) -> usize {
+        self.next_return_target += 1;
+        self.next_return_target - 1
+    }
+
+    /// Set the number of stack slots preallocated for call frames.
+    /// The pool is always large enough for the entry point's frame.
+    pub fn stack_capacity(&mut self, capacity: usize) {
+        self.stack_capacity = Some(capacity);
+    }
+
+    /// Register every function and check that the entry point can be invoked and
+    /// that all addresses used by the functions exist
+    pub fn build(self, context: TS::GlobalContext) -> Result<ExecutionEngine<TS>, FreightError> {
+        let mut engine = ExecutionEngine::new(context);
+        for _ in 0..self.num_globals {
+            engine.create_global();
+        }
+        engine.next_return_target = self.next_return_target;
+        for (func, return_target) in self.functions {
+            engine.register_function(func, return_target)?;
+        }
+
+        let Some(entry) = self.entry else {
+            return Err(FreightError::MissingEntryPoint);
+        };
+        let Some(entry) = engine.function_ref(entry.location) else {
+            return Err(FreightError::InvalidFunctionLocation {
+                location: entry.location,
+            });
+        };
+        if let FunctionType::CapturingDef(..) = entry.function_type {
+            return Err(FreightError::InvalidInvocationTarget);
+        }
+        engine.validate()?;
+
+        if let Some(capacity) = self.stack_capacity {
+            let capacity = capacity.max(entry.stack_size);
+            engine.stack = Shared::new(PoolCell::new(StackPool::with_capacity(capacity)));
+        }
+        engine.set_entry_point(entry);
+        Ok(engine)
+    }
+}
+
+impl<TS: TypeSystem> Default for EngineBuilder<TS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
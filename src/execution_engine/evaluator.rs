@@ -0,0 +1,1350 @@
+use super::{scope::EvalScope, stack::StackPool, stack::StackSlice, ExecutionEngine};
+use crate::{
+    error::{FreightError, OrReturn},
+    expression::{DebugInfo, Expression, NativeFunction, VariableType},
+    function::{FunctionRef, FunctionType},
+    operators::{
+        coerce, BinaryOperator, Initializer, LazyBinaryOperator, OpResult, TernaryOperator,
+        UnaryOperator,
+    },
+    slice_pool::RcSlicePool,
+    sync::Shared,
+    thunk::{Thunk, ThunkEnv, ThunkState},
+    value::{Value, ValueIter},
+    TypeSystem,
+};
+
+mod arena;
+mod resumable;
+
+pub(crate) use arena::StepPool;
+pub use resumable::{Step, Suspended};
+
+/// What the evaluator does next
+enum Next<'e, TS: TypeSystem> {
+    /// Start evaluating an expression
+    Eval(&'e Expression<TS>),
+    /// Hand a finished value to the innermost pending continuation
+    Value(TS::Value),
+}
+
+/// An expression waiting on the result of one of its subexpressions
+enum Continuation<'e, TS: TypeSystem> {
+    BinaryLeft(&'e TS::BinaryOp, &'e Expression<TS>),
+    BinaryRight(&'e TS::BinaryOp, TS::Value),
+    Unary(&'e TS::UnaryOp),
+    TernaryFirst(&'e TS::TernaryOp, &'e [Expression<TS>; 3]),
+    TernarySecond(&'e TS::TernaryOp, &'e Expression<TS>, TS::Value),
+    TernaryThird(&'e TS::TernaryOp, TS::Value, TS::Value),
+    LazyBinaryLeft(&'e TS::LazyBinaryOp, &'e Expression<TS>),
+    DynamicCall(&'e [Expression<TS>]),
+    /// The flag on assignments is whether they produce the assigned variable
+    AssignStack(usize, bool),
+    AssignGlobal(usize, bool),
+    AssignCaptured(usize),
+    CompoundAssign(&'e VariableType, &'e TS::BinaryOp),
+    DestructureAssign(&'e [VariableType]),
+    AssignDynamicTarget(&'e Expression<TS>, bool),
+    AssignDynamicValue(TS::Value, bool),
+    TypeAssert(&'e TS::TypeId),
+    IndexTarget(&'e Expression<TS>),
+    IndexKey(TS::Value),
+    IndexAssignTarget {
+        index: &'e Expression<TS>,
+        value: &'e Expression<TS>,
+    },
+    IndexAssignKey {
+        target: TS::Value,
+        value: &'e Expression<TS>,
+    },
+    IndexAssignValue {
+        target: TS::Value,
+        index: TS::Value,
+    },
+    Initialize {
+        init: &'e TS::Init,
+        remaining: &'e [Expression<TS>],
+        collected: InitArgs<TS>,
+    },
+    InitializeNamed {
+        init: &'e TS::Init,
+        field: &'e TS::FieldId,
+        remaining: &'e [(TS::FieldId, Expression<TS>)],
+        collected: Vec<(TS::FieldId, TS::Value)>,
+    },
+    TailCall {
+        func: &'e FunctionRef<TS>,
+        remaining: &'e [Expression<TS>],
+        collected: Vec<TS::Value>,
+    },
+    NativeCall {
+        func: &'e NativeFunction<TS>,
+        remaining: &'e [Expression<TS>],
+        collected: StackSlice<'e, TS::Value>,
+    },
+    ReturnTarget(usize),
+    Return(usize),
+    Loop {
+        target: usize,
+        body: &'e Expression<TS>,
+    },
+    Spanned(&'e DebugInfo),
+    Conditional {
+        then_branch: &'e Expression<TS>,
+        else_branch: Option<&'e Expression<TS>>,
+    },
+    And(&'e Expression<TS>),
+    Or(&'e Expression<TS>),
+    Coalesce(&'e Expression<TS>),
+    Sequence(&'e [Expression<TS>]),
+    Match {
+        arms: &'e [(TS::Value, Expression<TS>)],
+        default: Option<&'e Expression<TS>>,
+    },
+    TryCatch {
+        error_slot: usize,
+        handler: &'e Expression<TS>,
+    },
+    WhileCondition {
+        condition: &'e Expression<TS>,
+        body: &'e Expression<TS>,
+    },
+    WhileBody {
+        condition: &'e Expression<TS>,
+        body: &'e Expression<TS>,
+    },
+    ForIterable {
+        binding: usize,
+        body: &'e Expression<TS>,
+    },
+    ForBody {
+        binding: usize,
+        body: &'e Expression<TS>,
+        iter: ValueIter<TS::Value>,
+    },
+    Force,
+    /// Marks where a resumable call suspends once the yielded value is ready
+    Yield,
+}
+
+impl<TS: TypeSystem> Continuation<'_, TS> {
+    /// A short description of the subexpression this continuation is waiting on,
+    /// for the trace of an error raised there
+    fn describe(&self) -> String {
+        match self {
+            Self::BinaryLeft(op, _) => format!("left operand of {}", op.name()),
+            Self::BinaryRight(op, _) => format!("right operand of {}", op.name()),
+            Self::Unary(op) => format!("operand of {}", op.name()),
+            Self::TernaryFirst(op, _) => format!("first operand of {}", op.name()),
+            Self::TernarySecond(op, ..) => format!("second operand of {}", op.name()),
+            Self::TernaryThird(op, ..) => format!("third operand of {}", op.name()),
+            Self::LazyBinaryLeft(op, _) => format!("left operand of {}", op.name()),
+            Self::DynamicCall(_) => "callee of dynamic call".to_owned(),
+            Self::AssignStack(addr, _) => format!("value assigned to stack[{addr}]"),
+            Self::AssignGlobal(addr, _) => format!("value assigned to global[{addr}]"),
+            Self::AssignCaptured(addr) => format!("value assigned to captured[{addr}]"),
+            Self::CompoundAssign(target, op) => format!("value of {target} {}=", op.name()),
+            Self::DestructureAssign(targets) => {
+                format!("value destructured into {} variables", targets.len())
+            }
+            Self::AssignDynamicTarget(..) => "target of dynamic assignment".to_owned(),
+            Self::AssignDynamicValue(..) => "value of dynamic assignment".to_owned(),
+            Self::TypeAssert(expected) => format!("value asserted to be {expected:?}"),
+            Self::IndexTarget(_) => "indexed value".to_owned(),
+            Self::IndexKey(_) => "index".to_owned(),
+            Self::IndexAssignTarget { .. } => "indexed value of assignment".to_owned(),
+            Self::IndexAssignKey { .. } => "index of assignment".to_owned(),
+            Self::IndexAssignValue { .. } => "value assigned to index".to_owned(),
+            Self::Initialize {
+                init, collected, ..
+            } => format!("arg {} of initializer {init:?}", collected.len()),
+            Self::InitializeNamed { init, field, .. } => {
+                format!("field {field:?} of initializer {init:?}")
+            }
+            Self::TailCall {
+                func, collected, ..
+            } => format!("arg {} of tail call to {func}", collected.len()),
+            Self::NativeCall {
+                remaining,
+                collected,
+                ..
+            } => format!(
+                "arg {} of native call",
+                collected.len() - remaining.len() - 1
+            ),
+            Self::ReturnTarget(target) => format!("return target {target}"),
+            Self::Return(target) => format!("value returned to target {target}"),
+            Self::Loop { target, .. } => format!("loop {target}"),
+            Self::Spanned(span) => format!("span {span}"),
+            Self::Conditional { .. } => "condition".to_owned(),
+            Self::And(_) => "left operand of and".to_owned(),
+            Self::Or(_) => "left operand of or".to_owned(),
+            Self::Coalesce(_) => "left operand of coalesce".to_owned(),
+            Self::Sequence(_) => "sequence".to_owned(),
+            Self::Match { .. } => "match scrutinee".to_owned(),
+            Self::TryCatch { .. } => "try body".to_owned(),
+            Self::WhileCondition { .. } => "while condition".to_owned(),
+            Self::WhileBody { .. } => "while body".to_owned(),
+            Self::ForIterable { .. } => "for iterable".to_owned(),
+            Self::ForBody { .. } => "for body".to_owned(),
+            Self::Force => "forced value".to_owned(),
+            Self::Yield => "yielded value".to_owned(),
+        }
+    }
+}
+
+/// The values of an initializer's arguments so far, which are streamed into a builder
+/// if the initializer [begins](crate::operators::Initializer::begin) one
+enum InitArgs<TS: TypeSystem> {
+    Collected(Vec<TS::Value>),
+    /// A builder and how many values have been pushed to it
+    Streamed(TS::InitBuilder, usize),
+}
+
+impl<TS: TypeSystem> InitArgs<TS> {
+    fn new(init: &TS::Init, capacity: usize) -> Self {
+        match init.begin(capacity) {
+            Some(builder) => Self::Streamed(builder, 0),
+            None => Self::Collected(Vec::with_capacity(capacity)),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Self::Collected(values) => values.len(),
+            Self::Streamed(_, len) => *len,
+        }
+    }
+}
+
+/// Empty continuation buffers kept between evaluations, so evaluating doesn't allocate
+/// once the engine is warmed up
+pub(crate) struct ContinuationPool<TS: TypeSystem>(Vec<Vec<Continuation<'static, TS>>>);
+
+// SAFETY: every buffer in the pool is empty, so no continuation ever crosses threads
+unsafe impl<TS: TypeSystem> Send for ContinuationPool<TS> {}
+
+impl<TS: TypeSystem> Default for ContinuationPool<TS> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<TS: TypeSystem> ContinuationPool<TS> {
+    fn take<'e>(&mut self) -> Vec<Continuation<'e, TS>> {
+        self.0.pop().map(Self::recast).unwrap_or_default()
+    }
+
+    fn put_back(&mut self, buffer: Vec<Continuation<'_, TS>>) {
+        self.0.push(Self::recast(buffer));
+    }
+
+    /// Reuse the allocation of an empty buffer for continuations of another lifetime
+    fn recast<'a, 'b>(buffer: Vec<Continuation<'a, TS>>) -> Vec<Continuation<'b, TS>> {
+        assert!(buffer.is_empty());
+        let mut buffer = std::mem::ManuallyDrop::new(buffer);
+        // SAFETY: the types only differ in lifetime so they share a layout,
+        // and the buffer holds no continuations which could outlive their borrows
+        unsafe { Vec::from_raw_parts(buffer.as_mut_ptr().cast(), 0, buffer.capacity()) }
+    }
+}
+
+impl<TS: TypeSystem> ExecutionEngine<TS> {
+    /// Evaluate an expression using an explicit stack of continuations, so the depth of
+    /// an expression is limited only by memory. Only function calls recurse natively,
+    /// which is bounded by the maximum call depth.
+    pub(crate) fn evaluate_internal(
+        &mut self,
+        expr: &Expression<TS>,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<TS::Value, FreightError> {
+        let mut pending = self.continuations.take();
+        let mut next = self.start(expr, &mut pending, stack, captured);
+        loop {
+            let result = match next {
+                Ok(Next::Eval(expr)) => {
+                    next = self.start(expr, &mut pending, stack, captured);
+                    continue;
+                }
+                Ok(Next::Value(value)) => Ok(value),
+                Err(err) => Err(err),
+            };
+            match pending.pop() {
+                Some(continuation) => {
+                    next = self.resume(continuation, result, &mut pending, stack, captured)
+                }
+                None => {
+                    self.continuations.put_back(pending);
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Begin evaluating an expression, either finishing it immediately or
+    /// pushing a continuation and moving on to its first subexpression
+    fn start<'e>(
+        &mut self,
+        expr: &'e Expression<TS>,
+        pending: &mut Vec<Continuation<'e, TS>>,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<Next<'e, TS>, FreightError> {
+        self.consume_fuel()?;
+        self.check_cancellation()?;
+        self.stats.expressions_evaluated += 1;
+        let (continuation, next) = match expr {
+            Expression::RawValue(v) => return Ok(Next::Value(v.clone())),
+            Expression::PooledValue(index) => {
+                return Ok(Next::Value(self.constants[*index].dupe_ref()))
+            }
+            Expression::Variable(var) => {
+                let value = match var {
+                    VariableType::Captured(addr) => &captured[*addr],
+                    VariableType::Stack(addr) => &stack[*addr],
+                    VariableType::Global(addr) => self.global(*addr)?,
+                };
+                if self.strict_reads && value.is_uninitialized() {
+                    return Err(FreightError::UninitializedRead {
+                        variable: var.clone(),
+                    });
+                }
+                return Ok(Next::Value(value.dupe_ref()));
+            }
+            Expression::BinaryOpEval(op, operands) => {
+                let [l, r] = &**operands;
+                (Continuation::BinaryLeft(op, r), l)
+            }
+            Expression::UnaryOpEval(op, v) => (Continuation::Unary(op), &**v),
+            Expression::TernaryOpEval(op, operands) => {
+                (Continuation::TernaryFirst(op, operands), &operands[0])
+            }
+            Expression::LazyBinaryOpEval(op, operands) => {
+                let [l, r] = &**operands;
+                (Continuation::LazyBinaryLeft(op, r), l)
+            }
+            Expression::StaticFunctionCall(func, args)
+                if args.iter().any(Expression::is_spread) =>
+            {
+                let args = self.collect_spread_args(args, stack, captured)?;
+                return self.call_with_values(func, args).map(Next::Value);
+            }
+            Expression::StaticFunctionCall(func, args) => {
+                let mut args = args.iter().enumerate();
+                let arg_count = args.len();
+                return self
+                    .call_internal(
+                        func,
+                        |e| {
+                            let (i, arg) = args.next().unwrap();
+                            e.evaluate_internal(arg, stack, captured).map_err(|err| {
+                                e.traced(err, || format!("arg {i} of static call to {func}"))
+                            })
+                        },
+                        arg_count,
+                    )
+                    .map(Next::Value);
+            }
+            Expression::StaticFunctionCallNamed {
+                func,
+                positional,
+                named,
+            } => {
+                let positional = self.collect_spread_args(positional, stack, captured)?;
+                let mut values = Vec::with_capacity(named.len());
+                for (name, arg) in named {
+                    let value = self
+                        .evaluate_internal(arg, stack, captured)
+                        .map_err(|err| {
+                            self.traced(err, || format!("arg {name:?} of static call to {func}"))
+                        })?;
+                    values.push((name.clone(), value));
+                }
+                return self
+                    .call_named_internal(func, positional, values)
+                    .map(Next::Value);
+            }
+            Expression::TailCall(func, args) if args.iter().any(Expression::is_spread) => {
+                let args = self.collect_spread_args(args, stack, captured)?;
+                return self.request_tail_call(func, args);
+            }
+            Expression::TailCall(func, args) => {
+                let mut collected = std::mem::take(&mut self.tail_call_args);
+                collected.clear();
+                let Some((first, remaining)) = args.split_first() else {
+                    return self.request_tail_call(func, collected);
+                };
+                let continuation = Continuation::TailCall {
+                    func,
+                    remaining,
+                    collected,
+                };
+                (continuation, first)
+            }
+            Expression::DynamicFunctionCall(func, args) => {
+                (Continuation::DynamicCall(args), &**func)
+            }
+            Expression::FunctionCapture(func) => {
+                return self
+                    .capture_function(func, stack, captured)
+                    .map(Next::Value)
+            }
+            Expression::AssignStack(addr, expr) => {
+                (Continuation::AssignStack(*addr, false), &**expr)
+            }
+            Expression::AssignStackAndYield(addr, expr) => {
+                (Continuation::AssignStack(*addr, true), &**expr)
+            }
+            Expression::NativeFunctionCall(func, args) => {
+                let collected = StackPool::request(self.stack.clone(), args.len());
+                let Some((first, remaining)) = args.split_first() else {
+                    return self.call_native(func, collected).map(Next::Value);
+                };
+                let continuation = Continuation::NativeCall {
+                    func,
+                    remaining,
+                    collected,
+                };
+                (continuation, first)
+            }
+            Expression::NativeMacroCall(func, args) => {
+                self.stats.native_calls += 1;
+                return func(self, args, EvalScope::new(stack, captured)).map(Next::Value);
+            }
+            Expression::TypeAssert { value, .. } if !self.type_assertions => {
+                return Ok(Next::Eval(value))
+            }
+            Expression::TypeAssert { expected, value } => {
+                (Continuation::TypeAssert(expected), &**value)
+            }
+            Expression::Index { target, index } => (Continuation::IndexTarget(index), &**target),
+            Expression::IndexAssign {
+                target,
+                index,
+                value,
+            } => (Continuation::IndexAssignTarget { index, value }, &**target),
+            Expression::AssignGlobal(addr, expr) => {
+                (Continuation::AssignGlobal(*addr, false), &**expr)
+            }
+            Expression::AssignGlobalAndYield(addr, expr) => {
+                (Continuation::AssignGlobal(*addr, true), &**expr)
+            }
+            Expression::AssignCaptured(addr, expr) => {
+                (Continuation::AssignCaptured(*addr), &**expr)
+            }
+            Expression::CompoundAssign { target, op, value } => {
+                (Continuation::CompoundAssign(target, op), &**value)
+            }
+            Expression::UnaryAssign { target, op } => {
+                self.unary_assign(target, op, stack, captured)?;
+                return Ok(Next::Value(Default::default()));
+            }
+            Expression::Swap(a, b) => {
+                self.swap_variables(a, b, stack, captured)?;
+                return Ok(Next::Value(Default::default()));
+            }
+            Expression::DestructureAssign { targets, value } => {
+                (Continuation::DestructureAssign(targets), &**value)
+            }
+            Expression::AssignDynamic(args) => {
+                let [target, value] = &**args;
+                (Continuation::AssignDynamicTarget(value, false), target)
+            }
+            Expression::AssignDynamicAndYield(args) => {
+                let [target, value] = &**args;
+                (Continuation::AssignDynamicTarget(value, true), target)
+            }
+            Expression::Initialize(init, args) => {
+                let collected = InitArgs::new(init, args.len());
+                let Some((first, remaining)) = args.split_first() else {
+                    return self.initialize(init, collected).map(Next::Value);
+                };
+                let continuation = Continuation::Initialize {
+                    init,
+                    remaining,
+                    collected,
+                };
+                (continuation, first)
+            }
+            Expression::InitializeNamed(init, fields) => {
+                let collected = Vec::with_capacity(fields.len());
+                let Some(((field, first), remaining)) = fields.split_first() else {
+                    return self.initialize_named(init, collected).map(Next::Value);
+                };
+                let continuation = Continuation::InitializeNamed {
+                    init,
+                    field,
+                    remaining,
+                    collected,
+                };
+                (continuation, first)
+            }
+            Expression::ReturnTarget(target, expr) => {
+                (Continuation::ReturnTarget(*target), &**expr)
+            }
+            Expression::Return(target, expr) => (Continuation::Return(*target), &**expr),
+            Expression::Loop { target, body } => (
+                Continuation::Loop {
+                    target: *target,
+                    body,
+                },
+                &**body,
+            ),
+            Expression::Continue(target) => return Err(FreightError::Continue { target: *target }),
+            Expression::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let continuation = Continuation::Conditional {
+                    then_branch,
+                    else_branch: else_branch.as_deref(),
+                };
+                (continuation, &**condition)
+            }
+            Expression::And(operands) => {
+                let [l, r] = &**operands;
+                (Continuation::And(r), l)
+            }
+            Expression::Or(operands) => {
+                let [l, r] = &**operands;
+                (Continuation::Or(r), l)
+            }
+            Expression::Coalesce(operands) => {
+                let [l, r] = &**operands;
+                (Continuation::Coalesce(r), l)
+            }
+            Expression::Sequence(exprs) => match exprs.as_slice() {
+                [] => return Ok(Next::Value(Default::default())),
+                [only] => return Ok(Next::Eval(only)),
+                [first, rest @ ..] => (Continuation::Sequence(rest), first),
+            },
+            Expression::Match {
+                scrutinee,
+                arms,
+                default,
+            } => {
+                let continuation = Continuation::Match {
+                    arms,
+                    default: default.as_deref(),
+                };
+                (continuation, &**scrutinee)
+            }
+            Expression::TryCatch {
+                body,
+                error_slot,
+                handler,
+            } => {
+                let continuation = Continuation::TryCatch {
+                    error_slot: *error_slot,
+                    handler,
+                };
+                (continuation, &**body)
+            }
+            Expression::While { condition, body } => (
+                Continuation::WhileCondition { condition, body },
+                &**condition,
+            ),
+            Expression::For {
+                binding,
+                iterable,
+                body,
+            } => {
+                let continuation = Continuation::ForIterable {
+                    binding: *binding,
+                    body,
+                };
+                (continuation, &**iterable)
+            }
+            Expression::Spread(_) => return Err(FreightError::SpreadOutsideCall),
+            Expression::Spanned(span, expr) => (Continuation::Spanned(span), &**expr),
+            Expression::Lazy(body) => {
+                return self.create_thunk(body, stack, captured).map(Next::Value)
+            }
+            Expression::Force(thunk) => (Continuation::Force, &**thunk),
+            Expression::Yield(value) => (Continuation::Yield, &**value),
+        };
+        pending.push(continuation);
+        Ok(Next::Eval(next))
+    }
+
+    /// Continue an expression with the result of its latest subexpression. Errors
+    /// propagate through every continuation except return targets, loops, try/catch and spans.
+    fn resume<'e>(
+        &mut self,
+        continuation: Continuation<'e, TS>,
+        result: Result<TS::Value, FreightError>,
+        pending: &mut Vec<Continuation<'e, TS>>,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<Next<'e, TS>, FreightError> {
+        let (continuation, value) = match continuation {
+            Continuation::ReturnTarget(target) => {
+                return result.or_return(target, self).map(Next::Value)
+            }
+            Continuation::Loop { target, body } => {
+                match result {
+                    Err(FreightError::Return { target: t }) if t == target => {
+                        return Ok(Next::Value(std::mem::take(&mut self.return_value)))
+                    }
+                    Err(FreightError::Continue { target: t }) if t == target => {}
+                    Err(err) => return Err(err),
+                    Ok(_) => {}
+                }
+                // Loops make no calls, so they check for interrupts themselves to stay abortable
+                self.check_interrupt()?;
+                pending.push(Continuation::Loop { target, body });
+                return Ok(Next::Eval(body));
+            }
+            Continuation::Spanned(span) => {
+                return result
+                    .map(Next::Value)
+                    .map_err(|err| err.with_location(span))
+            }
+            Continuation::TryCatch {
+                error_slot,
+                handler,
+            } => {
+                return match result {
+                    Err(error) if error.is_catchable() => {
+                        stack[error_slot].assign(TS::error_to_value(error));
+                        Ok(Next::Eval(handler))
+                    }
+                    result => result.map(Next::Value),
+                }
+            }
+            continuation => match result {
+                Ok(value) => (continuation, value),
+                Err(err) => return Err(self.traced(err, || continuation.describe())),
+            },
+        };
+        let next = match (continuation, value) {
+            (Continuation::BinaryLeft(op, r), l) => {
+                pending.push(Continuation::BinaryRight(op, l));
+                Next::Eval(r)
+            }
+            (Continuation::BinaryRight(op, l), r) => Next::Value(self.apply_binary(op, &l, &r)?),
+            (Continuation::Unary(op), v) => Next::Value(self.apply_unary(op, &v)?),
+            (Continuation::TernaryFirst(op, operands), a) => {
+                pending.push(Continuation::TernarySecond(op, &operands[2], a));
+                Next::Eval(&operands[1])
+            }
+            (Continuation::TernarySecond(op, c, a), b) => {
+                pending.push(Continuation::TernaryThird(op, a, b));
+                Next::Eval(c)
+            }
+            (Continuation::TernaryThird(op, a, b), c) => {
+                Next::Value(self.apply_ternary(op, [&a, &b, &c])?)
+            }
+            (Continuation::LazyBinaryLeft(op, r), l) => {
+                Next::Value(self.apply_lazy(op, &l, |e| e.evaluate_internal(r, stack, captured))?)
+            }
+            (Continuation::DynamicCall(args), func) => {
+                Next::Value(self.call_dynamic(&func, args, stack, captured)?)
+            }
+            (Continuation::AssignStack(addr, yields), value) => {
+                stack[addr].assign(value);
+                Next::Value(Self::assigned(&stack[addr], yields))
+            }
+            (Continuation::AssignGlobal(addr, yields), value) => {
+                let global = self.global_mut(addr)?;
+                global.assign(value);
+                Next::Value(Self::assigned(global, yields))
+            }
+            (Continuation::AssignCaptured(addr), value) => {
+                captured[addr].dupe_ref().assign(value);
+                Next::Value(Default::default())
+            }
+            (Continuation::CompoundAssign(target, op), value) => {
+                #[cfg(feature = "profiling")]
+                self.profile.binary.record(op);
+                let mut captured_target;
+                let target = match target {
+                    VariableType::Stack(addr) => &mut stack[*addr],
+                    VariableType::Global(addr) => {
+                        // Borrowed by field so the context can be borrowed alongside it
+                        self.global(*addr)?;
+                        &mut self.globals[*addr]
+                    }
+                    VariableType::Captured(addr) => {
+                        captured_target = captured[*addr].dupe_ref();
+                        &mut captured_target
+                    }
+                };
+                match coerce::<TS>(op, target, &value) {
+                    Some((l, r)) => op
+                        .apply_2_ctx(&l, &r, &mut self.context)
+                        .map(|result| target.assign(result)),
+                    None => op.apply_2_assign(target, &value, &mut self.context),
+                }
+                .map_err(|err| self.traced(err, || format!("operator {}", op.name())))?;
+                Next::Value(Default::default())
+            }
+            (Continuation::DestructureAssign(targets), value) => {
+                let elements = value
+                    .list_elements()
+                    .ok_or(FreightError::NotDestructurable)?;
+                if elements.len() != targets.len() {
+                    return Err(FreightError::DestructureMismatch {
+                        expected: targets.len(),
+                        actual: elements.len(),
+                    });
+                }
+                for (target, element) in targets.iter().zip(elements) {
+                    match target {
+                        VariableType::Stack(addr) => stack[*addr].assign(element),
+                        VariableType::Global(addr) => self.global_mut(*addr)?.assign(element),
+                        VariableType::Captured(addr) => captured[*addr].dupe_ref().assign(element),
+                    }
+                }
+                Next::Value(Default::default())
+            }
+            (Continuation::TypeAssert(expected), value) => {
+                if value.get_type() != expected {
+                    return Err(FreightError::TypeMismatch {
+                        expected: format!("{expected:?}"),
+                        actual: value.type_name(),
+                    });
+                }
+                Next::Value(value)
+            }
+            (Continuation::IndexTarget(index), target) => {
+                pending.push(Continuation::IndexKey(target));
+                Next::Eval(index)
+            }
+            (Continuation::IndexKey(target), index) => Next::Value(target.get_index(&index)?),
+            (Continuation::IndexAssignTarget { index, value }, target) => {
+                pending.push(Continuation::IndexAssignKey { target, value });
+                Next::Eval(index)
+            }
+            (Continuation::IndexAssignKey { target, value }, index) => {
+                pending.push(Continuation::IndexAssignValue { target, index });
+                Next::Eval(value)
+            }
+            (Continuation::IndexAssignValue { mut target, index }, value) => {
+                target.set_index(&index, value)?;
+                Next::Value(Default::default())
+            }
+            (Continuation::AssignDynamicTarget(value, yields), target) => {
+                pending.push(Continuation::AssignDynamicValue(target.dupe_ref(), yields));
+                Next::Eval(value)
+            }
+            (Continuation::AssignDynamicValue(mut target, yields), value) => {
+                target.assign(value);
+                Next::Value(Self::assigned(&target, yields))
+            }
+            (
+                Continuation::Initialize {
+                    init,
+                    remaining,
+                    mut collected,
+                },
+                value,
+            ) => {
+                self.push_init_arg(init, &mut collected, value)?;
+                let Some((next, remaining)) = remaining.split_first() else {
+                    return self.initialize(init, collected).map(Next::Value);
+                };
+                pending.push(Continuation::Initialize {
+                    init,
+                    remaining,
+                    collected,
+                });
+                Next::Eval(next)
+            }
+            (
+                Continuation::InitializeNamed {
+                    init,
+                    field,
+                    remaining,
+                    mut collected,
+                },
+                value,
+            ) => {
+                collected.push((field.clone(), value));
+                let Some(((field, next), remaining)) = remaining.split_first() else {
+                    return self.initialize_named(init, collected).map(Next::Value);
+                };
+                pending.push(Continuation::InitializeNamed {
+                    init,
+                    field,
+                    remaining,
+                    collected,
+                });
+                Next::Eval(next)
+            }
+            (
+                Continuation::TailCall {
+                    func,
+                    remaining,
+                    mut collected,
+                },
+                value,
+            ) => {
+                collected.push(value);
+                let Some((next, remaining)) = remaining.split_first() else {
+                    return self.request_tail_call(func, collected);
+                };
+                pending.push(Continuation::TailCall {
+                    func,
+                    remaining,
+                    collected,
+                });
+                Next::Eval(next)
+            }
+            (
+                Continuation::NativeCall {
+                    func,
+                    remaining,
+                    mut collected,
+                },
+                value,
+            ) => {
+                let index = collected.len() - remaining.len() - 1;
+                collected[index] = value;
+                let Some((next, remaining)) = remaining.split_first() else {
+                    return self.call_native(func, collected).map(Next::Value);
+                };
+                pending.push(Continuation::NativeCall {
+                    func,
+                    remaining,
+                    collected,
+                });
+                Next::Eval(next)
+            }
+            (Continuation::Return(target), value) => {
+                self.return_value = value;
+                return Err(FreightError::Return { target });
+            }
+            (
+                Continuation::Conditional {
+                    then_branch,
+                    else_branch,
+                },
+                condition,
+            ) => {
+                if condition.is_truthy() {
+                    Next::Eval(then_branch)
+                } else if let Some(else_branch) = else_branch {
+                    Next::Eval(else_branch)
+                } else {
+                    Next::Value(Default::default())
+                }
+            }
+            (Continuation::And(r), l) => {
+                if l.is_truthy() {
+                    Next::Eval(r)
+                } else {
+                    Next::Value(l)
+                }
+            }
+            (Continuation::Or(r), l) => {
+                if l.is_truthy() {
+                    Next::Value(l)
+                } else {
+                    Next::Eval(r)
+                }
+            }
+            (Continuation::Coalesce(r), l) => {
+                if l.is_null() {
+                    Next::Eval(r)
+                } else {
+                    Next::Value(l)
+                }
+            }
+            (Continuation::Sequence(exprs), _) => match exprs {
+                [only] => Next::Eval(only),
+                [next, rest @ ..] => {
+                    pending.push(Continuation::Sequence(rest));
+                    Next::Eval(next)
+                }
+                [] => unreachable!("Sequences continue with at least one expression left"),
+            },
+            (Continuation::Match { arms, default }, scrutinee) => {
+                let arm = arms
+                    .iter()
+                    .find(|(constant, _)| scrutinee.matches_constant(constant))
+                    .map(|(_, arm)| arm)
+                    .or(default)
+                    .ok_or(FreightError::NoMatchingArm)?;
+                Next::Eval(arm)
+            }
+            (Continuation::WhileCondition { condition, body }, value) => {
+                if value.is_truthy() {
+                    pending.push(Continuation::WhileBody { condition, body });
+                    Next::Eval(body)
+                } else {
+                    Next::Value(Default::default())
+                }
+            }
+            // Loops make no calls, so they check for interrupts themselves to stay abortable
+            (Continuation::WhileBody { condition, body }, _) => {
+                self.check_interrupt()?;
+                pending.push(Continuation::WhileCondition { condition, body });
+                Next::Eval(condition)
+            }
+            (Continuation::ForIterable { binding, body }, iterable) => {
+                let Some(iter) = iterable.cast_to_iterator() else {
+                    return Err(FreightError::NotIterable);
+                };
+                Self::next_element(binding, body, iter, pending, stack)?
+            }
+            (
+                Continuation::ForBody {
+                    binding,
+                    body,
+                    iter,
+                },
+                _,
+            ) => {
+                self.check_interrupt()?;
+                Self::next_element(binding, body, iter, pending, stack)?
+            }
+            (Continuation::Force, value) => Next::Value(self.force(value)?),
+            // Resumable calls suspend before resuming this continuation, so it's only
+            // reached by yields they can't suspend at
+            (Continuation::Yield, _) => return Err(FreightError::YieldOutsideResumable),
+            (
+                Continuation::ReturnTarget(_)
+                | Continuation::Loop { .. }
+                | Continuation::TryCatch { .. }
+                | Continuation::Spanned(_),
+                _,
+            ) => {
+                unreachable!("Handled above")
+            }
+        };
+        Ok(next)
+    }
+
+    /// The result of an assignment to `variable`, which only yielding assignments produce
+    fn assigned(variable: &TS::Value, yields: bool) -> TS::Value {
+        if yields {
+            variable.dupe_ref()
+        } else {
+            Default::default()
+        }
+    }
+
+    // Errors raised by operators themselves are traced with the operator's name
+
+    fn apply_unary(
+        &mut self,
+        op: &TS::UnaryOp,
+        value: &TS::Value,
+    ) -> Result<TS::Value, FreightError> {
+        #[cfg(feature = "profiling")]
+        self.profile.unary.record(op);
+        op.apply_1_ctx(value, &mut self.context)
+            .map_err(|err| self.traced(err, || format!("operator {}", op.name())))
+    }
+
+    pub(super) fn apply_binary(
+        &mut self,
+        op: &TS::BinaryOp,
+        l: &TS::Value,
+        r: &TS::Value,
+    ) -> Result<TS::Value, FreightError> {
+        #[cfg(feature = "profiling")]
+        self.profile.binary.record(op);
+        let coerced = coerce::<TS>(op, l, r);
+        let (l, r) = coerced.as_ref().map_or((l, r), |(l, r)| (l, r));
+        match op.apply_2_select(l, r, &mut self.context) {
+            Ok(OpResult::Owned(value)) => Ok(value),
+            Ok(OpResult::Left) => Ok(l.dupe_ref()),
+            Ok(OpResult::Right) => Ok(r.dupe_ref()),
+            Err(err) => Err(self.traced(err, || format!("operator {}", op.name()))),
+        }
+    }
+
+    fn apply_ternary(
+        &mut self,
+        op: &TS::TernaryOp,
+        [a, b, c]: [&TS::Value; 3],
+    ) -> Result<TS::Value, FreightError> {
+        #[cfg(feature = "profiling")]
+        self.profile.ternary.record(op);
+        op.apply_3_ctx(a, b, c, &mut self.context)
+            .map_err(|err| self.traced(err, || format!("operator {}", op.name())))
+    }
+
+    /// Apply a lazy operator, letting it evaluate its right operand on demand. Evaluating
+    /// the right operand counts as a call towards the maximum call depth since it
+    /// recurses natively.
+    fn apply_lazy(
+        &mut self,
+        op: &TS::LazyBinaryOp,
+        left: &TS::Value,
+        mut right: impl FnMut(&mut Self) -> Result<TS::Value, FreightError>,
+    ) -> Result<TS::Value, FreightError> {
+        #[cfg(feature = "profiling")]
+        self.profile.lazy.record(op);
+        op.apply_lazy(left, &mut || {
+            if self.call_depth >= self.max_call_depth {
+                return Err(FreightError::StackOverflow {
+                    depth: self.call_depth,
+                });
+            }
+            self.call_depth += 1;
+            let result = right(self);
+            self.call_depth -= 1;
+            result.map_err(|err| self.traced(err, || format!("right operand of {}", op.name())))
+        })
+    }
+
+    /// Bind the next element of a `For` loop and evaluate the body, or finish the loop
+    fn next_element<'e>(
+        binding: usize,
+        body: &'e Expression<TS>,
+        mut iter: ValueIter<TS::Value>,
+        pending: &mut Vec<Continuation<'e, TS>>,
+        stack: &mut [TS::Value],
+    ) -> Result<Next<'e, TS>, FreightError> {
+        let Some(elem) = iter.next() else {
+            return Ok(Next::Value(Default::default()));
+        };
+        stack[binding].assign(elem?);
+        pending.push(Continuation::ForBody {
+            binding,
+            body,
+            iter,
+        });
+        Ok(Next::Eval(body))
+    }
+
+    fn capture_function(
+        &mut self,
+        func: &FunctionRef<TS>,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<TS::Value, FreightError> {
+        let FunctionType::CapturingDef(capture, layout) = &func.function_type else {
+            return Err(FreightError::InvalidInvocationTarget);
+        };
+        let mut func = func.clone();
+        for var in capture.iter() {
+            if let VariableType::Global(addr) = var {
+                self.global(*addr)?;
+            }
+        }
+        let captures_iter = capture.iter().enumerate().map(|(i, var)| {
+            let value = match var {
+                VariableType::Captured(addr) => &captured[*addr],
+                VariableType::Stack(addr) => &stack[*addr],
+                VariableType::Global(addr) => &self.globals[*addr],
+            };
+            if layout.is_by_value(i) {
+                value.clone_unaliased()
+            } else {
+                value.dupe_ref()
+            }
+        });
+
+        func.function_type =
+            FunctionType::CapturingRef(RcSlicePool::from_pool(self.rc_pool.clone(), captures_iter));
+        Ok(func.into())
+    }
+
+    /// Leave the current function, asking the frame which called it to call `func` instead
+    fn request_tail_call<'e>(
+        &mut self,
+        func: &FunctionRef<TS>,
+        args: Vec<TS::Value>,
+    ) -> Result<Next<'e, TS>, FreightError> {
+        self.tail_call = Some(func.clone());
+        self.tail_call_args = args;
+        Err(FreightError::TailCall)
+    }
+
+    fn create_thunk(
+        &mut self,
+        body: &Shared<Expression<TS>>,
+        stack: &[TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<TS::Value, FreightError> {
+        let env = ThunkEnv {
+            body: body.clone(),
+            stack: stack.iter().map(Value::dupe_ref).collect(),
+            captured: captured.iter().map(Value::dupe_ref).collect(),
+        };
+        TS::Value::from_thunk(Thunk::new(env)).ok_or(FreightError::LazyValuesUnsupported)
+    }
+
+    /// Evaluate the body of a thunk unless it has already been, counting as a call
+    /// towards the maximum call depth since it recurses natively
+    fn force(&mut self, value: TS::Value) -> Result<TS::Value, FreightError> {
+        let Some(thunk) = value.cast_to_thunk() else {
+            return Ok(value);
+        };
+        let thunk = thunk.clone();
+        let mut env = match thunk.begin_force() {
+            ThunkState::Forced(value) => return Ok(value.dupe_ref()),
+            ThunkState::Forcing => return Err(FreightError::RecursiveForce),
+            ThunkState::Pending(env) => env,
+        };
+        if self.call_depth >= self.max_call_depth {
+            thunk.end_force(ThunkState::Pending(env));
+            return Err(FreightError::StackOverflow {
+                depth: self.call_depth,
+            });
+        }
+        self.call_depth += 1;
+        let ThunkEnv {
+            body,
+            stack,
+            captured,
+        } = &mut *env;
+        let result = self.evaluate_internal(body, stack, captured);
+        let result = self.run_tail_calls(None, result);
+        self.call_depth -= 1;
+        match result {
+            Ok(value) => {
+                thunk.end_force(ThunkState::Forced(value.dupe_ref()));
+                Ok(value)
+            }
+            Err(err) => {
+                thunk.end_force(ThunkState::Pending(env));
+                match err {
+                    FreightError::Return { target } => {
+                        Err(FreightError::ReturnFromThunk { target })
+                    }
+                    err => Err(err),
+                }
+            }
+        }
+    }
+
+    fn call_dynamic(
+        &mut self,
+        func: &TS::Value,
+        args: &[Expression<TS>],
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<TS::Value, FreightError> {
+        let Some(func): Option<&FunctionRef<TS>> = func.cast_to_function() else {
+            return Err(FreightError::NotCallable {
+                type_name: func.type_name(),
+            });
+        };
+        if args.iter().any(Expression::is_spread) {
+            let args = self.collect_spread_args(args, stack, captured)?;
+            return self.call_with_values(func, args);
+        }
+        let mut iter = args.iter().enumerate();
+        let arg_count = iter.len();
+        self.call_internal(
+            func,
+            |e| {
+                let (i, arg) = iter.next().unwrap();
+                e.evaluate_internal(arg, stack, captured)
+                    .map_err(|err| e.traced(err, || format!("arg {i} of dynamic call to {func}")))
+            },
+            arg_count,
+        )
+    }
+
+    /// Apply a unary operator to a variable in place for [Expression::UnaryAssign]
+    fn unary_assign(
+        &mut self,
+        target: &VariableType,
+        op: &TS::UnaryOp,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<(), FreightError> {
+        #[cfg(feature = "profiling")]
+        self.profile.unary.record(op);
+        let mut captured_target;
+        let target = match target {
+            VariableType::Stack(addr) => &mut stack[*addr],
+            VariableType::Global(addr) => {
+                // Borrowed by field so the context can be borrowed alongside it
+                self.global(*addr)?;
+                &mut self.globals[*addr]
+            }
+            VariableType::Captured(addr) => {
+                captured_target = captured[*addr].dupe_ref();
+                &mut captured_target
+            }
+        };
+        op.apply_1_assign(target, &mut self.context)
+            .map_err(|err| self.traced(err, || format!("operator {}", op.name())))
+    }
+
+    /// Exchange the contents of two variables for [Expression::Swap]
+    fn swap_variables(
+        &mut self,
+        a: &VariableType,
+        b: &VariableType,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<(), FreightError> {
+        match (a, b) {
+            (VariableType::Stack(a), VariableType::Stack(b)) => stack.swap(*a, *b),
+            (VariableType::Global(a), VariableType::Global(b)) => {
+                self.global_mut(*a)?;
+                self.global_mut(*b)?;
+                self.globals.swap(*a, *b);
+            }
+            (VariableType::Stack(slot), VariableType::Global(global))
+            | (VariableType::Global(global), VariableType::Stack(slot)) => {
+                std::mem::swap(&mut stack[*slot], self.global_mut(*global)?);
+            }
+            (VariableType::Captured(slot), other) | (other, VariableType::Captured(slot)) => {
+                // Assigning to a fresh value takes a snapshot rather than another reference
+                let mut previous = TS::Value::default();
+                previous.assign(captured[*slot].dupe_ref());
+                let value = match other {
+                    VariableType::Stack(addr) => stack[*addr].dupe_ref(),
+                    VariableType::Global(addr) => self.global(*addr)?.dupe_ref(),
+                    VariableType::Captured(addr) => captured[*addr].dupe_ref(),
+                };
+                captured[*slot].dupe_ref().assign(value);
+                match other {
+                    VariableType::Stack(addr) => stack[*addr].assign(previous),
+                    VariableType::Global(addr) => self.global_mut(*addr)?.assign(previous),
+                    VariableType::Captured(addr) => captured[*addr].dupe_ref().assign(previous),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add a step to the trace of an error if traces are enabled
+    fn traced(&self, err: FreightError, step: impl FnOnce() -> String) -> FreightError {
+        if self.error_traces {
+            err.with_trace(step)
+        } else {
+            err
+        }
+    }
+
+    /// Evaluate the defaults of the arguments the caller left out, which are those from
+    /// `passed` on and those in `holes`, in the callee's frame
+    pub(super) fn fill_defaults(
+        &mut self,
+        func: &FunctionRef<TS>,
+        stack: &mut [TS::Value],
+        passed: usize,
+        holes: &[usize],
+    ) -> Result<(), FreightError> {
+        let captured = match &func.function_type {
+            FunctionType::Static => &[][..],
+            FunctionType::CapturingRef(captures) => captures,
+            _ => return Ok(()),
+        };
+        let function = self.get_function(func.location);
+        for i in 0..func.arg_count.max_capped() {
+            if i < passed && !holes.contains(&i) {
+                continue;
+            }
+            let value = match function.defaults.get(i) {
+                Some(Some(default)) => self
+                    .evaluate_internal(default, stack, captured)
+                    .map_err(|err| self.traced(err, || format!("default of arg {i} of {func}")))?,
+                // Arguments after those passed are already uninitialized
+                _ if i >= passed => continue,
+                _ if func.layout.is_alloc(i) => {
+                    stack[i] = TS::Value::uninitialized_reference();
+                    continue;
+                }
+                _ => TS::Value::uninitialized_value(),
+            };
+            stack[i] = if func.layout.is_alloc(i) {
+                value.into_ref()
+            } else {
+                value
+            };
+        }
+        Ok(())
+    }
+
+    /// Evaluate the arguments of a call up front, splicing in the elements of spread
+    /// arguments, since the argument count isn't known until they're evaluated
+    fn collect_spread_args(
+        &mut self,
+        args: &[Expression<TS>],
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<Vec<TS::Value>, FreightError> {
+        let mut collected = Vec::with_capacity(args.len());
+        for (i, arg) in args.iter().enumerate() {
+            let (spread, arg) = match arg {
+                Expression::Spread(list) => (true, &**list),
+                arg => (false, arg),
+            };
+            let value = self
+                .evaluate_internal(arg, stack, captured)
+                .map_err(|err| self.traced(err, || format!("arg {i} of call")))?;
+            if spread {
+                let elements = value.list_elements().ok_or(FreightError::NotSpreadable)?;
+                collected.extend(elements);
+            } else {
+                collected.push(value);
+            }
+        }
+        Ok(collected)
+    }
+
+    fn call_with_values(
+        &mut self,
+        func: &FunctionRef<TS>,
+        args: Vec<TS::Value>,
+    ) -> Result<TS::Value, FreightError> {
+        let mut args = args.into_iter();
+        let arg_count = args.len();
+        self.call_internal(func, |_| Ok(args.next().unwrap()), arg_count)
+    }
+
+    fn call_native(
+        &mut self,
+        func: &NativeFunction<TS>,
+        mut args: StackSlice<TS::Value>,
+    ) -> Result<TS::Value, FreightError> {
+        self.stats.native_calls += 1;
+        func(self, &mut args)
+    }
+
+    fn push_init_arg(
+        &mut self,
+        init: &TS::Init,
+        args: &mut InitArgs<TS>,
+        value: TS::Value,
+    ) -> Result<(), FreightError> {
+        match args {
+            InitArgs::Collected(values) => values.push(value),
+            InitArgs::Streamed(builder, len) => {
+                init.push(builder, value)
+                    .map_err(|err| self.traced(err, || format!("initializer {init:?}")))?;
+                *len += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn initialize(
+        &mut self,
+        init: &TS::Init,
+        args: InitArgs<TS>,
+    ) -> Result<TS::Value, FreightError> {
+        let value = match args {
+            InitArgs::Collected(values) => init.try_initialize(values, self),
+            InitArgs::Streamed(builder, _) => init.finish(builder, self),
+        }
+        .map_err(|err| self.traced(err, || format!("initializer {init:?}")))?;
+        self.charge_memory(&value)?;
+        Ok(value)
+    }
+
+    fn initialize_named(
+        &mut self,
+        init: &TS::Init,
+        fields: Vec<(TS::FieldId, TS::Value)>,
+    ) -> Result<TS::Value, FreightError> {
+        let value = init
+            .initialize_named(fields, self)
+            .map_err(|err| self.traced(err, || format!("initializer {init:?}")))?;
+        self.charge_memory(&value)?;
+        Ok(value)
+    }
+}
@@ -0,0 +1,476 @@
+use super::{ExecutionEngine, InitArgs, StackPool, StackSlice};
+use crate::{
+    error::{FreightError, OrReturn},
+    expression::{Children, ExpressionArena, Node, NodeId, VariableType},
+    operators::{BinaryOperator, LazyBinaryOperator, TernaryOperator, UnaryOperator},
+    value::Value,
+    TypeSystem,
+};
+
+/// What the arena evaluator does next
+enum Next<TS: TypeSystem> {
+    /// Start evaluating a node
+    Eval(NodeId),
+    /// Hand a finished value to the innermost pending step
+    Value(TS::Value),
+}
+
+/// A node waiting on the result of one of its children. Steps refer back to their node
+/// for anything else they need, so they stay small.
+enum Step<TS: TypeSystem> {
+    BinaryLeft(NodeId),
+    BinaryRight(NodeId, TS::Value),
+    Unary(NodeId),
+    TernaryFirst(NodeId),
+    TernarySecond(NodeId, TS::Value),
+    TernaryThird(NodeId, TS::Value, TS::Value),
+    LazyBinaryLeft(NodeId),
+    Initialize {
+        node: NodeId,
+        collected: InitArgs<TS>,
+    },
+    NativeCall {
+        node: NodeId,
+        arg: usize,
+        collected: StackSlice<'static, TS::Value>,
+    },
+    /// The flag on assignments is whether they produce the assigned variable
+    AssignStack(usize, bool),
+    AssignGlobal(usize, bool),
+    AssignCaptured(usize),
+    ReturnTarget(usize),
+    Return(usize),
+    Loop(usize, NodeId),
+    Conditional(NodeId),
+    And(NodeId),
+    Or(NodeId),
+    Coalesce(NodeId),
+    Sequence(Children),
+    WhileCondition(NodeId),
+    WhileBody(NodeId),
+}
+
+impl<TS: TypeSystem> Step<TS> {
+    /// A short description of the child this step is waiting on, matching the
+    /// descriptions of the boxed evaluator
+    fn describe(&self, arena: &ExpressionArena<TS>) -> String {
+        match self {
+            Self::BinaryLeft(node) | Self::BinaryRight(node, _) => {
+                let Node::BinaryOpEval(op, _) = arena.node_at(*node) else {
+                    unreachable!("Binary steps belong to binary nodes")
+                };
+                let side = match self {
+                    Self::BinaryLeft(_) => "left",
+                    _ => "right",
+                };
+                format!("{side} operand of {}", op.name())
+            }
+            Self::LazyBinaryLeft(node) => {
+                let Node::LazyBinaryOpEval(op, _) = arena.node_at(*node) else {
+                    unreachable!("Lazy binary steps belong to lazy binary nodes")
+                };
+                format!("left operand of {}", op.name())
+            }
+            Self::Unary(node) => {
+                let Node::UnaryOpEval(op, _) = arena.node_at(*node) else {
+                    unreachable!("Unary steps belong to unary nodes")
+                };
+                format!("operand of {}", op.name())
+            }
+            Self::TernaryFirst(node)
+            | Self::TernarySecond(node, _)
+            | Self::TernaryThird(node, ..) => {
+                let Node::TernaryOpEval(op, _) = arena.node_at(*node) else {
+                    unreachable!("Ternary steps belong to ternary nodes")
+                };
+                let position = match self {
+                    Self::TernaryFirst(_) => "first",
+                    Self::TernarySecond(..) => "second",
+                    _ => "third",
+                };
+                format!("{position} operand of {}", op.name())
+            }
+            Self::Initialize { node, collected } => {
+                let Node::Initialize(init, _) = arena.node_at(*node) else {
+                    unreachable!("Initialize steps belong to initializer nodes")
+                };
+                format!("arg {} of initializer {init:?}", collected.len())
+            }
+            Self::NativeCall { arg, .. } => format!("arg {arg} of native call"),
+            Self::AssignStack(addr, _) => format!("value assigned to stack[{addr}]"),
+            Self::AssignGlobal(addr, _) => format!("value assigned to global[{addr}]"),
+            Self::AssignCaptured(addr) => format!("value assigned to captured[{addr}]"),
+            Self::ReturnTarget(target) => format!("return target {target}"),
+            Self::Return(target) => format!("value returned to target {target}"),
+            Self::Loop(target, _) => format!("loop {target}"),
+            Self::Conditional(_) => "condition".to_owned(),
+            Self::And(_) => "left operand of and".to_owned(),
+            Self::Or(_) => "left operand of or".to_owned(),
+            Self::Coalesce(_) => "left operand of coalesce".to_owned(),
+            Self::Sequence(_) => "sequence".to_owned(),
+            Self::WhileCondition(_) => "while condition".to_owned(),
+            Self::WhileBody(_) => "while body".to_owned(),
+        }
+    }
+}
+
+/// Empty step buffers kept between evaluations, like
+/// [ContinuationPool](super::ContinuationPool)
+pub(crate) struct StepPool<TS: TypeSystem>(Vec<Vec<Step<TS>>>);
+
+// SAFETY: every buffer in the pool is empty, so no step ever crosses threads
+unsafe impl<TS: TypeSystem> Send for StepPool<TS> {}
+
+impl<TS: TypeSystem> Default for StepPool<TS> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<TS: TypeSystem> ExecutionEngine<TS> {
+    /// Evaluate a node of an arena, with the same semantics as
+    /// [ExecutionEngine::evaluate_internal] on the expression it was built from
+    pub(crate) fn evaluate_arena(
+        &mut self,
+        arena: &ExpressionArena<TS>,
+        root: NodeId,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<TS::Value, FreightError> {
+        let mut pending = self.arena_steps.0.pop().unwrap_or_default();
+        let mut next = self.start_node(arena, root, &mut pending, stack, captured);
+        loop {
+            let result = match next {
+                Ok(Next::Eval(id)) => {
+                    next = self.start_node(arena, id, &mut pending, stack, captured);
+                    continue;
+                }
+                Ok(Next::Value(value)) => Ok(value),
+                Err(err) => Err(err),
+            };
+            match pending.pop() {
+                Some(step) => {
+                    next = self.resume_node(arena, step, result, &mut pending, stack, captured)
+                }
+                None => {
+                    self.arena_steps.0.push(pending);
+                    return result;
+                }
+            }
+        }
+    }
+
+    /// Begin evaluating a node, either finishing it immediately or
+    /// pushing a step and moving on to its first child
+    fn start_node(
+        &mut self,
+        arena: &ExpressionArena<TS>,
+        id: NodeId,
+        pending: &mut Vec<Step<TS>>,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<Next<TS>, FreightError> {
+        self.consume_fuel()?;
+        self.check_cancellation()?;
+        self.stats.expressions_evaluated += 1;
+        let (step, next) = match arena.node_at(id) {
+            Node::Value(index) => return Ok(Next::Value(arena.values[*index as usize].clone())),
+            Node::PooledValue(index) => return Ok(Next::Value(self.constants[*index].dupe_ref())),
+            Node::Variable(var) => {
+                let value = match var {
+                    VariableType::Captured(addr) => &captured[*addr],
+                    VariableType::Stack(addr) => &stack[*addr],
+                    VariableType::Global(addr) => self.global(*addr)?,
+                };
+                if self.strict_reads && value.is_uninitialized() {
+                    return Err(FreightError::UninitializedRead {
+                        variable: var.clone(),
+                    });
+                }
+                return Ok(Next::Value(value.dupe_ref()));
+            }
+            Node::BinaryOpEval(_, [l, _]) => (Step::BinaryLeft(id), *l),
+            Node::UnaryOpEval(_, v) => (Step::Unary(id), *v),
+            Node::TernaryOpEval(_, [a, ..]) => (Step::TernaryFirst(id), *a),
+            Node::LazyBinaryOpEval(_, [l, _]) => (Step::LazyBinaryLeft(id), *l),
+            Node::Initialize(init, args) => {
+                let collected = InitArgs::new(init, args.len());
+                let Some(first) = arena.children_of(*args).first() else {
+                    return self.initialize(init, collected).map(Next::Value);
+                };
+                (
+                    Step::Initialize {
+                        node: id,
+                        collected,
+                    },
+                    *first,
+                )
+            }
+            Node::StaticFunctionCall(func, args) => {
+                let func = &arena.functions[*func as usize];
+                let mut args = arena.children_of(*args).iter().enumerate();
+                let arg_count = args.len();
+                return self
+                    .call_internal(
+                        func,
+                        |e| {
+                            let (i, arg) = args.next().unwrap();
+                            e.evaluate_arena(arena, *arg, stack, captured)
+                                .map_err(|err| {
+                                    e.traced(err, || format!("arg {i} of static call to {func}"))
+                                })
+                        },
+                        arg_count,
+                    )
+                    .map(Next::Value);
+            }
+            Node::TailCall(func, args) => {
+                let func = &arena.functions[*func as usize];
+                let mut collected = std::mem::take(&mut self.tail_call_args);
+                collected.clear();
+                for (i, arg) in arena.children_of(*args).iter().enumerate() {
+                    let value =
+                        self.evaluate_arena(arena, *arg, stack, captured)
+                            .map_err(|err| {
+                                self.traced(err, || format!("arg {i} of tail call to {func}"))
+                            })?;
+                    collected.push(value);
+                }
+                self.tail_call = Some(func.clone());
+                self.tail_call_args = collected;
+                return Err(FreightError::TailCall);
+            }
+            Node::NativeFunctionCall(func, args) => {
+                let collected = StackPool::request(self.stack.clone(), args.len());
+                let Some(first) = arena.children_of(*args).first() else {
+                    return self.call_native(func, collected).map(Next::Value);
+                };
+                let step = Step::NativeCall {
+                    node: id,
+                    arg: 0,
+                    collected,
+                };
+                (step, *first)
+            }
+            Node::AssignStack(addr, value, yields) => (Step::AssignStack(*addr, *yields), *value),
+            Node::AssignGlobal(addr, value, yields) => (Step::AssignGlobal(*addr, *yields), *value),
+            Node::AssignCaptured(addr, value) => (Step::AssignCaptured(*addr), *value),
+            Node::ReturnTarget(target, body) => (Step::ReturnTarget(*target), *body),
+            Node::Return(target, value) => (Step::Return(*target), *value),
+            Node::Loop(target, body) => (Step::Loop(*target, *body), *body),
+            Node::Continue(target) => return Err(FreightError::Continue { target: *target }),
+            Node::Conditional(condition, ..) => (Step::Conditional(id), *condition),
+            Node::While(condition, _) => (Step::WhileCondition(id), *condition),
+            Node::And([l, r]) => (Step::And(*r), *l),
+            Node::Or([l, r]) => (Step::Or(*r), *l),
+            Node::Coalesce([l, r]) => (Step::Coalesce(*r), *l),
+            Node::Sequence(exprs) => match arena.children_of(*exprs) {
+                [] => return Ok(Next::Value(Default::default())),
+                [only] => return Ok(Next::Eval(*only)),
+                [first, ..] => (Step::Sequence(exprs.rest()), *first),
+            },
+        };
+        pending.push(step);
+        Ok(Next::Eval(next))
+    }
+
+    /// Hand the result of a child to the step waiting on it
+    fn resume_node(
+        &mut self,
+        arena: &ExpressionArena<TS>,
+        step: Step<TS>,
+        result: Result<TS::Value, FreightError>,
+        pending: &mut Vec<Step<TS>>,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<Next<TS>, FreightError> {
+        let (step, value) = match step {
+            Step::ReturnTarget(target) => return result.or_return(target, self).map(Next::Value),
+            Step::Loop(target, body) => {
+                match result {
+                    Err(FreightError::Return { target: t }) if t == target => {
+                        return Ok(Next::Value(std::mem::take(&mut self.return_value)))
+                    }
+                    Err(FreightError::Continue { target: t }) if t == target => {}
+                    Err(err) => return Err(err),
+                    Ok(_) => {}
+                }
+                self.check_interrupt()?;
+                pending.push(Step::Loop(target, body));
+                return Ok(Next::Eval(body));
+            }
+            step => match result {
+                Ok(value) => (step, value),
+                Err(err) => return Err(self.traced(err, || step.describe(arena))),
+            },
+        };
+        let next = match (step, value) {
+            (Step::BinaryLeft(id), l) => {
+                let Node::BinaryOpEval(_, [_, r]) = arena.node_at(id) else {
+                    unreachable!("Binary steps belong to binary nodes")
+                };
+                pending.push(Step::BinaryRight(id, l));
+                Next::Eval(*r)
+            }
+            (Step::BinaryRight(id, l), r) => {
+                let Node::BinaryOpEval(op, _) = arena.node_at(id) else {
+                    unreachable!("Binary steps belong to binary nodes")
+                };
+                Next::Value(self.apply_binary(op, &l, &r)?)
+            }
+            (Step::Unary(id), v) => {
+                let Node::UnaryOpEval(op, _) = arena.node_at(id) else {
+                    unreachable!("Unary steps belong to unary nodes")
+                };
+                Next::Value(self.apply_unary(op, &v)?)
+            }
+            (Step::LazyBinaryLeft(id), l) => {
+                let Node::LazyBinaryOpEval(op, [_, r]) = arena.node_at(id) else {
+                    unreachable!("Lazy binary steps belong to lazy binary nodes")
+                };
+                let value =
+                    self.apply_lazy(op, &l, |e| e.evaluate_arena(arena, *r, stack, captured))?;
+                Next::Value(value)
+            }
+            (Step::TernaryFirst(id), a) => {
+                let Node::TernaryOpEval(_, [_, b, _]) = arena.node_at(id) else {
+                    unreachable!("Ternary steps belong to ternary nodes")
+                };
+                pending.push(Step::TernarySecond(id, a));
+                Next::Eval(*b)
+            }
+            (Step::TernarySecond(id, a), b) => {
+                let Node::TernaryOpEval(_, [_, _, c]) = arena.node_at(id) else {
+                    unreachable!("Ternary steps belong to ternary nodes")
+                };
+                pending.push(Step::TernaryThird(id, a, b));
+                Next::Eval(*c)
+            }
+            (Step::TernaryThird(id, a, b), c) => {
+                let Node::TernaryOpEval(op, _) = arena.node_at(id) else {
+                    unreachable!("Ternary steps belong to ternary nodes")
+                };
+                Next::Value(self.apply_ternary(op, [&a, &b, &c])?)
+            }
+            (
+                Step::Initialize {
+                    node,
+                    mut collected,
+                },
+                value,
+            ) => {
+                let Node::Initialize(init, args) = arena.node_at(node) else {
+                    unreachable!("Initialize steps belong to initializer nodes")
+                };
+                self.push_init_arg(init, &mut collected, value)?;
+                let Some(next) = arena.children_of(*args).get(collected.len()) else {
+                    return self.initialize(init, collected).map(Next::Value);
+                };
+                pending.push(Step::Initialize { node, collected });
+                Next::Eval(*next)
+            }
+            (
+                Step::NativeCall {
+                    node,
+                    arg,
+                    mut collected,
+                },
+                value,
+            ) => {
+                let Node::NativeFunctionCall(func, args) = arena.node_at(node) else {
+                    unreachable!("Native call steps belong to native call nodes")
+                };
+                collected[arg] = value;
+                let Some(next) = arena.children_of(*args).get(arg + 1) else {
+                    return self.call_native(func, collected).map(Next::Value);
+                };
+                pending.push(Step::NativeCall {
+                    node,
+                    arg: arg + 1,
+                    collected,
+                });
+                Next::Eval(*next)
+            }
+            (Step::AssignStack(addr, yields), value) => {
+                stack[addr].assign(value);
+                Next::Value(Self::assigned(&stack[addr], yields))
+            }
+            (Step::AssignGlobal(addr, yields), value) => {
+                let global = self.global_mut(addr)?;
+                global.assign(value);
+                Next::Value(Self::assigned(global, yields))
+            }
+            (Step::AssignCaptured(addr), value) => {
+                captured[addr].dupe_ref().assign(value);
+                Next::Value(Default::default())
+            }
+            (Step::Return(target), value) => {
+                self.return_value = value;
+                return Err(FreightError::Return { target });
+            }
+            (Step::Conditional(id), condition) => {
+                let Node::Conditional(_, then_branch, else_branch) = arena.node_at(id) else {
+                    unreachable!("Conditional steps belong to conditional nodes")
+                };
+                if condition.is_truthy() {
+                    Next::Eval(*then_branch)
+                } else if let Some(else_branch) = else_branch {
+                    Next::Eval(*else_branch)
+                } else {
+                    Next::Value(Default::default())
+                }
+            }
+            (Step::And(r), l) => {
+                if l.is_truthy() {
+                    Next::Eval(r)
+                } else {
+                    Next::Value(l)
+                }
+            }
+            (Step::Or(r), l) => {
+                if l.is_truthy() {
+                    Next::Value(l)
+                } else {
+                    Next::Eval(r)
+                }
+            }
+            (Step::Coalesce(r), l) => {
+                if l.is_null() {
+                    Next::Eval(r)
+                } else {
+                    Next::Value(l)
+                }
+            }
+            (Step::Sequence(exprs), _) => match arena.children_of(exprs) {
+                [only] => Next::Eval(*only),
+                [next, ..] => {
+                    pending.push(Step::Sequence(exprs.rest()));
+                    Next::Eval(*next)
+                }
+                [] => unreachable!("Sequences continue with at least one node left"),
+            },
+            (Step::WhileCondition(id), value) => {
+                let Node::While(_, body) = arena.node_at(id) else {
+                    unreachable!("While steps belong to while nodes")
+                };
+                if value.is_truthy() {
+                    pending.push(Step::WhileBody(id));
+                    Next::Eval(*body)
+                } else {
+                    Next::Value(Default::default())
+                }
+            }
+            // Loops make no calls, so they check for interrupts themselves to stay abortable
+            (Step::WhileBody(id), _) => {
+                let Node::While(condition, _) = arena.node_at(id) else {
+                    unreachable!("While steps belong to while nodes")
+                };
+                self.check_interrupt()?;
+                pending.push(Step::WhileCondition(id));
+                Next::Eval(*condition)
+            }
+            (Step::ReturnTarget(_) | Step::Loop(..), _) => unreachable!("Handled above"),
+        };
+        Ok(next)
+    }
+}
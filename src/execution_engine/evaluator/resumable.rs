@@ -0,0 +1,248 @@
+use super::{Continuation, Next};
+use crate::{
+    error::{FreightError, OrReturn},
+    execution_engine::ExecutionEngine,
+    function::{Function, FunctionRef, FunctionType},
+    slice_pool::IntoExactSizeIterator,
+    sync::Shared,
+    value::Value,
+    TypeSystem,
+};
+use std::fmt::Debug;
+
+/// How far a [resumable call](ExecutionEngine::call_resumable) got when it stopped
+pub enum Step<TS: TypeSystem> {
+    /// The call suspended at an [Expression::Yield](crate::expression::Expression::Yield)
+    /// with its value
+    Yielded(TS::Value),
+    /// The call finished with its result
+    Done(TS::Value),
+}
+
+impl<TS: TypeSystem> Step<TS> {
+    /// The yielded value or the result
+    pub fn into_value(self) -> TS::Value {
+        match self {
+            Self::Yielded(value) | Self::Done(value) => value,
+        }
+    }
+}
+
+impl<TS: TypeSystem> Debug for Step<TS> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Yielded(value) => f.debug_tuple("Yielded").field(value).finish(),
+            Self::Done(value) => f.debug_tuple("Done").field(value).finish(),
+        }
+    }
+}
+
+impl<TS: TypeSystem> PartialEq for Step<TS> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Yielded(a), Self::Yielded(b)) | (Self::Done(a), Self::Done(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+/// A call started by [ExecutionEngine::call_resumable], which owns the frame of the
+/// function and the evaluation state at its latest yield. Dropping it abandons the call.
+pub struct Suspended<TS: TypeSystem> {
+    /// Borrows from the body of `function`, so it's declared first to be dropped first
+    pending: Vec<Continuation<'static, TS>>,
+    function: Shared<Function<TS>>,
+    func: FunctionRef<TS>,
+    stack: Vec<TS::Value>,
+    /// The body expression being evaluated, or `None` once the call has finished
+    position: Option<usize>,
+    /// Whether the call is waiting at a yield, rather than before its first expression
+    suspended: bool,
+}
+
+/// Change the lifetime of the borrows held by continuations
+///
+/// # Safety
+/// The continuations must only borrow data which lives at least as long as `'b`
+unsafe fn recast<'a, 'b, TS: TypeSystem>(
+    pending: Vec<Continuation<'a, TS>>,
+) -> Vec<Continuation<'b, TS>> {
+    let mut pending = std::mem::ManuallyDrop::new(pending);
+    Vec::from_raw_parts(
+        pending.as_mut_ptr().cast(),
+        pending.len(),
+        pending.capacity(),
+    )
+}
+
+impl<TS: TypeSystem> Suspended<TS> {
+    /// Whether the call has finished, so it can't be resumed anymore
+    pub fn is_finished(&self) -> bool {
+        self.position.is_none()
+    }
+
+    /// Continue the call until its next yield or its end. The first resume starts the
+    /// body and ignores `value`, and the later ones produce it from the yield the call
+    /// was suspended at. The call finishes when it fails too.
+    pub fn resume(
+        &mut self,
+        engine: &mut ExecutionEngine<TS>,
+        value: TS::Value,
+    ) -> Result<Step<TS>, FreightError> {
+        let Some(position) = self.position else {
+            return Err(FreightError::ResumedAfterFinish);
+        };
+        engine.consume_fuel()?;
+        engine.check_interrupt()?;
+        if engine.call_depth >= engine.max_call_depth {
+            return Err(FreightError::StackOverflow {
+                depth: engine.call_depth,
+            });
+        }
+        engine.call_depth += 1;
+        let result = self.run(engine, position, value);
+        engine.call_depth -= 1;
+        let result = match result {
+            Ok(Step::Yielded(value)) => return Ok(Step::Yielded(value)),
+            Ok(Step::Done(value)) => Ok(value),
+            Err(err) => Err(err),
+        };
+        self.position = None;
+        self.stack.clear();
+        engine
+            .continuations
+            .put_back(std::mem::take(&mut self.pending));
+        engine
+            .run_tail_calls(Some(&self.func), result)
+            .map(Step::Done)
+            .map_err(ExecutionEngine::<TS>::unhandled_return)
+    }
+
+    fn run(
+        &mut self,
+        engine: &mut ExecutionEngine<TS>,
+        mut position: usize,
+        value: TS::Value,
+    ) -> Result<Step<TS>, FreightError> {
+        let function = self.function.clone();
+        let captured = match &self.func.function_type {
+            FunctionType::CapturingRef(captures) => &captures[..],
+            _ => &[],
+        };
+        let stack = &mut self.stack;
+        // SAFETY: the continuations only borrow from the body of `function`, which is
+        // kept alive here and by `self.function` while they're suspended
+        let mut pending = unsafe { recast(std::mem::take(&mut self.pending)) };
+        let mut next = match (self.suspended, function.expressions.get(position)) {
+            (true, _) => Ok(Next::Value(value)),
+            (false, Some(expr)) => engine.start(expr, &mut pending, stack, captured),
+            (false, None) => Ok(Next::Value(Default::default())),
+        };
+        let result = loop {
+            match engine.evaluate_resumable(next, &mut pending, stack, captured) {
+                Ok(Step::Done(_)) if position + 1 < function.expressions.len() => {
+                    position += 1;
+                    let expr = &function.expressions[position];
+                    next = engine.start(expr, &mut pending, stack, captured);
+                }
+                Err(err) => {
+                    break Err(err)
+                        .or_return(function.return_target, engine)
+                        .map(Step::Done)
+                }
+                result => break result,
+            }
+        };
+        self.position = Some(position);
+        self.suspended = matches!(result, Ok(Step::Yielded(_)));
+        // SAFETY: as above, and the continuations are all gone unless the call yielded
+        self.pending = unsafe { recast(pending) };
+        result
+    }
+}
+
+impl<TS: TypeSystem> ExecutionEngine<TS> {
+    /// Start a call which can be suspended by [Expression::Yield](crate::expression::Expression::Yield)
+    /// in the body of the function, and resumed with [Suspended::resume]. The function's
+    /// frame is moved out of the stack pool, so the call can outlive the frames after it.
+    pub fn call_resumable(
+        &mut self,
+        func: &FunctionRef<TS>,
+        args: impl IntoExactSizeIterator<Item = TS::Value>,
+    ) -> Result<Suspended<TS>, FreightError> {
+        let mut args: Vec<_> = args.into_exact_size_iter().collect();
+        let mut func = func.clone();
+        while let FunctionType::Bound { inner, args: bound } = &func.function_type {
+            args.splice(0..0, bound.iter().map(Value::clone_unaliased));
+            func = FunctionRef::clone(inner);
+        }
+        if !matches!(
+            func.function_type,
+            FunctionType::Static | FunctionType::CapturingRef(_)
+        ) {
+            return Err(FreightError::InvalidInvocationTarget);
+        }
+        let function =
+            self.try_get_function(func.location)
+                .ok_or(FreightError::InvalidFunctionLocation {
+                    location: func.location,
+                })?;
+        if function.reserved {
+            return Err(FreightError::UnfilledFunction {
+                function: func.to_string(),
+            });
+        }
+        self.stats.function_calls += 1;
+        let arg_count = args.len();
+        let mut args = args.into_iter();
+        let mut frame = self.prepare_frame(&func, |_| Ok(args.next().unwrap()), arg_count, &[])?;
+        let stack = frame.iter_mut().map(std::mem::take).collect();
+        drop(frame);
+        Ok(Suspended {
+            pending: self.continuations.take(),
+            function,
+            func,
+            stack,
+            position: Some(0),
+            suspended: false,
+        })
+    }
+
+    /// Like [ExecutionEngine::evaluate_internal] from a step already taken, but stopping
+    /// when a yield has its value. The continuations after the yield are left pending.
+    fn evaluate_resumable<'e>(
+        &mut self,
+        mut next: Result<Next<'e, TS>, FreightError>,
+        pending: &mut Vec<Continuation<'e, TS>>,
+        stack: &mut [TS::Value],
+        captured: &[TS::Value],
+    ) -> Result<Step<TS>, FreightError> {
+        loop {
+            let result = match next {
+                Ok(Next::Eval(expr)) => {
+                    next = self.start(expr, pending, stack, captured);
+                    continue;
+                }
+                Ok(Next::Value(value)) => Ok(value),
+                Err(err) => Err(err),
+            };
+            match pending.pop() {
+                // The arguments of a native call are collected in the stack pool, which
+                // has to be released in order, so the call can't suspend inside them
+                Some(Continuation::Yield) if result.is_ok() => {
+                    let in_native_call = pending.iter().any(|continuation| {
+                        matches!(continuation, Continuation::NativeCall { .. })
+                    });
+                    if !in_native_call {
+                        return result.map(Step::Yielded);
+                    }
+                    next = Err(FreightError::YieldOutsideResumable);
+                }
+                Some(continuation) => {
+                    next = self.resume(continuation, result, pending, stack, captured)
+                }
+                None => return result.map(Step::Done),
+            }
+        }
+    }
+}
@@ -0,0 +1,349 @@
+use super::ExecutionEngine;
+use crate::{
+    error::{AddressKind, FreightError},
+    expression::{DebugInfo, Expression},
+    function::{validate_function, ArgCount, Function, FunctionRef, FunctionType, StackLayout},
+    sync::Shared,
+    value::Value,
+    SerializableTypeSystem, TypeSystem,
+};
+use serde::{Deserialize, Serialize};
+
+/// The function table of an engine borrowed for serialization, created with
+/// [ExecutionEngine::export_functions]. It deserializes as [ExportedFunctions].
+#[derive(Serialize)]
+#[serde(bound = "TS: SerializableTypeSystem")]
+pub struct FunctionTableExport<'a, TS: TypeSystem> {
+    functions: Vec<ExportedFunctionView<'a, TS>>,
+    names: Vec<(&'a str, usize)>,
+    entry_point: Option<&'a FunctionRef<TS>>,
+    constants: &'a [TS::Value],
+    next_return_target: usize,
+}
+
+#[derive(Serialize)]
+#[serde(bound = "TS: SerializableTypeSystem")]
+struct ExportedFunctionView<'a, TS: TypeSystem> {
+    expressions: &'a [Expression<TS>],
+    return_target: usize,
+    arg_count: ArgCount,
+    stack_size: usize,
+    function_type: &'a FunctionType<TS>,
+    layout: &'a StackLayout,
+    name: Option<&'a str>,
+    source: Option<&'a DebugInfo>,
+    defaults: &'a [Option<Expression<TS>>],
+    arg_names: &'a [TS::FieldId],
+    eliminated_tail_calls: bool,
+}
+
+/// A function table serialized from a [FunctionTableExport], to be added to an engine
+/// with [ExecutionEngine::import_functions]
+#[derive(Debug, Deserialize)]
+#[serde(bound = "TS: SerializableTypeSystem")]
+pub struct ExportedFunctions<TS: TypeSystem> {
+    /// Every function in the table, in order of location
+    functions: Vec<ExportedFunction<TS>>,
+    names: Vec<(String, usize)>,
+    entry_point: Option<FunctionRef<TS>>,
+    constants: Vec<TS::Value>,
+    next_return_target: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(bound = "TS: SerializableTypeSystem")]
+struct ExportedFunction<TS: TypeSystem> {
+    expressions: Vec<Expression<TS>>,
+    return_target: usize,
+    arg_count: ArgCount,
+    stack_size: usize,
+    function_type: FunctionType<TS>,
+    layout: StackLayout,
+    name: Option<String>,
+    source: Option<DebugInfo>,
+    defaults: Vec<Option<Expression<TS>>>,
+    arg_names: Vec<TS::FieldId>,
+    eliminated_tail_calls: bool,
+}
+
+impl<TS: TypeSystem> ExportedFunctions<TS> {
+    /// How many functions were exported
+    pub fn len(&self) -> usize {
+        self.functions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    /// The entry point of the exporting engine, at its location there
+    pub fn entry_point(&self) -> Option<&FunctionRef<TS>> {
+        self.entry_point.as_ref()
+    }
+}
+
+/// Where each exported function was placed by [ExecutionEngine::import_functions]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocationMap {
+    /// The new location of each function, by its location in the exporting engine
+    locations: Vec<usize>,
+    /// Where the exported constants start in the importing engine's pool
+    constant_base: usize,
+    constant_count: usize,
+}
+
+impl RelocationMap {
+    /// The location in the importing engine of the function at `exported` in the
+    /// exporting one
+    pub fn location(&self, exported: usize) -> Option<usize> {
+        self.locations.get(exported).copied()
+    }
+
+    /// A reference from the exporting engine pointed at the same function after import
+    pub fn relocate<TS: TypeSystem>(
+        &self,
+        func: &FunctionRef<TS>,
+    ) -> Result<FunctionRef<TS>, FreightError> {
+        let mut func = func.clone();
+        self.relocate_in_place(&mut func)?;
+        Ok(func)
+    }
+
+    fn relocate_in_place<TS: TypeSystem>(
+        &self,
+        func: &mut FunctionRef<TS>,
+    ) -> Result<(), FreightError> {
+        check_exportable(func)?;
+        if let FunctionType::Bound { inner, .. } = &mut func.function_type {
+            self.relocate_in_place(Shared::make_mut(inner))?;
+        }
+        func.location =
+            self.location(func.location)
+                .ok_or(FreightError::InvalidFunctionLocation {
+                    location: func.location,
+                })?;
+        Ok(())
+    }
+
+    /// Relocate the functions and constants referenced by the body of the function
+    /// imported to `location`
+    fn relocate_body<TS: TypeSystem>(
+        &self,
+        location: usize,
+        expr: &mut Expression<TS>,
+    ) -> Result<(), FreightError> {
+        let mut result = Ok(());
+        expr.visit_post_order_mut(|expr| {
+            if result.is_err() {
+                return;
+            }
+            if let Expression::RawValue(value) = expr {
+                result = check_value(value);
+            } else if let Expression::PooledValue(index) = expr {
+                if *index >= self.constant_count {
+                    result = Err(FreightError::InvalidAddress {
+                        function: location,
+                        kind: AddressKind::Constant,
+                        address: *index,
+                    });
+                }
+                *index += self.constant_base;
+            } else if let Some(func) = function_ref_mut(expr) {
+                result = self.relocate_in_place(func);
+            }
+        });
+        result
+    }
+}
+
+/// The function referenced directly by an expression, if any
+fn function_ref_mut<TS: TypeSystem>(expr: &mut Expression<TS>) -> Option<&mut FunctionRef<TS>> {
+    match expr {
+        Expression::StaticFunctionCall(func, _)
+        | Expression::StaticFunctionCallNamed { func, .. }
+        | Expression::TailCall(func, _)
+        | Expression::FunctionCapture(func) => Some(func),
+        _ => None,
+    }
+}
+
+/// References hold locations which are rewritten on import, which can't be done for the
+/// functions referenced by captured values or for natives
+fn check_exportable<TS: TypeSystem>(func: &FunctionRef<TS>) -> Result<(), FreightError> {
+    let message = match &func.function_type {
+        FunctionType::CapturingRef(_) => "it holds captured values",
+        FunctionType::Native(_) => "it's native",
+        FunctionType::Bound { inner, .. } => return check_exportable(inner),
+        FunctionType::Static | FunctionType::CapturingDef(..) => return Ok(()),
+    };
+    Err(FreightError::NotExportable {
+        function: func.to_string(),
+        message: message.into(),
+    })
+}
+
+/// Values are opaque, so a function held by one would keep its location in the exporting
+/// engine
+fn check_value<V: Value>(value: &V) -> Result<(), FreightError> {
+    match value.cast_to_function() {
+        Some(func) => Err(FreightError::NotExportable {
+            function: func.to_string(),
+            message: "it's held by a value".into(),
+        }),
+        None => Ok(()),
+    }
+}
+
+impl<TS: SerializableTypeSystem> ExecutionEngine<TS> {
+    /// Borrow the whole function table, its names, the entry point and the constant pool
+    /// for serialization, so they can be imported into another engine. Fails if a
+    /// function is still reserved, or if a body references a function holding captured
+    /// values or a native, or a function held by a value or a constant, since these
+    /// can't be relocated.
+    pub fn export_functions(&self) -> Result<FunctionTableExport<'_, TS>, FreightError> {
+        let mut functions = Vec::with_capacity(self.functions.len());
+        for (location, func) in self.functions.iter().enumerate() {
+            if func.reserved {
+                return Err(FreightError::UnfilledFunction {
+                    function: format!("#{location}"),
+                });
+            }
+            let mut result = Ok(());
+            for expr in func
+                .expressions
+                .iter()
+                .chain(func.defaults.iter().flatten())
+            {
+                expr.walk(&mut |expr| match expr {
+                    Expression::StaticFunctionCall(func, _)
+                    | Expression::StaticFunctionCallNamed { func, .. }
+                    | Expression::TailCall(func, _)
+                    | Expression::FunctionCapture(func)
+                        if result.is_ok() =>
+                    {
+                        result = check_exportable(func)
+                    }
+                    Expression::RawValue(value) if result.is_ok() => result = check_value(value),
+                    _ => {}
+                });
+            }
+            result?;
+            functions.push(ExportedFunctionView {
+                expressions: &func.expressions,
+                return_target: func.return_target,
+                arg_count: func.arg_count,
+                stack_size: func.stack_size,
+                function_type: &func.function_type,
+                layout: &func.layout,
+                name: func.name(),
+                source: func.source(),
+                defaults: &func.defaults,
+                arg_names: &func.arg_names,
+                eliminated_tail_calls: func.eliminated_tail_calls,
+            });
+        }
+        if let Some(entry_point) = &self.entry_point {
+            check_exportable(entry_point)?;
+        }
+        for constant in &self.constants {
+            check_value(constant)?;
+        }
+        Ok(FunctionTableExport {
+            functions,
+            names: self
+                .function_names
+                .iter()
+                .map(|(name, location)| (name.as_str(), *location))
+                .collect(),
+            entry_point: self.entry_point.as_ref(),
+            constants: &self.constants,
+            next_return_target: self.next_return_target,
+        })
+    }
+
+    /// Add functions exported from another engine after those already registered,
+    /// rewriting the locations of the functions and constants they reference, and return
+    /// where each one was placed. Their names are registered too, and the exported entry
+    /// point becomes this engine's entry point. Globals are used at the same addresses,
+    /// so they have to match the exporting engine's. The functions are validated like
+    /// registered ones, and nothing is added if the import fails.
+    pub fn import_functions(
+        &mut self,
+        mut exported: ExportedFunctions<TS>,
+    ) -> Result<RelocationMap, FreightError> {
+        if self.finalized {
+            return Err(FreightError::FunctionTableFinalized);
+        }
+        let base = self.functions.len();
+        let relocation = RelocationMap {
+            locations: (base..base + exported.functions.len()).collect(),
+            constant_base: self.constants.len(),
+            constant_count: exported.constants.len(),
+        };
+        let mut names = Vec::with_capacity(exported.names.len());
+        for (name, location) in exported.names {
+            if self.function_names.contains_key(&name) {
+                return Err(FreightError::DuplicateFunctionName { name });
+            }
+            let location = relocation
+                .location(location)
+                .ok_or(FreightError::InvalidFunctionLocation { location })?;
+            names.push((name, location));
+        }
+        for constant in &exported.constants {
+            check_value(constant)?;
+        }
+        let entry_point = exported
+            .entry_point
+            .map(|entry_point| relocation.relocate(&entry_point))
+            .transpose()?;
+        for (location, func) in (base..).zip(&mut exported.functions) {
+            for expr in func
+                .expressions
+                .iter_mut()
+                .chain(func.defaults.iter_mut().flatten())
+            {
+                relocation.relocate_body(location, expr)?;
+            }
+        }
+
+        let mut functions = Vec::with_capacity(exported.functions.len());
+        for func in exported.functions {
+            functions.push(Function {
+                expressions: func.expressions,
+                return_target: func.return_target,
+                arg_count: func.arg_count,
+                stack_size: func.stack_size,
+                function_type: func.function_type,
+                layout: func.layout,
+                name: func.name.map(Shared::from),
+                source: func.source,
+                defaults: func.defaults,
+                arg_names: func.arg_names,
+                reserved: false,
+                eliminated_tail_calls: func.eliminated_tail_calls,
+            });
+        }
+        let num_functions = base + functions.len();
+        let num_constants = self.constants.len() + exported.constants.len();
+        for (location, func) in (base..).zip(&functions) {
+            validate_function(
+                func,
+                location,
+                self.num_globals,
+                num_functions,
+                num_constants,
+            )?;
+        }
+
+        self.constants.append(&mut exported.constants);
+        self.functions
+            .extend(functions.into_iter().map(Shared::new));
+        self.function_names.extend(names);
+        if entry_point.is_some() {
+            self.entry_point = entry_point;
+        }
+        self.next_return_target = self.next_return_target.max(exported.next_return_target);
+        Ok(relocation)
+    }
+}
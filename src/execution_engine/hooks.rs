@@ -0,0 +1,11 @@
+use crate::{function::FunctionRef, sync::MaybeSend, TypeSystem};
+
+/// Callbacks invoked around every function call made through an [ExecutionEngine](super::ExecutionEngine).
+/// With the `sync` feature, hooks must be `Send` so the engine can be moved between threads.
+pub trait CallHooks<TS: TypeSystem>: MaybeSend {
+    /// Called before a function runs, with its arguments after padding
+    fn on_call(&mut self, _func: &FunctionRef<TS>, _args: &[TS::Value]) {}
+
+    /// Called after a function returns, including returns through a return target
+    fn on_return(&mut self, _func: &FunctionRef<TS>, _value: &TS::Value) {}
+}
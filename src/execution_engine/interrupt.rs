@@ -0,0 +1,62 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// A handle which can abort a running [ExecutionEngine](super::ExecutionEngine) from another thread
+#[derive(Clone, Debug, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Request that the engine stop at the next function call with [FreightError::Interrupted](crate::error::FreightError::Interrupted)
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether an interrupt has been requested and not yet handled
+    pub fn is_interrupted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Consume a pending interrupt, returning whether there was one
+    pub(crate) fn take(&self) -> bool {
+        self.is_interrupted() && self.0.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// A callback polled by [ExecutionEngine::set_cancellation](super::ExecutionEngine::set_cancellation),
+/// which must be `Send` with the `sync` feature
+#[cfg(not(feature = "sync"))]
+pub type CancellationCallback = Box<dyn Fn() -> bool>;
+#[cfg(feature = "sync")]
+pub type CancellationCallback = Box<dyn Fn() -> bool + Send>;
+
+/// A host callback polled periodically to decide whether execution should stop,
+/// see [ExecutionEngine::set_cancellation](super::ExecutionEngine::set_cancellation)
+pub(crate) struct Cancellation {
+    poll_interval: u32,
+    remaining: u32,
+    is_cancelled: CancellationCallback,
+}
+
+impl Cancellation {
+    pub(crate) fn new(poll_interval: u32, is_cancelled: CancellationCallback) -> Self {
+        let poll_interval = poll_interval.max(1);
+        Self {
+            poll_interval,
+            remaining: poll_interval,
+            is_cancelled,
+        }
+    }
+
+    /// Count one evaluation, invoking the callback once every `poll_interval` evaluations
+    #[inline]
+    pub(crate) fn poll(&mut self) -> bool {
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return false;
+        }
+        self.remaining = self.poll_interval;
+        (self.is_cancelled)()
+    }
+}
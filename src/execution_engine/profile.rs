@@ -0,0 +1,148 @@
+use crate::{
+    operators::{BinaryOperator, LazyBinaryOperator, TernaryOperator, UnaryOperator},
+    TypeSystem,
+};
+use std::{
+    borrow::Cow,
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt::{Debug, Write},
+    hash::Hasher,
+    time::Duration,
+};
+
+/// Call statistics for a single function
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FunctionProfile {
+    /// The name of the function, if it has one
+    pub name: Option<String>,
+    /// How many times the function was called
+    pub calls: u64,
+    /// Total time spent in the function, including time spent in the functions it called
+    pub total_time: Duration,
+}
+
+/// How many times each operator of one kind was applied. They're keyed by a hash of
+/// their `Debug` representation, which is written straight into the hasher, so recording
+/// an application doesn't allocate.
+#[derive(Debug, Clone)]
+pub(crate) struct OperatorCounts<O>(HashMap<u64, (O, u64)>);
+
+impl<O> Default for OperatorCounts<O> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<O: Debug + Clone> OperatorCounts<O> {
+    pub(crate) fn record(&mut self, op: &O) {
+        let mut hasher = DebugHasher(DefaultHasher::new());
+        let _ = write!(hasher, "{op:?}");
+        // Only the first application of an operator clones it
+        self.0
+            .entry(hasher.0.finish())
+            .or_insert_with(|| (op.clone(), 0))
+            .1 += 1;
+    }
+
+    fn named<'a>(
+        &'a self,
+        name: impl Fn(&'a O) -> Cow<'a, str>,
+    ) -> impl Iterator<Item = (Cow<'a, str>, u64)> {
+        self.0.values().map(move |(op, count)| (name(op), *count))
+    }
+}
+
+/// Feeds formatted text into a hasher
+struct DebugHasher(DefaultHasher);
+
+impl Write for DebugHasher {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Per-function call statistics, indexed by function location, and how many times each
+/// operator was applied, grouped by [name](crate::operators::BinaryOperator::name). The
+/// names are only looked up when the operators are read.
+#[derive(Debug, Clone)]
+pub struct ProfileData<TS: TypeSystem> {
+    functions: Vec<FunctionProfile>,
+    pub(crate) unary: OperatorCounts<TS::UnaryOp>,
+    pub(crate) binary: OperatorCounts<TS::BinaryOp>,
+    pub(crate) ternary: OperatorCounts<TS::TernaryOp>,
+    pub(crate) lazy: OperatorCounts<TS::LazyBinaryOp>,
+}
+
+impl<TS: TypeSystem> Default for ProfileData<TS> {
+    fn default() -> Self {
+        Self {
+            functions: Vec::new(),
+            unary: OperatorCounts::default(),
+            binary: OperatorCounts::default(),
+            ternary: OperatorCounts::default(),
+            lazy: OperatorCounts::default(),
+        }
+    }
+}
+
+impl<TS: TypeSystem> ProfileData<TS> {
+    /// Get the statistics for the function at a location
+    pub fn get(&self, location: usize) -> Option<&FunctionProfile> {
+        self.functions.get(location)
+    }
+
+    /// Iterate over the statistics of every function which has been called, with its location
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &FunctionProfile)> {
+        self.functions
+            .iter()
+            .enumerate()
+            .filter(|(_, profile)| profile.calls > 0)
+    }
+
+    /// How many times operators with a name have been applied
+    pub fn operator_applications(&self, name: &str) -> u64 {
+        self.applications()
+            .filter(|(applied, _)| applied == name)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Iterate over the names of every operator which has been applied, with how many
+    /// times operators with that name were applied
+    pub fn operators(&self) -> impl Iterator<Item = (Cow<'_, str>, u64)> {
+        let mut grouped: HashMap<Cow<'_, str>, u64> = HashMap::new();
+        for (name, count) in self.applications() {
+            *grouped.entry(name).or_default() += count;
+        }
+        grouped.into_iter()
+    }
+
+    /// The name of every operator which has been applied with its count, where several
+    /// operators can share a name
+    fn applications(&self) -> impl Iterator<Item = (Cow<'_, str>, u64)> {
+        let unary = self.unary.named(UnaryOperator::name);
+        let binary = self.binary.named(BinaryOperator::name);
+        let ternary = self.ternary.named(TernaryOperator::name);
+        let lazy = self.lazy.named(LazyBinaryOperator::name);
+        unary.chain(binary).chain(ternary).chain(lazy)
+    }
+
+    pub(crate) fn record(&mut self, location: usize, name: Option<&str>, elapsed: Duration) {
+        if location >= self.functions.len() {
+            self.functions.resize(location + 1, Default::default());
+        }
+        let profile = &mut self.functions[location];
+        // Only the first call of a function allocates its name
+        if profile.calls == 0 {
+            profile.name = name.map(str::to_owned);
+        }
+        profile.calls += 1;
+        profile.total_time += elapsed;
+    }
+
+    /// Clear all recorded statistics
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
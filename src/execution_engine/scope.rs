@@ -0,0 +1,45 @@
+use crate::{error::FreightError, expression::Expression, TypeSystem};
+
+use super::ExecutionEngine;
+
+/// The variables visible where an
+/// [Expression::NativeMacroCall](crate::expression::Expression::NativeMacroCall) is
+/// evaluated, so the native can evaluate its argument expressions as if they were
+/// written in place of the call.
+///
+/// The arguments are evaluated in the calling frame, so any variable they assign is
+/// changed for the caller and for arguments evaluated after them. Variables read by an
+/// argument are [dupe_ref](crate::value::Value::dupe_ref)s, which alias the variable if
+/// it holds a reference and are copies otherwise. The native decides how many times and
+/// in which order its arguments are evaluated, including not at all. Errors from
+/// evaluating an argument include returns and tail calls, which only work if the native
+/// passes them on unchanged.
+pub struct EvalScope<'s, TS: TypeSystem> {
+    stack: &'s mut [TS::Value],
+    captured: &'s [TS::Value],
+}
+
+impl<'s, TS: TypeSystem> EvalScope<'s, TS> {
+    pub(crate) fn new(stack: &'s mut [TS::Value], captured: &'s [TS::Value]) -> Self {
+        Self { stack, captured }
+    }
+
+    /// Evaluate an expression in the calling frame
+    pub fn eval(
+        &mut self,
+        engine: &mut ExecutionEngine<TS>,
+        expr: &Expression<TS>,
+    ) -> Result<TS::Value, FreightError> {
+        engine.evaluate_internal(expr, self.stack, self.captured)
+    }
+
+    /// The stack slots of the calling frame
+    pub fn stack(&self) -> &[TS::Value] {
+        self.stack
+    }
+
+    /// The values captured by the calling function
+    pub fn captured(&self) -> &[TS::Value] {
+        self.captured
+    }
+}
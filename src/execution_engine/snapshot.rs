@@ -0,0 +1,81 @@
+use super::ExecutionEngine;
+use crate::{error::FreightError, value::Value, TypeSystem};
+
+/// A copy of the mutable state of an [ExecutionEngine], created with [ExecutionEngine::snapshot]
+#[derive(Debug)]
+pub struct EngineSnapshot<TS: TypeSystem> {
+    globals: Vec<TS::Value>,
+    global_seeds: Vec<Option<TS::Value>>,
+    return_value: TS::Value,
+    function_count: usize,
+}
+
+impl<TS: TypeSystem> ExecutionEngine<TS> {
+    /// Deep copy every global, so the copies share no references with the engine
+    pub fn deep_clone_globals(&self) -> Vec<TS::Value> {
+        self.globals.iter().map(Value::deep_clone).collect()
+    }
+
+    /// Capture the current globals and return value so they can be restored later
+    pub fn snapshot(&self) -> EngineSnapshot<TS> {
+        EngineSnapshot {
+            globals: self.deep_clone_globals(),
+            global_seeds: self.global_seeds.clone(),
+            return_value: self.return_value.deep_clone(),
+            function_count: self.functions.len(),
+        }
+    }
+
+    /// Create an independent copy of this engine which shares the function table but
+    /// deep copies all globals and pooled constants, so nothing the fork does is visible
+    /// in the original. The fork keeps the engine's settings.
+    pub fn fork(&self) -> ExecutionEngine<TS>
+    where
+        TS::GlobalContext: Clone,
+    {
+        let mut fork = ExecutionEngine::new(self.context.clone());
+        fork.num_globals = self.num_globals;
+        fork.globals = self.deep_clone_globals();
+        fork.global_seeds = self
+            .global_seeds
+            .iter()
+            .map(|seed| seed.as_ref().map(Value::deep_clone))
+            .collect();
+        fork.functions = self.functions.clone();
+        fork.native_count = self.native_count;
+        fork.function_names = self.function_names.clone();
+        fork.finalized = self.finalized;
+        fork.next_return_target = self.next_return_target;
+        fork.entry_point = self.entry_point.clone();
+        fork.fuel = self.fuel;
+        fork.max_call_depth = self.max_call_depth;
+        fork.constants = self.constants.iter().map(Value::deep_clone).collect();
+        fork.pool_constants = self.pool_constants;
+        fork.strict_reads = self.strict_reads;
+        fork.type_assertions = self.type_assertions;
+        fork.error_traces = self.error_traces;
+        fork.memory_limit = self.memory_limit;
+        fork.inline_threshold = self.inline_threshold;
+        fork.tail_call_elimination = self.tail_call_elimination;
+        fork
+    }
+
+    /// Roll the engine back to a snapshot. Fails if functions or globals were
+    /// created after the snapshot was taken, since they may depend on the newer state.
+    pub fn restore(&mut self, snapshot: EngineSnapshot<TS>) -> Result<(), FreightError> {
+        if snapshot.function_count != self.functions.len()
+            || snapshot.globals.len() != self.num_globals
+        {
+            return Err(FreightError::SnapshotMismatch {
+                expected_functions: snapshot.function_count,
+                actual_functions: self.functions.len(),
+                expected_globals: snapshot.globals.len(),
+                actual_globals: self.num_globals,
+            });
+        }
+        self.globals = snapshot.globals;
+        self.global_seeds = snapshot.global_seeds;
+        self.return_value = snapshot.return_value;
+        Ok(())
+    }
+}
@@ -1,37 +1,73 @@
-use std::{
-    cell::UnsafeCell,
-    ops::{Deref, DerefMut},
-    rc::Rc,
-};
+use std::ops::{Deref, DerefMut};
+
+use crate::sync::{PoolCell, Shared};
+
+/// The most frames of each size kept for reuse after the pool was exhausted
+const MAX_SPARE_FRAMES: usize = 8;
 
 pub struct StackPool<T: Default> {
     stack: Vec<T>,
     base: usize,
+    allocated: usize,
+    peak: usize,
+    /// Frames allocated when the buffer was exhausted, kept for reuse. Frames are
+    /// allocated with a power of two size, and indexed by its exponent.
+    spare: Vec<Vec<Box<[T]>>>,
+    reused: u64,
+    missed: u64,
+}
+
+enum Frame<'a, T> {
+    /// A frame borrowed from the pool's preallocated buffer
+    Pooled(&'a mut [T]),
+    /// A frame allocated separately because the pool was exhausted, which may be longer
+    /// than the frame
+    Allocated(Box<[T]>, usize),
 }
 
 pub struct StackSlice<'a, T: Default> {
-    slice: &'a mut [T],
-    stack: Rc<UnsafeCell<StackPool<T>>>,
+    frame: Frame<'a, T>,
+    stack: Shared<PoolCell<StackPool<T>>>,
 }
 
 impl<'a, T: Default> Deref for StackSlice<'a, T> {
     type Target = [T];
 
     fn deref(&self) -> &Self::Target {
-        self.slice
+        match &self.frame {
+            Frame::Pooled(slice) => slice,
+            Frame::Allocated(buffer, len) => &buffer[..*len],
+        }
     }
 }
 
 impl<'a, T: Default> DerefMut for StackSlice<'a, T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        self.slice
+        match &mut self.frame {
+            Frame::Pooled(slice) => slice,
+            Frame::Allocated(buffer, len) => &mut buffer[..*len],
+        }
     }
 }
 
+/// Frames are cleared when they're released, so the values left in them are dropped
+/// right away rather than when the slots are next used
 impl<'a, T: Default> Drop for StackSlice<'a, T> {
     fn drop(&mut self) {
-        let mut pool = unsafe { &mut *self.stack.get() };
-        pool.base -= self.slice.len();
+        self.fill_with(Default::default);
+        match &mut self.frame {
+            Frame::Pooled(slice) => {
+                let len = slice.len();
+                self.stack.with(|pool| pool.base -= len);
+            }
+            Frame::Allocated(buffer, len) => {
+                let (buffer, len) = (std::mem::take(buffer), *len);
+                self.stack.with(|pool| {
+                    pool.allocated -= len;
+                    pool.recycle(buffer);
+                });
+            }
+        }
     }
 }
 
@@ -41,27 +77,101 @@ impl<T: Default> StackPool<T> {
         for _ in 0..capacity {
             stack.push(Default::default());
         }
-        StackPool { stack, base: 0 }
+        StackPool {
+            stack,
+            base: 0,
+            allocated: 0,
+            peak: 0,
+            spare: Vec::new(),
+            reused: 0,
+            missed: 0,
+        }
     }
 
-    pub fn request<'a>(cell: Rc<UnsafeCell<Self>>, capacity: usize) -> StackSlice<'a, T> {
-        let this = unsafe { &mut *cell.get() };
-        if this.base + capacity >= this.stack.len() {
-            panic!("Stack overflow {} / {}", this.base, this.stack.len());
-        }
+    /// Borrow a frame from the pool, falling back to a spare frame from an earlier call or
+    /// a fresh allocation if the pool is exhausted
+    pub fn request<'a>(cell: Shared<PoolCell<Self>>, capacity: usize) -> StackSlice<'a, T> {
+        let ptr = cell.with(|this| {
+            if this.base + capacity > this.stack.len() {
+                this.allocated += capacity;
+                this.record_peak();
+                return Err(this.take_spare(capacity));
+            }
+            // The buffer is never resized, so the frame stays valid after the cell is released
+            let ptr = unsafe { this.stack.as_mut_ptr().add(this.base) };
+            this.base += capacity;
+            this.record_peak();
+            Ok(ptr)
+        });
 
-        unsafe {
-            let ptr = this.stack.as_mut_ptr().add(this.base);
+        let frame = match ptr {
+            Ok(ptr) => Frame::Pooled(unsafe { std::slice::from_raw_parts_mut(ptr, capacity) }),
+            Err(Some(buffer)) => Frame::Allocated(buffer, capacity),
+            Err(None) => {
+                let size = capacity.next_power_of_two();
+                Frame::Allocated((0..size).map(|_| Default::default()).collect(), capacity)
+            }
+        };
+        StackSlice { frame, stack: cell }
+    }
 
-            this.base += capacity;
-            let slice = std::slice::from_raw_parts_mut(ptr, capacity);
-            StackSlice { slice, stack: cell }
+    /// Take a spare frame which fits `capacity` slots, counting whether there was one
+    fn take_spare(&mut self, capacity: usize) -> Option<Box<[T]>> {
+        let bucket = capacity.next_power_of_two().trailing_zeros() as usize;
+        let buffer = self.spare.get_mut(bucket).and_then(Vec::pop);
+        match buffer {
+            Some(_) => self.reused += 1,
+            None => self.missed += 1,
+        }
+        buffer
+    }
+
+    /// Keep a cleared frame allocated by [StackPool::request] for reuse, unless there are
+    /// enough of its size already
+    fn recycle(&mut self, buffer: Box<[T]>) {
+        let bucket = buffer.len().trailing_zeros() as usize;
+        if self.spare.len() <= bucket {
+            self.spare.resize_with(bucket + 1, Vec::new);
+        }
+        if self.spare[bucket].len() < MAX_SPARE_FRAMES {
+            self.spare[bucket].push(buffer);
         }
     }
 
-    pub fn release(this: &UnsafeCell<Self>, capacity: usize) {
-        let mut this = unsafe { &mut *this.get() };
-        this.base -= capacity;
+    pub fn release(this: &PoolCell<Self>, capacity: usize) {
+        this.with(|this| this.base -= capacity);
+    }
+
+    /// The number of slots currently borrowed from the pool
+    pub fn in_use(&self) -> usize {
+        self.base
+    }
+
+    /// The most slots in use at once, counting frames allocated outside the pool
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    /// Restart peak tracking from the current usage
+    pub fn reset_peak(&mut self) {
+        self.peak = self.base + self.allocated;
+    }
+
+    /// How many frames requested while the pool was exhausted reused a spare frame, and
+    /// how many had to be allocated
+    pub fn spare_frames_used(&self) -> (u64, u64) {
+        (self.reused, self.missed)
+    }
+
+    /// Restart counting the uses of spare frames
+    pub fn reset_spare_frame_counts(&mut self) {
+        self.reused = 0;
+        self.missed = 0;
+    }
+
+    #[inline]
+    fn record_peak(&mut self) {
+        self.peak = self.peak.max(self.base + self.allocated);
     }
 }
 
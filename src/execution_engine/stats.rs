@@ -0,0 +1,19 @@
+/// Counters collected while the engine runs, see [super::ExecutionEngine::stats]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecutionStats {
+    /// Expression nodes evaluated
+    pub expressions_evaluated: u64,
+    /// Calls made through function references, including native functions
+    pub function_calls: u64,
+    /// Calls made by tail calls, which reuse the native stack frame of their caller.
+    /// These are also counted in `function_calls`.
+    pub tail_calls: u64,
+    /// Native functions invoked, either by reference or as a native call or macro expression
+    pub native_calls: u64,
+    /// The most stack slots in use at once
+    pub peak_stack: usize,
+    /// Frames needed once the stack was full which reused one freed by an earlier call
+    pub frame_pool_hits: u64,
+    /// Frames needed once the stack was full which had to be allocated
+    pub frame_pool_misses: u64,
+}
@@ -1,11 +1,25 @@
 use crate::{
     error::FreightError,
-    execution_engine::{ExecutionEngine, Stack},
-    function::FunctionRef,
+    execution_engine::{scope::EvalScope, ExecutionEngine, Stack},
+    function::{ArgCount, FunctionRef, FunctionType},
+    sync::Shared,
+    value::Value,
     TypeSystem,
 };
 
-use std::{fmt::Debug, ops::Deref};
+use std::{
+    fmt::{Debug, Display},
+    ops::Deref,
+};
+
+mod arena;
+mod infix;
+mod pretty;
+mod structural;
+
+pub(crate) use arena::{Children, Node};
+pub use arena::{ExpressionArena, NodeId};
+pub use infix::InfixBuilder;
 
 type NativeFuncInnerAlias<TS> = fn(
     &mut ExecutionEngine<TS>,
@@ -19,6 +33,32 @@ impl<TS: TypeSystem> NativeFunction<TS> {
     pub fn new(value: NativeFuncInnerAlias<TS>) -> Self {
         Self(value)
     }
+
+    /// A native taking one function and returning the arguments it takes, converted to a
+    /// value by the type system. Register it with
+    /// [ExecutionEngine::register_native] taking exactly one argument.
+    pub fn arity() -> Self
+    where
+        TS::Value: From<ArgCount>,
+    {
+        Self(native_arity::<TS>)
+    }
+}
+
+fn native_arity<TS: TypeSystem>(
+    engine: &mut ExecutionEngine<TS>,
+    args: Stack<TS::Value>,
+) -> Result<TS::Value, FreightError>
+where
+    TS::Value: From<ArgCount>,
+{
+    match engine.describe_value(&args[0]) {
+        Some(info) => Ok(info.arg_count.into()),
+        None => Err(FreightError::TypeMismatch {
+            expected: "function".into(),
+            actual: args[0].type_name(),
+        }),
+    }
 }
 
 impl<TS: TypeSystem> Deref for NativeFunction<TS> {
@@ -35,44 +75,472 @@ impl<TS: TypeSystem> Debug for NativeFunction<TS> {
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+// Native functions are pointers into the host program, so they can't be shipped elsewhere
+#[cfg(feature = "serde")]
+impl<TS: TypeSystem> serde::Serialize for NativeFunction<TS> {
+    fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(serde::ser::Error::custom(
+            "native functions can't be serialized",
+        ))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, TS: TypeSystem> serde::Deserialize<'de> for NativeFunction<TS> {
+    fn deserialize<D: serde::Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Err(serde::de::Error::custom(
+            "native functions can't be deserialized",
+        ))
+    }
+}
+
+type NativeMacroInnerAlias<TS> = for<'s> fn(
+    &mut ExecutionEngine<TS>,
+    &[Expression<TS>],
+    EvalScope<'s, TS>,
+) -> Result<<TS as TypeSystem>::Value, FreightError>;
+
+/// A native function which receives its arguments unevaluated, see [Expression::NativeMacroCall]
+#[derive(Clone)]
+pub struct NativeMacro<TS: TypeSystem>(NativeMacroInnerAlias<TS>);
+
+impl<TS: TypeSystem> NativeMacro<TS> {
+    pub fn new(value: NativeMacroInnerAlias<TS>) -> Self {
+        Self(value)
+    }
+
+    /// A native taking a single binary operation like `a + b`, which evaluates both
+    /// operands and applies the operator element-wise with
+    /// [ExecutionEngine::map_binary]. See [FunctionWriter::map_binary](crate::function::FunctionWriter::map_binary)
+    /// for a function to register it under a name.
+    #[cfg(feature = "variadic_functions")]
+    pub fn map_binary() -> Self {
+        Self(|engine, args, mut scope| {
+            let [Expression::BinaryOpEval(op, operands)] = args else {
+                return Err(FreightError::NotABinaryOperation);
+            };
+            let [a, b] = &**operands;
+            let a = scope.eval(engine, a)?;
+            let b = scope.eval(engine, b)?;
+            engine.map_binary(op, &a, &b)
+        })
+    }
+}
+
+impl<TS: TypeSystem> Deref for NativeMacro<TS> {
+    type Target = NativeMacroInnerAlias<TS>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<TS: TypeSystem> Debug for NativeMacro<TS> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("NativeMacro").finish()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<TS: TypeSystem> serde::Serialize for NativeMacro<TS> {
+    fn serialize<S: serde::Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(serde::ser::Error::custom(
+            "native macros can't be serialized",
+        ))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, TS: TypeSystem> serde::Deserialize<'de> for NativeMacro<TS> {
+    fn deserialize<D: serde::Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Err(serde::de::Error::custom(
+            "native macros can't be deserialized",
+        ))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum VariableType {
     Captured(usize),
     Stack(usize),
     Global(usize),
 }
 
+impl Display for VariableType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Captured(addr) => write!(f, "captured[{addr}]"),
+            Self::Stack(addr) => write!(f, "stack[{addr}]"),
+            Self::Global(addr) => write!(f, "global[{addr}]"),
+        }
+    }
+}
+
+/// A source location for error reporting, see [Expression::Spanned]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DebugInfo {
+    /// An id for the source file, assigned by the front end
+    pub file: usize,
+    /// The byte offset of the start of the span
+    pub start: usize,
+    /// The byte offset just after the end of the span
+    pub end: usize,
+}
+
+impl Display for DebugInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file {} bytes {}..{}", self.file, self.start, self.end)
+    }
+}
+
 /// Represents an expression tree that can be evaluated via an [ExecutionEngine]
+///
+/// Equality and hashing are structural rather than semantic: trees are equal when they have
+/// the same shape and equal leaves, functions are compared by location and native functions
+/// by address, so trees which always produce the same value may still differ.
 #[derive(Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "TS: crate::SerializableTypeSystem")
+)]
 pub enum Expression<TS: TypeSystem> {
     /// Evaluate to a raw value, no computation required
     RawValue(TS::Value),
+    /// Evaluate to a constant the engine stores once for every function using it,
+    /// see [ExecutionEngine::set_constant_pooling](crate::execution_engine::ExecutionEngine::set_constant_pooling)
+    PooledValue(usize),
     /// Retrieve a variable value as a reference
     Variable(VariableType),
     /// Evaluate a binary operation on two sub-expressions
-    BinaryOpEval(TS::BinaryOp, Box<[Expression<TS>; 2]>),
+    BinaryOpEval(
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                serialize_with = "crate::operators::op_ids::serialize_binary::<TS, _>",
+                deserialize_with = "crate::operators::op_ids::deserialize_binary::<TS, _>"
+            )
+        )]
+        TS::BinaryOp,
+        Box<[Expression<TS>; 2]>,
+    ),
     /// Evaluate a unary operation on a sub-expression
-    UnaryOpEval(TS::UnaryOp, Box<Expression<TS>>),
+    UnaryOpEval(
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                serialize_with = "crate::operators::op_ids::serialize_unary::<TS, _>",
+                deserialize_with = "crate::operators::op_ids::deserialize_unary::<TS, _>"
+            )
+        )]
+        TS::UnaryOp,
+        Box<Expression<TS>>,
+    ),
+    /// Evaluate a ternary operation on three sub-expressions, from left to right
+    TernaryOpEval(TS::TernaryOp, Box<[Expression<TS>; 3]>),
+    /// Evaluate a binary operation which decides whether to evaluate the right
+    /// sub-expression, and how many times, after seeing the value of the left one
+    LazyBinaryOpEval(TS::LazyBinaryOp, Box<[Expression<TS>; 2]>),
     Initialize(TS::Init, Vec<Expression<TS>>),
+    /// Evaluate the fields in order and pass them to
+    /// [Initializer::initialize_named](crate::operators::Initializer::initialize_named)
+    /// along with their ids
+    InitializeNamed(TS::Init, Vec<(TS::FieldId, Expression<TS>)>),
 
     /// Invoke a function that is known at compiletime
     StaticFunctionCall(FunctionRef<TS>, Vec<Expression<TS>>),
+    /// Invoke a function that is known at compiletime with positional arguments followed
+    /// by arguments given by name, see
+    /// [ExecutionEngine::call_named](crate::execution_engine::ExecutionEngine::call_named).
+    /// Every argument is evaluated in the order it's written before the call.
+    StaticFunctionCallNamed {
+        func: FunctionRef<TS>,
+        positional: Vec<Expression<TS>>,
+        named: Vec<(TS::FieldId, Expression<TS>)>,
+    },
+    /// Invoke a function that is known at compiletime in place of the function this is
+    /// evaluated in, reusing its native stack frame. Like a `Return`, this ends the
+    /// current function, leaving any enclosing `TryCatch`. The callee must be static,
+    /// since a capturing definition has nothing captured yet.
+    TailCall(FunctionRef<TS>, Vec<Expression<TS>>),
+    /// Evaluate to a list and pass its elements as separate arguments. Only allowed
+    /// directly in the arguments of a static, dynamic or tail call, where the elements
+    /// are counted against the callee's [ArgCount](crate::function::ArgCount).
+    Spread(Box<Expression<TS>>),
     /// Invoke a function whose identity is not known until runtime
     DynamicFunctionCall(Box<Expression<TS>>, Vec<Expression<TS>>),
-    /// Invoke a native function
+    /// Invoke a native function, which can't be serialized
     NativeFunctionCall(NativeFunction<TS>, Vec<Expression<TS>>),
-    /// Capture values from an environment, for closures
+    /// Invoke a native function with the argument expressions themselves, which it can
+    /// evaluate through the [EvalScope] of the call, see there for how they behave
+    NativeMacroCall(NativeMacro<TS>, Vec<Expression<TS>>),
+    /// Capture values from an environment, for closures. Each captured slot holds a
+    /// [dupe_ref](crate::value::Value::dupe_ref) of the variable, so it aliases the
+    /// variable if it holds a reference and is a snapshot of its value otherwise
     FunctionCapture(FunctionRef<TS>),
     /// Assign a value on the stack
     AssignStack(usize, Box<Expression<TS>>),
     /// Assign a global value
     AssignGlobal(usize, Box<Expression<TS>>),
+    /// Assign a value on the stack, producing a [dupe_ref](crate::value::Value::dupe_ref)
+    /// of the slot afterwards so assignments can be chained
+    AssignStackAndYield(usize, Box<Expression<TS>>),
+    /// Assign a global value, producing a [dupe_ref](crate::value::Value::dupe_ref)
+    /// of the global afterwards so assignments can be chained
+    AssignGlobalAndYield(usize, Box<Expression<TS>>),
+    /// Assign through a captured value, which is visible to the defining frame
+    /// when the captured variable is a reference
+    AssignCaptured(usize, Box<Expression<TS>>),
+    /// Apply a binary operator to a variable and the value, writing the result back to
+    /// the variable with [BinaryOperator::apply_2_assign](crate::operators::BinaryOperator::apply_2_assign).
+    /// The value is evaluated before the variable is read.
+    CompoundAssign {
+        target: VariableType,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                serialize_with = "crate::operators::op_ids::serialize_binary::<TS, _>",
+                deserialize_with = "crate::operators::op_ids::deserialize_binary::<TS, _>"
+            )
+        )]
+        op: TS::BinaryOp,
+        value: Box<Expression<TS>>,
+    },
+    /// Apply a unary operator to a variable, writing the result back to the variable with
+    /// [UnaryOperator::apply_1_assign](crate::operators::UnaryOperator::apply_1_assign)
+    /// and producing the default value
+    UnaryAssign {
+        target: VariableType,
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                serialize_with = "crate::operators::op_ids::serialize_unary::<TS, _>",
+                deserialize_with = "crate::operators::op_ids::deserialize_unary::<TS, _>"
+            )
+        )]
+        op: TS::UnaryOp,
+    },
+    /// Evaluate the value once and assign each of its list elements to the target
+    /// at the same position. The number of elements must match the number of targets.
+    DestructureAssign {
+        targets: Vec<VariableType>,
+        value: Box<Expression<TS>>,
+    },
+    /// Exchange the contents of two variables, producing the default value. Stack slots
+    /// and globals exchange their values outright, so references move along with them.
+    /// Captured slots can't be rebound, so their values are assigned through them instead.
+    Swap(VariableType, VariableType),
+    /// Evaluate the value and fail with [FreightError::TypeMismatch] unless its
+    /// [type](crate::value::Value::get_type) is the expected one, producing it unchanged
+    /// otherwise. The check is skipped when the engine's type assertions are disabled, see
+    /// [ExecutionEngine::set_type_assertions].
+    TypeAssert {
+        expected: TS::TypeId,
+        value: Box<Expression<TS>>,
+    },
+    /// Read an element of a container with [Value::get_index](crate::value::Value::get_index),
+    /// evaluating the target before the index
+    Index {
+        target: Box<Expression<TS>>,
+        index: Box<Expression<TS>>,
+    },
+    /// Replace an element of a container with [Value::set_index](crate::value::Value::set_index),
+    /// evaluating the target, index and value in that order and producing the default
+    /// value. The container only changes for other holders if the target evaluates to a
+    /// reference, such as a variable allocated as one.
+    IndexAssign {
+        target: Box<Expression<TS>>,
+        index: Box<Expression<TS>>,
+        value: Box<Expression<TS>>,
+    },
     /// Assign to a reference that will not be determined until runtime
     AssignDynamic(Box<[Expression<TS>; 2]>),
+    /// Assign to a reference that will not be determined until runtime,
+    /// producing a [dupe_ref](crate::value::Value::dupe_ref) of the target afterwards
+    AssignDynamicAndYield(Box<[Expression<TS>; 2]>),
     /// An expression which can be returned to
     ReturnTarget(usize, Box<Expression<TS>>),
     /// Return to the specified return target
     Return(usize, Box<Expression<TS>>),
+    /// Evaluate exactly one branch depending on the truthiness of the condition,
+    /// producing the default value if the condition is false and there is no else branch
+    Conditional {
+        condition: Box<Expression<TS>>,
+        then_branch: Box<Expression<TS>>,
+        else_branch: Option<Box<Expression<TS>>>,
+    },
+    /// Evaluate the body for as long as the condition is truthy, producing the default value
+    While {
+        condition: Box<Expression<TS>>,
+        body: Box<Expression<TS>>,
+    },
+    /// Evaluate the body repeatedly. The loop is also a return target, so `Return` to it
+    /// breaks out of the loop with a value, and `Continue` to it starts the next iteration.
+    Loop {
+        target: usize,
+        body: Box<Expression<TS>>,
+    },
+    /// Skip the rest of the current iteration of the enclosing `Loop` with this target
+    Continue(usize),
+    /// Evaluate the body once for each element of an iterable value, assigning the element
+    /// to the binding stack slot first, producing the default value
+    For {
+        binding: usize,
+        iterable: Box<Expression<TS>>,
+        body: Box<Expression<TS>>,
+    },
+    /// Evaluate the right side only if the left side is truthy,
+    /// producing whichever value decided the outcome
+    And(Box<[Expression<TS>; 2]>),
+    /// Evaluate the right side only if the left side is falsy,
+    /// producing whichever value decided the outcome
+    Or(Box<[Expression<TS>; 2]>),
+    /// Evaluate the right side only if the left side is [null](crate::value::Value::is_null),
+    /// producing whichever value was evaluated last
+    Coalesce(Box<[Expression<TS>; 2]>),
+    /// Evaluate each expression in order, producing the value of the last one,
+    /// or the default value if there are none
+    Sequence(Vec<Expression<TS>>),
+    /// Evaluate the scrutinee once and evaluate the first arm whose constant it matches,
+    /// falling back to the default arm. Without a default arm, failing to match is an error.
+    Match {
+        scrutinee: Box<Expression<TS>>,
+        arms: Vec<(TS::Value, Expression<TS>)>,
+        default: Option<Box<Expression<TS>>>,
+    },
+    /// Evaluate the body, and if it fails with a catchable error, store the error
+    /// in the error slot on the stack and evaluate the handler instead
+    TryCatch {
+        body: Box<Expression<TS>>,
+        error_slot: usize,
+        handler: Box<Expression<TS>>,
+    },
+    /// Evaluate the expression, attaching the span to any error raised inside it unless
+    /// a more deeply nested span already has been. See [FreightError::location].
+    Spanned(DebugInfo, Box<Expression<TS>>),
+    /// Create a thunk which evaluates the expression the first time it's forced, seeing
+    /// the variables of the current frame as they are at that point. A `Return` can't
+    /// leave the thunk, since the frame it targets may be gone by then.
+    Lazy(Shared<Expression<TS>>),
+    /// Evaluate the expression and force it if it's a thunk, evaluating the body at most
+    /// once. Other values are returned unchanged.
+    Force(Box<Expression<TS>>),
+    /// Suspend a [resumable call](crate::execution_engine::ExecutionEngine::call_resumable)
+    /// with the value of the expression, producing the value it's resumed with. The body
+    /// of the resumable function has to reach the yield itself, so it fails anywhere else,
+    /// including the arguments of calls other than tail calls, the right operand of a
+    /// lazy operator and the body of a thunk.
+    Yield(Box<Expression<TS>>),
+}
+
+// Both child visitors share this one exhaustive match, so a new variant can't be
+// forgotten by either of them. The body of a `Lazy` is only visited mutably while
+// no thunk shares it.
+macro_rules! for_each_child {
+    ($expr:expr, $f:ident, $iter:ident, $shared:path) => {
+        match $expr {
+            Expression::RawValue(_)
+            | Expression::PooledValue(_)
+            | Expression::Variable(_)
+            | Expression::FunctionCapture(_)
+            | Expression::Continue(_)
+            | Expression::UnaryAssign { .. }
+            | Expression::Swap(..) => {}
+            Expression::TernaryOpEval(_, operands) => operands.$iter().for_each($f),
+            Expression::BinaryOpEval(_, operands)
+            | Expression::LazyBinaryOpEval(_, operands)
+            | Expression::AssignDynamic(operands)
+            | Expression::AssignDynamicAndYield(operands)
+            | Expression::And(operands)
+            | Expression::Or(operands)
+            | Expression::Coalesce(operands) => operands.$iter().for_each($f),
+            Expression::UnaryOpEval(_, expr)
+            | Expression::AssignStack(_, expr)
+            | Expression::AssignGlobal(_, expr)
+            | Expression::AssignStackAndYield(_, expr)
+            | Expression::AssignGlobalAndYield(_, expr)
+            | Expression::AssignCaptured(_, expr)
+            | Expression::CompoundAssign { value: expr, .. }
+            | Expression::DestructureAssign { value: expr, .. }
+            | Expression::TypeAssert { value: expr, .. }
+            | Expression::ReturnTarget(_, expr)
+            | Expression::Return(_, expr)
+            | Expression::Spread(expr)
+            | Expression::Spanned(_, expr)
+            | Expression::Loop { body: expr, .. }
+            | Expression::Force(expr)
+            | Expression::Yield(expr) => $f(expr),
+            Expression::Lazy(body) => $shared(body).into_iter().for_each($f),
+            Expression::Initialize(_, exprs)
+            | Expression::StaticFunctionCall(_, exprs)
+            | Expression::TailCall(_, exprs)
+            | Expression::NativeFunctionCall(_, exprs)
+            | Expression::NativeMacroCall(_, exprs)
+            | Expression::Sequence(exprs) => exprs.$iter().for_each($f),
+            Expression::InitializeNamed(_, fields) => {
+                fields.$iter().for_each(|(_, field)| $f(field))
+            }
+            Expression::StaticFunctionCallNamed {
+                positional, named, ..
+            } => {
+                positional.$iter().for_each(&mut $f);
+                named.$iter().for_each(|(_, arg)| $f(arg));
+            }
+            Expression::DynamicFunctionCall(func, args) => {
+                $f(func);
+                args.$iter().for_each($f);
+            }
+            Expression::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                $f(condition);
+                $f(then_branch);
+                else_branch.$iter().for_each(|branch| $f(branch));
+            }
+            Expression::While { condition, body } => {
+                $f(condition);
+                $f(body);
+            }
+            Expression::Index { target, index } => {
+                $f(target);
+                $f(index);
+            }
+            Expression::IndexAssign {
+                target,
+                index,
+                value,
+            } => {
+                $f(target);
+                $f(index);
+                $f(value);
+            }
+            Expression::For { iterable, body, .. } => {
+                $f(iterable);
+                $f(body);
+            }
+            Expression::Match {
+                scrutinee,
+                arms,
+                default,
+            } => {
+                $f(scrutinee);
+                arms.$iter().for_each(|(_, arm)| $f(arm));
+                default.$iter().for_each(|default| $f(default));
+            }
+            Expression::TryCatch { body, handler, .. } => {
+                $f(body);
+                $f(handler);
+            }
+        }
+    };
+}
+
+fn shared_ref<T>(shared: &Shared<T>) -> Option<&T> {
+    Some(shared)
 }
 
 impl<TS: TypeSystem> Expression<TS> {
@@ -90,4 +558,206 @@ impl<TS: TypeSystem> Expression<TS> {
     pub fn global(addr: usize) -> Expression<TS> {
         Expression::Variable(VariableType::Global(addr))
     }
+
+    /// Lower a comparison chain like `a < b <= c` into `a < b && b <= c`, evaluating each
+    /// operand at most once and stopping at the first comparison that fails.
+    ///
+    /// Middle operands are kept in `scratch_slot` until the next comparison. Chains of more
+    /// than three operands reassign it while its previous value is the left operand, so it
+    /// should be a by-value slot (see [StackLayout::set_stack](crate::function::StackLayout::set_stack))
+    /// for reads of it to copy the value rather than share the reference.
+    pub fn chain_comparisons(
+        operands: Vec<Expression<TS>>,
+        ops: Vec<TS::BinaryOp>,
+        scratch_slot: usize,
+    ) -> Result<Expression<TS>, FreightError> {
+        if operands.len() != ops.len() + 1 {
+            return Err(FreightError::ComparisonChainMismatch {
+                operands: operands.len(),
+                operators: ops.len(),
+            });
+        }
+        let last = ops.len();
+        let mut operands = operands.into_iter();
+        let mut left = operands.next();
+        let mut links: Vec<_> = ops
+            .into_iter()
+            .zip(operands)
+            .enumerate()
+            .map(|(i, (op, right))| {
+                let left = left.take().unwrap_or(Expression::stack(scratch_slot));
+                let right = if i + 1 == last {
+                    right
+                } else {
+                    Expression::AssignStackAndYield(scratch_slot, right.into())
+                };
+                Expression::BinaryOpEval(op, [left, right].into())
+            })
+            .collect();
+        let Some(mut chain) = links.pop() else {
+            return Ok(left.expect("A chain without operators has one operand"));
+        };
+        while let Some(link) = links.pop() {
+            chain = Expression::And([link, chain].into());
+        }
+        Ok(chain)
+    }
+
+    /// The highest stack address this expression or any of its subexpressions uses,
+    /// including in the capture definitions of closures it creates. Stack addresses are
+    /// always static, so this accounts for every slot the expression can access.
+    pub fn max_stack_slot(&self) -> Option<usize> {
+        let mut max = None;
+        self.visit_stack_slots(|slot| max = max.max(Some(slot)));
+        max
+    }
+
+    /// Visit every stack address used in this expression tree, including those captured by
+    /// nested function definitions
+    pub(crate) fn visit_stack_slots(&self, mut f: impl FnMut(usize)) {
+        let mut pending = vec![self];
+        while let Some(expr) = pending.pop() {
+            expr.for_each_stack_slot(&mut f);
+            expr.for_each_child(|child| pending.push(child));
+        }
+    }
+
+    /// Visit each stack address used directly by this expression
+    fn for_each_stack_slot(&self, mut f: impl FnMut(usize)) {
+        match self {
+            Expression::Variable(VariableType::Stack(slot))
+            | Expression::AssignStack(slot, _)
+            | Expression::AssignStackAndYield(slot, _)
+            | Expression::CompoundAssign {
+                target: VariableType::Stack(slot),
+                ..
+            }
+            | Expression::UnaryAssign {
+                target: VariableType::Stack(slot),
+                ..
+            }
+            | Expression::For { binding: slot, .. }
+            | Expression::TryCatch {
+                error_slot: slot, ..
+            } => f(*slot),
+            Expression::FunctionCapture(func) => {
+                if let FunctionType::CapturingDef(captures, _) = &func.function_type {
+                    for var in captures.iter() {
+                        if let VariableType::Stack(slot) = var {
+                            f(*slot);
+                        }
+                    }
+                }
+            }
+            Expression::Swap(a, b) => {
+                for var in [a, b] {
+                    if let VariableType::Stack(slot) = var {
+                        f(*slot);
+                    }
+                }
+            }
+            Expression::DestructureAssign { targets, .. } => {
+                for var in targets {
+                    if let VariableType::Stack(slot) = var {
+                        f(*slot);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Visit each direct subexpression in evaluation order
+    pub(crate) fn is_spread(&self) -> bool {
+        matches!(self, Expression::Spread(_))
+    }
+
+    /// The arguments of a call which may contain [Expression::Spread]
+    pub(crate) fn call_args(&self) -> Option<&[Expression<TS>]> {
+        match self {
+            Expression::StaticFunctionCall(_, args)
+            | Expression::DynamicFunctionCall(_, args)
+            | Expression::TailCall(_, args) => Some(args),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn for_each_child<'a>(&'a self, mut f: impl FnMut(&'a Expression<TS>)) {
+        for_each_child!(self, f, iter, shared_ref)
+    }
+
+    /// Visit each direct subexpression mutably in evaluation order
+    pub(crate) fn for_each_child_mut(&mut self, mut f: impl FnMut(&mut Expression<TS>)) {
+        for_each_child!(self, f, iter_mut, Shared::get_mut)
+    }
+
+    /// Visit this expression and every subexpression, parents before their children
+    /// and siblings in evaluation order
+    pub fn walk(&self, f: &mut impl FnMut(&Expression<TS>)) {
+        let mut pending = vec![self];
+        while let Some(expr) = pending.pop() {
+            f(expr);
+            let len = pending.len();
+            expr.for_each_child(|child| pending.push(child));
+            pending[len..].reverse();
+        }
+    }
+
+    /// Rebuild this expression by passing each subexpression through `f`, children
+    /// before their parents, so `f` sees parents with their children already mapped
+    pub fn map(self, f: &mut impl FnMut(Expression<TS>) -> Expression<TS>) -> Expression<TS> {
+        let mut root = self;
+        root.visit_post_order_mut(|expr| {
+            let original = std::mem::replace(expr, Expression::Sequence(Vec::new()));
+            *expr = f(original);
+        });
+        root
+    }
+
+    /// Visit every subexpression mutably, children before their parents. Changes made
+    /// to an expression's children while visiting it are not visited.
+    pub(crate) fn visit_post_order_mut(&mut self, mut f: impl FnMut(&mut Expression<TS>)) {
+        // Every node is collected with its parents before it, then visited in reverse so
+        // children come first. This avoids recursing on deeply nested expressions.
+        let mut nodes = vec![self as *mut Expression<TS>];
+        let mut i = 0;
+        while i < nodes.len() {
+            let node = nodes[i];
+            // SAFETY: nothing is visited until every node is collected, so the tree is unchanged
+            unsafe { &mut *node }.for_each_child_mut(|child| nodes.push(child));
+            i += 1;
+        }
+        for node in nodes.into_iter().rev() {
+            // SAFETY: visiting a node can only drop or replace its children,
+            // which have already been visited
+            f(unsafe { &mut *node });
+        }
+    }
+
+    fn has_children(&self) -> bool {
+        let mut has_children = false;
+        self.for_each_child(|_| has_children = true);
+        has_children
+    }
+
+    /// Move every subexpression which has children of its own into `pending`
+    fn detach_children(&mut self, pending: &mut Vec<Expression<TS>>) {
+        self.for_each_child_mut(|expr| {
+            if expr.has_children() {
+                pending.push(std::mem::replace(expr, Expression::Sequence(Vec::new())));
+            }
+        });
+    }
+}
+
+impl<TS: TypeSystem> Drop for Expression<TS> {
+    fn drop(&mut self) {
+        // Expressions are taken apart with an explicit stack, since dropping a deeply
+        // nested expression recursively can overflow the native stack
+        let mut pending = Vec::new();
+        self.detach_children(&mut pending);
+        while let Some(mut expr) = pending.pop() {
+            expr.detach_children(&mut pending);
+        }
+    }
 }
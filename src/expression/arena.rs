@@ -0,0 +1,260 @@
+use super::{Expression, NativeFunction, VariableType};
+use crate::{function::FunctionRef, TypeSystem};
+
+/// The position of a node in an [ExpressionArena]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NodeId(u32);
+
+impl NodeId {
+    pub(crate) fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A run of consecutive child ids in [ExpressionArena::children]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Children {
+    start: u32,
+    len: u32,
+}
+
+impl Children {
+    pub(crate) fn len(self) -> usize {
+        self.len as usize
+    }
+
+    /// The children after the first one
+    pub(crate) fn rest(self) -> Children {
+        Children {
+            start: self.start + 1,
+            len: self.len - 1,
+        }
+    }
+}
+
+/// An expression whose children are ids in the same arena rather than boxes.
+/// Values and function references are kept out of line so nodes stay small.
+#[derive(Debug)]
+pub(crate) enum Node<TS: TypeSystem> {
+    Value(u32),
+    PooledValue(usize),
+    Variable(VariableType),
+    BinaryOpEval(TS::BinaryOp, [NodeId; 2]),
+    UnaryOpEval(TS::UnaryOp, NodeId),
+    TernaryOpEval(TS::TernaryOp, [NodeId; 3]),
+    LazyBinaryOpEval(TS::LazyBinaryOp, [NodeId; 2]),
+    Initialize(TS::Init, Children),
+    StaticFunctionCall(u32, Children),
+    TailCall(u32, Children),
+    NativeFunctionCall(NativeFunction<TS>, Children),
+    AssignStack(usize, NodeId, bool),
+    AssignGlobal(usize, NodeId, bool),
+    AssignCaptured(usize, NodeId),
+    ReturnTarget(usize, NodeId),
+    Return(usize, NodeId),
+    Conditional(NodeId, NodeId, Option<NodeId>),
+    While(NodeId, NodeId),
+    Loop(usize, NodeId),
+    Continue(usize),
+    And([NodeId; 2]),
+    Or([NodeId; 2]),
+    Coalesce([NodeId; 2]),
+    Sequence(Children),
+}
+
+/// Expression trees stored in contiguous buffers with index-based children, which avoids
+/// a separate allocation per node and keeps nodes close together while evaluating them.
+///
+/// Build one from boxed [Expression]s with [ExpressionArena::insert] and evaluate it with
+/// [ExecutionEngine::eval_arena](crate::execution_engine::ExecutionEngine::eval_arena).
+/// Registered functions keep their boxed expressions, so an arena only holds the trees
+/// inserted into it.
+#[derive(Debug)]
+pub struct ExpressionArena<TS: TypeSystem> {
+    pub(crate) nodes: Vec<Node<TS>>,
+    pub(crate) children: Vec<NodeId>,
+    pub(crate) values: Vec<TS::Value>,
+    pub(crate) functions: Vec<FunctionRef<TS>>,
+}
+
+impl<TS: TypeSystem> Default for ExpressionArena<TS> {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            children: Vec::new(),
+            values: Vec::new(),
+            functions: Vec::new(),
+        }
+    }
+}
+
+impl<TS: TypeSystem> ExpressionArena<TS> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of nodes in the arena
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Whether an expression can be stored in an arena. Only values, variables, operators,
+    /// initializers, static and native calls, assignments to variables and structured
+    /// control flow are supported, and a tree can be stored only if all of its nodes are.
+    pub fn supports(expr: &Expression<TS>) -> bool {
+        matches!(
+            expr,
+            Expression::RawValue(_)
+                | Expression::PooledValue(_)
+                | Expression::Variable(_)
+                | Expression::BinaryOpEval(..)
+                | Expression::UnaryOpEval(..)
+                | Expression::TernaryOpEval(..)
+                | Expression::LazyBinaryOpEval(..)
+                | Expression::Initialize(..)
+                | Expression::StaticFunctionCall(..)
+                | Expression::TailCall(..)
+                | Expression::NativeFunctionCall(..)
+                | Expression::AssignStack(..)
+                | Expression::AssignGlobal(..)
+                | Expression::AssignStackAndYield(..)
+                | Expression::AssignGlobalAndYield(..)
+                | Expression::AssignCaptured(..)
+                | Expression::ReturnTarget(..)
+                | Expression::Return(..)
+                | Expression::Conditional { .. }
+                | Expression::While { .. }
+                | Expression::Loop { .. }
+                | Expression::Continue(_)
+                | Expression::And(_)
+                | Expression::Or(_)
+                | Expression::Coalesce(_)
+                | Expression::Sequence(_)
+        )
+    }
+
+    /// Copy an expression tree into the arena and return the id of its root, or `None`
+    /// without changing the arena if the tree has nodes the arena doesn't
+    /// [support](ExpressionArena::supports)
+    pub fn insert(&mut self, expr: &Expression<TS>) -> Option<NodeId> {
+        let mut supported = true;
+        expr.walk(&mut |expr| supported &= Self::supports(expr));
+        if !supported {
+            return None;
+        }
+        // Nodes are added after their children, so the ids of the children of the next
+        // node to add are always at the end of `built`, in evaluation order
+        let mut built = Vec::new();
+        let mut pending = vec![(expr, false)];
+        while let Some((expr, children_built)) = pending.pop() {
+            let len = pending.len();
+            if !children_built {
+                pending.push((expr, true));
+                expr.for_each_child(|child| pending.push((child, false)));
+                pending[len + 1..].reverse();
+                continue;
+            }
+            let mut count = 0;
+            expr.for_each_child(|_| count += 1);
+            let children = built.split_off(built.len() - count);
+            let node = self.node(expr, &children);
+            built.push(self.push(node));
+        }
+        built.pop()
+    }
+
+    /// The node for a supported expression, given the ids of its children
+    fn node(&mut self, expr: &Expression<TS>, children: &[NodeId]) -> Node<TS> {
+        let first = || children[0];
+        let pair = || [children[0], children[1]];
+        match expr {
+            Expression::RawValue(value) => {
+                self.values.push(value.clone());
+                Node::Value(self.values.len() as u32 - 1)
+            }
+            Expression::PooledValue(index) => Node::PooledValue(*index),
+            Expression::Variable(var) => Node::Variable(var.clone()),
+            Expression::BinaryOpEval(op, _) => Node::BinaryOpEval(op.clone(), pair()),
+            Expression::UnaryOpEval(op, _) => Node::UnaryOpEval(op.clone(), first()),
+            Expression::LazyBinaryOpEval(op, _) => Node::LazyBinaryOpEval(op.clone(), pair()),
+            Expression::TernaryOpEval(op, _) => {
+                Node::TernaryOpEval(op.clone(), [children[0], children[1], children[2]])
+            }
+            Expression::Initialize(init, _) => {
+                Node::Initialize(init.clone(), self.push_children(children))
+            }
+            Expression::StaticFunctionCall(func, _) => {
+                self.functions.push(func.clone());
+                let func = self.functions.len() as u32 - 1;
+                Node::StaticFunctionCall(func, self.push_children(children))
+            }
+            Expression::TailCall(func, _) => {
+                self.functions.push(func.clone());
+                let func = self.functions.len() as u32 - 1;
+                Node::TailCall(func, self.push_children(children))
+            }
+            Expression::NativeFunctionCall(func, _) => {
+                Node::NativeFunctionCall(func.clone(), self.push_children(children))
+            }
+            Expression::AssignStack(addr, _) => Node::AssignStack(*addr, first(), false),
+            Expression::AssignStackAndYield(addr, _) => Node::AssignStack(*addr, first(), true),
+            Expression::AssignGlobal(addr, _) => Node::AssignGlobal(*addr, first(), false),
+            Expression::AssignGlobalAndYield(addr, _) => Node::AssignGlobal(*addr, first(), true),
+            Expression::AssignCaptured(addr, _) => Node::AssignCaptured(*addr, first()),
+            Expression::ReturnTarget(target, _) => Node::ReturnTarget(*target, first()),
+            Expression::Return(target, _) => Node::Return(*target, first()),
+            Expression::Conditional { .. } => {
+                Node::Conditional(children[0], children[1], children.get(2).copied())
+            }
+            Expression::While { .. } => Node::While(children[0], children[1]),
+            Expression::Loop { target, .. } => Node::Loop(*target, first()),
+            Expression::Continue(target) => Node::Continue(*target),
+            Expression::And(_) => Node::And(pair()),
+            Expression::Or(_) => Node::Or(pair()),
+            Expression::Coalesce(_) => Node::Coalesce(pair()),
+            Expression::Sequence(_) => Node::Sequence(self.push_children(children)),
+            _ => unreachable!("Unsupported expressions are rejected before building nodes"),
+        }
+    }
+
+    fn push(&mut self, node: Node<TS>) -> NodeId {
+        let id = u32::try_from(self.nodes.len()).expect("Arenas hold at most u32::MAX nodes");
+        self.nodes.push(node);
+        NodeId(id)
+    }
+
+    fn push_children(&mut self, children: &[NodeId]) -> Children {
+        let start = self.children.len() as u32;
+        self.children.extend_from_slice(children);
+        Children {
+            start,
+            len: children.len() as u32,
+        }
+    }
+
+    pub(crate) fn node_at(&self, id: NodeId) -> &Node<TS> {
+        &self.nodes[id.index()]
+    }
+
+    pub(crate) fn children_of(&self, children: Children) -> &[NodeId] {
+        let start = children.start as usize;
+        &self.children[start..start + children.len()]
+    }
+
+    /// Visit every variable the nodes of the arena use
+    pub(crate) fn for_each_variable(&self, mut f: impl FnMut(VariableType)) {
+        for node in &self.nodes {
+            match node {
+                Node::Variable(var) => f(var.clone()),
+                Node::AssignStack(addr, ..) => f(VariableType::Stack(*addr)),
+                Node::AssignGlobal(addr, ..) => f(VariableType::Global(*addr)),
+                Node::AssignCaptured(addr, _) => f(VariableType::Captured(*addr)),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,109 @@
+use super::Expression;
+use crate::{
+    error::FreightError,
+    operators::{Assoc, BinaryOperator},
+    TypeSystem,
+};
+
+/// Builds a tree of [Expression::BinaryOpEval] from a flat sequence of operands and binary
+/// operators, like the tokens of `1 + 2 * 3 - 4`, nesting them by each operator's
+/// [precedence](BinaryOperator::precedence) and [associativity](BinaryOperator::associativity).
+///
+/// Operands and operators have to alternate, starting and ending with an operand.
+#[derive(Debug)]
+pub struct InfixBuilder<TS: TypeSystem> {
+    operands: Vec<Expression<TS>>,
+    /// Operators waiting for their right operand, with non-decreasing precedence
+    operators: Vec<TS::BinaryOp>,
+}
+
+impl<TS: TypeSystem> Default for InfixBuilder<TS> {
+    fn default() -> Self {
+        Self {
+            operands: Vec::new(),
+            operators: Vec::new(),
+        }
+    }
+}
+
+impl<TS: TypeSystem> InfixBuilder<TS> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn expects_operand(&self) -> bool {
+        self.operands.len() == self.operators.len()
+    }
+
+    /// Add the next operand, which must follow an operator unless it's the first one
+    pub fn push_operand(&mut self, operand: Expression<TS>) -> Result<(), FreightError> {
+        if !self.expects_operand() {
+            return Err(FreightError::MalformedInfix {
+                message: "two operands in a row".into(),
+            });
+        }
+        self.operands.push(operand);
+        Ok(())
+    }
+
+    /// Add the next operator, which must follow an operand. Operators before it which bind
+    /// at least as tightly are applied first, failing if one has the same precedence but a
+    /// different associativity.
+    pub fn push_operator(&mut self, op: TS::BinaryOp) -> Result<(), FreightError> {
+        if self.expects_operand() {
+            return Err(FreightError::MalformedInfix {
+                message: format!("operator {} is missing its left operand", op.name()),
+            });
+        }
+        while let Some(top) = self.operators.last() {
+            if top.precedence() == op.precedence() && top.associativity() != op.associativity() {
+                return Err(FreightError::MixedAssociativity {
+                    left: top.name().into_owned(),
+                    right: op.name().into_owned(),
+                });
+            }
+            let applies_first = top.precedence() > op.precedence()
+                || (top.precedence() == op.precedence() && op.associativity() == Assoc::Left);
+            if !applies_first {
+                break;
+            }
+            self.reduce();
+        }
+        self.operators.push(op);
+        Ok(())
+    }
+
+    /// Apply the last operator to the last two operands
+    fn reduce(&mut self) {
+        let op = self.operators.pop().expect("Reduced without an operator");
+        let r = self
+            .operands
+            .pop()
+            .expect("Operators follow their left operand");
+        let l = self
+            .operands
+            .pop()
+            .expect("Operators follow their left operand");
+        self.operands
+            .push(Expression::BinaryOpEval(op, [l, r].into()));
+    }
+
+    /// The tree for everything pushed so far, which must end with an operand
+    pub fn build(mut self) -> Result<Expression<TS>, FreightError> {
+        if self.expects_operand() {
+            return Err(FreightError::MalformedInfix {
+                message: match self.operators.last() {
+                    Some(op) => format!("operator {} is missing its right operand", op.name()),
+                    None => "no operands".into(),
+                },
+            });
+        }
+        while !self.operators.is_empty() {
+            self.reduce();
+        }
+        Ok(self
+            .operands
+            .pop()
+            .expect("Checked that an operand is last"))
+    }
+}
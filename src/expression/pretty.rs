@@ -0,0 +1,379 @@
+use std::fmt::Write;
+
+use super::Expression;
+use crate::{
+    function::FunctionType,
+    operators::{BinaryOperator, LazyBinaryOperator, TernaryOperator, UnaryOperator},
+    TypeSystem,
+};
+
+const INDENT: &str = "    ";
+
+impl<TS: TypeSystem> Expression<TS> {
+    /// Render this expression as readable, indented text for debugging.
+    /// Operators, initializers and values are shown with their `Debug` representations.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0)
+            .expect("Writing to a String can't fail");
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) -> std::fmt::Result {
+        match self {
+            Expression::RawValue(value) => write!(out, "raw({value:?})"),
+            Expression::PooledValue(index) => write!(out, "constant[{index}]"),
+            Expression::Variable(var) => write!(out, "{var}"),
+            Expression::BinaryOpEval(op, operands) => {
+                let [l, r] = &**operands;
+                write!(out, "({} ", op.name())?;
+                l.write_pretty(out, indent)?;
+                out.push(' ');
+                r.write_pretty(out, indent)?;
+                out.push(')');
+                Ok(())
+            }
+            Expression::UnaryOpEval(op, operand) => {
+                write!(out, "({} ", op.name())?;
+                operand.write_pretty(out, indent)?;
+                out.push(')');
+                Ok(())
+            }
+            Expression::LazyBinaryOpEval(op, operands) => {
+                let [l, r] = &**operands;
+                write!(out, "(lazy {} ", op.name())?;
+                l.write_pretty(out, indent)?;
+                out.push(' ');
+                r.write_pretty(out, indent)?;
+                out.push(')');
+                Ok(())
+            }
+            Expression::TernaryOpEval(op, operands) => {
+                let [a, b, c] = &**operands;
+                write!(out, "({} ", op.name())?;
+                a.write_pretty(out, indent)?;
+                out.push(' ');
+                b.write_pretty(out, indent)?;
+                out.push(' ');
+                c.write_pretty(out, indent)?;
+                out.push(')');
+                Ok(())
+            }
+            Expression::Initialize(init, args) => {
+                write!(out, "init {init:?}")?;
+                Self::write_args(args, out, indent)
+            }
+            Expression::InitializeNamed(init, fields) => {
+                write!(out, "init {init:?}(")?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    write!(out, "{field:?}: ")?;
+                    value.write_pretty(out, indent)?;
+                }
+                out.push(')');
+                Ok(())
+            }
+            Expression::StaticFunctionCall(func, args) => {
+                write!(out, "call {func}")?;
+                Self::write_args(args, out, indent)
+            }
+            Expression::StaticFunctionCallNamed {
+                func,
+                positional,
+                named,
+            } => {
+                write!(out, "call {func}(")?;
+                for (i, arg) in positional.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    arg.write_pretty(out, indent)?;
+                }
+                for (i, (name, arg)) in named.iter().enumerate() {
+                    if i + positional.len() > 0 {
+                        out.push_str(", ");
+                    }
+                    write!(out, "{name:?}: ")?;
+                    arg.write_pretty(out, indent)?;
+                }
+                out.push(')');
+                Ok(())
+            }
+            Expression::TailCall(func, args) => {
+                write!(out, "tailcall {func}")?;
+                Self::write_args(args, out, indent)
+            }
+            Expression::Spread(list) => {
+                out.push_str("...");
+                list.write_pretty(out, indent)
+            }
+            Expression::DynamicFunctionCall(func, args) => {
+                out.push_str("call ");
+                func.write_pretty(out, indent)?;
+                Self::write_args(args, out, indent)
+            }
+            Expression::NativeFunctionCall(_, args) => {
+                out.push_str("call native");
+                Self::write_args(args, out, indent)
+            }
+            Expression::NativeMacroCall(_, args) => {
+                out.push_str("call native macro");
+                Self::write_args(args, out, indent)
+            }
+            Expression::FunctionCapture(func) => {
+                write!(out, "capture {func}[")?;
+                if let FunctionType::CapturingDef(captures, layout) = &func.function_type {
+                    for (i, var) in captures.iter().enumerate() {
+                        if i > 0 {
+                            out.push_str(", ");
+                        }
+                        if layout.is_by_value(i) {
+                            out.push_str("copy ");
+                        }
+                        write!(out, "{var}")?;
+                    }
+                }
+                out.push(']');
+                Ok(())
+            }
+            Expression::AssignStack(addr, value) => {
+                write!(out, "stack[{addr}] = ")?;
+                value.write_pretty(out, indent)
+            }
+            Expression::AssignGlobal(addr, value) => {
+                write!(out, "global[{addr}] = ")?;
+                value.write_pretty(out, indent)
+            }
+            Expression::AssignStackAndYield(addr, value) => {
+                write!(out, "(stack[{addr}] = ")?;
+                value.write_pretty(out, indent)?;
+                out.push(')');
+                Ok(())
+            }
+            Expression::AssignGlobalAndYield(addr, value) => {
+                write!(out, "(global[{addr}] = ")?;
+                value.write_pretty(out, indent)?;
+                out.push(')');
+                Ok(())
+            }
+            Expression::AssignCaptured(addr, value) => {
+                write!(out, "captured[{addr}] = ")?;
+                value.write_pretty(out, indent)
+            }
+            Expression::CompoundAssign { target, op, value } => {
+                write!(out, "{target} {}= ", op.name())?;
+                value.write_pretty(out, indent)
+            }
+            Expression::UnaryAssign { target, op } => write!(out, "{}= {target}", op.name()),
+            Expression::Swap(a, b) => write!(out, "swap {a}, {b}"),
+            Expression::DestructureAssign { targets, value } => {
+                out.push('(');
+                for (i, target) in targets.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    write!(out, "{target}")?;
+                }
+                out.push_str(") = ");
+                value.write_pretty(out, indent)
+            }
+            Expression::TypeAssert { expected, value } => {
+                write!(out, "assert {expected:?} ")?;
+                value.write_pretty(out, indent)
+            }
+            Expression::Index { target, index } => {
+                target.write_pretty(out, indent)?;
+                out.push('[');
+                index.write_pretty(out, indent)?;
+                out.push(']');
+                Ok(())
+            }
+            Expression::IndexAssign {
+                target,
+                index,
+                value,
+            } => {
+                target.write_pretty(out, indent)?;
+                out.push('[');
+                index.write_pretty(out, indent)?;
+                out.push_str("] = ");
+                value.write_pretty(out, indent)
+            }
+            Expression::AssignDynamic(operands) => {
+                let [target, value] = &**operands;
+                out.push('*');
+                target.write_pretty(out, indent)?;
+                out.push_str(" = ");
+                value.write_pretty(out, indent)
+            }
+            Expression::AssignDynamicAndYield(operands) => {
+                let [target, value] = &**operands;
+                out.push_str("(*");
+                target.write_pretty(out, indent)?;
+                out.push_str(" = ");
+                value.write_pretty(out, indent)?;
+                out.push(')');
+                Ok(())
+            }
+            Expression::ReturnTarget(target, body) => {
+                write!(out, "target #{target} ")?;
+                Self::write_block(body, out, indent)
+            }
+            Expression::Return(target, value) => {
+                write!(out, "return #{target} ")?;
+                value.write_pretty(out, indent)
+            }
+            Expression::Conditional {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                out.push_str("if ");
+                condition.write_pretty(out, indent)?;
+                out.push(' ');
+                Self::write_block(then_branch, out, indent)?;
+                if let Some(else_branch) = else_branch {
+                    out.push_str(" else ");
+                    Self::write_block(else_branch, out, indent)?;
+                }
+                Ok(())
+            }
+            Expression::And(operands)
+            | Expression::Or(operands)
+            | Expression::Coalesce(operands) => {
+                let name = match self {
+                    Expression::And(_) => "and",
+                    Expression::Or(_) => "or",
+                    _ => "coalesce",
+                };
+                let [l, r] = &**operands;
+                write!(out, "({name} ")?;
+                l.write_pretty(out, indent)?;
+                out.push(' ');
+                r.write_pretty(out, indent)?;
+                out.push(')');
+                Ok(())
+            }
+            Expression::Sequence(exprs) => Self::write_statements(exprs, out, indent),
+            Expression::Match {
+                scrutinee,
+                arms,
+                default,
+            } => {
+                out.push_str("match ");
+                scrutinee.write_pretty(out, indent)?;
+                out.push_str(" {\n");
+                for (constant, arm) in arms {
+                    Self::write_indent(out, indent + 1);
+                    write!(out, "{constant:?} => ")?;
+                    arm.write_pretty(out, indent + 1)?;
+                    out.push('\n');
+                }
+                if let Some(default) = default {
+                    Self::write_indent(out, indent + 1);
+                    out.push_str("_ => ");
+                    default.write_pretty(out, indent + 1)?;
+                    out.push('\n');
+                }
+                Self::write_indent(out, indent);
+                out.push('}');
+                Ok(())
+            }
+            Expression::TryCatch {
+                body,
+                error_slot,
+                handler,
+            } => {
+                out.push_str("try ");
+                Self::write_block(body, out, indent)?;
+                write!(out, " catch stack[{error_slot}] ")?;
+                Self::write_block(handler, out, indent)
+            }
+            Expression::Spanned(span, expr) => {
+                write!(out, "@[{}:{}..{}] ", span.file, span.start, span.end)?;
+                expr.write_pretty(out, indent)
+            }
+            Expression::Lazy(body) => {
+                out.push_str("lazy ");
+                Self::write_block(body, out, indent)
+            }
+            Expression::Force(thunk) => {
+                out.push_str("force ");
+                thunk.write_pretty(out, indent)
+            }
+            Expression::Yield(value) => {
+                out.push_str("yield ");
+                value.write_pretty(out, indent)
+            }
+            Expression::While { condition, body } => {
+                out.push_str("while ");
+                condition.write_pretty(out, indent)?;
+                out.push(' ');
+                Self::write_block(body, out, indent)
+            }
+            Expression::Loop { target, body } => {
+                write!(out, "loop #{target} ")?;
+                Self::write_block(body, out, indent)
+            }
+            Expression::Continue(target) => write!(out, "continue #{target}"),
+            Expression::For {
+                binding,
+                iterable,
+                body,
+            } => {
+                write!(out, "for stack[{binding}] in ")?;
+                iterable.write_pretty(out, indent)?;
+                out.push(' ');
+                Self::write_block(body, out, indent)
+            }
+        }
+    }
+
+    fn write_args(args: &[Expression<TS>], out: &mut String, indent: usize) -> std::fmt::Result {
+        out.push('(');
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                out.push_str(", ");
+            }
+            arg.write_pretty(out, indent)?;
+        }
+        out.push(')');
+        Ok(())
+    }
+
+    /// Write an expression as the body of a block, unwrapping sequences into statements
+    fn write_block(body: &Expression<TS>, out: &mut String, indent: usize) -> std::fmt::Result {
+        match body {
+            Expression::Sequence(exprs) => Self::write_statements(exprs, out, indent),
+            body => Self::write_statements(std::slice::from_ref(body), out, indent),
+        }
+    }
+
+    fn write_statements(
+        exprs: &[Expression<TS>],
+        out: &mut String,
+        indent: usize,
+    ) -> std::fmt::Result {
+        if exprs.is_empty() {
+            out.push_str("{}");
+            return Ok(());
+        }
+        out.push_str("{\n");
+        for expr in exprs {
+            Self::write_indent(out, indent + 1);
+            expr.write_pretty(out, indent + 1)?;
+            out.push('\n');
+        }
+        Self::write_indent(out, indent);
+        out.push('}');
+        Ok(())
+    }
+
+    fn write_indent(out: &mut String, indent: usize) {
+        for _ in 0..indent {
+            out.push_str(INDENT);
+        }
+    }
+}
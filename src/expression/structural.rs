@@ -0,0 +1,285 @@
+use std::{
+    hash::{Hash, Hasher},
+    mem::discriminant,
+};
+
+use super::{Expression, NativeFunction, NativeMacro};
+use crate::TypeSystem;
+
+// Equality and hashing are structural: two trees are equal when they have the same shape
+// and equal leaves, even if they would behave differently because of the engine they run
+// in, and trees which always produce the same result can still differ. Both walk the tree
+// iteratively, since trees can be deeper than the native stack.
+
+impl<TS: TypeSystem> PartialEq for Expression<TS>
+where
+    TS::UnaryOp: PartialEq,
+    TS::BinaryOp: PartialEq,
+    TS::TernaryOp: PartialEq,
+    TS::LazyBinaryOp: PartialEq,
+    TS::Init: PartialEq,
+{
+    fn eq(&self, other: &Self) -> bool {
+        let mut pending = vec![(self, other)];
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        while let Some((a, b)) = pending.pop() {
+            if !a.node_eq(b) {
+                return false;
+            }
+            a.for_each_child(|child| left.push(child));
+            b.for_each_child(|child| right.push(child));
+            if left.len() != right.len() {
+                return false;
+            }
+            pending.extend(left.drain(..).zip(right.drain(..)));
+        }
+        true
+    }
+}
+
+impl<TS: TypeSystem> Eq for Expression<TS>
+where
+    TS::Value: Eq,
+    TS::UnaryOp: Eq,
+    TS::BinaryOp: Eq,
+    TS::TernaryOp: Eq,
+    TS::LazyBinaryOp: Eq,
+    TS::Init: Eq,
+    TS::TypeId: Eq,
+{
+}
+
+impl<TS: TypeSystem> Hash for Expression<TS>
+where
+    TS::Value: Hash,
+    TS::UnaryOp: Hash,
+    TS::BinaryOp: Hash,
+    TS::TernaryOp: Hash,
+    TS::LazyBinaryOp: Hash,
+    TS::Init: Hash,
+    TS::TypeId: Hash,
+    TS::FieldId: Hash,
+{
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut pending = vec![self];
+        while let Some(expr) = pending.pop() {
+            expr.hash_node(state);
+            let len = pending.len();
+            expr.for_each_child(|child| pending.push(child));
+            // The number of children keeps the shape of the tree unambiguous
+            state.write_usize(pending.len() - len);
+        }
+    }
+}
+
+impl<TS: TypeSystem> Expression<TS> {
+    /// Compare the kind and leaves of two expressions, but not their children.
+    /// Keep in sync with [Self::hash_node].
+    fn node_eq(&self, other: &Self) -> bool
+    where
+        TS::UnaryOp: PartialEq,
+        TS::BinaryOp: PartialEq,
+        TS::TernaryOp: PartialEq,
+        TS::LazyBinaryOp: PartialEq,
+        TS::Init: PartialEq,
+    {
+        use Expression as E;
+        match (self, other) {
+            (E::RawValue(a), E::RawValue(b)) => a == b,
+            (E::PooledValue(a), E::PooledValue(b))
+            | (E::AssignStack(a, _), E::AssignStack(b, _))
+            | (E::AssignGlobal(a, _), E::AssignGlobal(b, _))
+            | (E::AssignStackAndYield(a, _), E::AssignStackAndYield(b, _))
+            | (E::AssignGlobalAndYield(a, _), E::AssignGlobalAndYield(b, _))
+            | (E::AssignCaptured(a, _), E::AssignCaptured(b, _))
+            | (E::ReturnTarget(a, _), E::ReturnTarget(b, _))
+            | (E::Return(a, _), E::Return(b, _))
+            | (E::Loop { target: a, .. }, E::Loop { target: b, .. })
+            | (E::Continue(a), E::Continue(b))
+            | (E::For { binding: a, .. }, E::For { binding: b, .. })
+            | (E::TryCatch { error_slot: a, .. }, E::TryCatch { error_slot: b, .. }) => a == b,
+            (E::Variable(a), E::Variable(b)) => a == b,
+            (E::BinaryOpEval(a, _), E::BinaryOpEval(b, _)) => a == b,
+            (E::UnaryOpEval(a, _), E::UnaryOpEval(b, _)) => a == b,
+            (E::TernaryOpEval(a, _), E::TernaryOpEval(b, _)) => a == b,
+            (E::LazyBinaryOpEval(a, _), E::LazyBinaryOpEval(b, _)) => a == b,
+            (E::Initialize(a, _), E::Initialize(b, _)) => a == b,
+            (E::InitializeNamed(a, a_fields), E::InitializeNamed(b, b_fields)) => {
+                a == b
+                    && a_fields.len() == b_fields.len()
+                    && a_fields.iter().zip(b_fields).all(|((a, _), (b, _))| a == b)
+            }
+            (
+                E::StaticFunctionCallNamed {
+                    func: a,
+                    positional: a_positional,
+                    named: a_named,
+                },
+                E::StaticFunctionCallNamed {
+                    func: b,
+                    positional: b_positional,
+                    named: b_named,
+                },
+            ) => {
+                a == b
+                    && a_positional.len() == b_positional.len()
+                    && a_named.len() == b_named.len()
+                    && a_named.iter().zip(b_named).all(|((a, _), (b, _))| a == b)
+            }
+            (E::StaticFunctionCall(a, _), E::StaticFunctionCall(b, _))
+            | (E::TailCall(a, _), E::TailCall(b, _))
+            | (E::FunctionCapture(a), E::FunctionCapture(b)) => a == b,
+            (E::NativeFunctionCall(a, _), E::NativeFunctionCall(b, _)) => a == b,
+            (E::NativeMacroCall(a, _), E::NativeMacroCall(b, _)) => a == b,
+            (
+                E::CompoundAssign { target, op, .. },
+                E::CompoundAssign {
+                    target: other_target,
+                    op: other_op,
+                    ..
+                },
+            ) => target == other_target && op == other_op,
+            (E::DestructureAssign { targets: a, .. }, E::DestructureAssign { targets: b, .. }) => {
+                a == b
+            }
+            (
+                E::UnaryAssign { target, op },
+                E::UnaryAssign {
+                    target: other_target,
+                    op: other_op,
+                },
+            ) => target == other_target && op == other_op,
+            (E::Swap(a, b), E::Swap(c, d)) => a == c && b == d,
+            (E::TypeAssert { expected: a, .. }, E::TypeAssert { expected: b, .. }) => a == b,
+            (E::Match { arms: a, .. }, E::Match { arms: b, .. }) => {
+                a.len() == b.len() && a.iter().zip(b).all(|((a, _), (b, _))| a == b)
+            }
+            (E::Spanned(a, _), E::Spanned(b, _)) => a == b,
+            // Everything else has no leaves, so only the kind matters
+            _ => discriminant(self) == discriminant(other),
+        }
+    }
+
+    /// Hash the kind and leaves of this expression, but not its children
+    fn hash_node<H: Hasher>(&self, state: &mut H)
+    where
+        TS::Value: Hash,
+        TS::UnaryOp: Hash,
+        TS::BinaryOp: Hash,
+        TS::TernaryOp: Hash,
+        TS::LazyBinaryOp: Hash,
+        TS::Init: Hash,
+        TS::TypeId: Hash,
+        TS::FieldId: Hash,
+    {
+        discriminant(self).hash(state);
+        match self {
+            Expression::RawValue(value) => value.hash(state),
+            Expression::PooledValue(addr)
+            | Expression::AssignStack(addr, _)
+            | Expression::AssignGlobal(addr, _)
+            | Expression::AssignStackAndYield(addr, _)
+            | Expression::AssignGlobalAndYield(addr, _)
+            | Expression::AssignCaptured(addr, _)
+            | Expression::ReturnTarget(addr, _)
+            | Expression::Return(addr, _)
+            | Expression::Loop { target: addr, .. }
+            | Expression::Continue(addr)
+            | Expression::For { binding: addr, .. }
+            | Expression::TryCatch {
+                error_slot: addr, ..
+            } => addr.hash(state),
+            Expression::Variable(var) => var.hash(state),
+            Expression::BinaryOpEval(op, _) => op.hash(state),
+            Expression::UnaryOpEval(op, _) => op.hash(state),
+            Expression::TernaryOpEval(op, _) => op.hash(state),
+            Expression::LazyBinaryOpEval(op, _) => op.hash(state),
+            Expression::Initialize(init, _) => init.hash(state),
+            Expression::InitializeNamed(init, fields) => {
+                init.hash(state);
+                state.write_usize(fields.len());
+                fields.iter().for_each(|(field, _)| field.hash(state));
+            }
+            Expression::StaticFunctionCallNamed {
+                func,
+                positional,
+                named,
+            } => {
+                func.hash(state);
+                state.write_usize(positional.len());
+                state.write_usize(named.len());
+                named.iter().for_each(|(name, _)| name.hash(state));
+            }
+            Expression::StaticFunctionCall(func, _)
+            | Expression::TailCall(func, _)
+            | Expression::FunctionCapture(func) => func.hash(state),
+            Expression::NativeFunctionCall(func, _) => func.hash(state),
+            Expression::NativeMacroCall(func, _) => func.hash(state),
+            Expression::CompoundAssign { target, op, .. } => {
+                target.hash(state);
+                op.hash(state);
+            }
+            Expression::UnaryAssign { target, op } => {
+                target.hash(state);
+                op.hash(state);
+            }
+            Expression::DestructureAssign { targets, .. } => targets.hash(state),
+            Expression::Swap(a, b) => {
+                a.hash(state);
+                b.hash(state);
+            }
+            Expression::TypeAssert { expected, .. } => expected.hash(state),
+            Expression::Match { arms, .. } => {
+                state.write_usize(arms.len());
+                arms.iter().for_each(|(constant, _)| constant.hash(state));
+            }
+            Expression::Spanned(span, _) => span.hash(state),
+            Expression::Spread(_)
+            | Expression::DynamicFunctionCall(..)
+            | Expression::Index { .. }
+            | Expression::IndexAssign { .. }
+            | Expression::AssignDynamic(_)
+            | Expression::AssignDynamicAndYield(_)
+            | Expression::Conditional { .. }
+            | Expression::While { .. }
+            | Expression::And(_)
+            | Expression::Or(_)
+            | Expression::Coalesce(_)
+            | Expression::Sequence(_)
+            | Expression::Lazy(_)
+            | Expression::Force(_)
+            | Expression::Yield(_) => {}
+        }
+    }
+}
+
+// Native functions and macros are compared by address. The same function may have several
+// addresses, so this can report distinct functions where there's one, but never the reverse.
+
+impl<TS: TypeSystem> PartialEq for NativeFunction<TS> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 as usize == other.0 as usize
+    }
+}
+
+impl<TS: TypeSystem> Eq for NativeFunction<TS> {}
+
+impl<TS: TypeSystem> Hash for NativeFunction<TS> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.0 as usize).hash(state);
+    }
+}
+
+impl<TS: TypeSystem> PartialEq for NativeMacro<TS> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 as usize == other.0 as usize
+    }
+}
+
+impl<TS: TypeSystem> Eq for NativeMacro<TS> {}
+
+impl<TS: TypeSystem> Hash for NativeMacro<TS> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self.0 as usize).hash(state);
+    }
+}
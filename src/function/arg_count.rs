@@ -1,13 +1,21 @@
-use std::ops::{Bound, RangeBounds};
+use std::{
+    fmt::Display,
+    ops::{Bound, RangeBounds},
+};
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+/// How many arguments a function accepts. Arguments are bound to the first stack slots of
+/// the function's frame, up to [ArgCount::max_capped] of them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ArgCount {
     Fixed(usize),
     Range {
         min: usize,
         max: usize,
     },
-    #[cfg(feature = "variadic_functions")]
+    /// At least `min` arguments, of which the first `max` are bound to their own slots and
+    /// the rest are collected into a list in the slot after them. Calling these requires
+    /// the `variadic_functions` feature.
     Variadic {
         min: usize,
         max: usize,
@@ -15,109 +23,115 @@ pub enum ArgCount {
 }
 
 impl ArgCount {
-    #[cfg(not(feature = "variadic_functions"))]
-    pub fn new<RB: RangeBounds<usize>>(args: RB) -> ArgCount {
-        let min = match args.start_bound() {
-            Bound::Included(m) => Some(*m),
-            Bound::Excluded(m) => Some(m + 1),
-            Bound::Unbounded => None,
-        }
-        .unwrap_or(0);
+    /// Exactly `n` arguments
+    pub fn exact(n: usize) -> ArgCount {
+        ArgCount::Fixed(n)
+    }
 
-        let max = match args.end_bound() {
-            Bound::Included(m) => Some(*m),
-            Bound::Excluded(m) => Some(m - 1),
-            Bound::Unbounded => None,
-        }
-        .unwrap_or(0);
+    /// Between `min` and `max` arguments inclusive, which is [ArgCount::exact] when
+    /// they're equal
+    pub fn range(min: usize, max: usize) -> ArgCount {
         if min == max {
             ArgCount::Fixed(min)
         } else {
             ArgCount::Range { min, max }
         }
     }
-    #[cfg(feature = "variadic_functions")]
-    pub fn new<RB: RangeBounds<usize>>(args: RB) -> ArgCount {
-        let min = match args.start_bound() {
-            Bound::Included(m) => Some(*m),
-            Bound::Excluded(m) => Some(m + 1),
-            Bound::Unbounded => None,
-        }
-        .unwrap_or(0);
 
+    /// `n` or more arguments, the first `n` bound to their own slots and the rest
+    /// collected into a list
+    pub fn at_least(n: usize) -> ArgCount {
+        ArgCount::Variadic { min: n, max: n }
+    }
+
+    fn bounds<RB: RangeBounds<usize>>(args: RB) -> (usize, Option<usize>) {
+        let min = match args.start_bound() {
+            Bound::Included(m) => *m,
+            Bound::Excluded(m) => m + 1,
+            Bound::Unbounded => 0,
+        };
         let max = match args.end_bound() {
             Bound::Included(m) => Some(*m),
             Bound::Excluded(m) => Some(m - 1),
             Bound::Unbounded => None,
         };
-        match max {
-            Some(max) => ArgCount::Range { min, max },
-            None => ArgCount::Variadic { min, max: min },
-        }
+        (min, max)
     }
 
-    #[cfg(feature = "variadic_functions")]
-    pub fn new_variadic<RB: RangeBounds<usize>>(args: RB) -> ArgCount {
-        let min = match args.start_bound() {
-            Bound::Included(m) => Some(*m),
-            Bound::Excluded(m) => Some(m + 1),
-            Bound::Unbounded => None,
+    /// The argument count for a range like `1..=3`, or `1..` for [ArgCount::at_least]
+    pub fn new<RB: RangeBounds<usize>>(args: RB) -> ArgCount {
+        match Self::bounds(args) {
+            (min, Some(max)) => ArgCount::range(min, max),
+            (min, None) => ArgCount::at_least(min),
         }
-        .unwrap_or(0);
+    }
 
-        let max = match args.end_bound() {
-            Bound::Included(m) => Some(*m),
-            Bound::Excluded(m) => Some(m - 1),
-            Bound::Unbounded => None,
-        };
-        match max {
-            Some(max) => ArgCount::Variadic { min, max },
-            None => ArgCount::Variadic { min, max: min },
+    /// Like [ArgCount::new], but always variadic, with a bounded range giving how many of
+    /// the arguments are bound to their own slots
+    pub fn new_variadic<RB: RangeBounds<usize>>(args: RB) -> ArgCount {
+        match Self::bounds(args) {
+            (min, Some(max)) => ArgCount::Variadic { min, max },
+            (min, None) => ArgCount::Variadic { min, max: min },
         }
     }
 
+    /// The fewest arguments accepted
     pub fn min(&self) -> usize {
         match self {
             ArgCount::Range { min, max: _ } => *min,
             ArgCount::Fixed(f) => *f,
-            #[cfg(feature = "variadic_functions")]
             ArgCount::Variadic { min, max: _ } => *min,
         }
     }
 
+    /// The most arguments accepted, or `None` if there's no limit
     pub fn max(&self) -> Option<usize> {
         match self {
             ArgCount::Range { min: _, max } => Some(*max),
             ArgCount::Fixed(f) => Some(*f),
-            #[cfg(feature = "variadic_functions")]
             ArgCount::Variadic { min: _, max: _ } => None,
         }
     }
 
+    /// How many arguments are bound to their own slots, which is all of them except those
+    /// collected into the list of a variadic function
     pub fn max_capped(&self) -> usize {
         match self {
             ArgCount::Range { min: _, max } => *max,
             ArgCount::Fixed(f) => *f,
-            #[cfg(feature = "variadic_functions")]
             ArgCount::Variadic { min: _, max } => *max,
         }
     }
 
-    pub fn valid_arg_count(&self, val: usize) -> bool {
+    /// Whether `n` arguments are accepted
+    pub fn contains(&self, n: usize) -> bool {
         match self {
-            ArgCount::Range { min, max } => val >= *min && val <= *max,
-            ArgCount::Fixed(f) => val == *f,
-            #[cfg(feature = "variadic_functions")]
-            ArgCount::Variadic { min, max: _ } => val >= *min,
+            ArgCount::Range { min, max } => n >= *min && n <= *max,
+            ArgCount::Fixed(f) => n == *f,
+            ArgCount::Variadic { min, max: _ } => n >= *min,
         }
     }
 
+    /// The number of stack slots taken by the arguments
     pub fn stack_size(&self) -> usize {
         match self {
             ArgCount::Fixed(f) => *f,
             ArgCount::Range { min: _, max } => *max,
-            #[cfg(feature = "variadic_functions")]
             ArgCount::Variadic { min: _, max } => max + 1,
         }
     }
 }
+
+/// Describes the accepted counts like "exactly 1 argument" or "2 to 4 arguments"
+impl Display for ArgCount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let plural = |n: usize| if n == 1 { "argument" } else { "arguments" };
+        match (self.min(), self.max()) {
+            (0, None) => f.write_str("any number of arguments"),
+            (min, None) => write!(f, "at least {min} {}", plural(min)),
+            (min, Some(max)) if min == max => write!(f, "exactly {min} {}", plural(min)),
+            (0, Some(max)) => write!(f, "at most {max} {}", plural(max)),
+            (min, Some(max)) => write!(f, "{min} to {max} arguments"),
+        }
+    }
+}
@@ -1,7 +1,13 @@
+use std::{
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
+
 use super::{arg_count::ArgCount, FunctionType};
-use crate::{expression::NativeFunction, TypeSystem};
+use crate::{error::FreightError, expression::NativeFunction, sync::Shared, TypeSystem};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StackLayout(u128);
 
 impl StackLayout {
@@ -28,12 +34,18 @@ impl StackLayout {
 
 /// Represents a reference to a function that has been included in a VM
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "TS: crate::SerializableTypeSystem")
+)]
 pub struct FunctionRef<TS: TypeSystem> {
     pub(crate) arg_count: ArgCount,
     pub(crate) stack_size: usize,
     pub(crate) location: usize,
     pub function_type: FunctionType<TS>,
     pub layout: StackLayout,
+    pub(crate) name: Option<Shared<str>>,
 }
 
 impl<TS: TypeSystem> PartialEq for FunctionRef<TS> {
@@ -41,11 +53,28 @@ impl<TS: TypeSystem> PartialEq for FunctionRef<TS> {
         match (&self.function_type, &other.function_type) {
             (FunctionType::Native(_), FunctionType::Native(_)) => self.location == other.location,
             (FunctionType::Native(_), _) | (_, FunctionType::Native(_)) => false,
+            (
+                FunctionType::Bound { inner, args },
+                FunctionType::Bound {
+                    inner: other_inner,
+                    args: other_args,
+                },
+            ) => inner == other_inner && Shared::ptr_eq(args, other_args),
+            (FunctionType::Bound { .. }, _) | (_, FunctionType::Bound { .. }) => false,
             _ => self.location == other.location,
         }
     }
 }
 
+impl<TS: TypeSystem> Eq for FunctionRef<TS> {}
+
+impl<TS: TypeSystem> Hash for FunctionRef<TS> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        matches!(self.function_type, FunctionType::Native(_)).hash(state);
+        self.location.hash(state);
+    }
+}
+
 impl<TS: TypeSystem> FunctionRef<TS> {
     /// Create a new native function
     pub fn new_native(id: usize, func: NativeFunction<TS>, arg_count: ArgCount) -> Self {
@@ -55,7 +84,47 @@ impl<TS: TypeSystem> FunctionRef<TS> {
             stack_size: arg_count.stack_size(),
             function_type: FunctionType::Native(func),
             layout: StackLayout::no_alloc(),
+            name: None,
+        }
+    }
+
+    /// A reference calling this function with `args` in front of the arguments it's called
+    /// with, so it takes that many fewer. Each call gets its own
+    /// [unaliased](crate::value::Value::clone_unaliased) copy of the bound values. Fails if
+    /// the function can't take that many arguments, or is a capturing definition.
+    pub fn bind(&self, args: Vec<TS::Value>) -> Result<FunctionRef<TS>, FreightError> {
+        if let FunctionType::CapturingDef(..) = self.function_type {
+            return Err(FreightError::InvalidInvocationTarget);
         }
+        let bound = args.len();
+        if let Some(max) = self.arg_count.max().filter(|max| bound > *max) {
+            return Err(FreightError::TooManyBoundArguments {
+                function: self.to_string(),
+                bound,
+                max,
+            });
+        }
+        let arg_count = match self.arg_count {
+            ArgCount::Variadic { min, max } => ArgCount::Variadic {
+                min: min.saturating_sub(bound),
+                max: max.saturating_sub(bound),
+            },
+            count => ArgCount::range(
+                count.min().saturating_sub(bound),
+                count.max_capped() - bound,
+            ),
+        };
+        Ok(FunctionRef {
+            arg_count,
+            stack_size: self.stack_size,
+            location: self.location,
+            function_type: FunctionType::Bound {
+                inner: Shared::new(self.clone()),
+                args: args.into(),
+            },
+            layout: self.layout.clone(),
+            name: self.name.clone(),
+        })
     }
 
     /// The number of arguments the function takes
@@ -72,4 +141,20 @@ impl<TS: TypeSystem> FunctionRef<TS> {
     pub fn address(&self) -> usize {
         self.location
     }
+
+    /// The name the function was given with
+    /// [FunctionWriter::set_name](super::FunctionWriter::set_name), if any
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+/// Shows the function's name, or `#` and its location if it doesn't have one
+impl<TS: TypeSystem> Display for FunctionRef<TS> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => f.write_str(name),
+            None => write!(f, "#{}", self.location),
+        }
+    }
 }
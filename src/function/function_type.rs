@@ -1,17 +1,58 @@
 use crate::expression::{NativeFunction, VariableType};
 use crate::slice_pool::PooledRcSlice;
+use crate::sync::Shared;
 use crate::TypeSystem;
 use std::fmt::Debug;
-use std::rc::Rc;
+
+/// Which values a capturing function copies when it's created rather than aliasing.
+/// By-value captures are copied with [Value::clone_unaliased](crate::value::Value::clone_unaliased),
+/// so closures created in a loop each see the value the loop variable had at the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CaptureLayout(u128);
+
+impl CaptureLayout {
+    pub fn all_by_ref() -> CaptureLayout {
+        CaptureLayout(0)
+    }
+
+    pub fn all_by_value() -> CaptureLayout {
+        CaptureLayout(u128::MAX)
+    }
+
+    pub fn set_by_value(&mut self, capture: usize) {
+        self.0 |= 1 << capture;
+    }
+
+    pub fn set_by_ref(&mut self, capture: usize) {
+        self.0 &= !(1 << capture);
+    }
+
+    pub fn is_by_value(&self, capture: usize) -> bool {
+        (self.0 & (1 << capture)) != 0
+    }
+}
 
 #[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound = "TS: crate::SerializableTypeSystem")
+)]
 pub enum FunctionType<TS: TypeSystem> {
     /// Static reference to a function, which can't capture any values.
     Static,
     /// Reference to a function which captures values, but hasn't been initialized with those values.
-    CapturingDef(Rc<[VariableType]>),
-    /// Reference to a function which captures values bundled with those captured values
+    CapturingDef(Shared<[VariableType]>, CaptureLayout),
+    /// Reference to a function which captures values bundled with those captured values,
+    /// which alias the captured variables when they hold references
     CapturingRef(PooledRcSlice<TS::Value>),
-    /// Reference to a native function
+    /// Reference to a native function, which can't be serialized
     Native(NativeFunction<TS>),
+    /// Reference to another function with its first arguments already given, see
+    /// [FunctionRef::bind](super::FunctionRef::bind)
+    Bound {
+        inner: Shared<super::FunctionRef<TS>>,
+        args: Shared<[TS::Value]>,
+    },
 }
@@ -1,6 +1,11 @@
 use super::arg_count::ArgCount;
-use super::{Function, FunctionRef, FunctionType, StackLayout};
-use crate::expression::VariableType;
+use super::validation::{validate_addresses, validate_captures};
+use super::{CaptureLayout, Function, FunctionRef, FunctionType, StackLayout};
+use crate::error::FreightError;
+#[cfg(feature = "variadic_functions")]
+use crate::expression::NativeMacro;
+use crate::expression::{DebugInfo, VariableType};
+use crate::sync::Shared;
 use crate::{expression::Expression, TypeSystem};
 use std::fmt::Debug;
 
@@ -10,6 +15,17 @@ pub struct FunctionWriter<TS: TypeSystem> {
     pub(crate) args: ArgCount,
     pub(crate) expressions: Vec<Expression<TS>>,
     pub(crate) function_type: FunctionType<TS>,
+    pub(crate) validate: bool,
+    /// Whether the body may only use variables from [FunctionWriter::create_variable]
+    pub(crate) allocated_only: bool,
+    /// Whether captures were added with [FunctionWriter::capture]
+    pub(crate) resolved_captures: bool,
+    pub(crate) name: Option<Shared<str>>,
+    pub(crate) source: Option<DebugInfo>,
+    /// The default of each optional argument, by index
+    pub(crate) defaults: Vec<Option<Expression<TS>>>,
+    /// The names of the first arguments, by index
+    pub(crate) arg_names: Vec<TS::FieldId>,
     pub layout: StackLayout,
 }
 
@@ -20,6 +36,13 @@ impl<TS: TypeSystem> FunctionWriter<TS> {
             variable_count: 0,
             expressions: vec![],
             function_type: FunctionType::Static,
+            validate: true,
+            allocated_only: false,
+            resolved_captures: false,
+            name: None,
+            source: None,
+            defaults: Vec::new(),
+            arg_names: Vec::new(),
             layout: StackLayout::all_alloc(),
         }
     }
@@ -32,11 +55,34 @@ impl<TS: TypeSystem> FunctionWriter<TS> {
             args,
             variable_count: 0,
             expressions: vec![],
-            function_type: FunctionType::CapturingDef(capture.into()),
+            function_type: FunctionType::CapturingDef(capture.into(), CaptureLayout::all_by_ref()),
+            validate: true,
+            allocated_only: false,
+            resolved_captures: false,
+            name: None,
+            source: None,
+            defaults: Vec::new(),
+            arg_names: Vec::new(),
             layout: StackLayout::all_alloc(),
         }
     }
 
+    /// A function of two arguments applying an operator to them element-wise with
+    /// [ExecutionEngine::map_binary](crate::execution_engine::ExecutionEngine::map_binary),
+    /// for hosts to register under a name of their choosing
+    #[cfg(feature = "variadic_functions")]
+    pub fn map_binary(op: TS::BinaryOp) -> FunctionWriter<TS> {
+        let mut func = FunctionWriter::new(ArgCount::Fixed(2));
+        func.evaluate_expression(Expression::NativeMacroCall(
+            NativeMacro::map_binary(),
+            vec![Expression::BinaryOpEval(
+                op,
+                [Expression::stack(0), Expression::stack(1)].into(),
+            )],
+        ));
+        func
+    }
+
     pub fn to_ref(&self, location: usize) -> FunctionRef<TS> {
         FunctionRef {
             arg_count: self.args,
@@ -44,12 +90,95 @@ impl<TS: TypeSystem> FunctionWriter<TS> {
             location,
             function_type: self.function_type.clone(),
             layout: self.layout.clone(),
+            name: self.name.clone(),
         }
     }
 
+    /// Name the function in diagnostics, profiles and pretty-printed trees, which
+    /// otherwise show it as `#` and its location
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        self.name = Some(Shared::from(name.into()));
+    }
+
+    /// Record where the function is defined in the source, see
+    /// [ExecutionEngine::function_source](crate::execution_engine::ExecutionEngine::function_source)
+    pub fn set_source(&mut self, source: DebugInfo) {
+        self.source = Some(source);
+    }
+
     /// Convert this into a capturing function which will capture the specified values from its environment
     pub fn set_captures(&mut self, capture: Vec<VariableType>) {
-        self.function_type = FunctionType::CapturingDef(capture.into());
+        self.function_type =
+            FunctionType::CapturingDef(capture.into(), CaptureLayout::all_by_ref());
+    }
+
+    /// Capture a variable of the frame creating this function, making it a capturing
+    /// function if it isn't already, and return the address to read it with
+    /// [VariableType::Captured]. Capturing the same variable again returns the same address.
+    /// Captured addresses are then checked when the function is built with
+    /// [FunctionWriter::try_build] and when registering, even with validation disabled.
+    pub fn capture(&mut self, outer: VariableType) -> usize {
+        self.resolved_captures = true;
+        let FunctionType::CapturingDef(captures, _) = &mut self.function_type else {
+            self.function_type =
+                FunctionType::CapturingDef([outer].into(), CaptureLayout::all_by_ref());
+            return 0;
+        };
+        if let Some(existing) = captures.iter().position(|var| *var == outer) {
+            return existing;
+        }
+        let mut extended = captures.to_vec();
+        extended.push(outer);
+        *captures = extended.into();
+        captures.len() - 1
+    }
+
+    /// How many variables the function captures
+    pub fn capture_count(&self) -> usize {
+        match &self.function_type {
+            FunctionType::CapturingDef(captures, _) => captures.len(),
+            _ => 0,
+        }
+    }
+
+    /// Check that every captured address used by the body is one of the function's captures
+    pub fn check_captures(&self) -> Result<(), FreightError> {
+        let capture_count = self.capture_count();
+        validate_captures(&self.expressions, capture_count)?;
+        for default in self.defaults.iter().flatten() {
+            validate_captures(std::slice::from_ref(default), capture_count)?;
+        }
+        Ok(())
+    }
+
+    /// Choose which captures are copied by value rather than aliased, see [CaptureLayout].
+    /// Has no effect on functions which don't capture.
+    pub fn set_capture_layout(&mut self, layout: CaptureLayout) {
+        if let FunctionType::CapturingDef(_, current) = &mut self.function_type {
+            *current = layout;
+        }
+    }
+
+    /// Give an optional argument an expression to evaluate when a caller leaves it out,
+    /// rather than it starting uninitialized. Defaults are evaluated in the callee's frame
+    /// after the arguments which were passed, in order, so they can read earlier arguments.
+    pub fn set_default(&mut self, arg: usize, default: Expression<TS>) -> Result<(), FreightError> {
+        let (min, max) = (self.args.min(), self.args.max_capped());
+        if arg < min || arg >= max {
+            return Err(FreightError::NotAnOptionalArgument { arg, min, max });
+        }
+        if self.defaults.len() <= arg {
+            self.defaults.resize_with(arg + 1, || None);
+        }
+        self.defaults[arg] = Some(default);
+        Ok(())
+    }
+
+    /// Name the first arguments, in order, so callers can pass them by name with
+    /// [Expression::StaticFunctionCallNamed]. Names past the positional arguments are
+    /// never matched.
+    pub fn set_arg_names(&mut self, names: Vec<TS::FieldId>) {
+        self.arg_names = names;
     }
 
     /// Create a new variable in the scope of this function and return its address
@@ -59,16 +188,111 @@ impl<TS: TypeSystem> FunctionWriter<TS> {
         var
     }
 
+    /// How many variables the frame has besides the arguments
+    pub fn variable_count(&self) -> usize {
+        self.variable_count
+    }
+
+    /// Derive the variable count from [FunctionWriter::create_variable] alone, so the
+    /// frame has exactly the variables created. Using any other stack address past the
+    /// arguments is then an error from [FunctionWriter::try_build] and when registering,
+    /// even with validation disabled.
+    pub fn allocate_variables(&mut self) {
+        self.allocated_only = true;
+    }
+
+    /// Check that every stack address used by the body is an argument or a created
+    /// variable, listing those which aren't in [FreightError::UnallocatedVariables], and
+    /// return the stack size
+    pub fn check_variables(&self) -> Result<usize, FreightError> {
+        let stack_size = self.args.stack_size() + self.variable_count;
+        let mut unallocated = Vec::new();
+        for expr in self
+            .expressions
+            .iter()
+            .chain(self.defaults.iter().flatten())
+        {
+            expr.visit_stack_slots(|slot| {
+                if slot >= stack_size {
+                    unallocated.push(slot);
+                }
+            });
+        }
+        if unallocated.is_empty() {
+            return Ok(stack_size);
+        }
+        unallocated.sort_unstable();
+        unallocated.dedup();
+        Err(FreightError::UnallocatedVariables {
+            addresses: unallocated,
+            stack_size,
+        })
+    }
+
+    /// Size the frame to fit exactly the stack addresses used by the body, replacing the
+    /// count of variables created so far, and return the resulting stack size
+    pub fn infer_layout(&mut self) -> usize {
+        let used = self
+            .expressions
+            .iter()
+            .chain(self.defaults.iter().flatten())
+            .filter_map(Expression::max_stack_slot)
+            .max()
+            .map_or(0, |slot| slot + 1);
+        self.variable_count = used.saturating_sub(self.args.stack_size());
+        self.args.stack_size() + self.variable_count
+    }
+
+    /// Skip checking stack and captured addresses when this function is registered,
+    /// for bodies constructed dynamically whose addresses are guaranteed some other way
+    pub fn disable_validation(&mut self) {
+        self.validate = false;
+    }
+
+    /// Check that every stack and captured address in the body fits within this function's frame
+    pub fn validate(&self) -> Result<(), FreightError> {
+        let capture_count = self.capture_count();
+        let stack_size = self.args.stack_size() + self.variable_count;
+        validate_addresses(&self.expressions, stack_size, capture_count)?;
+        for default in self.defaults.iter().flatten() {
+            validate_addresses(std::slice::from_ref(default), stack_size, capture_count)?;
+        }
+        Ok(())
+    }
+
     /// Add an expression to be evaluated when this function is called
     pub fn evaluate_expression(&mut self, expr: Expression<TS>) {
         self.expressions.push(expr);
     }
 
+    /// Create a function from this writer, first checking its variables if
+    /// [FunctionWriter::allocate_variables] was called and its captured addresses if
+    /// [FunctionWriter::capture] was
+    pub fn try_build(self, return_target: usize) -> Result<Function<TS>, FreightError> {
+        if self.allocated_only {
+            self.check_variables()?;
+        }
+        if self.resolved_captures {
+            self.check_captures()?;
+        }
+        Ok(self.build(return_target))
+    }
+
     /// Create a function from this writer
     pub fn build(self, return_target: usize) -> Function<TS> {
         Function {
+            stack_size: self.args.stack_size() + self.variable_count,
+            arg_count: self.args,
             expressions: self.expressions,
             return_target,
+            function_type: self.function_type,
+            layout: self.layout,
+            name: self.name,
+            source: self.source,
+            defaults: self.defaults,
+            arg_names: self.arg_names,
+            reserved: false,
+            eliminated_tail_calls: false,
         }
     }
 }
@@ -0,0 +1,28 @@
+use super::ArgCount;
+use crate::expression::DebugInfo;
+
+/// What a function reference looks like from the outside, see
+/// [ExecutionEngine::describe_function](crate::execution_engine::ExecutionEngine::describe_function)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionInfo {
+    /// The arguments the reference takes, after those already given to
+    /// [FunctionRef::bind](super::FunctionRef::bind)
+    pub arg_count: ArgCount,
+    /// Whether extra arguments are collected into a list
+    pub variadic: bool,
+    /// How many values the function captures, or will capture once it's created if the
+    /// reference is a capturing definition
+    pub captures: usize,
+    /// How many arguments were given to [FunctionRef::bind](super::FunctionRef::bind)
+    pub bound: usize,
+    /// The stack slots used by a call, including the arguments
+    pub stack_size: usize,
+    /// The address of the function in the function table, or the id of a native
+    pub location: usize,
+    /// Whether the reference is to a native function
+    pub native: bool,
+    /// The name the function was given, if any
+    pub name: Option<String>,
+    /// Where the function is defined, if its writer was given a source
+    pub source: Option<DebugInfo>,
+}
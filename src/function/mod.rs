@@ -1,7 +1,8 @@
 use crate::{
     error::{FreightError, OrReturn},
     execution_engine::ExecutionEngine,
-    expression::Expression,
+    expression::{DebugInfo, Expression},
+    sync::Shared,
     TypeSystem,
 };
 use std::fmt::Debug;
@@ -10,19 +11,69 @@ mod arg_count;
 mod function_ref;
 mod function_type;
 mod function_writer;
+mod info;
+mod validation;
 
 pub use arg_count::*;
 pub use function_ref::*;
 pub use function_type::*;
 pub use function_writer::*;
+pub use info::*;
+pub(crate) use validation::{validate_addresses, validate_arena_addresses, validate_function};
 
 #[derive(Debug)]
 pub struct Function<TS: TypeSystem> {
     pub(crate) expressions: Vec<Expression<TS>>,
     pub(crate) return_target: usize,
+    pub(crate) arg_count: ArgCount,
+    pub(crate) stack_size: usize,
+    pub(crate) function_type: FunctionType<TS>,
+    pub(crate) layout: StackLayout,
+    /// The name given with [FunctionWriter::set_name]
+    pub(crate) name: Option<Shared<str>>,
+    /// Where the function is defined, given with [FunctionWriter::set_source]
+    pub(crate) source: Option<DebugInfo>,
+    /// The defaults of optional arguments, see [FunctionWriter::set_default]
+    pub(crate) defaults: Vec<Option<Expression<TS>>>,
+    /// The names of the first arguments, see [FunctionWriter::set_arg_names]
+    pub(crate) arg_names: Vec<TS::FieldId>,
+    /// Whether this is a placeholder from [ExecutionEngine::reserve_function] whose body
+    /// hasn't been filled yet
+    pub(crate) reserved: bool,
+    /// Whether static calls in tail position were turned into tail calls, see
+    /// [ExecutionEngine::set_tail_call_elimination]
+    pub(crate) eliminated_tail_calls: bool,
 }
 
 impl<TS: TypeSystem> Function<TS> {
+    /// Create a reference to this function, given its address in the function table
+    pub fn to_ref(&self, location: usize) -> FunctionRef<TS> {
+        FunctionRef {
+            arg_count: self.arg_count,
+            stack_size: self.stack_size,
+            location,
+            function_type: self.function_type.clone(),
+            layout: self.layout.clone(),
+            name: self.name.clone(),
+        }
+    }
+
+    /// The name given with [FunctionWriter::set_name], if any
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Where the function is defined, if given with [FunctionWriter::set_source]
+    pub fn source(&self) -> Option<&DebugInfo> {
+        self.source.as_ref()
+    }
+
+    /// Whether this is a placeholder from [ExecutionEngine::reserve_function] still
+    /// waiting for [ExecutionEngine::fill_function]
+    pub fn is_reserved(&self) -> bool {
+        self.reserved
+    }
+
     pub fn call(
         &self,
         engine: &mut ExecutionEngine<TS>,
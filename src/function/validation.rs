@@ -0,0 +1,338 @@
+use crate::{
+    error::{AddressKind, FreightError},
+    expression::{Expression, ExpressionArena, VariableType},
+    function::{Function, FunctionRef, FunctionType},
+    value::Value,
+    TypeSystem,
+};
+
+/// Check that every stack and captured address used by a function body fits in its frame
+pub(crate) fn validate_addresses<TS: TypeSystem>(
+    expressions: &[Expression<TS>],
+    stack_size: usize,
+    capture_count: usize,
+) -> Result<(), FreightError> {
+    let frame = Frame {
+        stack_size,
+        capture_count,
+        engine: None,
+    };
+    frame.validate_all(expressions)
+}
+
+/// Check that every captured address used by a function body is one of its captures
+pub(crate) fn validate_captures<TS: TypeSystem>(
+    expressions: &[Expression<TS>],
+    capture_count: usize,
+) -> Result<(), FreightError> {
+    validate_addresses(expressions, usize::MAX, capture_count)
+}
+
+/// Check that every stack and captured address used in an arena fits in a frame
+pub(crate) fn validate_arena_addresses<TS: TypeSystem>(
+    arena: &ExpressionArena<TS>,
+    stack_size: usize,
+    capture_count: usize,
+) -> Result<(), FreightError> {
+    let frame = Frame {
+        stack_size,
+        capture_count,
+        engine: None,
+    };
+    let mut result = Ok(());
+    arena.for_each_variable(|var| {
+        if result.is_ok() {
+            result = frame.validate_variable(&var);
+        }
+    });
+    result
+}
+
+/// Check every address used by a registered function, including globals and the
+/// locations of functions it references, which may not exist until after registration,
+/// and that every return in it can be handled
+pub(crate) fn validate_function<TS: TypeSystem>(
+    func: &Function<TS>,
+    location: usize,
+    num_globals: usize,
+    num_functions: usize,
+    num_constants: usize,
+) -> Result<(), FreightError> {
+    let capture_count = match &func.function_type {
+        FunctionType::CapturingDef(captures, _) => captures.len(),
+        _ => 0,
+    };
+    let frame = Frame {
+        stack_size: func.stack_size,
+        capture_count,
+        engine: Some(EngineBounds {
+            location,
+            num_globals,
+            num_functions,
+            num_constants,
+        }),
+    };
+    frame.validate_all(&func.expressions)?;
+    for default in func.defaults.iter().flatten() {
+        frame.validate_all(std::slice::from_ref(default))?;
+    }
+    validate_return_targets(func, location)
+}
+
+/// Check that every `Return` targets the function it's in or an enclosing `ReturnTarget`
+/// or `Loop`, and every `Continue` targets an enclosing `Loop`. A `Return` in the body of
+/// a `Lazy` can never be handled, so nothing is in scope there.
+fn validate_return_targets<TS: TypeSystem>(
+    func: &Function<TS>,
+    location: usize,
+) -> Result<(), FreightError> {
+    // The targets in scope and whether they're loops, innermost last,
+    // where `None` hides the targets before it
+    let mut targets = vec![Some((func.return_target, false))];
+    let mut pending: Vec<_> = func
+        .expressions
+        .iter()
+        .rev()
+        .map(|expr| (expr, 1))
+        .collect();
+    while let Some((expr, scope)) = pending.pop() {
+        targets.truncate(scope);
+        match expr {
+            Expression::Return(target, _) if !in_scope(&targets, *target, false) => {
+                return Err(FreightError::ReturnTargetOutOfScope {
+                    function: location,
+                    target: *target,
+                });
+            }
+            Expression::Continue(target) if !in_scope(&targets, *target, true) => {
+                return Err(FreightError::ContinueOutOfScope {
+                    function: location,
+                    target: *target,
+                });
+            }
+            Expression::ReturnTarget(target, _) => targets.push(Some((*target, false))),
+            Expression::Loop { target, .. } => targets.push(Some((*target, true))),
+            Expression::Lazy(_) => targets.push(None),
+            _ => {}
+        }
+        let len = pending.len();
+        let scope = targets.len();
+        expr.for_each_child(|child| pending.push((child, scope)));
+        pending[len..].reverse();
+    }
+    Ok(())
+}
+
+fn in_scope(targets: &[Option<(usize, bool)>], target: usize, loops_only: bool) -> bool {
+    targets
+        .iter()
+        .rev()
+        .map_while(|t| *t)
+        .any(|(t, is_loop)| t == target && (is_loop || !loops_only))
+}
+
+/// Check that every spread directly under an expression is one of its call arguments
+fn validate_spreads<TS: TypeSystem>(expr: &Expression<TS>) -> Result<(), FreightError> {
+    let mut spreads = 0;
+    expr.for_each_child(|child| spreads += child.is_spread() as usize);
+    let allowed = expr
+        .call_args()
+        .map_or(0, |args| args.iter().filter(|arg| arg.is_spread()).count());
+    if spreads > allowed {
+        return Err(FreightError::SpreadOutsideCall);
+    }
+    Ok(())
+}
+
+struct Frame {
+    stack_size: usize,
+    capture_count: usize,
+    engine: Option<EngineBounds>,
+}
+
+/// Bounds which are only known once a function is in the engine's function table
+struct EngineBounds {
+    location: usize,
+    num_globals: usize,
+    num_functions: usize,
+    num_constants: usize,
+}
+
+impl Frame {
+    /// The error for an address outside its bounds, attributed to the function being
+    /// validated when checking the whole engine
+    fn invalid(&self, kind: AddressKind, address: usize, size: usize) -> FreightError {
+        match (&self.engine, kind) {
+            (Some(engine), kind) => FreightError::InvalidAddress {
+                function: engine.location,
+                kind,
+                address,
+            },
+            (None, AddressKind::Stack) => FreightError::StackSlotOutOfRange {
+                slot: address,
+                size,
+            },
+            (None, AddressKind::Captured) => FreightError::CaptureSlotOutOfRange {
+                slot: address,
+                size,
+            },
+            (None, _) => {
+                unreachable!("Globals, functions and constants are only checked against an engine")
+            }
+        }
+    }
+
+    fn validate_variable(&self, var: &VariableType) -> Result<(), FreightError> {
+        match var {
+            VariableType::Stack(slot) => self.validate_stack(*slot),
+            VariableType::Captured(slot) => self.validate_captured(*slot),
+            VariableType::Global(addr) => self.validate_global(*addr),
+        }
+    }
+
+    fn validate_captured(&self, slot: usize) -> Result<(), FreightError> {
+        if slot >= self.capture_count {
+            return Err(self.invalid(AddressKind::Captured, slot, self.capture_count));
+        }
+        Ok(())
+    }
+
+    fn validate_stack(&self, slot: usize) -> Result<(), FreightError> {
+        if slot >= self.stack_size {
+            return Err(self.invalid(AddressKind::Stack, slot, self.stack_size));
+        }
+        Ok(())
+    }
+
+    fn validate_global(&self, addr: usize) -> Result<(), FreightError> {
+        match &self.engine {
+            Some(engine) if addr >= engine.num_globals => {
+                Err(self.invalid(AddressKind::Global, addr, engine.num_globals))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn validate_constant(&self, index: usize) -> Result<(), FreightError> {
+        match &self.engine {
+            Some(engine) if index >= engine.num_constants => {
+                Err(self.invalid(AddressKind::Constant, index, engine.num_constants))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn validate_function_ref<TS: TypeSystem>(
+        &self,
+        func: &FunctionRef<TS>,
+    ) -> Result<(), FreightError> {
+        match (&self.engine, &func.function_type) {
+            // Native functions aren't stored in the function table
+            (_, FunctionType::Native(_)) => Ok(()),
+            (_, FunctionType::Bound { inner, .. }) => self.validate_function_ref(inner),
+            (Some(engine), _) if func.location >= engine.num_functions => {
+                Err(self.invalid(AddressKind::Function, func.location, engine.num_functions))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check every expression and all of their subexpressions, using an explicit stack
+    /// so deeply nested expressions can't overflow the native stack
+    fn validate_all<TS: TypeSystem>(&self, exprs: &[Expression<TS>]) -> Result<(), FreightError> {
+        if exprs.iter().any(Expression::is_spread) {
+            return Err(FreightError::SpreadOutsideCall);
+        }
+        let mut pending: Vec<_> = exprs.iter().rev().collect();
+        while let Some(expr) = pending.pop() {
+            self.validate_expression(expr)?;
+            validate_spreads(expr)?;
+            let len = pending.len();
+            expr.for_each_child(|child| pending.push(child));
+            pending[len..].reverse();
+        }
+        Ok(())
+    }
+
+    fn validate_value<V: Value>(&self, value: &V) -> Result<(), FreightError> {
+        match value.cast_to_function() {
+            Some(func) => self.validate_function_ref(func),
+            None => Ok(()),
+        }
+    }
+
+    /// Check the addresses used directly by an expression, not including its subexpressions
+    fn validate_expression<TS: TypeSystem>(
+        &self,
+        expr: &Expression<TS>,
+    ) -> Result<(), FreightError> {
+        match expr {
+            Expression::RawValue(value) => self.validate_value(value),
+            Expression::PooledValue(index) => self.validate_constant(*index),
+            Expression::Variable(var) => self.validate_variable(var),
+            Expression::StaticFunctionCall(func, _)
+            | Expression::TailCall(func, _)
+            | Expression::StaticFunctionCallNamed { func, .. } => self.validate_function_ref(func),
+            Expression::FunctionCapture(func) => {
+                self.validate_function_ref(func)?;
+                match &func.function_type {
+                    FunctionType::CapturingDef(captures, _) => captures
+                        .iter()
+                        .try_for_each(|var| self.validate_variable(var)),
+                    _ => Ok(()),
+                }
+            }
+            Expression::AssignStack(slot, _) | Expression::AssignStackAndYield(slot, _) => {
+                self.validate_stack(*slot)
+            }
+            Expression::AssignGlobal(addr, _) | Expression::AssignGlobalAndYield(addr, _) => {
+                self.validate_global(*addr)
+            }
+            Expression::AssignCaptured(slot, _) => self.validate_captured(*slot),
+            Expression::CompoundAssign { target, .. } | Expression::UnaryAssign { target, .. } => {
+                self.validate_variable(target)
+            }
+            Expression::Swap(a, b) => {
+                self.validate_variable(a)?;
+                self.validate_variable(b)
+            }
+            Expression::DestructureAssign { targets, .. } => targets
+                .iter()
+                .try_for_each(|var| self.validate_variable(var)),
+            Expression::Match { arms, .. } => arms
+                .iter()
+                .try_for_each(|(constant, _)| self.validate_value(constant)),
+            Expression::TryCatch { error_slot, .. } => self.validate_stack(*error_slot),
+            Expression::For { binding, .. } => self.validate_stack(*binding),
+            Expression::BinaryOpEval(..)
+            | Expression::UnaryOpEval(..)
+            | Expression::TernaryOpEval(..)
+            | Expression::LazyBinaryOpEval(..)
+            | Expression::Initialize(..)
+            | Expression::InitializeNamed(..)
+            | Expression::NativeFunctionCall(..)
+            | Expression::NativeMacroCall(..)
+            | Expression::Index { .. }
+            | Expression::Coalesce(_)
+            | Expression::TypeAssert { .. }
+            | Expression::IndexAssign { .. }
+            | Expression::Sequence(_)
+            | Expression::DynamicFunctionCall(..)
+            | Expression::ReturnTarget(..)
+            | Expression::Return(..)
+            | Expression::AssignDynamic(_)
+            | Expression::AssignDynamicAndYield(_)
+            | Expression::And(_)
+            | Expression::Or(_)
+            | Expression::Conditional { .. }
+            | Expression::While { .. }
+            | Expression::Loop { .. }
+            | Expression::Continue(_)
+            | Expression::Lazy(_)
+            | Expression::Force(_)
+            | Expression::Yield(_)
+            | Expression::Spread(_)
+            | Expression::Spanned(..) => Ok(()),
+        }
+    }
+}
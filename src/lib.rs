@@ -1,14 +1,20 @@
-use operators::{BinaryOperator, Initializer, UnaryOperator};
+use error::FreightError;
+use operators::{BinaryOperator, Initializer, LazyBinaryOperator, TernaryOperator, UnaryOperator};
 use std::fmt::Debug;
 use value::Value;
 
+#[cfg(feature = "dyn_engine")]
+pub mod dyn_engine;
 pub mod error;
 pub mod execution_engine;
 pub mod expression;
 pub mod function;
 pub mod operators;
+pub mod optimize;
 pub mod ref_pool;
 pub mod slice_pool;
+pub mod sync;
+pub mod thunk;
 pub mod value;
 
 /// Defines the type system for a programming language
@@ -19,12 +25,82 @@ pub trait TypeSystem: Debug + Clone + 'static {
     type UnaryOp: UnaryOperator<Self::Value>;
     /// The binary operator type for a language
     type BinaryOp: BinaryOperator<Self::Value>;
+    /// The ternary operator type for a language, which can be `()` if it has none
+    type TernaryOp: TernaryOperator<Self::Value>;
+    /// The type of binary operators which only evaluate their right operand if they need
+    /// it, which can be `()` if a language has none
+    type LazyBinaryOp: LazyBinaryOperator<Self::Value>;
     /// The initializers type for creating new values that take multiple expressions
     type Init: Initializer<Self>;
+    /// A value being built by [Initializer::begin], which can be `()` if no initializer
+    /// builds values as their arguments are evaluated. Associated types can't have
+    /// defaults, so every type system has to name one.
+    type InitBuilder;
     /// The type id type for a language
     type TypeId: PartialEq + Debug;
+    /// Identifies the fields of values built by
+    /// [Initializer::initialize_named], usually `usize`
+    type FieldId: Clone + PartialEq + Debug;
     /// A global context object to be stored in the ExecutionEngine
     type GlobalContext: Debug;
+
+    /// Convert an error caught by [Expression::TryCatch](expression::Expression::TryCatch)
+    /// into a value the handler can inspect. By default the error is dropped and the
+    /// handler gets the default value, so languages using `TryCatch` should override it.
+    fn error_to_value(_error: FreightError) -> Self::Value {
+        Self::Value::default()
+    }
+
+    /// The unary operator with an [op_id](UnaryOperator::op_id), or `None` if there
+    /// isn't one
+    #[cfg(feature = "serde")]
+    fn unary_op_from_id(id: u32) -> Option<Self::UnaryOp>;
+
+    /// The binary operator with an [op_id](BinaryOperator::op_id), or `None` if there
+    /// isn't one
+    #[cfg(feature = "serde")]
+    fn binary_op_from_id(id: u32) -> Option<Self::BinaryOp>;
+
+    /// Convert the operands of a binary operator to types it can be applied to, like
+    /// promoting an integer to a float to add it to one. Only called when the operator
+    /// [needs coercion](BinaryOperator::needs_coercion), and `None` applies the operator
+    /// to the operands unchanged.
+    fn coerce_operands(
+        _op: &Self::BinaryOp,
+        _l: &Self::Value,
+        _r: &Self::Value,
+    ) -> Option<(Self::Value, Self::Value)> {
+        None
+    }
+}
+
+/// A [TypeSystem] whose values, operators and initializers can be serialized, so
+/// expression trees and function references written for it can be too. Unary and binary
+/// operators are serialized by their ids instead, see [BinaryOperator::op_id].
+#[cfg(feature = "serde")]
+pub trait SerializableTypeSystem:
+    TypeSystem<
+    Value: serde::Serialize + serde::de::DeserializeOwned,
+    TernaryOp: serde::Serialize + serde::de::DeserializeOwned,
+    LazyBinaryOp: serde::Serialize + serde::de::DeserializeOwned,
+    Init: serde::Serialize + serde::de::DeserializeOwned,
+    TypeId: serde::Serialize + serde::de::DeserializeOwned,
+    FieldId: serde::Serialize + serde::de::DeserializeOwned,
+>
+{
+}
+
+#[cfg(feature = "serde")]
+impl<TS> SerializableTypeSystem for TS where
+    TS: TypeSystem<
+        Value: serde::Serialize + serde::de::DeserializeOwned,
+        TernaryOp: serde::Serialize + serde::de::DeserializeOwned,
+        LazyBinaryOp: serde::Serialize + serde::de::DeserializeOwned,
+        Init: serde::Serialize + serde::de::DeserializeOwned,
+        TypeId: serde::Serialize + serde::de::DeserializeOwned,
+        FieldId: serde::Serialize + serde::de::DeserializeOwned,
+    >
+{
 }
 
 #[cfg(test)]
@@ -1,22 +1,347 @@
-use crate::{execution_engine::ExecutionEngine, value::Value};
-use std::fmt::Debug;
+use crate::{error::FreightError, execution_engine::ExecutionEngine, value::Value, TypeSystem};
+use std::{borrow::Cow, fmt::Debug};
+
+/// The global context of the type system a value belongs to
+type GlobalContext<V> = <<V as Value>::TS as TypeSystem>::GlobalContext;
+
+/// The unary operators of the type system a value belongs to
+type UnaryOp<V> = <<V as Value>::TS as TypeSystem>::UnaryOp;
 
 #[derive(Clone, Debug)]
 pub enum Operator<TS: crate::TypeSystem> {
     Binary(TS::BinaryOp),
     Unary(TS::UnaryOp),
+    Ternary(TS::TernaryOp),
+    LazyBinary(TS::LazyBinaryOp),
 }
 
 pub trait UnaryOperator<V: Value>: Debug + Clone {
     fn apply_1(&self, val: &V) -> V;
+
+    /// An identifier for the operator which stays the same across versions of a language,
+    /// so serialized expressions keep their meaning. The type system maps it back with
+    /// [TypeSystem::unary_op_from_id]. Only needed with the `serde` feature.
+    #[cfg(feature = "serde")]
+    fn op_id(&self) -> u32;
+
+    /// The name of the operator in diagnostics, pretty-printed trees and profiles,
+    /// which is its `Debug` representation by default
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Owned(format!("{self:?}"))
+    }
+
+    /// Apply the operator, or fail with an error like [FreightError::OperatorFailed].
+    /// By default it never fails.
+    fn try_apply_1(&self, val: &V) -> Result<V, FreightError> {
+        Ok(self.apply_1(val))
+    }
+
+    /// Apply the operator with access to the engine's global context. This is what the
+    /// engine calls, and by default it ignores the context and calls [Self::try_apply_1].
+    fn apply_1_ctx(&self, val: &V, _ctx: &mut GlobalContext<V>) -> Result<V, FreightError> {
+        self.try_apply_1(val)
+    }
+
+    /// Apply the operator to a variable and store the result in it, as in `a++`.
+    /// By default this assigns the result of [Self::apply_1_ctx], but it can be overridden
+    /// to update the target in place.
+    fn apply_1_assign(
+        &self,
+        target: &mut V,
+        ctx: &mut GlobalContext<V>,
+    ) -> Result<(), FreightError> {
+        let result = self.apply_1_ctx(target, ctx)?;
+        target.assign(result);
+        Ok(())
+    }
+
+    /// What applying this operator to the result of `inner` can be replaced with by
+    /// [fuse_unary](crate::optimize::fuse_unary), like `-(-x)` with `x`. This should only
+    /// return something when the replacement produces the same result and errors for
+    /// every operand, and by default nothing is fused.
+    fn fuse(&self, _inner: &UnaryOp<V>) -> Option<FusedUnary<V::TS>> {
+        None
+    }
+
+    /// Whether applying this operator to constants can be done ahead of time by
+    /// [fold_constants](crate::optimize::fold_constants), which requires it to never
+    /// panic, have no side effects, ignore the global context, and produce a result that's
+    /// safe to share. Applications which fail are left to fail at runtime.
+    /// Pure applications whose result is unused are removed by
+    /// [simplify](crate::optimize::simplify).
+    fn is_pure(&self) -> bool {
+        false
+    }
+}
+
+/// What a unary operator applied to another one becomes, see [UnaryOperator::fuse]
+#[derive(Debug, Clone, PartialEq)]
+pub enum FusedUnary<TS: TypeSystem> {
+    /// Apply a single operator instead
+    Op(TS::UnaryOp),
+    /// Use the inner operand as it is, since the operators undo each other
+    Cancel,
+}
+
+/// The result of [BinaryOperator::apply_2_select]
+#[derive(Debug, Clone, PartialEq)]
+pub enum OpResult<V> {
+    /// A new value
+    Owned(V),
+    /// The left operand, which the engine passes on as a [dupe_ref](Value::dupe_ref), so
+    /// it aliases the operand if that's a reference
+    Left,
+    /// The right operand, passed on like [OpResult::Left]
+    Right,
 }
 
 pub trait BinaryOperator<V: Value>: Debug + Clone {
     fn apply_2(&self, a: &V, b: &V) -> V;
+
+    /// Whether the operands should be passed through
+    /// [TypeSystem::coerce_operands] before applying the operator, like when their types
+    /// differ. This is checked on every application, so it should be cheap.
+    fn needs_coercion(&self, _a: &V, _b: &V) -> bool {
+        false
+    }
+
+    /// An identifier for the operator which stays the same across versions of a language,
+    /// so serialized expressions keep their meaning. The type system maps it back with
+    /// [TypeSystem::binary_op_from_id]. Only needed with the `serde` feature.
+    #[cfg(feature = "serde")]
+    fn op_id(&self) -> u32;
+
+    /// The name of the operator in diagnostics, pretty-printed trees and profiles,
+    /// which is its `Debug` representation by default
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Owned(format!("{self:?}"))
+    }
+
+    /// Apply the operator, or fail with an error like [FreightError::OperatorFailed].
+    /// By default it never fails.
+    fn try_apply_2(&self, a: &V, b: &V) -> Result<V, FreightError> {
+        Ok(self.apply_2(a, b))
+    }
+
+    /// Apply the operator with access to the engine's global context. This is what the
+    /// engine calls, and by default it ignores the context and calls [Self::try_apply_2].
+    fn apply_2_ctx(&self, a: &V, b: &V, _ctx: &mut GlobalContext<V>) -> Result<V, FreightError> {
+        self.try_apply_2(a, b)
+    }
+
+    /// Apply the operator, producing one of the operands rather than a copy of it when
+    /// that's the result, like the larger of two values for `max`. This is what the engine
+    /// calls, and by default it produces the result of [Self::apply_2_ctx].
+    fn apply_2_select(
+        &self,
+        a: &V,
+        b: &V,
+        ctx: &mut GlobalContext<V>,
+    ) -> Result<OpResult<V>, FreightError> {
+        self.apply_2_ctx(a, b, ctx).map(OpResult::Owned)
+    }
+
+    /// How tightly the operator binds its operands when building trees with
+    /// [InfixBuilder](crate::expression::InfixBuilder), higher binding tighter
+    fn precedence(&self) -> u8 {
+        0
+    }
+
+    /// How chains of operators with the same precedence are grouped, left by default
+    fn associativity(&self) -> Assoc {
+        Assoc::Left
+    }
+
+    /// Apply the operator to a variable and store the result in it, as in `a += b`.
+    /// By default this assigns the result of [Self::apply_2_ctx], but it can be overridden
+    /// to update the target in place, like appending to a list without copying it.
+    fn apply_2_assign(
+        &self,
+        target: &mut V,
+        rhs: &V,
+        ctx: &mut GlobalContext<V>,
+    ) -> Result<(), FreightError> {
+        let result = self.apply_2_ctx(target, rhs, ctx)?;
+        target.assign(result);
+        Ok(())
+    }
+
+    /// Whether applying this operator to constants can be done ahead of time by
+    /// [fold_constants](crate::optimize::fold_constants), which requires it to never
+    /// panic, have no side effects, ignore the global context, and produce a result that's
+    /// safe to share. Applications which fail are left to fail at runtime.
+    /// Pure applications whose result is unused are removed by
+    /// [simplify](crate::optimize::simplify).
+    fn is_pure(&self) -> bool {
+        false
+    }
+}
+
+/// How a chain of binary operators with the same [precedence](BinaryOperator::precedence)
+/// is grouped when building trees with [InfixBuilder](crate::expression::InfixBuilder)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Assoc {
+    /// `a - b - c` is `(a - b) - c`
+    #[default]
+    Left,
+    /// `a ^ b ^ c` is `a ^ (b ^ c)`
+    Right,
+}
+
+/// The operands of a binary operator after [TypeSystem::coerce_operands], or `None` if
+/// they're applied as they are
+pub(crate) fn coerce<TS: TypeSystem>(
+    op: &TS::BinaryOp,
+    l: &TS::Value,
+    r: &TS::Value,
+) -> Option<(TS::Value, TS::Value)> {
+    if op.needs_coercion(l, r) {
+        TS::coerce_operands(op, l, r)
+    } else {
+        None
+    }
+}
+
+/// An operator over three operands, which are evaluated left to right.
+/// Type systems without any can use `()`.
+pub trait TernaryOperator<V: Value>: Debug + Clone {
+    fn apply_3(&self, a: &V, b: &V, c: &V) -> V;
+
+    /// The name of the operator in diagnostics, pretty-printed trees and profiles,
+    /// which is its `Debug` representation by default
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Owned(format!("{self:?}"))
+    }
+
+    /// Apply the operator, or fail with an error like [FreightError::OperatorFailed].
+    /// By default it never fails.
+    fn try_apply_3(&self, a: &V, b: &V, c: &V) -> Result<V, FreightError> {
+        Ok(self.apply_3(a, b, c))
+    }
+
+    /// Apply the operator with access to the engine's global context. This is what the
+    /// engine calls, and by default it ignores the context and calls [Self::try_apply_3].
+    fn apply_3_ctx(
+        &self,
+        a: &V,
+        b: &V,
+        c: &V,
+        _ctx: &mut GlobalContext<V>,
+    ) -> Result<V, FreightError> {
+        self.try_apply_3(a, b, c)
+    }
+
+    /// Whether applying this operator to constants can be done ahead of time,
+    /// like [BinaryOperator::is_pure]
+    fn is_pure(&self) -> bool {
+        false
+    }
+}
+
+impl<V: Value> TernaryOperator<V> for () {
+    fn apply_3(&self, _: &V, _: &V, _: &V) -> V {
+        V::default()
+    }
+}
+
+/// A binary operator which decides whether to evaluate its right operand after seeing
+/// the left one. Type systems without any can use `()`.
+pub trait LazyBinaryOperator<V: Value>: Debug + Clone {
+    /// Apply the operator, calling `right` to evaluate the right operand if it's needed.
+    /// Errors from `right` should be passed on, since they include control flow like
+    /// returns out of the right operand.
+    fn apply_lazy(
+        &self,
+        left: &V,
+        right: &mut dyn FnMut() -> Result<V, FreightError>,
+    ) -> Result<V, FreightError>;
+
+    /// The name of the operator in diagnostics, pretty-printed trees and profiles,
+    /// which is its `Debug` representation by default
+    fn name(&self) -> Cow<'_, str> {
+        Cow::Owned(format!("{self:?}"))
+    }
 }
 
+impl<V: Value> LazyBinaryOperator<V> for () {
+    fn apply_lazy(
+        &self,
+        _: &V,
+        _: &mut dyn FnMut() -> Result<V, FreightError>,
+    ) -> Result<V, FreightError> {
+        Ok(V::default())
+    }
+}
+
+/// Builds values like lists and maps out of the values of several expressions, for
+/// [Expression::Initialize](crate::expression::Expression::Initialize).
+///
+/// Every method creating a value gets the engine, so it can use the global context or call
+/// back into the engine with [ExecutionEngine::call] while the expression is evaluated.
 pub trait Initializer<TS: crate::TypeSystem>: Debug + Clone {
     fn initialize(&self, values: Vec<TS::Value>, ctx: &mut ExecutionEngine<TS>) -> TS::Value;
+
+    /// Create the value, or fail with an error like [FreightError::InitializerFailed] when
+    /// the values can't make one, such as an odd number of values for the keys and
+    /// values of a map. This is what the engine calls, and by default it never fails.
+    fn try_initialize(
+        &self,
+        values: Vec<TS::Value>,
+        ctx: &mut ExecutionEngine<TS>,
+    ) -> Result<TS::Value, FreightError> {
+        Ok(self.initialize(values, ctx))
+    }
+
+    /// Start building a value from `capacity` values, which are passed to [Self::push] as
+    /// they're evaluated and turned into the value by [Self::finish], so they don't have
+    /// to be collected first. By default this returns `None`, and the values are collected
+    /// and passed to [Self::try_initialize] instead. Initializers which return builders
+    /// have to override [Self::push] and [Self::finish] too.
+    fn begin(&self, _capacity: usize) -> Option<TS::InitBuilder> {
+        None
+    }
+
+    /// Add the next value to a builder from [Self::begin]. Override it along with
+    /// [Self::begin], since by default it fails with [FreightError::InitializerFailed].
+    fn push(&self, _builder: &mut TS::InitBuilder, _value: TS::Value) -> Result<(), FreightError> {
+        Err(unsupported_builder(self, "push"))
+    }
+
+    /// Create the value from a builder from [Self::begin] once every value is pushed.
+    /// Override it along with [Self::begin], since by default it fails with
+    /// [FreightError::InitializerFailed].
+    fn finish(
+        &self,
+        _builder: TS::InitBuilder,
+        _ctx: &mut ExecutionEngine<TS>,
+    ) -> Result<TS::Value, FreightError> {
+        Err(unsupported_builder(self, "finish"))
+    }
+
+    /// Create a value from fields given by id, in the order they were written, for
+    /// [Expression::InitializeNamed](crate::expression::Expression::InitializeNamed).
+    /// Fields the initializer doesn't expect should fail with [FreightError::UnknownField]
+    /// or [FreightError::DuplicateField]. By default there are no fields.
+    fn initialize_named(
+        &self,
+        fields: Vec<(TS::FieldId, TS::Value)>,
+        ctx: &mut ExecutionEngine<TS>,
+    ) -> Result<TS::Value, FreightError> {
+        match fields.into_iter().next() {
+            Some((field, _)) => Err(FreightError::UnknownField {
+                field: format!("{field:?}"),
+            }),
+            None => self.try_initialize(Vec::new(), ctx),
+        }
+    }
+}
+
+/// The error from the defaults of [Initializer::push] and [Initializer::finish]
+fn unsupported_builder(init: &impl Debug, method: &str) -> FreightError {
+    FreightError::InitializerFailed {
+        initializer: format!("{init:?}"),
+        message: format!("begin returned a builder, but {method} isn't overridden"),
+    }
 }
 
 impl<TS: crate::TypeSystem> Initializer<TS> for () {
@@ -24,3 +349,48 @@ impl<TS: crate::TypeSystem> Initializer<TS> for () {
         TS::Value::default()
     }
 }
+
+/// Serialize unary and binary operators as their [op_id](BinaryOperator::op_id)s rather
+/// than their own representation, for the `serialize_with` and `deserialize_with`
+/// attributes of expressions
+#[cfg(feature = "serde")]
+pub(crate) mod op_ids {
+    use super::{BinaryOperator, UnaryOperator};
+    use crate::{error::FreightError, TypeSystem};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    fn unknown<E: Error>(kind: &str, id: u32) -> E {
+        E::custom(FreightError::UnknownOperatorId {
+            kind: kind.into(),
+            id,
+        })
+    }
+
+    pub(crate) fn serialize_unary<TS: TypeSystem, S: Serializer>(
+        op: &TS::UnaryOp,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(op.op_id())
+    }
+
+    pub(crate) fn deserialize_unary<'de, TS: TypeSystem, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<TS::UnaryOp, D::Error> {
+        let id = u32::deserialize(deserializer)?;
+        TS::unary_op_from_id(id).ok_or_else(|| unknown("unary", id))
+    }
+
+    pub(crate) fn serialize_binary<TS: TypeSystem, S: Serializer>(
+        op: &TS::BinaryOp,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u32(op.op_id())
+    }
+
+    pub(crate) fn deserialize_binary<'de, TS: TypeSystem, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<TS::BinaryOp, D::Error> {
+        let id = u32::deserialize(deserializer)?;
+        TS::binary_op_from_id(id).ok_or_else(|| unknown("binary", id))
+    }
+}
@@ -0,0 +1,313 @@
+//! Passes which simplify expression trees ahead of evaluation
+
+use crate::{
+    expression::{Expression, VariableType},
+    function::{ArgCount, Function, FunctionType},
+    operators::{coerce, BinaryOperator, FusedUnary, TernaryOperator, UnaryOperator},
+    sync::Shared,
+    TypeSystem,
+};
+
+/// Replace every operator application whose operands are all constants with its result,
+/// including inside nested constant expressions. Only operators which declare themselves
+/// pure are applied, and applications which fail are kept so they fail when evaluated.
+pub fn fold_constants<TS: TypeSystem>(expr: &mut Expression<TS>) {
+    expr.visit_post_order_mut(fold_node);
+}
+
+fn fold_node<TS: TypeSystem>(expr: &mut Expression<TS>) {
+    let value = match expr {
+        Expression::BinaryOpEval(op, operands) if op.is_pure() => match &**operands {
+            [Expression::RawValue(l), Expression::RawValue(r)] => match coerce::<TS>(op, l, r) {
+                Some((l, r)) => op.try_apply_2(&l, &r),
+                None => op.try_apply_2(l, r),
+            },
+            _ => return,
+        },
+        Expression::UnaryOpEval(op, operand) if op.is_pure() => match &**operand {
+            Expression::RawValue(v) => op.try_apply_1(v),
+            _ => return,
+        },
+        Expression::TernaryOpEval(op, operands) if op.is_pure() => match &**operands {
+            [Expression::RawValue(a), Expression::RawValue(b), Expression::RawValue(c)] => {
+                op.try_apply_3(a, b, c)
+            }
+            _ => return,
+        },
+        _ => return,
+    };
+    if let Ok(value) = value {
+        *expr = Expression::RawValue(value);
+    }
+}
+
+/// Remove expressions whose results are discarded by a `Sequence` and which have no
+/// side effects, self-assignments like `stack[0] = stack[0]`, and sequences nested
+/// directly in sequences or holding a single expression. Operator applications are only
+/// removed if the operator is pure, and calls, initializers and assignments never are.
+/// Reading a variable is assumed to succeed, so errors from strict reads or missing
+/// globals in removed expressions are not preserved. Returns the number of nodes removed.
+pub fn simplify<TS: TypeSystem>(expr: &mut Expression<TS>) -> usize {
+    let before = node_count(expr);
+    expr.visit_post_order_mut(simplify_node);
+    before - node_count(expr)
+}
+
+fn simplify_node<TS: TypeSystem>(expr: &mut Expression<TS>) {
+    if is_self_assignment(expr) {
+        *expr = Expression::Sequence(Vec::new());
+        return;
+    }
+    let Expression::Sequence(exprs) = expr else {
+        return;
+    };
+    let mut last = exprs.pop();
+    let mut simplified = Vec::with_capacity(exprs.len() + 1);
+    for mut expr in exprs.drain(..) {
+        match &mut expr {
+            // Children are simplified first, so nested sequences are already flat
+            Expression::Sequence(inner) => simplified.append(inner),
+            _ => simplified.push(expr),
+        }
+    }
+    simplified.retain(|expr| !is_pure(expr));
+    match &mut last {
+        Some(Expression::Sequence(inner)) if !inner.is_empty() => simplified.append(inner),
+        _ => simplified.extend(last),
+    }
+    *expr = match simplified.len() {
+        1 => simplified.pop().unwrap(),
+        _ => Expression::Sequence(simplified),
+    };
+}
+
+/// Collapse unary operators applied directly to other unary operators where the outer one
+/// [fuses](UnaryOperator::fuse) with the inner one, like `-(-x)` into `x`, so the
+/// intermediate value isn't created. Operators which don't fuse are left alone.
+/// Returns the number of nodes removed.
+pub fn fuse_unary<TS: TypeSystem>(expr: &mut Expression<TS>) -> usize {
+    let before = node_count(expr);
+    expr.visit_post_order_mut(fuse_node);
+    before - node_count(expr)
+}
+
+fn fuse_node<TS: TypeSystem>(expr: &mut Expression<TS>) {
+    // A fused operator can fuse again with the operator its operand applies
+    loop {
+        let Expression::UnaryOpEval(outer, operand) = expr else {
+            return;
+        };
+        let Expression::UnaryOpEval(inner, inner_operand) = &mut **operand else {
+            return;
+        };
+        let Some(fused) = outer.fuse(inner) else {
+            return;
+        };
+        let inner_operand =
+            std::mem::replace(&mut **inner_operand, Expression::Sequence(Vec::new()));
+        *expr = match fused {
+            FusedUnary::Op(op) => Expression::UnaryOpEval(op, inner_operand.into()),
+            FusedUnary::Cancel => inner_operand,
+        };
+    }
+}
+
+fn is_self_assignment<TS: TypeSystem>(expr: &Expression<TS>) -> bool {
+    let (target, value) = match expr {
+        Expression::AssignStack(slot, value) => (VariableType::Stack(*slot), value),
+        Expression::AssignGlobal(addr, value) => (VariableType::Global(*addr), value),
+        Expression::AssignCaptured(slot, value) => (VariableType::Captured(*slot), value),
+        _ => return false,
+    };
+    matches!(&**value, Expression::Variable(read) if *read == target)
+}
+
+/// Whether evaluating an expression can be skipped without changing what the program does
+fn is_pure<TS: TypeSystem>(expr: &Expression<TS>) -> bool {
+    let mut pure = true;
+    expr.walk(&mut |expr| {
+        pure &= match expr {
+            Expression::BinaryOpEval(op, _) => op.is_pure(),
+            Expression::UnaryOpEval(op, _) => op.is_pure(),
+            Expression::TernaryOpEval(op, _) => op.is_pure(),
+            Expression::RawValue(_)
+            | Expression::PooledValue(_)
+            | Expression::Variable(_)
+            | Expression::Sequence(_)
+            | Expression::Conditional { .. }
+            | Expression::And(_)
+            | Expression::Or(_)
+            | Expression::Spanned(..) => true,
+            _ => false,
+        }
+    });
+    pure
+}
+
+fn node_count<TS: TypeSystem>(expr: &Expression<TS>) -> usize {
+    let mut count = 0;
+    expr.walk(&mut |_| count += 1);
+    count
+}
+
+/// Replace static calls to the given functions with the body of the callee, if its body
+/// is a single operator tree of at most `max_nodes` nodes which reads each argument
+/// exactly once and in order, so the arguments are still evaluated once each and in the
+/// same order. Calls to functions which aren't in `functions` yet, including recursive
+/// ones, are left alone.
+pub(crate) fn inline_calls<TS: TypeSystem>(
+    expr: &mut Expression<TS>,
+    functions: &[Shared<Function<TS>>],
+    max_nodes: usize,
+) {
+    expr.visit_post_order_mut(|expr| {
+        let Expression::StaticFunctionCall(func, args) = expr else {
+            return;
+        };
+        let (FunctionType::Static, ArgCount::Fixed(arg_count)) =
+            (&func.function_type, func.arg_count)
+        else {
+            return;
+        };
+        let Some(callee) = functions.get(func.location) else {
+            return;
+        };
+        let [body] = callee.expressions.as_slice() else {
+            return;
+        };
+        if args.len() != arg_count
+            || args.iter().any(Expression::is_spread)
+            || !is_inlinable(body, arg_count, max_nodes)
+        {
+            return;
+        }
+        let mut args = std::mem::take(args).into_iter();
+        *expr = instantiate(body, &mut args);
+    });
+}
+
+fn is_inlinable<TS: TypeSystem>(body: &Expression<TS>, arg_count: usize, max_nodes: usize) -> bool {
+    let mut nodes = 0;
+    let mut next_arg = 0;
+    let mut inlinable = true;
+    body.walk(&mut |expr| {
+        nodes += 1;
+        inlinable &= match expr {
+            Expression::Variable(VariableType::Stack(slot)) => {
+                next_arg += 1;
+                *slot == next_arg - 1
+            }
+            Expression::RawValue(_)
+            | Expression::PooledValue(_)
+            | Expression::BinaryOpEval(..)
+            | Expression::UnaryOpEval(..)
+            | Expression::TernaryOpEval(..)
+            | Expression::Initialize(..) => true,
+            _ => false,
+        }
+    });
+    inlinable && nodes <= max_nodes && next_arg == arg_count
+}
+
+/// Copy an inlinable body, replacing each argument read with the next argument
+fn instantiate<TS: TypeSystem>(
+    body: &Expression<TS>,
+    args: &mut impl Iterator<Item = Expression<TS>>,
+) -> Expression<TS> {
+    match body {
+        Expression::RawValue(value) => Expression::RawValue(value.clone()),
+        Expression::PooledValue(index) => Expression::PooledValue(*index),
+        Expression::Variable(_) => args.next().unwrap(),
+        Expression::BinaryOpEval(op, operands) => {
+            let [l, r] = &**operands;
+            let l = instantiate(l, args);
+            Expression::BinaryOpEval(op.clone(), [l, instantiate(r, args)].into())
+        }
+        Expression::UnaryOpEval(op, operand) => {
+            Expression::UnaryOpEval(op.clone(), instantiate(operand, args).into())
+        }
+        Expression::TernaryOpEval(op, operands) => {
+            let [a, b, c] = &**operands;
+            let a = instantiate(a, args);
+            let b = instantiate(b, args);
+            Expression::TernaryOpEval(op.clone(), [a, b, instantiate(c, args)].into())
+        }
+        Expression::Initialize(init, exprs) => Expression::Initialize(
+            init.clone(),
+            exprs.iter().map(|expr| instantiate(expr, args)).collect(),
+        ),
+        _ => unreachable!("Only bodies checked by is_inlinable are instantiated"),
+    }
+}
+
+/// Replace static calls whose value a function body would return directly with
+/// [Expression::TailCall], so recursion through them doesn't grow the native stack.
+/// Those are calls last in the body, last in a `Sequence`, a branch of a `Conditional` or
+/// the body of a `ReturnTarget` in that position, or returned by a `Return` to the
+/// function or such a `ReturnTarget`, outside of loops and `TryCatch`. Bodies which
+/// already make tail calls are left alone, since the engine can't tell those apart.
+/// Returns the number of calls replaced.
+pub(crate) fn eliminate_tail_calls<TS: TypeSystem>(
+    body: &mut [Expression<TS>],
+    return_target: usize,
+) -> usize {
+    let mut tail_calls = false;
+    for expr in body.iter() {
+        expr.walk(&mut |expr| tail_calls |= matches!(expr, Expression::TailCall(..)));
+    }
+    if tail_calls {
+        return 0;
+    }
+    let mut count = 0;
+    mark_block(body, true, &mut vec![return_target], &mut count);
+    count
+}
+
+fn mark_block<TS: TypeSystem>(
+    exprs: &mut [Expression<TS>],
+    tail: bool,
+    targets: &mut Vec<usize>,
+    count: &mut usize,
+) {
+    let last = exprs.len().saturating_sub(1);
+    for (i, expr) in exprs.iter_mut().enumerate() {
+        mark_tail_calls(expr, tail && i == last, targets, count);
+    }
+}
+
+fn mark_tail_calls<TS: TypeSystem>(
+    expr: &mut Expression<TS>,
+    tail: bool,
+    targets: &mut Vec<usize>,
+    count: &mut usize,
+) {
+    match expr {
+        Expression::StaticFunctionCall(func, args)
+            if tail && !matches!(func.function_type, FunctionType::CapturingDef(..)) =>
+        {
+            *expr = Expression::TailCall(func.clone(), std::mem::take(args));
+            *count += 1;
+        }
+        Expression::Return(target, value) if targets.contains(target) => {
+            mark_tail_calls(value, true, targets, count)
+        }
+        Expression::Sequence(exprs) => mark_block(exprs, tail, targets, count),
+        Expression::Conditional {
+            then_branch,
+            else_branch,
+            ..
+        } => {
+            mark_tail_calls(then_branch, tail, targets, count);
+            if let Some(else_branch) = else_branch {
+                mark_tail_calls(else_branch, tail, targets, count);
+            }
+        }
+        Expression::ReturnTarget(target, body) if tail => {
+            targets.push(*target);
+            mark_tail_calls(body, true, targets, count);
+            targets.pop();
+        }
+        Expression::ReturnTarget(_, body) => mark_tail_calls(body, false, targets, count),
+        _ => (),
+    }
+}
@@ -1,22 +1,22 @@
 use std::{
-    cell::RefCell,
     collections::VecDeque,
     ops::{Deref, DerefMut},
-    rc::Rc,
 };
 
+use crate::sync::{PoolCell, Shared};
+
 pub trait PoolableRef: Default + ShouldRecycle + Clone {}
 impl<T: PoolableRef + Clone> PoolableRef for T {}
 
-impl<T> ShouldRecycle for Rc<T> {
+impl<T> ShouldRecycle for Shared<T> {
     fn should_recycle(&self) -> bool {
-        Rc::strong_count(self) + Rc::weak_count(self) == 0
+        Shared::strong_count(self) + Shared::weak_count(self) == 0
     }
 }
 
 pub struct PooledRef<T: PoolableRef> {
     val: T,
-    pool: Rc<RefCell<RefPool<T>>>,
+    pool: Shared<PoolCell<RefPool<T>>>,
 }
 
 impl<T: PoolableRef> Deref for PooledRef<T> {
@@ -36,7 +36,8 @@ impl<T: PoolableRef> DerefMut for PooledRef<T> {
 impl<T: PoolableRef> Drop for PooledRef<T> {
     fn drop(&mut self) {
         if self.should_recycle() {
-            self.pool.borrow_mut().insert(self.clone())
+            let val = self.val.clone();
+            self.pool.with(|pool| pool.insert(val));
         }
     }
 }
@@ -62,8 +63,8 @@ impl<T: PoolableRef> RefPool<T> {
         }
     }
 
-    pub fn request(cell: Rc<RefCell<Self>>) -> PooledRef<T> {
-        let val = cell.borrow_mut().pool.pop_back().unwrap_or_default();
+    pub fn request(cell: Shared<PoolCell<Self>>) -> PooledRef<T> {
+        let val = cell.with(|this| this.pool.pop_back()).unwrap_or_default();
         PooledRef { val, pool: cell }
     }
 
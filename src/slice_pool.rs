@@ -1,15 +1,15 @@
 use std::{
-    cell::UnsafeCell,
     collections::VecDeque,
     fmt::Debug,
     marker::PhantomData,
     ops::{Deref, DerefMut},
-    rc::Rc,
 };
 
+use crate::sync::{PoolCell, Shared};
+
 pub type PooledVec<T> = Pooled<T, Vec<T>>;
-pub type PooledRcSlice<T> = Pooled<T, Rc<[T]>>;
-pub type RcSlicePool<T> = SlicePool<T, Rc<[T]>>;
+pub type PooledRcSlice<T> = Pooled<T, Shared<[T]>>;
+pub type RcSlicePool<T> = SlicePool<T, Shared<[T]>>;
 pub type PooledBoxSlice<T> = Pooled<T, Box<[T]>>;
 pub type BoxSlicePool<T> = SlicePool<T, Box<[T]>>;
 
@@ -20,7 +20,7 @@ pub struct SlicePool<T, C: Poolable<T>> {
 }
 
 pub struct Pooled<T, C: Poolable<T>> {
-    pool: Rc<UnsafeCell<SlicePool<T, C>>>,
+    pool: Shared<PoolCell<SlicePool<T, C>>>,
     collection: C,
 }
 
@@ -72,8 +72,8 @@ impl<T, C: Poolable<T>> DerefMut for Pooled<T, C> {
 
 impl<T, C: Poolable<T>> Drop for Pooled<T, C> {
     fn drop(&mut self) {
-        self.collection
-            .insert_to_pool(unsafe { &mut *self.pool.get() });
+        let collection = &mut self.collection;
+        self.pool.with(|pool| collection.insert_to_pool(pool));
     }
 }
 
@@ -84,9 +84,9 @@ pub trait Poolable<T>: Sized {
     fn capacity(&self) -> usize;
 }
 
-impl<T: Default> Poolable<T> for Rc<[T]> {
+impl<T: Default> Poolable<T> for Shared<[T]> {
     fn insert_to_pool(&mut self, pool: &mut SlicePool<T, Self>) {
-        if Rc::strong_count(self) + Rc::weak_count(self) != 1 {
+        if Shared::strong_count(self) + Shared::weak_count(self) != 1 {
             return;
         }
         let clone = self.clone();
@@ -102,7 +102,7 @@ impl<T: Default> Poolable<T> for Rc<[T]> {
     }
 
     fn populate(&mut self, mut next: impl FnMut() -> T, len: usize) {
-        let slice = Rc::get_mut(self).expect("Non-unique rc slice in pool");
+        let slice = Shared::get_mut(self).expect("Non-unique rc slice in pool");
         for item in slice.iter_mut().take(len) {
             *item = next();
         }
@@ -115,7 +115,11 @@ impl<T: Default> Poolable<T> for Rc<[T]> {
 
 impl<T: Default> Poolable<T> for Box<[T]> {
     fn insert_to_pool(&mut self, pool: &mut SlicePool<T, Self>) {
-        pool.insert(std::mem::take(self));
+        // A rejected slice is dropped with the `Pooled` instead, since dropping its
+        // elements here could re-enter the pool
+        if pool.has_room(self.len()) {
+            pool.insert(std::mem::take(self));
+        }
     }
 
     fn with_capacity(capacity: usize) -> Self {
@@ -158,6 +162,24 @@ impl<T, C: Poolable<T> + Debug> Debug for Pooled<T, C> {
     }
 }
 
+// Captured values are serialized as a plain sequence, and deserialized into a slice which
+// isn't returned to any engine's pool
+#[cfg(feature = "serde")]
+impl<T: Default + serde::Serialize> serde::Serialize for PooledRcSlice<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Default + serde::Deserialize<'de>> serde::Deserialize<'de> for PooledRcSlice<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let values = Vec::<T>::deserialize(deserializer)?;
+        let pool = Shared::new(PoolCell::new(SlicePool::with_max_cache_per(0)));
+        Ok(SlicePool::from_pool(pool, values))
+    }
+}
+
 impl<T, C: Poolable<T>> Default for SlicePool<T, C> {
     fn default() -> Self {
         Self::with_max_cache_per(1000)
@@ -174,6 +196,12 @@ impl<T, C: Poolable<T>> SlicePool<T, C> {
         }
     }
 
+    fn has_room(&self, capacity: usize) -> bool {
+        self.pool
+            .get(capacity)
+            .is_some_and(|cache| cache.len() < self.max_cache_per)
+    }
+
     pub fn insert(&mut self, container: C) {
         if let Some(v) = self.pool.get_mut(container.capacity()) {
             if v.len() < self.max_cache_per {
@@ -182,12 +210,13 @@ impl<T, C: Poolable<T>> SlicePool<T, C> {
         }
     }
 
-    pub fn request(cell: Rc<UnsafeCell<Self>>, capacity: usize) -> Pooled<T, C> {
-        let this = unsafe { &mut *cell.get() };
-        let collection = this
-            .pool
-            .get_mut(capacity)
-            .and_then(|cache| cache.pop_back())
+    pub fn request(cell: Shared<PoolCell<Self>>, capacity: usize) -> Pooled<T, C> {
+        let collection = cell
+            .with(|this| {
+                this.pool
+                    .get_mut(capacity)
+                    .and_then(|cache| cache.pop_back())
+            })
             .unwrap_or_else(|| C::with_capacity(capacity));
         Pooled {
             pool: cell,
@@ -196,7 +225,7 @@ impl<T, C: Poolable<T>> SlicePool<T, C> {
     }
 
     pub fn from_pool(
-        cell: Rc<UnsafeCell<Self>>,
+        cell: Shared<PoolCell<Self>>,
         elems: impl IntoExactSizeIterator<Item = T>,
     ) -> Pooled<T, C> {
         let mut iter = elems.into_exact_size_iter();
@@ -209,7 +238,7 @@ impl<T, C: Poolable<T>> SlicePool<T, C> {
     }
 
     pub fn from_pool_with_fn(
-        cell: Rc<UnsafeCell<Self>>,
+        cell: Shared<PoolCell<Self>>,
         capacity: usize,
         f: impl FnMut() -> T,
     ) -> Pooled<T, C> {
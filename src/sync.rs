@@ -0,0 +1,49 @@
+//! Shared ownership primitives which become thread safe with the `sync` feature,
+//! so that an engine and the values it created can be moved to another thread together
+
+#[cfg(not(feature = "sync"))]
+pub type Shared<T> = std::rc::Rc<T>;
+#[cfg(feature = "sync")]
+pub type Shared<T> = std::sync::Arc<T>;
+
+/// Requires `Send` only when the `sync` feature is enabled, for trait objects stored in the engine
+#[cfg(not(feature = "sync"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "sync"))]
+impl<T: ?Sized> MaybeSend for T {}
+#[cfg(feature = "sync")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "sync")]
+impl<T: ?Sized + Send> MaybeSend for T {}
+
+/// Mutable state shared between an engine and the values it creates, such as memory pools
+#[derive(Debug, Default)]
+pub struct PoolCell<T> {
+    #[cfg(not(feature = "sync"))]
+    inner: std::cell::UnsafeCell<T>,
+    #[cfg(feature = "sync")]
+    inner: std::sync::Mutex<T>,
+}
+
+impl<T> PoolCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: value.into(),
+        }
+    }
+
+    /// Run a closure with exclusive access to the contents.
+    /// The closure must not access the same cell again or drop anything which might.
+    #[inline]
+    pub(crate) fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        #[cfg(not(feature = "sync"))]
+        // SAFETY: the engine is single threaded and callers never re-enter the cell
+        let result = f(unsafe { &mut *self.inner.get() });
+        #[cfg(feature = "sync")]
+        let result = f(&mut self
+            .inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner));
+        result
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{
+    execution_engine::ExecutionEngine,
+    expression::{Expression, ExpressionArena},
+};
+
+use super::{
+    add, number,
+    type_system::{TestBinaryOperator, TestInitializer, TestTypeSystem, TestUnaryOperator},
+};
+
+fn lt(l: Expression<TestTypeSystem>, r: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::BinaryOpEval(TestBinaryOperator::Lt, [l, r].into())
+}
+
+const SLOTS: usize = 4;
+
+/// A deterministic pseudo-random number expression over the first [SLOTS] stack slots
+fn generate(seed: &mut u64, depth: usize) -> Expression<TestTypeSystem> {
+    *seed = seed
+        .wrapping_mul(6364136223846793005)
+        .wrapping_add(1442695040888963407);
+    let choice = (*seed >> 33) as usize;
+    if depth == 0 {
+        return match choice % 2 {
+            0 => number((choice % 7) as i64),
+            _ => Expression::stack(choice % SLOTS),
+        };
+    }
+    let mut child = || generate(seed, depth - 1);
+    match choice % 8 {
+        0 => Expression::UnaryOpEval(TestUnaryOperator::Inc, child().into()),
+        1 => Expression::Conditional {
+            condition: lt(child(), child()).into(),
+            then_branch: child().into(),
+            else_branch: Some(child().into()),
+        },
+        2 => Expression::AssignStackAndYield(choice % SLOTS, child().into()),
+        3 => Expression::Sequence(vec![child(), child(), child()]),
+        4 => Expression::And([child(), child()].into()),
+        5 => Expression::Or([child(), child()].into()),
+        6 => Expression::Coalesce([child(), child()].into()),
+        _ => add(child(), child()),
+    }
+}
+
+/// Runs a generated body many times in a loop, then collects the slots into a list
+fn program(seed: u64) -> Expression<TestTypeSystem> {
+    let mut seed = seed;
+    let mut exprs: Vec<_> = (0..SLOTS)
+        .map(|slot| Expression::AssignStack(slot, number(slot as i64).into()))
+        .collect();
+    exprs.push(Expression::While {
+        condition: lt(Expression::stack(0), number(2000)).into(),
+        body: Expression::AssignStack(0, add(Expression::stack(0), generate(&mut seed, 6)).into())
+            .into(),
+    });
+    exprs.push(Expression::Initialize(
+        TestInitializer::List,
+        (0..SLOTS).map(Expression::stack).collect(),
+    ));
+    Expression::Sequence(exprs)
+}
+
+#[test]
+fn test_arena_evaluation_matches_boxed() {
+    for seed in 0..20 {
+        let expr = program(seed);
+        let mut arena = ExpressionArena::new();
+        let root = arena.insert(&expr).unwrap();
+
+        let mut boxed = ExecutionEngine::<TestTypeSystem>::new_default();
+        let mut unboxed = ExecutionEngine::<TestTypeSystem>::new_default();
+        let expected = boxed.eval(&expr, SLOTS);
+        assert!(expected.is_ok());
+        assert_eq!(unboxed.eval_arena(&arena, root, SLOTS), expected);
+        assert_eq!(
+            unboxed.stats().expressions_evaluated,
+            boxed.stats().expressions_evaluated
+        );
+    }
+}
+
+#[test]
+fn test_arena_errors_match_boxed() {
+    let expr = Expression::Loop {
+        target: 0,
+        body: Expression::AssignStack(0, add(number(1), Expression::global(3)).into()).into(),
+    };
+    let mut arena = ExpressionArena::new();
+    let root = arena.insert(&expr).unwrap();
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_error_traces(true);
+    let expected = engine.eval(&expr, 1);
+    assert!(expected.is_err());
+    assert_eq!(engine.eval_arena(&arena, root, 1), expected);
+
+    // Addresses are checked up front, like boxed expressions
+    assert!(engine.eval_arena(&arena, root, 0).is_err());
+}
+
+#[test]
+fn test_unsupported_expressions_are_not_inserted() {
+    let mut arena = ExpressionArena::new();
+    arena.insert(&add(number(1), number(2))).unwrap();
+    assert_eq!(arena.len(), 3);
+    let lazy = add(number(1), Expression::Lazy(number(2).into()));
+    assert!(!ExpressionArena::supports(&Expression::Lazy(
+        number(2).into()
+    )));
+    assert_eq!(arena.insert(&lazy), None);
+    assert_eq!(arena.len(), 3);
+}
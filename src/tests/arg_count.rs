@@ -0,0 +1,144 @@
+use crate::{
+    execution_engine::ExecutionEngine,
+    function::{ArgCount, FunctionWriter},
+};
+
+use super::type_system::TestTypeSystem;
+
+#[test]
+fn test_arg_count_constructors() {
+    // The argument count, the counts from 0 to 5 it accepts, its slots and its description
+    let table = [
+        (
+            ArgCount::exact(0),
+            [true, false, false, false, false, false],
+            0,
+            "exactly 0 arguments",
+        ),
+        (
+            ArgCount::exact(1),
+            [false, true, false, false, false, false],
+            1,
+            "exactly 1 argument",
+        ),
+        (
+            ArgCount::exact(3),
+            [false, false, false, true, false, false],
+            3,
+            "exactly 3 arguments",
+        ),
+        (
+            ArgCount::range(1, 3),
+            [false, true, true, true, false, false],
+            3,
+            "1 to 3 arguments",
+        ),
+        (
+            ArgCount::range(0, 2),
+            [true, true, true, false, false, false],
+            2,
+            "at most 2 arguments",
+        ),
+        (
+            ArgCount::range(0, 1),
+            [true, true, false, false, false, false],
+            1,
+            "at most 1 argument",
+        ),
+        (
+            ArgCount::range(2, 2),
+            [false, false, true, false, false, false],
+            2,
+            "exactly 2 arguments",
+        ),
+        (
+            ArgCount::at_least(0),
+            [true, true, true, true, true, true],
+            1,
+            "any number of arguments",
+        ),
+        (
+            ArgCount::at_least(1),
+            [false, true, true, true, true, true],
+            2,
+            "at least 1 argument",
+        ),
+        (
+            ArgCount::at_least(4),
+            [false, false, false, false, true, true],
+            5,
+            "at least 4 arguments",
+        ),
+        (
+            ArgCount::new(2..=4),
+            [false, false, true, true, true, false],
+            4,
+            "2 to 4 arguments",
+        ),
+        (
+            ArgCount::new(1..3),
+            [false, true, true, false, false, false],
+            2,
+            "1 to 2 arguments",
+        ),
+        (
+            ArgCount::new(2..),
+            [false, false, true, true, true, true],
+            3,
+            "at least 2 arguments",
+        ),
+        (
+            ArgCount::new_variadic(1..3),
+            [false, true, true, true, true, true],
+            3,
+            "at least 1 argument",
+        ),
+    ];
+    for (count, accepted, stack_size, description) in table {
+        for (n, accepted) in accepted.into_iter().enumerate() {
+            assert_eq!(count.contains(n), accepted, "{count:?} with {n} arguments");
+        }
+        assert_eq!(count.stack_size(), stack_size, "{count:?}");
+        assert_eq!(count.to_string(), description);
+        let accepted_max = (0..100).rev().find(|n| count.contains(*n));
+        match count.max() {
+            Some(max) => assert_eq!(accepted_max, Some(max)),
+            None => assert_eq!(accepted_max, Some(99)),
+        }
+        assert_eq!((0..).find(|n| count.contains(*n)), Some(count.min()));
+    }
+    assert_eq!(ArgCount::range(2, 2), ArgCount::exact(2));
+    assert_eq!(ArgCount::new(1..), ArgCount::at_least(1));
+}
+
+#[test]
+fn test_arg_count_errors_describe_the_range() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = engine
+        .register_function(FunctionWriter::new(ArgCount::range(1, 2)), 0)
+        .unwrap();
+    let err = engine.call(&func, []).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Function #{} expected 1 to 2 arguments, got 0",
+            func.location
+        )
+    );
+}
+
+#[cfg(not(feature = "variadic_functions"))]
+#[test]
+fn test_variadic_calls_need_the_feature() {
+    use super::type_system::{TestValue, TestValueWrapper};
+    use crate::error::FreightError;
+
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = engine
+        .register_function(FunctionWriter::new(ArgCount::at_least(0)), 0)
+        .unwrap();
+    assert_eq!(
+        engine.call(&func, [TestValueWrapper(TestValue::Number(1))]),
+        Err(FreightError::VariadicFunctionsDisabled)
+    );
+}
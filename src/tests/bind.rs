@@ -0,0 +1,161 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::{Expression, VariableType},
+    function::{ArgCount, FunctionRef, FunctionWriter},
+};
+
+use super::{
+    binary, number,
+    type_system::{TestBinaryOperator, TestTypeSystem, TestValue, TestValueWrapper},
+    value,
+};
+
+fn function(func: FunctionRef<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::RawValue(TestValueWrapper(TestValue::Function(func)))
+}
+
+/// Registers a function of two arguments computing `a - b`
+fn register_sub(engine: &mut ExecutionEngine<TestTypeSystem>) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(ArgCount::Fixed(2));
+    func.evaluate_expression(binary(
+        TestBinaryOperator::Sub,
+        Expression::stack(0),
+        Expression::stack(1),
+    ));
+    engine.register_function(func, 0).unwrap()
+}
+
+#[test]
+fn test_bind_reduces_the_arg_count() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let sub = register_sub(&mut engine);
+
+    let from_ten = sub.bind(vec![value(10)]).unwrap();
+    assert_eq!(from_ten.arg_count(), ArgCount::Fixed(1));
+    assert_ne!(from_ten, sub);
+    assert_eq!(engine.call(&from_ten, [value(3)]), Ok(value(7)));
+
+    let seven = from_ten.bind(vec![value(3)]).unwrap();
+    assert_eq!(seven.arg_count(), ArgCount::Fixed(0));
+    assert_eq!(engine.call(&seven, []), Ok(value(7)));
+
+    let range = FunctionRef::<TestTypeSystem> {
+        arg_count: ArgCount::Range { min: 1, max: 3 },
+        ..sub.clone()
+    };
+    assert_eq!(
+        range.bind(vec![value(1), value(2)]).unwrap().arg_count(),
+        ArgCount::Range { min: 0, max: 1 }
+    );
+}
+
+#[test]
+fn test_binding_too_many_arguments_fails() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let sub = register_sub(&mut engine);
+    let err = sub.bind(vec![value(1), value(2), value(3)]).unwrap_err();
+    assert_eq!(
+        err,
+        FreightError::TooManyBoundArguments {
+            function: "#0".into(),
+            bound: 3,
+            max: 2,
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "Cannot bind 3 arguments to function #0, which takes at most 2"
+    );
+
+    // Arity is still checked against the whole argument list when called
+    let from_ten = sub.bind(vec![value(10)]).unwrap();
+    let err = engine.call(&from_ten, [value(1), value(2)]).unwrap_err();
+    assert_eq!(
+        err.root().to_string(),
+        "Function #0 expected exactly 2 arguments, got 3"
+    );
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_bind_variadic_function() {
+    use super::list;
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // Puts its first argument in front of the extra ones, ignoring the second
+    let mut func = FunctionWriter::new(ArgCount::new_variadic(1..=2));
+    func.evaluate_expression(binary(
+        TestBinaryOperator::Prepend,
+        Expression::stack(0),
+        Expression::stack(2),
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+
+    let bound = func.bind(vec![value(1), value(2)]).unwrap();
+    assert_eq!(bound.arg_count(), ArgCount::Variadic { min: 0, max: 0 });
+    let call = Expression::DynamicFunctionCall(function(bound).into(), vec![number(3), number(4)]);
+    assert_eq!(engine.evaluate(&call), Ok(list(&[1, 3, 4])));
+
+    // Bound arguments may spill into the list of extra arguments
+    let bound = func.bind(vec![value(1), value(2), value(3)]).unwrap();
+    let call = Expression::DynamicFunctionCall(function(bound.clone()).into(), vec![number(4)]);
+    assert_eq!(engine.evaluate(&call), Ok(list(&[1, 3, 4])));
+    let call = Expression::DynamicFunctionCall(function(bound).into(), vec![]);
+    assert_eq!(engine.evaluate(&call), Ok(list(&[1, 3])));
+}
+
+#[test]
+fn test_bind_capturing_closure() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // Creates a closure of two arguments computing `(captured - a) - b`
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(2), vec![VariableType::Stack(0)]);
+    closure.evaluate_expression(binary(
+        TestBinaryOperator::Sub,
+        binary(
+            TestBinaryOperator::Sub,
+            Expression::Variable(VariableType::Captured(0)),
+            Expression::stack(0),
+        ),
+        Expression::stack(1),
+    ));
+    let closure = engine.register_function(closure, 0).unwrap();
+    let mut make = FunctionWriter::new(ArgCount::Fixed(1));
+    make.evaluate_expression(Expression::FunctionCapture(closure.clone()));
+    let make = engine.register_function(make, 0).unwrap();
+
+    let TestValueWrapper(TestValue::Function(instance)) = engine.call(&make, [value(100)]).unwrap()
+    else {
+        panic!("Creating a closure should produce a function");
+    };
+    let bound = instance.bind(vec![value(10)]).unwrap();
+    let call = Expression::DynamicFunctionCall(function(bound.clone()).into(), vec![number(1)]);
+    assert_eq!(engine.evaluate(&call), Ok(value(89)));
+
+    // Tail calls go through the binding too
+    let mut caller = FunctionWriter::new(ArgCount::Fixed(0));
+    caller.evaluate_expression(Expression::TailCall(bound, vec![number(2)]));
+    let caller = engine.register_function(caller, 0).unwrap();
+    assert_eq!(engine.call(&caller, []), Ok(value(88)));
+
+    // Definitions have to be captured before they can be bound
+    assert_eq!(
+        closure.bind(vec![value(1)]),
+        Err(FreightError::InvalidInvocationTarget)
+    );
+}
+
+#[test]
+fn test_bound_arguments_are_copied_for_each_call() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.evaluate_expression(Expression::AssignStack(
+        0,
+        binary(TestBinaryOperator::Add, Expression::stack(0), number(1)).into(),
+    ));
+    func.evaluate_expression(Expression::stack(0));
+    let func = engine.register_function(func, 0).unwrap();
+    let bound = func.bind(vec![value(1)]).unwrap();
+    assert_eq!(engine.call(&bound, []), Ok(value(2)));
+    assert_eq!(engine.call(&bound, []), Ok(value(2)));
+}
@@ -0,0 +1,79 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::{Expression, VariableType},
+    function::{ArgCount, FunctionType, FunctionWriter},
+    value::Value,
+};
+
+use super::{
+    binary,
+    type_system::{TestBinaryOperator, TestTypeSystem},
+    value,
+};
+
+fn captured(addr: usize) -> Expression<TestTypeSystem> {
+    Expression::Variable(VariableType::Captured(addr))
+}
+
+#[test]
+fn test_capture_nested_closures() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // `x => y => z => (x - y) - z`, where the innermost closure captures `x` from the
+    // captures of the middle one
+    let mut middle = FunctionWriter::new(ArgCount::Fixed(1));
+    let middle_x = middle.capture(VariableType::Stack(0));
+
+    let mut inner = FunctionWriter::new(ArgCount::Fixed(1));
+    let x = inner.capture(VariableType::Captured(middle_x));
+    let y = inner.capture(VariableType::Stack(0));
+    assert_eq!((x, y), (0, 1));
+    assert_eq!(inner.capture(VariableType::Stack(0)), y);
+    assert_eq!(inner.capture_count(), 2);
+    inner.evaluate_expression(binary(
+        TestBinaryOperator::Sub,
+        binary(TestBinaryOperator::Sub, captured(x), captured(y)),
+        Expression::stack(0),
+    ));
+    let inner = engine.register_function(inner, 0).unwrap();
+    let FunctionType::CapturingDef(captures, _) = &inner.function_type else {
+        panic!("Capturing should make a capturing function");
+    };
+    assert_eq!(
+        &captures[..],
+        [VariableType::Captured(0), VariableType::Stack(0)]
+    );
+
+    middle.evaluate_expression(Expression::FunctionCapture(inner));
+    let middle = engine.register_function(middle, 0).unwrap();
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(1));
+    outer.evaluate_expression(Expression::FunctionCapture(middle));
+    let outer = engine.register_function(outer, 0).unwrap();
+
+    let middle = engine.call(&outer, [value(100)]).unwrap();
+    let middle = middle.cast_to_function().unwrap().clone();
+    let inner = engine.call(&middle, [value(10)]).unwrap();
+    let inner = inner.cast_to_function().unwrap().clone();
+    assert_eq!(engine.call(&inner, [value(1)]), Ok(value(89)));
+    assert_eq!(engine.call(&inner, [value(2)]), Ok(value(88)));
+}
+
+#[test]
+fn test_captured_addresses_checked() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let writer = || {
+        let mut func = FunctionWriter::<TestTypeSystem>::new(ArgCount::Fixed(0));
+        let x = func.capture(VariableType::Stack(0));
+        func.evaluate_expression(binary(TestBinaryOperator::Add, captured(x), captured(1)));
+        func.disable_validation();
+        func
+    };
+    let expected = FreightError::CaptureSlotOutOfRange { slot: 1, size: 1 };
+    assert_eq!(writer().check_captures(), Err(expected.clone()));
+    assert_eq!(writer().try_build(0).unwrap_err(), expected);
+    assert_eq!(engine.register_function(writer(), 0), Err(expected));
+
+    let mut fixed = writer();
+    assert_eq!(fixed.capture(VariableType::Global(0)), 1);
+    assert_eq!(fixed.check_captures(), Ok(()));
+}
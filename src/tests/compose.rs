@@ -0,0 +1,126 @@
+use crate::{
+    error::FreightError, execution_engine::ExecutionEngine, expression::Expression,
+    function::ArgCount,
+};
+
+use super::{
+    add, register_named,
+    type_system::{TestBinaryOperator, TestTypeSystem, TestUnaryOperator},
+    value,
+};
+
+#[test]
+fn test_compose_three_functions() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let add = register_named(
+        &mut engine,
+        "add",
+        ArgCount::Fixed(2),
+        add(Expression::stack(0), Expression::stack(1)),
+    );
+    let inc = register_named(
+        &mut engine,
+        "inc",
+        ArgCount::Fixed(1),
+        Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::stack(0).into()),
+    );
+    let double = register_named(
+        &mut engine,
+        "double",
+        ArgCount::Fixed(1),
+        Expression::BinaryOpEval(
+            TestBinaryOperator::Mul,
+            [Expression::stack(0), Expression::RawValue(value(2))].into(),
+        ),
+    );
+
+    let inc_sum = engine.compose(&inc, &add).unwrap();
+    let composed = engine.compose(&double, &inc_sum).unwrap();
+    assert_eq!(inc_sum.arg_count(), ArgCount::Fixed(2));
+    assert_eq!(composed.arg_count(), ArgCount::Fixed(2));
+    assert_eq!(composed.name(), Some("double . inc . add"));
+    assert_eq!(engine.call(&composed, [value(3), value(4)]), Ok(value(16)));
+
+    // Composing the other way around gives the other order
+    let inc_double = engine.compose(&inc, &double).unwrap();
+    assert_eq!(inc_double.arg_count(), ArgCount::Fixed(1));
+    assert_eq!(engine.call(&inc_double, [value(3)]), Ok(value(7)));
+
+    let err = engine.call(&composed, [value(3)]).unwrap_err();
+    assert_eq!(
+        err.root().to_string(),
+        "Function double . inc . add expected exactly 2 arguments, got 1"
+    );
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_compose_variadic_inner_function() {
+    use super::type_system::{TestValue, TestValueWrapper};
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let prepend = register_named(
+        &mut engine,
+        "prepend",
+        ArgCount::at_least(1),
+        Expression::BinaryOpEval(
+            TestBinaryOperator::Prepend,
+            [Expression::stack(0), Expression::stack(1)].into(),
+        ),
+    );
+    let identity = register_named(
+        &mut engine,
+        "identity",
+        ArgCount::Fixed(1),
+        Expression::stack(0),
+    );
+    let composed = engine.compose(&identity, &prepend).unwrap();
+    assert_eq!(composed.arg_count(), ArgCount::at_least(1));
+    assert_eq!(
+        engine.call(&composed, [value(1), value(2), value(3)]),
+        Ok(TestValueWrapper(TestValue::List(vec![
+            value(1),
+            value(2),
+            value(3)
+        ])))
+    );
+}
+
+#[test]
+fn test_compose_checks_arg_counts() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let add = register_named(
+        &mut engine,
+        "add",
+        ArgCount::Fixed(2),
+        add(Expression::stack(0), Expression::stack(1)),
+    );
+    let optional = register_named(
+        &mut engine,
+        "optional",
+        ArgCount::new(1..=2),
+        Expression::stack(0),
+    );
+
+    assert_eq!(
+        engine.compose(&add, &add).unwrap_err().to_string(),
+        "Function add expected exactly 2 arguments, got 1"
+    );
+    // An outer function only needs to accept one argument
+    assert!(engine.compose(&optional, &add).is_ok());
+    assert_eq!(
+        engine.compose(&add, &optional),
+        Err(FreightError::IncorrectArgumentCount {
+            expected_min: 2,
+            expected_max: Some(2),
+            actual: 1,
+            function: Some(add.location),
+            name: Some("add".into()),
+        })
+    );
+    assert_eq!(
+        engine.compose(&optional, &optional),
+        Err(FreightError::OptionalArgumentsNotComposable {
+            function: "optional".into()
+        })
+    );
+}
@@ -0,0 +1,84 @@
+use crate::{
+    error::{AddressKind, FreightError},
+    execution_engine::ExecutionEngine,
+    expression::Expression,
+    function::{ArgCount, FunctionRef},
+};
+
+use super::{
+    alloc_counter::count_allocations,
+    number, raw_list, register,
+    type_system::{TestBinaryOperator, TestTypeSystem, TestValue, TestValueWrapper},
+};
+
+fn body(engine: &ExecutionEngine<TestTypeSystem>, func: &FunctionRef<TestTypeSystem>) -> String {
+    engine.get_function(func.location).expressions[0].pretty()
+}
+
+#[test]
+fn test_pooling_disabled_by_default() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert!(!engine.constant_pooling());
+    let func = register(&mut engine, ArgCount::Fixed(0), number(1));
+    assert_eq!(body(&engine, &func), "raw(TestValueWrapper(Number(1)))");
+    assert!(engine.constants().is_empty());
+}
+
+#[test]
+fn test_identical_constants_are_pooled_once() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_constant_pooling(true);
+    let sum = |l, r| Expression::BinaryOpEval(TestBinaryOperator::Add, [l, r].into());
+    let first = register(&mut engine, ArgCount::Fixed(0), sum(number(1), number(2)));
+    let second = register(
+        &mut engine,
+        ArgCount::Fixed(0),
+        sum(number(2), sum(number(1), number(3))),
+    );
+    assert_eq!(engine.constants().len(), 3);
+    assert_eq!(body(&engine, &first), "(+ constant[1] constant[0])");
+    assert_eq!(
+        body(&engine, &second),
+        "(+ constant[0] (+ constant[1] constant[2]))"
+    );
+    assert_eq!(
+        engine.call(&first, []),
+        Ok(TestValueWrapper(TestValue::Number(3)))
+    );
+    assert_eq!(
+        engine.call(&second, []),
+        Ok(TestValueWrapper(TestValue::Number(6)))
+    );
+    engine.validate().unwrap();
+}
+
+#[test]
+fn test_pooled_constants_are_not_copied() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let values: Vec<_> = (0..100).collect();
+    let copied = register(&mut engine, ArgCount::Fixed(0), raw_list(&values));
+    engine.set_constant_pooling(true);
+    let pooled = register(&mut engine, ArgCount::Fixed(0), raw_list(&values));
+
+    let first = engine.call(&pooled, []).unwrap();
+    engine.call(&copied, []).unwrap();
+    let (second, allocations) = count_allocations(|| engine.call(&pooled, []).unwrap());
+    assert_eq!(allocations, 0);
+    assert!(first.ref_eq(&second));
+    let (_, allocations) = count_allocations(|| engine.call(&copied, []).unwrap());
+    assert!(allocations > 0);
+}
+
+#[test]
+fn test_validate_pooled_constant_index() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = register(&mut engine, ArgCount::Fixed(0), Expression::PooledValue(0));
+    assert_eq!(
+        engine.validate(),
+        Err(FreightError::InvalidAddress {
+            function: func.location,
+            kind: AddressKind::Constant,
+            address: 0,
+        })
+    );
+}
@@ -0,0 +1,585 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::Expression,
+    function::{ArgCount, FunctionRef, FunctionWriter},
+};
+
+use super::{
+    add, list, number,
+    type_system::{
+        TestBinaryOperator, TestTypeSystem, TestUnaryOperator, TestValue, TestValueWrapper,
+    },
+    value,
+};
+
+#[test]
+fn test_conditional_evaluates_one_branch() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let then_global = engine.create_global();
+    let else_global = engine.create_global();
+    let conditional = |condition| Expression::Conditional {
+        condition: Box::new(number(condition)),
+        then_branch: Expression::AssignGlobal(then_global, number(1).into()).into(),
+        else_branch: Some(Expression::AssignGlobal(else_global, number(2).into()).into()),
+    };
+
+    engine.evaluate(&conditional(5)).unwrap();
+    assert_eq!(engine.get_global(then_global), Some(&value(1)));
+    assert_eq!(
+        engine.get_global(else_global),
+        Some(&TestValueWrapper(TestValue::Null))
+    );
+
+    engine.reset();
+    engine.evaluate(&conditional(0)).unwrap();
+    assert_eq!(
+        engine.get_global(then_global),
+        Some(&TestValueWrapper(TestValue::Null))
+    );
+    assert_eq!(engine.get_global(else_global), Some(&value(2)));
+}
+
+#[test]
+fn test_conditional_value() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let choose = |condition, else_branch: Option<i64>| Expression::Conditional {
+        condition: Box::new(number(condition)),
+        then_branch: number(10).into(),
+        else_branch: else_branch.map(|n| number(n).into()),
+    };
+    assert_eq!(engine.evaluate(&choose(1, Some(20))), Ok(value(10)));
+    assert_eq!(engine.evaluate(&choose(0, Some(20))), Ok(value(20)));
+    assert_eq!(
+        engine.evaluate(&choose(0, None)),
+        Ok(TestValueWrapper(TestValue::Null))
+    );
+}
+
+#[test]
+fn test_and_or_short_circuit() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let assign = || Expression::AssignGlobal(global, number(1).into());
+    let null = TestValueWrapper(TestValue::Null);
+
+    let and = |l| Expression::And([number(l), assign()].into());
+    assert_eq!(engine.evaluate(&and(0)), Ok(value(0)));
+    assert_eq!(engine.get_global(global), Some(&null));
+    assert_eq!(engine.evaluate(&and(5)), Ok(null.clone()));
+    assert_eq!(engine.get_global(global), Some(&value(1)));
+
+    engine.reset();
+    let or = |l| Expression::Or([number(l), assign()].into());
+    assert_eq!(engine.evaluate(&or(3)), Ok(value(3)));
+    assert_eq!(engine.get_global(global), Some(&null));
+    assert_eq!(engine.evaluate(&or(0)), Ok(null));
+    assert_eq!(engine.get_global(global), Some(&value(1)));
+}
+
+#[test]
+fn test_coalesce_short_circuit() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let fallback = || {
+        Expression::Sequence(vec![
+            Expression::AssignGlobal(global, number(1).into()),
+            number(2),
+        ])
+    };
+    let coalesce = |l| Expression::Coalesce([l, fallback()].into());
+    let null = TestValueWrapper(TestValue::Null);
+
+    // Falsy values other than null are kept
+    assert_eq!(engine.evaluate(&coalesce(number(0))), Ok(value(0)));
+    assert_eq!(engine.get_global(global), Some(&null));
+    assert_eq!(
+        engine.evaluate(&coalesce(Expression::RawValue(null.clone()))),
+        Ok(value(2))
+    );
+    assert_eq!(engine.get_global(global), Some(&value(1)));
+
+    // Reading a variable nothing was assigned to also falls back
+    engine.reset();
+    assert_eq!(
+        engine.eval(&coalesce(Expression::stack(0)), 1),
+        Ok(value(2))
+    );
+    assert_eq!(engine.get_global(global), Some(&value(1)));
+}
+
+/// `i < limit`
+fn less_than(i: usize, limit: i64) -> Expression<TestTypeSystem> {
+    Expression::BinaryOpEval(
+        TestBinaryOperator::Lt,
+        [Expression::stack(i), number(limit)].into(),
+    )
+}
+
+/// `i = i + 1`
+fn increment(i: usize) -> Expression<TestTypeSystem> {
+    Expression::AssignStack(
+        i,
+        Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::stack(i).into()).into(),
+    )
+}
+
+/// Builds a function which counts a local up from zero with `body` as the loop body
+fn counting_loop(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    limit: i64,
+    body: impl FnOnce(usize) -> Expression<TestTypeSystem>,
+) -> FunctionRef<TestTypeSystem> {
+    let target = engine.create_return_target();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let i = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(i, number(0).into()));
+    func.evaluate_expression(Expression::While {
+        condition: less_than(i, limit).into(),
+        body: body(i).into(),
+    });
+    func.evaluate_expression(Expression::stack(i));
+    engine.register_function(func, target).unwrap()
+}
+
+#[test]
+fn test_while_zero_iterations() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let func = counting_loop(&mut engine, 0, |_| {
+        Expression::AssignGlobal(global, number(1).into())
+    });
+    assert_eq!(engine.call(&func, []), Ok(value(0)));
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Null))
+    );
+}
+
+#[test]
+fn test_while_many_iterations() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = counting_loop(&mut engine, 100_000, increment);
+    assert_eq!(engine.call(&func, []), Ok(value(100_000)));
+}
+
+#[test]
+fn test_while_early_return() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let target = engine.next_return_target;
+    let func = counting_loop(&mut engine, 100, |i| Expression::Conditional {
+        condition: less_than(i, 10).into(),
+        then_branch: increment(i).into(),
+        else_branch: Some(
+            Expression::Return(
+                target,
+                Expression::BinaryOpEval(
+                    TestBinaryOperator::Add,
+                    [Expression::stack(i), number(1000)].into(),
+                )
+                .into(),
+            )
+            .into(),
+        ),
+    });
+    assert_eq!(engine.call(&func, []), Ok(value(1010)));
+}
+
+#[test]
+fn test_infinite_while_is_abortable() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let infinite = Expression::While {
+        condition: number(1).into(),
+        body: number(0).into(),
+    };
+    engine.set_fuel(Some(1000));
+    assert_eq!(
+        engine.evaluate(&infinite),
+        Err(FreightError::OutOfFuel { consumed: 1000 })
+    );
+    engine.set_fuel(None);
+
+    engine.interrupt_handle().interrupt();
+    assert_eq!(engine.evaluate(&infinite), Err(FreightError::Interrupted));
+}
+
+fn if_else(
+    condition: Expression<TestTypeSystem>,
+    then_branch: Expression<TestTypeSystem>,
+    else_branch: Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    Expression::Conditional {
+        condition: condition.into(),
+        then_branch: then_branch.into(),
+        else_branch: Some(else_branch.into()),
+    }
+}
+
+#[test]
+fn test_loop_break_past_outer_loop() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let [outer, inner] = [(); 2].map(|_| engine.create_return_target());
+    // Break the inner loop on its own until the third outer iteration, then break both
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(0).into()),
+        Expression::Loop {
+            target: outer,
+            body: Expression::Sequence(vec![
+                increment(0),
+                Expression::Loop {
+                    target: inner,
+                    body: if_else(
+                        less_than(0, 3),
+                        Expression::Return(inner, number(0).into()),
+                        Expression::Return(outer, add(Expression::stack(0), number(100)).into()),
+                    )
+                    .into(),
+                },
+            ])
+            .into(),
+        },
+    ]);
+    assert_eq!(engine.eval(&expr, 1), Ok(value(103)));
+}
+
+#[test]
+fn test_loop_continue_levels() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let [outer, inner] = [(); 2].map(|_| engine.create_return_target());
+    let [i, j, sum] = [0, 1, 2];
+    // Each outer iteration runs the inner body twice, continuing the inner loop
+    // the first time and the outer loop the second time
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(i, number(0).into()),
+        Expression::AssignStack(sum, number(0).into()),
+        Expression::Loop {
+            target: outer,
+            body: Expression::Sequence(vec![
+                if_else(
+                    less_than(i, 3),
+                    increment(i),
+                    Expression::Return(outer, Expression::stack(sum).into()),
+                ),
+                Expression::AssignStack(j, number(0).into()),
+                Expression::Loop {
+                    target: inner,
+                    body: Expression::Sequence(vec![
+                        increment(j),
+                        increment(sum),
+                        if_else(
+                            less_than(j, 2),
+                            Expression::Continue(inner),
+                            Expression::Continue(outer),
+                        ),
+                        Expression::AssignStack(sum, number(-100).into()),
+                    ])
+                    .into(),
+                },
+                Expression::AssignStack(sum, number(-100).into()),
+            ])
+            .into(),
+        },
+    ]);
+    assert_eq!(engine.eval(&expr, 3), Ok(value(6)));
+}
+
+#[test]
+fn test_infinite_loop_is_abortable() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let target = engine.create_return_target();
+    let infinite = Expression::Loop {
+        target,
+        body: number(0).into(),
+    };
+    engine.set_fuel(Some(1000));
+    assert_eq!(
+        engine.evaluate(&infinite),
+        Err(FreightError::OutOfFuel { consumed: 1000 })
+    );
+}
+
+/// Builds a function which sums the elements of its argument with a `For` loop,
+/// returning early with the partial sum when an element is 0
+fn sum_elements(engine: &mut ExecutionEngine<TestTypeSystem>) -> FunctionRef<TestTypeSystem> {
+    let target = engine.create_return_target();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    let sum = func.create_variable();
+    let elem = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(sum, number(0).into()));
+    func.evaluate_expression(Expression::For {
+        binding: elem,
+        iterable: Expression::stack(0).into(),
+        body: Expression::Conditional {
+            condition: Expression::stack(elem).into(),
+            then_branch: Expression::AssignStack(
+                sum,
+                Expression::BinaryOpEval(
+                    TestBinaryOperator::Add,
+                    [Expression::stack(sum), Expression::stack(elem)].into(),
+                )
+                .into(),
+            )
+            .into(),
+            else_branch: Some(Expression::Return(target, Expression::stack(sum).into()).into()),
+        }
+        .into(),
+    });
+    func.evaluate_expression(Expression::stack(sum));
+    engine.register_function(func, target).unwrap()
+}
+
+#[test]
+fn test_for_over_list() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = sum_elements(&mut engine);
+    assert_eq!(engine.call(&func, [list(&[1, 2, 3])]), Ok(value(6)));
+    assert_eq!(engine.call(&func, [list(&[])]), Ok(value(0)));
+    assert_eq!(engine.call(&func, [list(&[4, 5, 0, 6])]), Ok(value(9)));
+}
+
+#[test]
+fn test_for_over_custom_iterable() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = sum_elements(&mut engine);
+    // Numbers iterate from 0, which immediately returns
+    assert_eq!(engine.call(&func, [value(10)]), Ok(value(0)));
+    assert_eq!(
+        engine.call(&func, [TestValueWrapper(TestValue::Null)]),
+        Err(FreightError::NotIterable)
+    );
+}
+
+#[test]
+fn test_for_aborts_on_error() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let elem = func.create_variable();
+    func.evaluate_expression(Expression::For {
+        binding: elem,
+        iterable: number(1000).into(),
+        body: Expression::AssignGlobal(global, Expression::stack(elem).into()).into(),
+    });
+    let func = engine.register_function(func, 0).unwrap();
+    engine.set_fuel(Some(100));
+    assert_eq!(
+        engine.call(&func, []),
+        Err(FreightError::OutOfFuel { consumed: 100 })
+    );
+    assert_eq!(engine.get_global(global), Some(&value(47)));
+}
+
+#[test]
+fn test_sequence() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+
+    let target = engine.create_return_target();
+    let mut double = FunctionWriter::new(ArgCount::Fixed(1));
+    double.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::stack(0), Expression::stack(0)].into(),
+    ));
+    let double = engine.register_function(double, target).unwrap();
+
+    let target = engine.create_return_target();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    let x = func.create_variable();
+    func.evaluate_expression(Expression::Sequence(vec![
+        Expression::AssignStack(
+            x,
+            Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::stack(0).into()).into(),
+        ),
+        Expression::AssignGlobal(
+            global,
+            Expression::StaticFunctionCall(double, vec![Expression::stack(x)]).into(),
+        ),
+        Expression::stack(x),
+    ]));
+    let func = engine.register_function(func, target).unwrap();
+
+    assert_eq!(engine.call(&func, [value(4)]), Ok(value(5)));
+    assert_eq!(engine.get_global(global), Some(&value(10)));
+    assert_eq!(
+        engine.evaluate(&Expression::Sequence(vec![])),
+        Ok(TestValueWrapper(TestValue::Null))
+    );
+}
+
+#[test]
+fn test_sequence_return_skips_remaining() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let target = engine.create_return_target();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::Sequence(vec![
+        Expression::Return(target, number(1).into()),
+        Expression::AssignGlobal(global, number(2).into()),
+        number(3),
+    ]));
+    let func = engine.register_function(func, target).unwrap();
+
+    assert_eq!(engine.call(&func, []), Ok(value(1)));
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Null))
+    );
+}
+
+/// Invoking a non-function value, which always fails with [not_callable]
+fn failing() -> Expression<TestTypeSystem> {
+    Expression::DynamicFunctionCall(number(1).into(), vec![])
+}
+
+fn not_callable() -> FreightError {
+    FreightError::NotCallable {
+        type_name: "Number".into(),
+    }
+}
+
+fn try_catch(
+    body: Expression<TestTypeSystem>,
+    error_slot: usize,
+    handler: Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    Expression::TryCatch {
+        body: body.into(),
+        error_slot,
+        handler: handler.into(),
+    }
+}
+
+#[test]
+fn test_try_catch() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(
+        engine.eval(&try_catch(number(1), 0, number(2)), 1),
+        Ok(value(1))
+    );
+    assert_eq!(
+        engine.eval(&try_catch(failing(), 0, Expression::stack(0)), 1),
+        Ok(TestValueWrapper(TestValue::Error(not_callable())))
+    );
+}
+
+#[test]
+fn test_try_catch_nested() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let inner = try_catch(failing(), 0, number(1));
+    assert_eq!(
+        engine.eval(&try_catch(inner, 1, number(2)), 2),
+        Ok(value(1))
+    );
+
+    // An error in the inner handler is caught by the outer one
+    let inner = try_catch(failing(), 0, failing());
+    let caught = engine.eval(&try_catch(inner, 1, Expression::stack(1)), 2);
+    assert_eq!(
+        caught,
+        Ok(TestValueWrapper(TestValue::Error(not_callable())))
+    );
+}
+
+#[test]
+fn test_try_catch_handler_error_propagates() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(
+        engine.eval(&try_catch(failing(), 0, failing()), 1),
+        Err(not_callable())
+    );
+}
+
+#[test]
+fn test_try_catch_passes_through_returns_and_aborts() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let target = engine.create_return_target();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let error = func.create_variable();
+    func.evaluate_expression(try_catch(
+        Expression::Return(target, number(1).into()),
+        error,
+        Expression::AssignGlobal(global, number(2).into()),
+    ));
+    let func = engine.register_function(func, target).unwrap();
+    assert_eq!(engine.call(&func, []), Ok(value(1)));
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Null))
+    );
+
+    let infinite = Expression::While {
+        condition: number(1).into(),
+        body: number(0).into(),
+    };
+    engine.interrupt_handle().interrupt();
+    assert_eq!(
+        engine.eval(&try_catch(infinite, 0, number(2)), 1),
+        Err(FreightError::Interrupted)
+    );
+}
+
+fn match_number(
+    scrutinee: i64,
+    arms: &[(i64, i64)],
+    default: Option<i64>,
+) -> Expression<TestTypeSystem> {
+    Expression::Match {
+        scrutinee: number(scrutinee).into(),
+        arms: arms
+            .iter()
+            .map(|&(constant, result)| (value(constant), number(result)))
+            .collect(),
+        default: default.map(|n| number(n).into()),
+    }
+}
+
+#[test]
+fn test_match() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let arms = [(1, 10), (2, 20), (3, 30)];
+    assert_eq!(
+        engine.evaluate(&match_number(2, &arms, None)),
+        Ok(value(20))
+    );
+    assert_eq!(
+        engine.evaluate(&match_number(5, &arms, Some(99))),
+        Ok(value(99))
+    );
+    assert_eq!(
+        engine.evaluate(&match_number(5, &arms, None)),
+        Err(FreightError::NoMatchingArm)
+    );
+}
+
+#[test]
+fn test_match_duplicate_constants() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let arms = [(1, 10), (1, 20)];
+    assert_eq!(
+        engine.evaluate(&match_number(1, &arms, Some(99))),
+        Ok(value(10))
+    );
+}
+
+#[test]
+fn test_match_evaluates_scrutinee_once() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    engine.set_global(global, value(0)).unwrap();
+    let scrutinee = Expression::Sequence(vec![
+        Expression::AssignGlobal(
+            global,
+            Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::global(global).into())
+                .into(),
+        ),
+        Expression::global(global),
+    ]);
+    let matched = Expression::Match {
+        scrutinee: scrutinee.into(),
+        arms: vec![(value(0), number(0)), (value(1), number(10))],
+        default: None,
+    };
+    assert_eq!(engine.evaluate(&matched), Ok(value(10)));
+    assert_eq!(engine.get_global(global), Some(&value(1)));
+}
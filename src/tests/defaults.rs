@@ -0,0 +1,164 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::Expression,
+    function::{ArgCount, FunctionRef, FunctionWriter},
+    value::Value,
+};
+
+use super::{
+    add, list, logged, number,
+    type_system::{
+        TestBinaryOperator, TestInitializer, TestTypeSystem, TestValue, TestValueWrapper,
+    },
+    value,
+};
+
+/// Registers `f(a, b = a + 1, c = log(b + 10))`, which returns its arguments in a list
+fn with_defaults(engine: &mut ExecutionEngine<TestTypeSystem>) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(ArgCount::Range { min: 1, max: 3 });
+    func.set_default(1, add(Expression::stack(0), number(1)))
+        .unwrap();
+    func.set_default(2, logged(add(Expression::stack(1), number(10))))
+        .unwrap();
+    func.evaluate_expression(Expression::Initialize(
+        TestInitializer::List,
+        (0..3).map(Expression::stack).collect(),
+    ));
+    engine.register_function(func, 0).unwrap()
+}
+
+#[test]
+fn test_defaults_fill_omitted_arguments() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = with_defaults(&mut engine);
+    assert_eq!(engine.call(&func, [value(1)]), Ok(list(&[1, 2, 12])));
+    assert_eq!(engine.context.output, ["Number(12)"]);
+
+    // Defaults use the arguments which were passed, and aren't evaluated for them
+    assert_eq!(
+        engine.call(&func, [value(1), value(5)]),
+        Ok(list(&[1, 5, 15]))
+    );
+    assert_eq!(
+        engine.call(&func, [value(1), value(5), value(0)]),
+        Ok(list(&[1, 5, 0]))
+    );
+    assert_eq!(engine.context.output, ["Number(12)", "Number(15)"]);
+
+    // Calls from expressions fill them in too
+    let call = Expression::StaticFunctionCall(func.clone(), vec![number(7)]);
+    assert_eq!(engine.evaluate(&call), Ok(list(&[7, 8, 18])));
+}
+
+#[test]
+fn test_arguments_without_defaults_stay_uninitialized() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Range { min: 0, max: 2 });
+    func.set_default(1, number(4)).unwrap();
+    func.evaluate_expression(Expression::Initialize(
+        TestInitializer::List,
+        (0..2).map(Expression::stack).collect(),
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+    let Ok(TestValueWrapper(TestValue::List(values))) = engine.call(&func, []) else {
+        panic!("expected a list");
+    };
+    assert!(values[0].is_uninitialized());
+    assert_eq!(values[1], value(4));
+}
+
+#[test]
+fn test_defaults_only_for_optional_arguments() {
+    let mut func = FunctionWriter::<TestTypeSystem>::new(ArgCount::Range { min: 1, max: 3 });
+    assert_eq!(
+        func.set_default(0, number(1)),
+        Err(FreightError::NotAnOptionalArgument {
+            arg: 0,
+            min: 1,
+            max: 3
+        })
+    );
+    assert!(func.set_default(3, number(1)).is_err());
+    assert_eq!(
+        FunctionWriter::<TestTypeSystem>::new(ArgCount::Fixed(2))
+            .set_default(1, number(1))
+            .unwrap_err()
+            .to_string(),
+        "Argument 1 can't have a default, since no arguments are optional"
+    );
+
+    // Defaults are checked like the body when the function is registered
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    func.set_default(2, Expression::stack(5)).unwrap();
+    assert!(engine.register_function(func, 0).is_err());
+}
+
+#[test]
+fn test_failing_defaults_are_traced() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_error_traces(true);
+    let mut func = FunctionWriter::new(ArgCount::Range { min: 1, max: 2 });
+    func.set_name("divide");
+    func.set_default(
+        1,
+        Expression::BinaryOpEval(
+            TestBinaryOperator::Div,
+            [number(1), Expression::stack(0)].into(),
+        ),
+    )
+    .unwrap();
+    func.evaluate_expression(Expression::stack(1));
+    let func = engine.register_function(func, 0).unwrap();
+    assert_eq!(engine.call(&func, [value(1)]), Ok(value(1)));
+    let err = engine.call(&func, [value(0)]).unwrap_err();
+    assert_eq!(
+        err.trace(),
+        [
+            "operator /".to_owned(),
+            "default of arg 1 of divide".to_owned()
+        ]
+    );
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_defaults_with_variadic_arguments() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // f(a, b = a + 1, ...rest)
+    let mut func = FunctionWriter::new(ArgCount::Variadic { min: 1, max: 2 });
+    func.set_default(1, add(Expression::stack(0), number(1)))
+        .unwrap();
+    // Only positional arguments can have defaults
+    assert!(func.set_default(2, number(0)).is_err());
+    func.evaluate_expression(Expression::Initialize(
+        TestInitializer::List,
+        (0..3).map(Expression::stack).collect(),
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+
+    assert_eq!(
+        engine.call(&func, [value(1)]),
+        Ok(TestValueWrapper(TestValue::List(vec![
+            value(1),
+            value(2),
+            list(&[])
+        ])))
+    );
+    assert_eq!(
+        engine.call(&func, [value(1), value(5)]),
+        Ok(TestValueWrapper(TestValue::List(vec![
+            value(1),
+            value(5),
+            list(&[])
+        ])))
+    );
+    assert_eq!(
+        engine.call(&func, [value(1), value(5), value(6), value(7)]),
+        Ok(TestValueWrapper(TestValue::List(vec![
+            value(1),
+            value(5),
+            list(&[6, 7])
+        ])))
+    );
+}
@@ -0,0 +1,148 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::{DebugInfo, Expression, NativeFunction, VariableType},
+    function::{ArgCount, FunctionInfo, FunctionRef, FunctionWriter},
+};
+
+use super::{
+    type_system::{TestTypeSystem, TestValue, TestValueWrapper},
+    value,
+};
+
+fn function(func: FunctionRef<TestTypeSystem>) -> TestValueWrapper {
+    TestValueWrapper(TestValue::Function(func))
+}
+
+#[test]
+fn test_describe_plain_function() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let source = DebugInfo {
+        file: 2,
+        start: 10,
+        end: 20,
+    };
+    let mut func = FunctionWriter::new(ArgCount::new(1..=2));
+    func.create_variable();
+    func.set_source(source.clone());
+    func.evaluate_expression(Expression::stack(0));
+    let func = engine.register_function_named("first", func, 0).unwrap();
+
+    let info = FunctionInfo {
+        arg_count: ArgCount::Range { min: 1, max: 2 },
+        variadic: false,
+        captures: 0,
+        bound: 0,
+        stack_size: 3,
+        location: func.address(),
+        native: false,
+        name: Some("first".into()),
+        source: Some(source),
+    };
+    assert_eq!(engine.describe_function(&func), info);
+    assert_eq!(engine.describe_value(&function(func)), Some(info));
+    assert_eq!(engine.describe_value(&value(1)), None);
+}
+
+#[test]
+fn test_describe_capturing_function() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut closure = FunctionWriter::new_capturing(
+        ArgCount::Fixed(0),
+        vec![VariableType::Stack(0), VariableType::Stack(1)],
+    );
+    closure.evaluate_expression(Expression::Variable(VariableType::Captured(1)));
+    let closure = engine.register_function(closure, 0).unwrap();
+    let mut make = FunctionWriter::new(ArgCount::Fixed(2));
+    make.evaluate_expression(Expression::FunctionCapture(closure.clone()));
+    let make = engine.register_function(make, 0).unwrap();
+
+    let definition = engine.describe_function(&closure);
+    assert_eq!(definition.captures, 2);
+    let instance = engine.call(&make, [value(1), value(2)]).unwrap();
+    let info = engine.describe_value(&instance).unwrap();
+    assert_eq!(info, definition);
+    assert_eq!(info.arg_count, ArgCount::Fixed(0));
+    assert!(!info.native);
+}
+
+#[test]
+fn test_describe_bound_function() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(3));
+    func.set_name("three");
+    func.evaluate_expression(Expression::stack(2));
+    let func = engine.register_function(func, 0).unwrap();
+
+    let once = func.bind(vec![value(1)]).unwrap();
+    let twice = once.bind(vec![value(2)]).unwrap();
+    let info = engine.describe_function(&twice);
+    assert_eq!(info.arg_count, ArgCount::Fixed(1));
+    assert_eq!(info.bound, 2);
+    assert_eq!(info.captures, 0);
+    assert_eq!(info.location, func.address());
+    assert_eq!(info.name.as_deref(), Some("three"));
+}
+
+#[test]
+fn test_describe_variadic_and_native_functions() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = FunctionWriter::new(ArgCount::at_least(1));
+    let func = engine.register_function(func, 0).unwrap();
+    let info = engine.describe_function(&func);
+    assert!(info.variadic);
+    assert_eq!(info.arg_count.max(), None);
+    assert_eq!(info.stack_size, 2);
+
+    let arity = engine.register_native(NativeFunction::arity(), ArgCount::Fixed(1));
+    let info = engine.describe_function(&arity);
+    assert!(info.native);
+    assert!(!info.variadic);
+    assert_eq!(info.name, None);
+    assert_eq!(info.source, None);
+}
+
+#[test]
+fn test_arity_native() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let arity = engine.register_native(NativeFunction::arity(), ArgCount::Fixed(1));
+    let range = engine
+        .register_function(FunctionWriter::new(ArgCount::new(1..=3)), 0)
+        .unwrap();
+    let variadic = engine
+        .register_function(FunctionWriter::new(ArgCount::at_least(2)), 0)
+        .unwrap();
+
+    // Called by a script function given the function to describe
+    let mut describe = FunctionWriter::new(ArgCount::Fixed(1));
+    describe.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::RawValue(function(arity.clone())).into(),
+        vec![Expression::stack(0)],
+    ));
+    let describe = engine.register_function(describe, 0).unwrap();
+
+    let pair = |min, max| TestValueWrapper(TestValue::List(vec![value(min), max]));
+    assert_eq!(
+        engine.call(&describe, [function(range)]),
+        Ok(pair(1, value(3)))
+    );
+    assert_eq!(
+        engine.call(&describe, [function(variadic.clone())]),
+        Ok(pair(2, TestValueWrapper(TestValue::Null)))
+    );
+    assert_eq!(
+        engine.call(
+            &describe,
+            [function(variadic.bind(vec![value(0)]).unwrap())]
+        ),
+        Ok(pair(1, TestValueWrapper(TestValue::Null)))
+    );
+    assert_eq!(
+        engine.call(&arity, [function(arity.clone())]),
+        Ok(pair(1, value(1)))
+    );
+    assert!(matches!(
+        engine.call(&arity, [value(1)]),
+        Err(FreightError::TypeMismatch { .. })
+    ));
+}
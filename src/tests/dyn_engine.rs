@@ -0,0 +1,106 @@
+use crate::{
+    dyn_engine::{DynExecutionEngine, DynValue, ValueBridge},
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::Expression,
+    function::{ArgCount, FunctionWriter},
+    TypeSystem,
+};
+
+use super::text_type_system::{TextOperator, TextTypeSystem, TextValue};
+use super::type_system::{TestBinaryOperator, TestTypeSystem, TestValue, TestValueWrapper};
+
+impl ValueBridge for TestValueWrapper {
+    fn to_dyn(&self) -> DynValue {
+        match self.resolve() {
+            TestValue::Number(n) => Box::new(n),
+            _ => Box::new(()),
+        }
+    }
+
+    fn from_dyn(value: DynValue) -> Result<Self, DynValue> {
+        value
+            .downcast::<i64>()
+            .map(|n| TestValueWrapper(TestValue::Number(n)))
+    }
+}
+
+impl ValueBridge for TextValue {
+    fn to_dyn(&self) -> DynValue {
+        match self {
+            TextValue::Text(text) => Box::new(text.clone()),
+            _ => Box::new(()),
+        }
+    }
+
+    fn from_dyn(value: DynValue) -> Result<Self, DynValue> {
+        value.downcast::<String>().map(TextValue::Text)
+    }
+}
+
+/// Builds an engine whose first function combines its arguments and whose entry point reads global 0
+fn build_engine<TS: TypeSystem>(op: TS::BinaryOp) -> Box<dyn DynExecutionEngine>
+where
+    TS::Value: ValueBridge,
+    TS::GlobalContext: Default,
+{
+    let mut engine = ExecutionEngine::<TS>::new_default();
+    let global = engine.create_global();
+    let mut combine = FunctionWriter::new(ArgCount::Fixed(2));
+    combine.evaluate_expression(Expression::BinaryOpEval(
+        op,
+        [Expression::stack(0), Expression::stack(1)].into(),
+    ));
+    engine.register_function(combine, 0).unwrap();
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    main.evaluate_expression(Expression::global(global));
+    let main = engine.register_function(main, 0).unwrap();
+    engine.set_entry_point(main);
+    engine.into_dyn()
+}
+
+#[test]
+fn test_dyn_engines_share_interface() {
+    let mut engines = [
+        build_engine::<TestTypeSystem>(TestBinaryOperator::Add),
+        build_engine::<TextTypeSystem>(TextOperator::Concat),
+    ];
+    let inputs: [fn() -> DynValue; 2] = [|| Box::new(20i64), || Box::new(String::from("ab"))];
+
+    for (engine, input) in engines.iter_mut().zip(inputs) {
+        let combined = engine.call_by_index(0, vec![input(), input()]).unwrap();
+        engine.set_global(0, combined).unwrap();
+        let result = engine.run().unwrap();
+        assert_eq!(
+            format!("{:?}", engine.get_global(0).unwrap()),
+            format!("{result:?}")
+        );
+    }
+
+    assert_eq!(engines[0].run().unwrap().downcast_ref::<i64>(), Some(&40));
+    assert_eq!(
+        engines[1].run().unwrap().downcast_ref::<String>(),
+        Some(&String::from("abab"))
+    );
+}
+
+#[test]
+fn test_dyn_engine_rejects_foreign_values() {
+    let mut engine = build_engine::<TextTypeSystem>(TextOperator::Concat);
+    assert_eq!(
+        engine.set_global(0, Box::new(5i64)),
+        Err(FreightError::IncompatibleDynValue { value: "5".into() })
+    );
+    assert_eq!(
+        engine
+            .call_by_index(0, vec![Box::new(String::new()), Box::new(1.5f64)])
+            .err(),
+        Some(FreightError::IncompatibleDynValue {
+            value: "1.5".into()
+        })
+    );
+    assert_eq!(
+        engine.call_by_index(2, vec![]).err(),
+        Some(FreightError::InvalidFunctionLocation { location: 2 })
+    );
+}
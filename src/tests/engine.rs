@@ -0,0 +1,1747 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    error::{AddressKind, FreightError},
+    execution_engine::{
+        builder::EngineBuilder, hooks::CallHooks, scope::EvalScope, stats::ExecutionStats,
+        ExecutionEngine, Stack,
+    },
+    expression::{DebugInfo, Expression, NativeFunction, NativeMacro, VariableType},
+    function::{ArgCount, CaptureLayout, FunctionRef, FunctionWriter, StackLayout},
+    value::Value,
+};
+
+use super::{
+    alloc_counter::count_allocations,
+    list, number, raw_list,
+    type_system::{
+        TestBinaryOperator, TestContext, TestInitializer, TestTypeSystem, TestUnaryOperator,
+        TestValue, TestValueWrapper,
+    },
+};
+
+/// Builds an entry point which returns the initial value of `global` and then overwrites it
+fn read_then_write_global(engine: &mut ExecutionEngine<TestTypeSystem>, global: usize) {
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    let initial = main.create_variable();
+    main.evaluate_expression(Expression::AssignStack(
+        initial,
+        Expression::global(global).into(),
+    ));
+    main.evaluate_expression(Expression::AssignGlobal(global, number(7).into()));
+    main.evaluate_expression(Expression::stack(initial));
+    let main = engine.register_function(main, 0).unwrap();
+    engine.set_entry_point(main);
+}
+
+#[test]
+fn test_run_twice() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    read_then_write_global(&mut engine, global);
+
+    let first = engine.run().unwrap();
+    assert_eq!(
+        engine.globals[global],
+        TestValueWrapper(TestValue::Number(7))
+    );
+    let second = engine.run().unwrap();
+    assert_eq!(first, TestValueWrapper(TestValue::Null));
+    assert_eq!(first, second);
+    assert_eq!(engine.return_value, TestValueWrapper::default());
+}
+
+#[test]
+fn test_run_after_create_global() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    read_then_write_global(&mut engine, global);
+    engine.run().unwrap();
+
+    let other = engine.create_global();
+    assert_eq!(other, 1);
+    engine.run().unwrap();
+    assert_eq!(engine.globals.len(), 2);
+    assert_eq!(engine.globals[other], TestValueWrapper(TestValue::Null));
+}
+
+#[test]
+fn test_global_out_of_range() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.create_global();
+    read_then_write_global(&mut engine, 1);
+    assert_eq!(
+        engine.run(),
+        Err(FreightError::GlobalOutOfRange {
+            address: 1,
+            num_globals: 1
+        })
+    );
+}
+
+#[test]
+fn test_missing_entry_point() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(engine.run(), Err(FreightError::MissingEntryPoint));
+}
+
+fn add_entry_point(engine: &mut ExecutionEngine<TestTypeSystem>, args: ArgCount) {
+    let mut main = FunctionWriter::new(args);
+    main.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::stack(0), Expression::stack(1)].into(),
+    ));
+    let main = engine.register_function(main, 0).unwrap();
+    engine.set_entry_point(main);
+}
+
+#[test]
+fn test_run_with_args() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    add_entry_point(&mut engine, ArgCount::Fixed(2));
+    assert_eq!(
+        engine.run_with_args(vec![
+            TestValueWrapper(TestValue::Number(2)),
+            TestValueWrapper(TestValue::Number(3)),
+        ]),
+        Ok(TestValueWrapper(TestValue::Number(5)))
+    );
+}
+
+#[test]
+fn test_run_with_too_few_args() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    add_entry_point(&mut engine, ArgCount::Fixed(2));
+    assert_eq!(
+        engine.run_with_args(vec![TestValueWrapper(TestValue::Number(2))]),
+        Err(FreightError::IncorrectArgumentCount {
+            expected_min: 2,
+            expected_max: Some(2),
+            actual: 1,
+            function: Some(0),
+            name: None,
+        })
+    );
+}
+
+#[test]
+fn test_run_with_too_many_args() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    add_entry_point(&mut engine, ArgCount::Range { min: 1, max: 2 });
+    assert_eq!(
+        engine.run_with_args(vec![TestValueWrapper(TestValue::Number(2)); 3]),
+        Err(FreightError::IncorrectArgumentCount {
+            expected_min: 1,
+            expected_max: Some(2),
+            actual: 3,
+            function: Some(0),
+            name: None,
+        })
+    );
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_run_with_variadic_args() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut main = FunctionWriter::new(ArgCount::new_variadic(1..));
+    main.evaluate_expression(Expression::stack(1));
+    let main = engine.register_function(main, 0).unwrap();
+    engine.set_entry_point(main);
+    let args = vec![
+        TestValueWrapper(TestValue::Number(1)),
+        TestValueWrapper(TestValue::Number(2)),
+        TestValueWrapper(TestValue::Number(3)),
+    ];
+    assert_eq!(
+        engine.run_with_args(args.clone()),
+        Ok(TestValueWrapper(TestValue::List(args[1..].to_vec())))
+    );
+}
+
+fn double(engine: &mut ExecutionEngine<TestTypeSystem>) -> FunctionRef<TestTypeSystem> {
+    let mut double = FunctionWriter::new(ArgCount::Fixed(1));
+    double.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::stack(0), Expression::stack(0)].into(),
+    ));
+    engine.register_function(double, 0).unwrap()
+}
+
+#[test]
+fn test_tail_call_replaces_caller() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let double = double(&mut engine);
+    let mut identity = FunctionWriter::new(ArgCount::Fixed(1));
+    identity.evaluate_expression(Expression::stack(0));
+    let identity = engine.register_function(identity, 0).unwrap();
+
+    // The tail call ends the caller even when nested in another call's arguments
+    let mut caller = FunctionWriter::new(ArgCount::Fixed(0));
+    caller.evaluate_expression(Expression::AssignGlobal(
+        global,
+        Expression::StaticFunctionCall(
+            identity,
+            vec![Expression::TailCall(double, vec![number(3)])],
+        )
+        .into(),
+    ));
+    caller.evaluate_expression(number(100));
+    let caller = engine.register_function(caller, 0).unwrap();
+    assert_eq!(
+        engine.call(&caller, []),
+        Ok(TestValueWrapper(TestValue::Number(6)))
+    );
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Null))
+    );
+    assert_eq!(engine.stats().tail_calls, 1);
+}
+
+#[test]
+fn test_tail_call_from_expression() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let double = double(&mut engine);
+    assert_eq!(
+        engine.evaluate(&Expression::TailCall(double, vec![number(4)])),
+        Ok(TestValueWrapper(TestValue::Number(8)))
+    );
+    assert_eq!(engine.stack.with(|stack| stack.in_use()), 0);
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_tail_call_variadic() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut rest = FunctionWriter::new(ArgCount::new_variadic(1..));
+    rest.evaluate_expression(Expression::stack(1));
+    let rest = engine.register_function(rest, 0).unwrap();
+    let call = Expression::TailCall(rest, vec![number(1), number(2), number(3)]);
+    assert_eq!(
+        engine.evaluate(&call),
+        Ok(TestValueWrapper(TestValue::List(vec![
+            TestValueWrapper(TestValue::Number(2)),
+            TestValueWrapper(TestValue::Number(3)),
+        ])))
+    );
+}
+
+#[test]
+fn test_tail_call_capturing_definition() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let closure = FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![]);
+    let closure = engine.register_function(closure, 0).unwrap();
+    assert_eq!(
+        engine.evaluate(&Expression::TailCall(closure, vec![])),
+        Err(FreightError::InvalidInvocationTarget)
+    );
+}
+
+fn spread(list: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::Spread(list.into())
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_spread_into_variadic() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut rest = FunctionWriter::new(ArgCount::new_variadic(1..));
+    rest.evaluate_expression(Expression::stack(1));
+    let rest = engine.register_function(rest, 0).unwrap();
+
+    let call = Expression::StaticFunctionCall(
+        rest.clone(),
+        vec![spread(raw_list(&[1, 2])), number(3), spread(raw_list(&[4]))],
+    );
+    let expected = [2, 3, 4].map(|n| TestValueWrapper(TestValue::Number(n)));
+    assert_eq!(
+        engine.evaluate(&call),
+        Ok(TestValueWrapper(TestValue::List(expected.to_vec())))
+    );
+
+    let call = Expression::DynamicFunctionCall(
+        Expression::RawValue(rest.clone().into()).into(),
+        vec![spread(raw_list(&[]))],
+    );
+    assert_eq!(
+        engine.evaluate(&call),
+        Err(FreightError::IncorrectArgumentCount {
+            expected_min: 1,
+            expected_max: None,
+            actual: 0,
+            function: Some(rest.location),
+            name: None,
+        })
+    );
+}
+
+#[test]
+fn test_dynamic_call_diagnostics() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let double = double(&mut engine);
+    let call = |func, args| {
+        let call = Expression::DynamicFunctionCall(Expression::RawValue(func).into(), args);
+        let span = DebugInfo {
+            file: 2,
+            start: 5,
+            end: 15,
+        };
+        Expression::Spanned(span, call.into())
+    };
+
+    let err = engine
+        .evaluate(&call(TestValueWrapper(TestValue::Number(1)), vec![]))
+        .unwrap_err();
+    assert_eq!(
+        err.root(),
+        &FreightError::NotCallable {
+            type_name: "Number".into()
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "Cannot invoke a value of type Number at file 2 bytes 5..15"
+    );
+
+    let err = engine
+        .evaluate(&call(double.clone().into(), vec![number(1), number(2)]))
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "Function #{} expected exactly 1 argument, got 2 at file 2 bytes 5..15",
+            double.location
+        )
+    );
+}
+
+#[test]
+fn test_spread_checks_arg_count() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let double = double(&mut engine);
+    let call = |args| Expression::StaticFunctionCall(double.clone(), vec![spread(raw_list(args))]);
+    assert_eq!(
+        engine.evaluate(&call(&[4])),
+        Ok(TestValueWrapper(TestValue::Number(8)))
+    );
+    assert_eq!(
+        engine.evaluate(&call(&[4, 5])),
+        Err(FreightError::IncorrectArgumentCount {
+            expected_min: 1,
+            expected_max: Some(1),
+            actual: 2,
+            function: Some(double.location),
+            name: None,
+        })
+    );
+    assert_eq!(
+        engine.evaluate(&Expression::TailCall(double, vec![spread(raw_list(&[5]))])),
+        Ok(TestValueWrapper(TestValue::Number(10)))
+    );
+}
+
+#[test]
+fn test_spread_non_list() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let double = double(&mut engine);
+    let call = Expression::StaticFunctionCall(double, vec![spread(number(1))]);
+    assert_eq!(engine.evaluate(&call), Err(FreightError::NotSpreadable));
+}
+
+#[test]
+fn test_destructure_into_stack_and_globals() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    let a = main.create_variable();
+    let b = main.create_variable();
+    main.evaluate_expression(Expression::DestructureAssign {
+        targets: vec![
+            VariableType::Stack(a),
+            VariableType::Global(global),
+            VariableType::Stack(b),
+        ],
+        value: raw_list(&[1, 20, 300]).into(),
+    });
+    main.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::stack(a), Expression::stack(b)].into(),
+    ));
+    let main = engine.register_function(main, 0).unwrap();
+    assert_eq!(
+        engine.call(&main, []),
+        Ok(TestValueWrapper(TestValue::Number(301)))
+    );
+    assert_eq!(
+        engine.globals[global],
+        TestValueWrapper(TestValue::Number(20))
+    );
+}
+
+#[test]
+fn test_destructure_evaluates_value_once() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let counter = engine.create_global();
+    engine.globals[counter] = TestValueWrapper(TestValue::Number(0));
+    // Each evaluation of the value increments the counter and produces a fresh list
+    let value = Expression::Sequence(vec![
+        Expression::CompoundAssign {
+            target: VariableType::Global(counter),
+            op: TestBinaryOperator::Add,
+            value: number(1).into(),
+        },
+        Expression::Initialize(
+            TestInitializer::List,
+            vec![Expression::global(counter), number(5)],
+        ),
+    ]);
+    let expr = Expression::Sequence(vec![
+        Expression::DestructureAssign {
+            targets: vec![VariableType::Stack(0), VariableType::Stack(1)],
+            value: value.into(),
+        },
+        Expression::BinaryOpEval(
+            TestBinaryOperator::Add,
+            [Expression::stack(0), Expression::stack(1)].into(),
+        ),
+    ]);
+    assert_eq!(
+        engine.eval(&expr, 2),
+        Ok(TestValueWrapper(TestValue::Number(6)))
+    );
+    assert_eq!(
+        engine.globals[counter],
+        TestValueWrapper(TestValue::Number(1))
+    );
+}
+
+#[test]
+fn test_destructure_length_mismatch() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let destructure = |value| Expression::DestructureAssign {
+        targets: vec![VariableType::Stack(0), VariableType::Stack(1)],
+        value: Box::new(value),
+    };
+    assert_eq!(
+        engine.eval(&destructure(raw_list(&[1, 2, 3])), 2),
+        Err(FreightError::DestructureMismatch {
+            expected: 2,
+            actual: 3
+        })
+    );
+    assert_eq!(
+        engine.eval(&destructure(raw_list(&[1])), 2),
+        Err(FreightError::DestructureMismatch {
+            expected: 2,
+            actual: 1
+        })
+    );
+    assert_eq!(
+        engine.eval(&destructure(number(1)), 2),
+        Err(FreightError::NotDestructurable)
+    );
+}
+
+#[test]
+fn test_spread_outside_call() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let double = double(&mut engine);
+    let misplaced = [
+        spread(raw_list(&[1])),
+        Expression::UnaryOpEval(TestUnaryOperator::Inc, spread(raw_list(&[1])).into()),
+        Expression::DynamicFunctionCall(spread(raw_list(&[1])).into(), vec![]),
+        Expression::StaticFunctionCall(
+            double,
+            vec![Expression::Sequence(vec![spread(raw_list(&[1]))])],
+        ),
+    ];
+    for expr in misplaced {
+        let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+        func.evaluate_expression(expr);
+        assert_eq!(
+            engine.register_function(func, 0).map(|_| ()),
+            Err(FreightError::SpreadOutsideCall)
+        );
+    }
+    assert_eq!(
+        engine.evaluate(&spread(raw_list(&[1]))),
+        Err(FreightError::SpreadOutsideCall)
+    );
+}
+
+#[derive(Default)]
+struct RecordingHooks(Arc<Mutex<Vec<String>>>);
+
+impl CallHooks<TestTypeSystem> for RecordingHooks {
+    fn on_call(&mut self, func: &FunctionRef<TestTypeSystem>, args: &[TestValueWrapper]) {
+        let args: Vec<_> = args.iter().map(TestValueWrapper::resolve).collect();
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("call #{} {:?}", func.address(), args));
+    }
+
+    fn on_return(&mut self, func: &FunctionRef<TestTypeSystem>, value: &TestValueWrapper) {
+        self.0
+            .lock()
+            .unwrap()
+            .push(format!("return #{} {:?}", func.address(), value.resolve()));
+    }
+}
+
+#[test]
+fn test_call_hooks() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut inc = FunctionWriter::new(ArgCount::Fixed(1));
+    let target = engine.create_return_target();
+    inc.evaluate_expression(Expression::Return(
+        target,
+        Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::stack(0).into()).into(),
+    ));
+    let inc = engine.register_function(inc, target).unwrap();
+
+    let mut double_inc = FunctionWriter::new(ArgCount::Range { min: 0, max: 1 });
+    double_inc.evaluate_expression(Expression::StaticFunctionCall(
+        inc.clone(),
+        vec![Expression::StaticFunctionCall(inc, vec![number(1)])],
+    ));
+    let double_inc = engine.register_function(double_inc, 0).unwrap();
+
+    let log = Arc::new(Mutex::new(vec![]));
+    engine.set_hooks(RecordingHooks(log.clone()));
+    assert_eq!(
+        engine.call(&double_inc, []),
+        Ok(TestValueWrapper(TestValue::Number(3)))
+    );
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "call #1 [Uninitialized]",
+            "call #0 [Number(1)]",
+            "return #0 Number(2)",
+            "call #0 [Number(2)]",
+            "return #0 Number(3)",
+            "return #1 Number(3)",
+        ]
+    );
+
+    assert!(engine.clear_hooks().is_some());
+    engine.call(&double_inc, []).unwrap();
+    assert_eq!(log.lock().unwrap().len(), 6);
+}
+
+#[test]
+fn test_call_by_name() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut add = FunctionWriter::new(ArgCount::Fixed(2));
+    add.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::stack(0), Expression::stack(1)].into(),
+    ));
+    let add = engine.register_function_named("add", add, 0).unwrap();
+
+    assert_eq!(engine.get_function_by_name("add"), Some(add));
+    assert_eq!(engine.get_function_by_name("sub"), None);
+    assert_eq!(
+        engine.call_by_name(
+            "add",
+            [
+                TestValueWrapper(TestValue::Number(4)),
+                TestValueWrapper(TestValue::Number(5))
+            ]
+        ),
+        Ok(TestValueWrapper(TestValue::Number(9)))
+    );
+    assert_eq!(
+        engine.call_by_name("sub", []),
+        Err(FreightError::UnknownFunctionName { name: "sub".into() })
+    );
+    assert!(format!("{engine:?}").contains("\"add\": 0"));
+}
+
+#[test]
+fn test_duplicate_function_name() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine
+        .register_function_named("main", FunctionWriter::new(ArgCount::Fixed(0)), 0)
+        .unwrap();
+    assert_eq!(
+        engine.register_function_named("main", FunctionWriter::new(ArgCount::Fixed(0)), 0),
+        Err(FreightError::DuplicateFunctionName {
+            name: "main".into()
+        })
+    );
+}
+
+#[test]
+fn test_global_accessors() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let input = engine.create_global();
+    let output = engine.create_global();
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    main.evaluate_expression(Expression::AssignGlobal(
+        output,
+        Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::global(input).into()).into(),
+    ));
+    let main = engine.register_function(main, 0).unwrap();
+    engine.set_entry_point(main);
+
+    engine
+        .set_global(input, TestValueWrapper(TestValue::Number(41)))
+        .unwrap();
+    for _ in 0..2 {
+        engine.run().unwrap();
+        assert_eq!(
+            engine.get_global(input),
+            Some(&TestValueWrapper(TestValue::Number(41)))
+        );
+        assert_eq!(
+            engine.get_global(output),
+            Some(&TestValueWrapper(TestValue::Number(42)))
+        );
+    }
+    assert_eq!(
+        engine.globals().cloned().collect::<Vec<_>>(),
+        [
+            TestValueWrapper(TestValue::Number(41)),
+            TestValueWrapper(TestValue::Number(42))
+        ]
+    );
+}
+
+#[test]
+fn test_global_accessors_out_of_range() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.create_global();
+    assert_eq!(engine.get_global(1), None);
+    assert_eq!(
+        engine.set_global(1, TestValueWrapper(TestValue::Number(1))),
+        Err(FreightError::GlobalOutOfRange {
+            address: 1,
+            num_globals: 1
+        })
+    );
+}
+
+#[test]
+fn test_run_incremental() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let counter = engine.create_global();
+    engine
+        .set_global(counter, TestValueWrapper(TestValue::Number(0)))
+        .unwrap();
+
+    let mut lines = vec![];
+    for i in 1..=20 {
+        let mut line = FunctionWriter::new(ArgCount::Fixed(0));
+        line.evaluate_expression(Expression::AssignGlobal(
+            counter,
+            Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::global(counter).into())
+                .into(),
+        ));
+        line.evaluate_expression(Expression::global(counter));
+        let line = engine.register_function(line, 0).unwrap();
+        assert_eq!(
+            engine.run_incremental(&line),
+            Ok(TestValueWrapper(TestValue::Number(i)))
+        );
+        lines.push(line);
+    }
+
+    assert_eq!(
+        engine.run_incremental(&lines[0]),
+        Ok(TestValueWrapper(TestValue::Number(21)))
+    );
+}
+
+#[test]
+fn test_snapshot_restore() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let mut inc = FunctionWriter::new(ArgCount::Fixed(0));
+    inc.evaluate_expression(Expression::AssignGlobal(
+        global,
+        Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::global(global).into()).into(),
+    ));
+    let inc = engine.register_function(inc, 0).unwrap();
+    engine
+        .set_global(global, TestValueWrapper(TestValue::Number(1)))
+        .unwrap();
+
+    let snapshot = engine.snapshot();
+    let before = engine.get_global(global).unwrap().deep_clone();
+    engine.run_incremental(&inc).unwrap();
+    engine.run_incremental(&inc).unwrap();
+    assert_eq!(before, TestValueWrapper(TestValue::Number(1)));
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Number(3)))
+    );
+
+    engine.restore(snapshot).unwrap();
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Number(1)))
+    );
+    engine.run_incremental(&inc).unwrap();
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Number(2)))
+    );
+}
+
+#[test]
+fn test_restore_after_function_table_changed() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.create_global();
+    let snapshot = engine.snapshot();
+    engine
+        .register_function(FunctionWriter::new(ArgCount::Fixed(0)), 0)
+        .unwrap();
+    assert_eq!(
+        engine.restore(snapshot),
+        Err(FreightError::SnapshotMismatch {
+            expected_functions: 0,
+            actual_functions: 1,
+            expected_globals: 1,
+            actual_globals: 1,
+        })
+    );
+}
+
+#[test]
+fn test_call_with_captures() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut closure = FunctionWriter::new_capturing(
+        ArgCount::Fixed(1),
+        vec![VariableType::Stack(0), VariableType::Stack(1)],
+    );
+    closure.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [
+            Expression::stack(0),
+            Expression::BinaryOpEval(
+                TestBinaryOperator::Add,
+                [Expression::captured(0), Expression::captured(1)].into(),
+            ),
+        ]
+        .into(),
+    ));
+    let closure = engine.register_function(closure, 0).unwrap();
+
+    assert_eq!(
+        engine.call_with_captures(
+            &closure,
+            [TestValueWrapper(TestValue::Number(1))],
+            vec![
+                TestValueWrapper(TestValue::Number(10)),
+                TestValueWrapper(TestValue::Number(100))
+            ],
+        ),
+        Ok(TestValueWrapper(TestValue::Number(111)))
+    );
+    assert_eq!(
+        engine.call_with_captures(
+            &closure,
+            [TestValueWrapper(TestValue::Number(1))],
+            vec![TestValueWrapper(TestValue::Number(10))],
+        ),
+        Err(FreightError::IncorrectCaptureCount {
+            expected: 2,
+            actual: 1
+        })
+    );
+    assert_eq!(
+        engine.call(&closure, [TestValueWrapper(TestValue::Number(1))]),
+        Err(FreightError::InvalidInvocationTarget)
+    );
+}
+
+/// Builds a function which captures a counter in a closure, calls the closure three
+/// times to increment it, then returns the counter as seen by the defining frame
+fn counter_closure(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    layout: StackLayout,
+) -> FunctionRef<TestTypeSystem> {
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    outer.layout = layout;
+    let counter = outer.create_variable();
+    let closure_slot = outer.create_variable();
+
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(counter)]);
+    closure.evaluate_expression(Expression::AssignCaptured(
+        0,
+        Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::captured(0).into()).into(),
+    ));
+    closure.evaluate_expression(Expression::captured(0));
+    let closure = engine.register_function(closure, 0).unwrap();
+
+    let call = || Expression::DynamicFunctionCall(Expression::stack(closure_slot).into(), vec![]);
+    outer.evaluate_expression(Expression::AssignStack(counter, number(0).into()));
+    outer.evaluate_expression(Expression::AssignStack(
+        closure_slot,
+        Expression::FunctionCapture(closure).into(),
+    ));
+    outer.evaluate_expression(Expression::Sequence(vec![call(), call(), call()]));
+    outer.evaluate_expression(Expression::stack(counter));
+    engine.register_function(outer, 0).unwrap()
+}
+
+#[test]
+fn test_assign_captured() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let shared = counter_closure(&mut engine, StackLayout::all_alloc());
+    assert_eq!(
+        engine.call(&shared, []),
+        Ok(TestValueWrapper(TestValue::Number(3)))
+    );
+
+    // Without references the closure increments its own snapshot of the counter
+    let snapshot = counter_closure(&mut engine, StackLayout::no_alloc());
+    assert_eq!(
+        engine.call(&snapshot, []),
+        Ok(TestValueWrapper(TestValue::Number(0)))
+    );
+}
+
+/// Builds a function which creates a closure over a loop variable for each of three
+/// iterations, then returns a list of what each closure sees
+fn closures_in_loop(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    layout: CaptureLayout,
+) -> FunctionRef<TestTypeSystem> {
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    let i = outer.create_variable();
+    let closures = [(); 3].map(|_| outer.create_variable());
+
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(i)]);
+    closure.set_capture_layout(layout);
+    closure.evaluate_expression(Expression::captured(0));
+    let closure = engine.register_function(closure, 0).unwrap();
+
+    for (n, slot) in closures.into_iter().enumerate() {
+        outer.evaluate_expression(Expression::AssignStack(i, number(n as i64).into()));
+        outer.evaluate_expression(Expression::AssignStack(
+            slot,
+            Expression::FunctionCapture(closure.clone()).into(),
+        ));
+    }
+    let calls = closures
+        .map(|slot| Expression::DynamicFunctionCall(Expression::stack(slot).into(), vec![]));
+    outer.evaluate_expression(Expression::Initialize(TestInitializer::List, calls.into()));
+    engine.register_function(outer, 0).unwrap()
+}
+
+#[test]
+fn test_capture_by_value() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let by_ref = closures_in_loop(&mut engine, CaptureLayout::all_by_ref());
+    assert_eq!(engine.call(&by_ref, []), Ok(list(&[2, 2, 2])));
+    let by_value = closures_in_loop(&mut engine, CaptureLayout::all_by_value());
+    assert_eq!(engine.call(&by_value, []), Ok(list(&[0, 1, 2])));
+}
+
+#[test]
+fn test_assign_to_value_capture() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    let [shared, copied, closure_slot] = [(); 3].map(|_| outer.create_variable());
+
+    let mut closure = FunctionWriter::new_capturing(
+        ArgCount::Fixed(0),
+        vec![VariableType::Stack(shared), VariableType::Stack(copied)],
+    );
+    let mut layout = CaptureLayout::all_by_ref();
+    layout.set_by_value(1);
+    closure.set_capture_layout(layout);
+    for captured in [0, 1] {
+        closure.evaluate_expression(Expression::AssignCaptured(captured, number(5).into()));
+    }
+    let closure = engine.register_function(closure, 0).unwrap();
+    assert!(Expression::FunctionCapture(closure.clone())
+        .pretty()
+        .ends_with("[stack[0], copy stack[1]]"));
+
+    outer.evaluate_expression(Expression::AssignStack(shared, number(1).into()));
+    outer.evaluate_expression(Expression::AssignStack(copied, number(1).into()));
+    outer.evaluate_expression(Expression::AssignStack(
+        closure_slot,
+        Expression::FunctionCapture(closure).into(),
+    ));
+    outer.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::stack(closure_slot).into(),
+        vec![],
+    ));
+    outer.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::stack(shared), Expression::stack(copied)].into(),
+    ));
+    let outer = engine.register_function(outer, 0).unwrap();
+    // Only the capture by reference writes through to the defining frame
+    assert_eq!(
+        engine.call(&outer, []),
+        Ok(TestValueWrapper(TestValue::Number(6)))
+    );
+}
+
+fn add_assign(target: VariableType, n: i64) -> Expression<TestTypeSystem> {
+    Expression::CompoundAssign {
+        target,
+        op: TestBinaryOperator::Add,
+        value: number(n).into(),
+    }
+}
+
+fn swap(a: VariableType, b: VariableType) -> Expression<TestTypeSystem> {
+    Expression::Swap(a, b)
+}
+
+#[test]
+fn test_swap_values() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    engine
+        .set_global(global, TestValueWrapper(TestValue::Number(3)))
+        .unwrap();
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(1).into()),
+        Expression::AssignStack(1, number(2).into()),
+        swap(VariableType::Stack(0), VariableType::Stack(1)),
+        swap(VariableType::Stack(1), VariableType::Global(global)),
+        Expression::Initialize(
+            TestInitializer::List,
+            vec![Expression::stack(0), Expression::stack(1)],
+        ),
+    ]);
+    assert_eq!(
+        engine.eval(&expr, 2),
+        Ok(TestValueWrapper(TestValue::List(vec![
+            TestValueWrapper(TestValue::Number(2)),
+            TestValueWrapper(TestValue::Number(3)),
+        ])))
+    );
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Number(1)))
+    );
+}
+
+#[test]
+fn test_swap_references() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let a = engine.create_global();
+    let b = engine.create_global();
+    let first = TestValueWrapper::new_ref(TestValue::Number(1));
+    let second = TestValueWrapper::new_ref(TestValue::Number(2));
+    engine.set_global(a, first.clone()).unwrap();
+    engine.set_global(b, second.clone()).unwrap();
+
+    // Each global now holds the other reference, so assignments follow the swap
+    let expr = Expression::Sequence(vec![
+        swap(VariableType::Global(a), VariableType::Global(b)),
+        Expression::AssignGlobal(a, number(20).into()),
+        Expression::AssignGlobal(b, number(10).into()),
+    ]);
+    engine.evaluate(&expr).unwrap();
+    assert!(engine.get_global(a).unwrap().ref_eq(&second));
+    assert!(engine.get_global(b).unwrap().ref_eq(&first));
+    assert_eq!(first.resolve(), TestValue::Number(10));
+    assert_eq!(second.resolve(), TestValue::Number(20));
+}
+
+#[test]
+fn test_swap_captured() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let outside = TestValueWrapper::new_ref(TestValue::Number(2));
+    engine.set_global(global, outside.clone()).unwrap();
+
+    // The closure swaps its captured counter with the global, then increments both
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    outer.layout = StackLayout::all_alloc();
+    let counter = outer.create_variable();
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(counter)]);
+    closure.evaluate_expression(swap(
+        VariableType::Captured(0),
+        VariableType::Global(global),
+    ));
+    closure.evaluate_expression(add_assign(VariableType::Captured(0), 100));
+    closure.evaluate_expression(add_assign(VariableType::Global(global), 10));
+    let closure = engine.register_function(closure, 0).unwrap();
+    outer.evaluate_expression(Expression::AssignStack(counter, number(1).into()));
+    outer.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::FunctionCapture(closure).into(),
+        vec![],
+    ));
+    outer.evaluate_expression(Expression::stack(counter));
+    let outer = engine.register_function(outer, 0).unwrap();
+
+    // The counter and the global keep their references but exchange their values
+    assert_eq!(
+        engine.call(&outer, []),
+        Ok(TestValueWrapper(TestValue::Number(102)))
+    );
+    assert!(engine.get_global(global).unwrap().ref_eq(&outside));
+    assert_eq!(outside.resolve(), TestValue::Number(11));
+}
+
+#[test]
+fn test_compound_assign() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    engine
+        .set_global(global, TestValueWrapper(TestValue::Number(10)))
+        .unwrap();
+    engine
+        .evaluate(&add_assign(VariableType::Global(global), 5))
+        .unwrap();
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Number(15)))
+    );
+
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(0)]);
+    closure.evaluate_expression(add_assign(VariableType::Captured(0), 10));
+    let closure = engine.register_function(closure, 0).unwrap();
+
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    let x = outer.create_variable();
+    outer.evaluate_expression(Expression::AssignStack(x, number(1).into()));
+    outer.evaluate_expression(add_assign(VariableType::Stack(x), 2));
+    outer.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::FunctionCapture(closure).into(),
+        vec![],
+    ));
+    outer.evaluate_expression(Expression::stack(x));
+    let outer = engine.register_function(outer, 0).unwrap();
+    assert_eq!(
+        engine.call(&outer, []),
+        Ok(TestValueWrapper(TestValue::Number(13)))
+    );
+}
+
+#[cfg(feature = "profiling")]
+#[test]
+fn test_profile() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let expensive = super::limits::exponential_calls(&mut engine, 12);
+    let mut cheap = FunctionWriter::new(ArgCount::Fixed(0));
+    cheap.set_name("cheap");
+    cheap.evaluate_expression(number(1));
+    let cheap = engine.register_function(cheap, 0).unwrap();
+
+    for _ in 0..3 {
+        engine.call(&cheap, []).unwrap();
+    }
+    engine.call(&expensive, []).unwrap();
+
+    let profile = engine.profile();
+    let cheap_profile = profile.get(cheap.address()).unwrap();
+    let expensive_profile = profile.get(expensive.address()).unwrap();
+    assert_eq!(cheap_profile.calls, 3);
+    assert_eq!(expensive_profile.calls, 1);
+    assert_eq!(profile.get(0).unwrap().calls, 1 << 12);
+    assert!(expensive_profile.total_time > cheap_profile.total_time);
+    assert_eq!(cheap_profile.name.as_deref(), Some("cheap"));
+    assert_eq!(expensive_profile.name, None);
+
+    engine.reset_profile();
+    assert_eq!(engine.profile().iter().count(), 0);
+}
+
+#[test]
+fn test_function_names() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_error_traces(true);
+    let unnamed = double(&mut engine);
+    let mut named = FunctionWriter::new(ArgCount::Fixed(1));
+    named.set_name("double");
+    named.set_source(DebugInfo {
+        file: 1,
+        start: 10,
+        end: 40,
+    });
+    named.evaluate_expression(Expression::stack(0));
+    let named = engine.register_function(named, 0).unwrap();
+    let by_name = engine
+        .register_function_named("triple", FunctionWriter::new(ArgCount::Fixed(0)), 0)
+        .unwrap();
+
+    assert_eq!(engine.function_name(unnamed.location), None);
+    assert_eq!(engine.function_name(named.location), Some("double"));
+    assert_eq!(engine.function_name(by_name.location), Some("triple"));
+    assert_eq!(engine.function_name(100), None);
+    assert_eq!(
+        engine.function_source(named.location),
+        Some(&DebugInfo {
+            file: 1,
+            start: 10,
+            end: 40
+        })
+    );
+    assert_eq!(engine.function_source(unnamed.location), None);
+    assert_eq!(unnamed.to_string(), format!("#{}", unnamed.location));
+    assert_eq!(named.to_string(), "double");
+
+    let err = engine.call(&named, []).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Function double expected exactly 1 argument, got 0"
+    );
+
+    let call = Expression::StaticFunctionCall(
+        named.clone(),
+        vec![Expression::StaticFunctionCall(unnamed.clone(), vec![])],
+    );
+    assert_eq!(
+        call.pretty(),
+        format!("call double(call #{}())", unnamed.location)
+    );
+    let err = engine.evaluate(&call).unwrap_err();
+    assert_eq!(err.trace(), ["arg 0 of static call to double".to_owned()]);
+}
+
+#[test]
+fn test_run_function() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let mut first = FunctionWriter::new(ArgCount::Fixed(0));
+    first.evaluate_expression(number(1));
+    engine.register_function(first, 0).unwrap();
+    let mut second = FunctionWriter::new(ArgCount::Fixed(1));
+    let local = second.create_variable();
+    second.evaluate_expression(Expression::AssignStack(local, Expression::stack(0).into()));
+    second.evaluate_expression(Expression::AssignGlobal(
+        global,
+        Expression::stack(local).into(),
+    ));
+    second.evaluate_expression(Expression::UnaryOpEval(
+        TestUnaryOperator::Inc,
+        Expression::global(global).into(),
+    ));
+    let second = engine.register_function(second, 0).unwrap();
+
+    engine.globals.clear();
+    assert_eq!(
+        engine.run_function(
+            second.address(),
+            vec![TestValueWrapper(TestValue::Number(5))]
+        ),
+        Ok(TestValueWrapper(TestValue::Number(6)))
+    );
+    assert_eq!(engine.globals().len(), 1);
+    assert_eq!(
+        engine.run_function(0, vec![]),
+        Ok(TestValueWrapper(TestValue::Number(1)))
+    );
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Number(5)))
+    );
+    assert_eq!(
+        engine.run_function(2, vec![]),
+        Err(FreightError::InvalidFunctionLocation { location: 2 })
+    );
+}
+
+fn log_arg(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let message = format!("{:?}", args[0].resolve());
+    engine.with_context(|ctx| ctx.output.push(message));
+    Ok(args[0].clone())
+}
+
+fn log_and_call(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let depth = engine.with_context(|ctx| {
+        ctx.output.push("outer".into());
+        ctx.output.len()
+    });
+    let func = args[0].cast_to_function().unwrap().clone();
+    let result = engine.call(&func, [TestValueWrapper(TestValue::Number(depth as i64))])?;
+    engine.with_context(|ctx| ctx.output.push("outer done".into()));
+    Ok(result)
+}
+
+/// Evaluates the second argument if the first is truthy and the third otherwise
+fn native_if(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: &[Expression<TestTypeSystem>],
+    mut scope: EvalScope<TestTypeSystem>,
+) -> Result<TestValueWrapper, FreightError> {
+    let condition = scope.eval(engine, &args[0])?;
+    let branch = if condition.is_truthy() { 1 } else { 2 };
+    scope.eval(engine, &args[branch])
+}
+
+fn call_if(
+    condition: Expression<TestTypeSystem>,
+    then_branch: Expression<TestTypeSystem>,
+    else_branch: Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    Expression::NativeMacroCall(
+        NativeMacro::new(native_if),
+        vec![condition, then_branch, else_branch],
+    )
+}
+
+#[test]
+fn test_native_macro_short_circuits() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // The branch not taken would fail, since the global doesn't exist
+    let expr = |condition| {
+        Expression::Sequence(vec![
+            Expression::AssignStack(0, number(condition).into()),
+            call_if(
+                Expression::stack(0),
+                Expression::AssignStack(1, number(10).into()),
+                Expression::global(0),
+            ),
+            Expression::stack(1),
+        ])
+    };
+    assert_eq!(
+        engine.eval(&expr(1), 2),
+        Ok(TestValueWrapper(TestValue::Number(10)))
+    );
+    assert_eq!(
+        engine.eval(&expr(0), 2),
+        Err(FreightError::GlobalOutOfRange {
+            address: 0,
+            num_globals: 0
+        })
+    );
+    assert_eq!(engine.stats().native_calls, 2);
+}
+
+#[test]
+fn test_native_macro_passes_on_returns() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.evaluate_expression(call_if(
+        Expression::stack(0),
+        Expression::Return(0, number(1).into()),
+        number(2),
+    ));
+    func.evaluate_expression(number(3));
+    let func = engine.register_function(func, 0).unwrap();
+    let call = |n| [TestValueWrapper(TestValue::Number(n))];
+    assert_eq!(
+        engine.call(&func, call(1)),
+        Ok(TestValueWrapper(TestValue::Number(1)))
+    );
+    assert_eq!(
+        engine.call(&func, call(0)),
+        Ok(TestValueWrapper(TestValue::Number(3)))
+    );
+}
+
+#[test]
+fn test_context_access_from_nested_natives() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut inner = FunctionWriter::new(ArgCount::Fixed(1));
+    inner.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(log_arg),
+        vec![Expression::stack(0)],
+    ));
+    let inner = engine.register_function(inner, 0).unwrap();
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    outer.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(log_and_call),
+        vec![Expression::RawValue(inner.into())],
+    ));
+    let outer = engine.register_function(outer, 0).unwrap();
+
+    assert_eq!(
+        engine.call(&outer, []),
+        Ok(TestValueWrapper(TestValue::Number(1)))
+    );
+    let output = engine.swap_context(TestContext::default()).output;
+    assert_eq!(output, ["outer", "Number(1)", "outer done"]);
+
+    engine.call(&outer, []).unwrap();
+    assert_eq!(engine.context.output.len(), 3);
+}
+
+fn register_many(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    _: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    for i in 0..1000 {
+        let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+        func.evaluate_expression(number(i));
+        engine.register_function(func, 0)?;
+    }
+    Ok(TestValueWrapper(TestValue::Null))
+}
+
+#[test]
+fn test_register_while_function_is_running() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let local = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(local, number(41).into()));
+    func.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(register_many),
+        vec![],
+    ));
+    func.evaluate_expression(Expression::UnaryOpEval(
+        TestUnaryOperator::Inc,
+        Expression::stack(local).into(),
+    ));
+    func.evaluate_expression(Expression::stack(local));
+    func.evaluate_expression(Expression::UnaryOpEval(
+        TestUnaryOperator::Inc,
+        Expression::stack(local).into(),
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+
+    assert_eq!(
+        engine.call(&func, []),
+        Ok(TestValueWrapper(TestValue::Number(42)))
+    );
+    assert_eq!(engine.functions.len(), 1001);
+    assert_eq!(
+        engine.run_function(1000, vec![]),
+        Ok(TestValueWrapper(TestValue::Number(999)))
+    );
+}
+
+#[test]
+fn test_register_after_finalize() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(register_many),
+        vec![],
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+    engine.finalize();
+    assert!(engine.is_finalized());
+
+    assert_eq!(
+        engine.register_function(FunctionWriter::new(ArgCount::Fixed(0)), 0),
+        Err(FreightError::FunctionTableFinalized)
+    );
+    assert_eq!(
+        engine.call(&func, []),
+        Err(FreightError::FunctionTableFinalized)
+    );
+    assert_eq!(engine.functions.len(), 1);
+}
+
+/// Assigns the second argument to the first element of the list passed as the first argument
+fn set_first(
+    _: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let TestValue::List(items) = args[0].resolve() else {
+        return Err(FreightError::InvalidInvocationTarget);
+    };
+    items[0].clone().assign(args[1].clone());
+    Ok(TestValueWrapper(TestValue::Null))
+}
+
+#[test]
+fn test_fork() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let mut mutate = FunctionWriter::new(ArgCount::Fixed(0));
+    mutate.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(set_first),
+        vec![Expression::global(global), number(2)],
+    ));
+    let mutate = engine.register_function(mutate, 0).unwrap();
+    engine.reset();
+    engine.globals[global].assign(TestValueWrapper(TestValue::List(vec![
+        TestValueWrapper::new_ref(TestValue::Number(1)),
+    ])));
+    engine.context.output.push("parent".into());
+
+    let mut fork = engine.fork();
+    fork.context.output.push("fork".into());
+    fork.run_incremental(&mutate).unwrap();
+    let expected = |n| {
+        Some(TestValueWrapper(TestValue::List(vec![TestValueWrapper(
+            TestValue::Number(n),
+        )])))
+    };
+    assert_eq!(fork.get_global(global).cloned(), expected(2));
+    assert_eq!(engine.get_global(global).cloned(), expected(1));
+    assert_eq!(engine.context.output, ["parent"]);
+
+    engine.run_incremental(&mutate).unwrap();
+    assert_eq!(engine.get_global(global).cloned(), expected(2));
+}
+
+#[test]
+fn test_fork_with_pooled_constants() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_constant_pooling(true);
+    engine.set_strict_reads(true);
+    engine.set_inline_threshold(Some(4));
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [number(40), number(2)].into(),
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+    assert_eq!(engine.constants().len(), 2);
+
+    let mut fork = engine.fork();
+    assert_eq!(fork.constants(), engine.constants());
+    assert!(fork.constant_pooling());
+    assert!(fork.strict_reads());
+    assert_eq!(fork.inline_threshold(), Some(4));
+    assert_eq!(
+        fork.call(&func, []),
+        Ok(TestValueWrapper(TestValue::Number(42)))
+    );
+
+    // Pooled constants are references, which the fork doesn't share
+    let forty = fork
+        .constants()
+        .iter()
+        .position(|constant| constant.resolve() == TestValue::Number(40))
+        .unwrap();
+    fork.constants[forty].assign(TestValueWrapper(TestValue::Number(0)));
+    assert_eq!(
+        fork.call(&func, []),
+        Ok(TestValueWrapper(TestValue::Number(2)))
+    );
+    assert_eq!(
+        engine.call(&func, []),
+        Ok(TestValueWrapper(TestValue::Number(42)))
+    );
+}
+
+#[test]
+fn test_unhandled_return_target() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let own_target = engine.create_return_target();
+    let stray_target = engine.create_return_target();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::Return(stray_target, number(5).into()));
+    let func = engine.register_function(func, own_target).unwrap();
+
+    assert_eq!(
+        engine.call(&func, []),
+        Err(FreightError::UnhandledReturnTarget {
+            target: stray_target
+        })
+    );
+    assert_eq!(
+        engine.last_return_value(),
+        &TestValueWrapper(TestValue::Number(5))
+    );
+    assert_eq!(
+        engine.evaluate(&Expression::Return(stray_target, number(6).into())),
+        Err(FreightError::UnhandledReturnTarget {
+            target: stray_target
+        })
+    );
+    assert_eq!(
+        engine.evaluate(&Expression::ReturnTarget(
+            stray_target,
+            Expression::Return(stray_target, number(7).into()).into()
+        )),
+        Ok(TestValueWrapper(TestValue::Number(7)))
+    );
+}
+
+#[test]
+fn test_execution_stats() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut add = FunctionWriter::new(ArgCount::Fixed(2));
+    add.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::stack(0), Expression::stack(1)].into(),
+    ));
+    let add = engine.register_function(add, 0).unwrap();
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    let x = main.create_variable();
+    main.evaluate_expression(Expression::AssignStack(x, number(3).into()));
+    main.evaluate_expression(Expression::StaticFunctionCall(
+        add,
+        vec![Expression::stack(x), number(2)],
+    ));
+    main.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(log_arg),
+        vec![Expression::stack(x)],
+    ));
+    let main = engine.register_function(main, 0).unwrap();
+    engine.set_entry_point(main);
+
+    engine.run().unwrap();
+    let expected = ExecutionStats {
+        expressions_evaluated: 10,
+        function_calls: 2,
+        tail_calls: 0,
+        native_calls: 1,
+        peak_stack: 3,
+        frame_pool_hits: 0,
+        frame_pool_misses: 0,
+    };
+    assert_eq!(engine.stats(), expected);
+
+    engine.run().unwrap();
+    assert_eq!(engine.stats().expressions_evaluated, 20);
+    assert_eq!(engine.stats().peak_stack, 3);
+
+    engine.clear_stats();
+    assert_eq!(engine.stats(), ExecutionStats::default());
+}
+
+#[test]
+fn test_engine_builder() {
+    let mut builder = EngineBuilder::<TestTypeSystem>::new();
+    let global = builder.create_global();
+    let target = builder.create_return_target();
+    let mut double = FunctionWriter::new(ArgCount::Fixed(1));
+    double.evaluate_expression(Expression::Return(
+        target,
+        Expression::BinaryOpEval(
+            TestBinaryOperator::Add,
+            [Expression::stack(0), Expression::stack(0)].into(),
+        )
+        .into(),
+    ));
+    let double = builder.add_function(double, target);
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    main.evaluate_expression(Expression::AssignGlobal(global, number(4).into()));
+    main.evaluate_expression(Expression::StaticFunctionCall(
+        double,
+        vec![Expression::global(global)],
+    ));
+    let main = builder.add_function(main, 0);
+    builder.set_entry(main);
+    builder.stack_capacity(1);
+
+    let mut engine = builder.build(TestContext::default()).unwrap();
+    assert_eq!(engine.create_return_target(), target + 1);
+    assert_eq!(engine.run(), Ok(TestValueWrapper(TestValue::Number(8))));
+    assert_eq!(
+        engine.get_global(global),
+        Some(&TestValueWrapper(TestValue::Number(4)))
+    );
+}
+
+#[test]
+fn test_engine_builder_errors() {
+    let mut builder = EngineBuilder::<TestTypeSystem>::new();
+    builder.add_function(FunctionWriter::new(ArgCount::Fixed(0)), 0);
+    assert_eq!(
+        builder.build(TestContext::default()).err(),
+        Some(FreightError::MissingEntryPoint)
+    );
+
+    let mut builder = EngineBuilder::<TestTypeSystem>::new();
+    let unregistered = FunctionWriter::new(ArgCount::Fixed(0)).to_ref(3);
+    builder.set_entry(unregistered);
+    assert_eq!(
+        builder.build(TestContext::default()).err(),
+        Some(FreightError::InvalidFunctionLocation { location: 3 })
+    );
+
+    let mut builder = EngineBuilder::<TestTypeSystem>::new();
+    let closure = FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![]);
+    let closure = builder.add_function(closure, 0);
+    builder.set_entry(closure);
+    assert_eq!(
+        builder.build(TestContext::default()).err(),
+        Some(FreightError::InvalidInvocationTarget)
+    );
+
+    let mut builder = EngineBuilder::<TestTypeSystem>::new();
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    main.evaluate_expression(Expression::global(0));
+    let main = builder.add_function(main, 0);
+    builder.set_entry(main);
+    assert_eq!(
+        builder.build(TestContext::default()).err(),
+        Some(FreightError::InvalidAddress {
+            function: 0,
+            kind: AddressKind::Global,
+            address: 0
+        })
+    );
+}
+
+#[test]
+fn test_eval() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    engine
+        .set_global(global, TestValueWrapper(TestValue::Number(2)))
+        .unwrap();
+
+    assert_eq!(
+        engine.eval(&Expression::AssignGlobal(global, number(5).into()), 0),
+        Ok(TestValueWrapper(TestValue::Null))
+    );
+    let sum = Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::global(global), number(1)].into(),
+    );
+    assert_eq!(
+        engine.eval(&sum, 0),
+        Ok(TestValueWrapper(TestValue::Number(6)))
+    );
+
+    // Arguments are evaluated in order, so the native is never reached
+    let early_return = Expression::NativeFunctionCall(
+        NativeFunction::new(log_arg),
+        vec![
+            Expression::AssignStack(0, number(3).into()),
+            Expression::AssignStack(1, Expression::global(global).into()),
+            Expression::Return(
+                7,
+                Expression::BinaryOpEval(
+                    TestBinaryOperator::Add,
+                    [Expression::stack(0), Expression::stack(1)].into(),
+                )
+                .into(),
+            ),
+        ],
+    );
+    assert_eq!(
+        engine.eval(&early_return, 2),
+        Ok(TestValueWrapper(TestValue::Number(8)))
+    );
+    assert!(engine.context.output.is_empty());
+    assert_eq!(engine.stack.with(|stack| stack.in_use()), 0);
+
+    assert_eq!(
+        engine.eval(&early_return, 1),
+        Err(FreightError::StackSlotOutOfRange { slot: 1, size: 1 })
+    );
+}
+
+/// Calls a function taking one argument which assigns 5 to it, passing it a global
+fn assign_to_argument(layout: StackLayout) -> TestValueWrapper {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.layout = layout;
+    func.evaluate_expression(Expression::AssignStack(0, number(5).into()));
+    let func = engine.register_function(func, 0).unwrap();
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    main.evaluate_expression(Expression::AssignGlobal(global, number(1).into()));
+    main.evaluate_expression(Expression::StaticFunctionCall(
+        func,
+        vec![Expression::global(global)],
+    ));
+    main.evaluate_expression(Expression::global(global));
+    let main = engine.register_function(main, 0).unwrap();
+    engine.call(&main, []).unwrap()
+}
+
+#[test]
+fn test_arguments_alias_variables() {
+    assert_eq!(
+        assign_to_argument(StackLayout::all_alloc()),
+        TestValueWrapper(TestValue::Number(5))
+    );
+    assert_eq!(
+        assign_to_argument(StackLayout::no_alloc()),
+        TestValueWrapper(TestValue::Number(5))
+    );
+}
+
+#[test]
+fn test_temporary_arguments_are_not_cloned() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.layout = StackLayout::no_alloc();
+    func.evaluate_expression(number(0));
+    let func = engine.register_function(func, 0).unwrap();
+    let list = || Expression::Initialize(TestInitializer::List, vec![number(1), number(2)]);
+    let mut call = FunctionWriter::new(ArgCount::Fixed(0));
+    call.evaluate_expression(Expression::StaticFunctionCall(func, vec![list()]));
+    let call = engine.register_function(call, 0).unwrap();
+    let mut discard = FunctionWriter::new(ArgCount::Fixed(0));
+    discard.evaluate_expression(list());
+    discard.evaluate_expression(number(0));
+    let discard = engine.register_function(discard, 0).unwrap();
+
+    // Warm up once so lazily grown engine tables don't count
+    let mut count = |func: &FunctionRef<TestTypeSystem>| {
+        engine.call(func, []).unwrap();
+        count_allocations(|| engine.call(func, []).unwrap()).1
+    };
+    // Passing the list allocates nothing beyond building it
+    let discarded = count(&discard);
+    assert_eq!(count(&call), discarded);
+}
+
+#[test]
+fn test_chained_assignments() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let [a, b] = [(); 2].map(|_| engine.create_global());
+    let list = Expression::Initialize(TestInitializer::List, vec![number(1), number(2)]);
+    // a = b = [1, 2]
+    let expr = Expression::AssignGlobalAndYield(
+        a,
+        Expression::AssignGlobalAndYield(b, list.into()).into(),
+    );
+    let result = engine.evaluate(&expr).unwrap();
+    let expected = TestValueWrapper(TestValue::List(vec![
+        TestValueWrapper(TestValue::Number(1)),
+        TestValueWrapper(TestValue::Number(2)),
+    ]));
+    assert_eq!(engine.globals[a], expected);
+    assert_eq!(engine.globals[b], expected);
+    // The result is a reference to the outer variable, and assigning a reference copies
+    // the value it refers to, so the globals hold equal values but don't alias each other
+    assert!(result.ref_eq(&engine.globals[a]));
+    assert!(!engine.globals[a].ref_eq(&engine.globals[b]));
+
+    // The plain assignments still produce the default value
+    let expr = Expression::AssignGlobal(a, number(3).into());
+    assert_eq!(
+        engine.evaluate(&expr),
+        Ok(TestValueWrapper(TestValue::Null))
+    );
+}
+
+#[test]
+fn test_chained_stack_and_dynamic_assignments() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    // global = *stack[0] = stack[1] = 5, then read the slots back through the sum
+    let expr = Expression::Sequence(vec![
+        Expression::AssignGlobal(
+            global,
+            Expression::AssignDynamicAndYield(
+                [
+                    Expression::stack(0),
+                    Expression::AssignStackAndYield(1, number(5).into()),
+                ]
+                .into(),
+            )
+            .into(),
+        ),
+        Expression::BinaryOpEval(
+            TestBinaryOperator::Add,
+            [Expression::stack(0), Expression::stack(1)].into(),
+        ),
+    ]);
+    assert_eq!(
+        engine.eval(&expr, 2),
+        Ok(TestValueWrapper(TestValue::Number(10)))
+    );
+    assert_eq!(
+        engine.globals[global],
+        TestValueWrapper(TestValue::Number(5))
+    );
+}
@@ -0,0 +1,134 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::Expression,
+    function::{ArgCount, FunctionRef, FunctionWriter},
+};
+
+use super::{
+    number,
+    type_system::{TestBinaryOperator, TestTypeSystem},
+    value,
+};
+
+/// A function returning `base` when its argument is 0, and otherwise calling `next` with
+/// the argument minus one
+fn step(next: FunctionRef<TestTypeSystem>, base: i64) -> FunctionWriter<TestTypeSystem> {
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.evaluate_expression(Expression::Conditional {
+        condition: Expression::BinaryOpEval(
+            TestBinaryOperator::Lt,
+            [number(0), Expression::stack(0)].into(),
+        )
+        .into(),
+        then_branch: Expression::Return(
+            0,
+            Expression::StaticFunctionCall(
+                next,
+                vec![Expression::BinaryOpEval(
+                    TestBinaryOperator::Sub,
+                    [Expression::stack(0), number(1)].into(),
+                )],
+            )
+            .into(),
+        )
+        .into(),
+        else_branch: None,
+    });
+    func.evaluate_expression(number(base));
+    func
+}
+
+#[test]
+fn test_mutual_recursion_between_reserved_functions() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let even = engine.reserve_function(ArgCount::Fixed(1), 1).unwrap();
+    let odd = engine.reserve_function(ArgCount::Fixed(1), 1).unwrap();
+    engine
+        .fill_function(even.location, step(odd.clone(), 1), 0)
+        .unwrap();
+    engine
+        .fill_function(odd.location, step(even.clone(), 0), 0)
+        .unwrap();
+    engine.validate().unwrap();
+
+    assert_eq!(engine.call(&even, [value(4)]).unwrap(), value(1));
+    assert_eq!(engine.call(&even, [value(3)]).unwrap(), value(0));
+    assert_eq!(engine.call(&odd, [value(7)]).unwrap(), value(1));
+}
+
+#[test]
+fn test_calling_an_unfilled_function_is_an_error() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let even = engine.reserve_function(ArgCount::Fixed(1), 1).unwrap();
+    let odd = engine.reserve_function(ArgCount::Fixed(1), 1).unwrap();
+    engine
+        .fill_function(even.location, step(odd.clone(), 1), 0)
+        .unwrap();
+    engine.validate().unwrap();
+    assert!(engine.get_function(odd.location).is_reserved());
+
+    // The filled function works until it reaches the one which never was
+    assert_eq!(engine.call(&even, [value(0)]).unwrap(), value(1));
+    let err = engine.call(&even, [value(2)]).unwrap_err();
+    assert!(matches!(
+        err.root(),
+        FreightError::UnfilledFunction { function } if function == "#1"
+    ));
+    let err = engine.call(&odd, [value(2)]).unwrap_err();
+    assert_eq!(
+        err.root().to_string(),
+        "Function #1 was reserved but its body was never filled"
+    );
+}
+
+#[test]
+fn test_filling_checks_the_reservation() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = engine.reserve_function(ArgCount::Fixed(1), 1).unwrap();
+
+    let mut wide = FunctionWriter::new(ArgCount::Fixed(1));
+    wide.create_variable();
+    assert!(matches!(
+        engine.fill_function(func.location, wide, 0),
+        Err(FreightError::ReservationMismatch { function: 0, .. })
+    ));
+    assert!(matches!(
+        engine.fill_function(func.location, FunctionWriter::new(ArgCount::Fixed(2)), 0),
+        Err(FreightError::ReservationMismatch { function: 0, .. })
+    ));
+    assert!(matches!(
+        engine.fill_function(
+            func.location,
+            FunctionWriter::new_capturing(ArgCount::Fixed(1), vec![]),
+            0
+        ),
+        Err(FreightError::ReservationMismatch { function: 0, .. })
+    ));
+
+    engine
+        .fill_function(func.location, FunctionWriter::new(ArgCount::Fixed(1)), 0)
+        .unwrap();
+    // Filling twice, or filling a function which was registered normally, is an error
+    assert!(matches!(
+        engine.fill_function(func.location, FunctionWriter::new(ArgCount::Fixed(1)), 0),
+        Err(FreightError::NotReserved { function: 0 })
+    ));
+    let registered = engine
+        .register_function(FunctionWriter::new(ArgCount::Fixed(0)), 0)
+        .unwrap();
+    assert!(matches!(
+        engine.fill_function(
+            registered.location,
+            FunctionWriter::new(ArgCount::Fixed(0)),
+            0
+        ),
+        Err(FreightError::NotReserved { function: 1 })
+    ));
+
+    engine.finalize();
+    assert!(matches!(
+        engine.reserve_function(ArgCount::Fixed(0), 0),
+        Err(FreightError::FunctionTableFinalized)
+    ));
+}
@@ -0,0 +1,152 @@
+use crate::{
+    error::FreightError,
+    execution_engine::{ExecutionEngine, Stack, Step},
+    expression::{Expression, NativeFunction},
+    function::{ArgCount, FunctionType, FunctionWriter},
+    sync::Shared,
+};
+
+use super::{
+    number,
+    type_system::{
+        TestBinaryOperator, TestInitializer, TestTypeSystem, TestValue, TestValueWrapper,
+    },
+    value,
+};
+
+fn yielded(value: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::Yield(value.into())
+}
+
+/// The number a step stopped with, looking through references
+fn step_number(step: Step<TestTypeSystem>) -> (bool, TestValue) {
+    let done = matches!(step, Step::Done(_));
+    (done, step.into_value().resolve())
+}
+
+#[test]
+fn test_counter_generator() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // Counts up from its argument forever
+    let mut counter = FunctionWriter::new(ArgCount::Fixed(1));
+    counter.evaluate_expression(Expression::Loop {
+        target: 0,
+        body: Expression::Sequence(vec![
+            yielded(Expression::stack(0)),
+            Expression::AssignStack(
+                0,
+                Expression::BinaryOpEval(
+                    TestBinaryOperator::Add,
+                    [Expression::stack(0), number(1)].into(),
+                )
+                .into(),
+            ),
+        ])
+        .into(),
+    });
+    let counter = engine.register_function(counter, 0).unwrap();
+    let mut double = FunctionWriter::new(ArgCount::Fixed(1));
+    double.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Mul,
+        [Expression::stack(0), number(2)].into(),
+    ));
+    let double = engine.register_function(double, 0).unwrap();
+
+    let mut suspended = engine.call_resumable(&counter, [value(5)]).unwrap();
+    for expected in 5..8 {
+        let step = suspended.resume(&mut engine, Default::default()).unwrap();
+        assert_eq!(step_number(step), (false, TestValue::Number(expected)));
+        // The suspended frame is outside the stack pool, so other calls can use it
+        assert_eq!(
+            engine.call(&double, [value(expected)]),
+            Ok(value(expected * 2))
+        );
+        assert_eq!(engine.stack.with(|stack| stack.in_use()), 0);
+    }
+    assert!(!suspended.is_finished());
+    assert_eq!(engine.call_depth, 0);
+}
+
+#[test]
+fn test_generator_receives_values_and_finishes() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let received = func.create_variable();
+    func.evaluate_expression(yielded(number(1)));
+    func.evaluate_expression(Expression::AssignStack(received, yielded(number(2)).into()));
+    func.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::stack(received), number(10)].into(),
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+
+    let mut suspended = engine.call_resumable(&func, []).unwrap();
+    let mut resume = |sent| suspended.resume(&mut engine, value(sent)).map(step_number);
+    assert_eq!(resume(100), Ok((false, TestValue::Number(1))));
+    assert_eq!(resume(100), Ok((false, TestValue::Number(2))));
+    assert_eq!(resume(5), Ok((true, TestValue::Number(15))));
+    assert_eq!(resume(5), Err(FreightError::ResumedAfterFinish));
+    assert!(suspended.is_finished());
+}
+
+#[test]
+fn test_dropping_suspended_call() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.evaluate_expression(Expression::Initialize(
+        TestInitializer::List,
+        vec![Expression::stack(0), yielded(number(1)), yielded(number(2))],
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+    let mut identity = FunctionWriter::new(ArgCount::Fixed(1));
+    identity.evaluate_expression(Expression::stack(0));
+    let identity = engine.register_function(identity, 0).unwrap();
+
+    let bound = identity.bind(vec![]).unwrap();
+    let FunctionType::Bound { inner, .. } = &bound.function_type else {
+        panic!("Expected a bound function");
+    };
+    let arg = TestValueWrapper(TestValue::Function(bound.clone()));
+    let mut suspended = engine.call_resumable(&func, [arg]).unwrap();
+    let step = suspended.resume(&mut engine, Default::default()).unwrap();
+    assert_eq!(step_number(step), (false, TestValue::Number(1)));
+    assert!(Shared::strong_count(inner) > 1);
+
+    // Dropped with the argument both in its frame and collected by the initializer
+    drop(suspended);
+    assert_eq!(Shared::strong_count(inner), 1);
+    assert_eq!(engine.call(&identity, [value(3)]), Ok(value(3)));
+    assert_eq!(engine.call_depth, 0);
+}
+
+fn first(
+    _: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    Ok(args[0].clone())
+}
+
+#[test]
+fn test_yield_outside_resumable_call() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(yielded(number(1)));
+    let func = engine.register_function(func, 0).unwrap();
+    let err = engine.call(&func, []).unwrap_err();
+    assert_eq!(err.root(), &FreightError::YieldOutsideResumable);
+
+    // Native call arguments are collected in the stack pool, so they can't hold a yield
+    let mut native = FunctionWriter::new(ArgCount::Fixed(0));
+    native.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(first),
+        vec![yielded(number(1))],
+    ));
+    let native = engine.register_function(native, 0).unwrap();
+    let mut suspended = engine.call_resumable(&native, []).unwrap();
+    let err = suspended
+        .resume(&mut engine, Default::default())
+        .unwrap_err();
+    assert_eq!(err.root(), &FreightError::YieldOutsideResumable);
+    assert!(suspended.is_finished());
+    assert_eq!(engine.stack.with(|stack| stack.in_use()), 0);
+}
@@ -0,0 +1,106 @@
+use crate::{error::FreightError, execution_engine::ExecutionEngine, expression::Expression};
+
+use super::{
+    list, number, raw_list,
+    type_system::{TestInitializer, TestTypeSystem, TestValue, TestValueWrapper},
+};
+
+fn index(target: Expression<TestTypeSystem>, i: i64) -> Expression<TestTypeSystem> {
+    Expression::Index {
+        target: target.into(),
+        index: number(i).into(),
+    }
+}
+
+fn index_assign(
+    target: Expression<TestTypeSystem>,
+    i: i64,
+    value: Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    Expression::IndexAssign {
+        target: target.into(),
+        index: number(i).into(),
+        value: value.into(),
+    }
+}
+
+#[test]
+fn test_index() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let list = Expression::Initialize(TestInitializer::List, vec![number(4), number(5)]);
+    assert_eq!(
+        engine.evaluate(&index(list, 1)),
+        Ok(TestValueWrapper(TestValue::Number(5)))
+    );
+}
+
+#[test]
+fn test_index_errors() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(
+        engine.evaluate(&index(raw_list(&[1, 2]), 2)),
+        Err(FreightError::IndexOutOfRange { index: "2".into() })
+    );
+    assert_eq!(
+        engine.evaluate(&index_assign(raw_list(&[1, 2]), 5, number(0))),
+        Err(FreightError::IndexOutOfRange { index: "5".into() })
+    );
+    assert_eq!(
+        engine.evaluate(&index(number(1), 0)),
+        Err(FreightError::NotIndexable {
+            type_name: "Number".into()
+        })
+    );
+}
+
+#[test]
+fn test_index_assign_through_references() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    let held = TestValueWrapper::new_ref(list(&[1, 2, 3]).0);
+    engine.set_global(global, held.clone()).unwrap();
+
+    // Reading the global produces another reference to the list, which sees the change
+    let expr = Expression::Sequence(vec![
+        index_assign(Expression::global(global), 1, number(20)),
+        index(Expression::global(global), 1),
+    ]);
+    assert_eq!(
+        engine.evaluate(&expr),
+        Ok(TestValueWrapper(TestValue::Number(20)))
+    );
+    assert_eq!(held.resolve(), list(&[1, 20, 3]).0);
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_index_variadic_args() {
+    use crate::function::{ArgCount, FunctionWriter, StackLayout};
+
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // Swaps the first two of its variadic arguments, which are collected with `gen_list`,
+    // after moving them into a variable allocated as a reference
+    let mut func = FunctionWriter::new(ArgCount::new_variadic(0..));
+    func.layout = StackLayout::all_alloc();
+    let args = func.create_variable();
+    let first = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(args, Expression::stack(0).into()));
+    func.evaluate_expression(Expression::AssignStack(
+        first,
+        index(Expression::stack(args), 0).into(),
+    ));
+    func.evaluate_expression(index_assign(
+        Expression::stack(args),
+        0,
+        index(Expression::stack(args), 1),
+    ));
+    func.evaluate_expression(index_assign(
+        Expression::stack(args),
+        1,
+        Expression::stack(first),
+    ));
+    func.evaluate_expression(Expression::stack(args));
+    let func = engine.register_function(func, 0).unwrap();
+    let args = [1, 2, 3].map(|n| TestValueWrapper(TestValue::Number(n)));
+    assert_eq!(engine.call(&func, args), Ok(list(&[2, 1, 3])));
+}
@@ -0,0 +1,160 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::{Expression, InfixBuilder},
+};
+
+use super::{
+    list, number, raw_list,
+    type_system::{TestBinaryOperator, TestTypeSystem, TestValue, TestValueWrapper},
+};
+
+fn op(
+    op: TestBinaryOperator,
+    l: Expression<TestTypeSystem>,
+    r: Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    Expression::BinaryOpEval(op, [l, r].into())
+}
+
+/// Builds the tree for operands and the operators between them
+fn infix(
+    first: Expression<TestTypeSystem>,
+    rest: Vec<(TestBinaryOperator, Expression<TestTypeSystem>)>,
+) -> Result<Expression<TestTypeSystem>, FreightError> {
+    let mut builder = InfixBuilder::new();
+    builder.push_operand(first)?;
+    for (op, operand) in rest {
+        builder.push_operator(op)?;
+        builder.push_operand(operand)?;
+    }
+    builder.build()
+}
+
+#[test]
+fn test_infix_precedence() {
+    use TestBinaryOperator::*;
+    // 1 + 2 * 3 - 4
+    let expr = infix(
+        number(1),
+        vec![(Add, number(2)), (Mul, number(3)), (Sub, number(4))],
+    );
+    let expected = op(
+        Sub,
+        op(Add, number(1), op(Mul, number(2), number(3))),
+        number(4),
+    );
+    assert_eq!(expr, Ok(expected));
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(
+        engine.eval(&expr.unwrap(), 0),
+        Ok(TestValueWrapper(TestValue::Number(3)))
+    );
+
+    // 1 < 8 / 4 / 2 - 1
+    let expr = infix(
+        number(1),
+        vec![
+            (Lt, number(8)),
+            (Div, number(4)),
+            (Div, number(2)),
+            (Sub, number(1)),
+        ],
+    );
+    let expected = op(
+        Lt,
+        number(1),
+        op(
+            Sub,
+            op(Div, op(Div, number(8), number(4)), number(2)),
+            number(1),
+        ),
+    );
+    assert_eq!(expr, Ok(expected));
+}
+
+#[test]
+fn test_infix_right_associativity() {
+    use TestBinaryOperator::*;
+    let tail = || raw_list(&[4]);
+    // 1 :: 2 :: 3 :: [4]
+    let expr = infix(
+        number(1),
+        vec![
+            (Prepend, number(2)),
+            (Prepend, number(3)),
+            (Prepend, tail()),
+        ],
+    );
+    let expected = op(
+        Prepend,
+        number(1),
+        op(Prepend, number(2), op(Prepend, number(3), tail())),
+    );
+    assert_eq!(expr, Ok(expected));
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(engine.eval(&expr.unwrap(), 0), Ok(list(&[1, 2, 3, 4])));
+
+    // Tighter operators still apply first within a right-associative chain
+    let expr = infix(
+        number(1),
+        vec![(Prepend, number(2)), (Mul, number(3)), (Prepend, tail())],
+    );
+    let expected = op(
+        Prepend,
+        number(1),
+        op(Prepend, op(Mul, number(2), number(3)), tail()),
+    );
+    assert_eq!(expr, Ok(expected));
+}
+
+#[test]
+fn test_infix_rejects_mixed_associativity() {
+    use TestBinaryOperator::*;
+    let tail = || raw_list(&[3]);
+    assert_eq!(
+        infix(number(1), vec![(Add, number(2)), (Prepend, tail())]),
+        Err(FreightError::MixedAssociativity {
+            left: "+".into(),
+            right: "::".into(),
+        })
+    );
+    // Mixing is found even when a tighter operator is in between
+    assert_eq!(
+        infix(
+            number(1),
+            vec![(Prepend, number(2)), (Mul, number(3)), (Sub, number(1))]
+        ),
+        Err(FreightError::MixedAssociativity {
+            left: "::".into(),
+            right: "-".into(),
+        })
+    );
+}
+
+#[test]
+fn test_infix_rejects_malformed_sequences() {
+    let mut builder = InfixBuilder::<TestTypeSystem>::new();
+    assert!(matches!(
+        builder.push_operator(TestBinaryOperator::Add),
+        Err(FreightError::MalformedInfix { .. })
+    ));
+    builder.push_operand(number(1)).unwrap();
+    assert!(matches!(
+        builder.push_operand(number(2)),
+        Err(FreightError::MalformedInfix { .. })
+    ));
+    builder.push_operator(TestBinaryOperator::Add).unwrap();
+    assert_eq!(
+        builder.build(),
+        Err(FreightError::MalformedInfix {
+            message: "operator + is missing its right operand".into(),
+        })
+    );
+    assert!(InfixBuilder::<TestTypeSystem>::new().build().is_err());
+
+    // A single operand is built as it is
+    let mut builder = InfixBuilder::<TestTypeSystem>::new();
+    builder.push_operand(number(5)).unwrap();
+    assert_eq!(builder.build(), Ok(number(5)));
+}
@@ -0,0 +1,213 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::Expression,
+    function::{ArgCount, FunctionWriter},
+    operators::Initializer,
+};
+
+use super::{
+    alloc_counter::count_allocations,
+    list, logged, number,
+    type_system::{
+        PairsBuilder, TestBinaryOperator, TestInitializer, TestTypeSystem, TestValue,
+        TestValueWrapper,
+    },
+    value,
+};
+
+fn point(fields: Vec<(usize, Expression<TestTypeSystem>)>) -> Expression<TestTypeSystem> {
+    Expression::InitializeNamed(TestInitializer::Point, fields)
+}
+
+#[test]
+fn test_named_fields_match_positions() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let positional = Expression::Initialize(TestInitializer::Point, vec![number(1), number(2)]);
+    let expected = engine.evaluate(&positional).unwrap();
+
+    // Fields are evaluated in the order they're written, not by id
+    let named = point(vec![(1, logged(number(2))), (0, logged(number(1)))]);
+    assert_eq!(
+        named.pretty(),
+        "init Point(1: call native(raw(TestValueWrapper(Number(2)))), \
+        0: call native(raw(TestValueWrapper(Number(1)))))"
+    );
+    assert_eq!(engine.evaluate(&named), Ok(expected.clone()));
+    assert_eq!(engine.context.output, ["Number(2)", "Number(1)"]);
+    assert_eq!(
+        engine.evaluate(&point(vec![(0, number(1)), (1, number(2))])),
+        Ok(expected)
+    );
+}
+
+#[test]
+fn test_initializers_reject_unexpected_fields() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(
+        engine.evaluate(&point(vec![(0, number(1)), (2, number(2))])),
+        Err(FreightError::UnknownField { field: "2".into() })
+    );
+    assert_eq!(
+        engine.evaluate(&point(vec![(1, number(1)), (1, number(2))])),
+        Err(FreightError::DuplicateField { field: "1".into() })
+    );
+    assert_eq!(
+        engine.evaluate(&Expression::InitializeNamed(
+            TestInitializer::List,
+            vec![(0, number(1))]
+        )),
+        Err(FreightError::UnknownField { field: "0".into() })
+    );
+
+    engine.set_error_traces(true);
+    let err = engine.evaluate(&point(vec![(3, number(1))])).unwrap_err();
+    assert_eq!(err.trace(), ["initializer Point"]);
+}
+
+#[test]
+fn test_failed_initializers_reach_the_host() {
+    for init in [TestInitializer::Pairs, TestInitializer::BufferedPairs] {
+        let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+        engine.set_error_traces(true);
+        let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+        func.evaluate_expression(Expression::Initialize(
+            init.clone(),
+            vec![number(1), number(2), Expression::stack(0)],
+        ));
+        let func = engine.register_function(func, 0).unwrap();
+
+        let err = engine
+            .call(&func, [TestValueWrapper(TestValue::Number(3))])
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Initializer {init:?} failed: expected an even number of values, got 3\n    \
+                in initializer {init:?}"
+            )
+        );
+    }
+
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let pairs = Expression::Initialize(TestInitializer::Pairs, (1..=4).map(number).collect());
+    let pair = |a, b| {
+        TestValueWrapper(TestValue::List(vec![
+            TestValueWrapper(TestValue::Number(a)),
+            TestValueWrapper(TestValue::Number(b)),
+        ]))
+    };
+    assert_eq!(
+        engine.evaluate(&pairs),
+        Ok(TestValueWrapper(TestValue::List(vec![
+            pair(1, 2),
+            pair(3, 4)
+        ])))
+    );
+}
+
+#[test]
+fn test_streaming_initializers_skip_collecting_values() {
+    const VALUES: usize = 100;
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut build = |init: TestInitializer| {
+        // Each value is a list, so every copy of one allocates
+        let args = (0..VALUES as i64)
+            .map(|n| {
+                let element = TestValueWrapper(TestValue::Number(n));
+                Expression::RawValue(TestValueWrapper(TestValue::List(vec![element])))
+            })
+            .collect();
+        let expr = Expression::Initialize(init, args);
+        engine.evaluate(&expr).unwrap();
+        count_allocations(|| engine.evaluate(&expr))
+    };
+    let (streamed, streamed_allocations) = build(TestInitializer::Pairs);
+    let (buffered, buffered_allocations) = build(TestInitializer::BufferedPairs);
+    assert_eq!(streamed, buffered);
+    // Buffering allocates the buffer and copies every value out of it
+    assert_eq!(buffered_allocations, streamed_allocations + VALUES + 1);
+}
+
+#[test]
+fn test_initializers_call_back_into_the_engine() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_error_traces(true);
+    let mut divide = FunctionWriter::new(ArgCount::Fixed(1));
+    divide.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Div,
+        [number(10), Expression::stack(0)].into(),
+    ));
+    let divide = engine.register_function(divide, 0).unwrap();
+
+    // The mapped values come from a local, which has to survive the calls
+    let mut main = FunctionWriter::new(ArgCount::Fixed(1));
+    let local = main.create_variable();
+    main.evaluate_expression(Expression::AssignStack(local, number(5).into()));
+    main.evaluate_expression(Expression::Initialize(
+        TestInitializer::Map,
+        vec![
+            Expression::RawValue(TestValueWrapper(TestValue::Function(divide))),
+            Expression::stack(0),
+            logged(number(2)),
+            Expression::stack(local),
+        ],
+    ));
+    let main = engine.register_function(main, 0).unwrap();
+
+    assert_eq!(
+        engine.call(&main, [TestValueWrapper(TestValue::Number(1))]),
+        Ok(list(&[10, 5, 2]))
+    );
+
+    // Errors from the called function come out through the initializer
+    let err = engine
+        .call(&main, [TestValueWrapper(TestValue::Number(0))])
+        .unwrap_err();
+    assert!(err.to_string().contains("division by zero"));
+    assert!(err.to_string().contains("initializer Map"));
+
+    // The engine is left in a usable state
+    assert_eq!(
+        engine.call(&main, [TestValueWrapper(TestValue::Number(2))]),
+        Ok(list(&[5, 5, 2]))
+    );
+}
+
+/// Builds values with [TestInitializer::Pairs] without overriding the rest of the builder
+#[derive(Debug, Clone)]
+struct BeginOnly;
+
+impl Initializer<TestTypeSystem> for BeginOnly {
+    fn initialize(
+        &self,
+        values: Vec<TestValueWrapper>,
+        _: &mut ExecutionEngine<TestTypeSystem>,
+    ) -> TestValueWrapper {
+        TestValueWrapper(TestValue::List(values))
+    }
+
+    fn begin(&self, capacity: usize) -> Option<PairsBuilder> {
+        Initializer::<TestTypeSystem>::begin(&TestInitializer::Pairs, capacity)
+    }
+}
+
+#[test]
+fn test_builders_without_push_and_finish_fail() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut builder = BeginOnly.begin(2).unwrap();
+    assert_eq!(
+        BeginOnly.push(&mut builder, value(1)),
+        Err(FreightError::InitializerFailed {
+            initializer: "BeginOnly".into(),
+            message: "begin returned a builder, but push isn't overridden".into(),
+        })
+    );
+    assert_eq!(
+        BeginOnly.finish(builder, &mut engine),
+        Err(FreightError::InitializerFailed {
+            initializer: "BeginOnly".into(),
+            message: "begin returned a builder, but finish isn't overridden".into(),
+        })
+    );
+}
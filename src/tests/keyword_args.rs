@@ -0,0 +1,175 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::Expression,
+    function::{ArgCount, FunctionRef, FunctionWriter},
+    value::Value,
+};
+
+use super::{
+    add, list, logged, number,
+    type_system::{
+        TestBinaryOperator, TestInitializer, TestTypeSystem, TestValue, TestValueWrapper,
+    },
+    value,
+};
+
+const X: usize = 10;
+const Y: usize = 11;
+const Z: usize = 12;
+
+fn call(
+    func: &FunctionRef<TestTypeSystem>,
+    positional: Vec<Expression<TestTypeSystem>>,
+    named: Vec<(usize, Expression<TestTypeSystem>)>,
+) -> Expression<TestTypeSystem> {
+    Expression::StaticFunctionCallNamed {
+        func: func.clone(),
+        positional,
+        named,
+    }
+}
+
+/// Registers `f(x, y, z = x + y)` returning its arguments in a list
+fn register_f(engine: &mut ExecutionEngine<TestTypeSystem>) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(ArgCount::Range { min: 2, max: 3 });
+    func.set_name("f");
+    func.set_arg_names(vec![X, Y, Z]);
+    func.set_default(2, add(Expression::stack(0), Expression::stack(1)))
+        .unwrap();
+    func.evaluate_expression(Expression::Initialize(
+        TestInitializer::List,
+        (0..3).map(Expression::stack).collect(),
+    ));
+    engine.register_function(func, 0).unwrap()
+}
+
+#[test]
+fn test_named_arguments_are_reordered() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let f = register_f(&mut engine);
+
+    // Arguments are evaluated as written, then passed by position
+    let expr = call(
+        &f,
+        vec![],
+        vec![
+            (Z, logged(number(3))),
+            (Y, logged(number(2))),
+            (X, logged(number(1))),
+        ],
+    );
+    assert_eq!(engine.evaluate(&expr), Ok(list(&[1, 2, 3])));
+    assert_eq!(
+        engine.context.output,
+        ["Number(3)", "Number(2)", "Number(1)"]
+    );
+
+    // Positional arguments come first
+    let expr = call(&f, vec![number(1)], vec![(Y, number(2))]);
+    assert_eq!(engine.evaluate(&expr), Ok(list(&[1, 2, 3])));
+    assert_eq!(
+        engine.call_named(&f, vec![value(5), value(6)], vec![(Z, value(0))]),
+        Ok(list(&[5, 6, 0]))
+    );
+    assert_eq!(
+        expr.pretty(),
+        "call f(raw(TestValueWrapper(Number(1))), 11: raw(TestValueWrapper(Number(2))))"
+    );
+}
+
+#[test]
+fn test_named_arguments_are_checked() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let f = register_f(&mut engine);
+    assert_eq!(
+        engine.call_named(&f, vec![value(1)], vec![(X, value(2)), (Y, value(3))]),
+        Err(FreightError::DuplicateArgument {
+            function: "f".into(),
+            name: "10".into(),
+        })
+    );
+    assert_eq!(
+        engine.call_named(
+            &f,
+            vec![],
+            vec![(Y, value(2)), (Y, value(3)), (X, value(1))]
+        ),
+        Err(FreightError::DuplicateArgument {
+            function: "f".into(),
+            name: "11".into(),
+        })
+    );
+    assert_eq!(
+        engine.call_named(&f, vec![value(1)], vec![(13, value(2))]),
+        Err(FreightError::UnknownArgument {
+            function: "f".into(),
+            name: "13".into(),
+        })
+    );
+    let err = engine
+        .call_named(&f, vec![], vec![(Z, value(0)), (X, value(1))])
+        .unwrap_err();
+    assert_eq!(
+        err,
+        FreightError::MissingArgument {
+            function: "f".into(),
+            arg: "11".into(),
+        }
+    );
+    assert_eq!(err.to_string(), "Function f is missing argument 11");
+
+    // Functions without names only take positional arguments
+    let mut unnamed = FunctionWriter::new(ArgCount::Fixed(1));
+    unnamed.evaluate_expression(Expression::stack(0));
+    let unnamed = engine.register_function(unnamed, 0).unwrap();
+    assert_eq!(
+        engine.call_named(&unnamed, vec![value(4)], vec![]),
+        Ok(value(4))
+    );
+    assert_eq!(
+        engine
+            .call_named(&unnamed, vec![], vec![(X, value(4))])
+            .unwrap_err()
+            .to_string(),
+        format!("Function #{} has no argument named 10", unnamed.location)
+    );
+}
+
+#[test]
+fn test_named_arguments_fill_gaps_with_defaults() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // g(a, b = a + 1, c, d = c)
+    let mut g = FunctionWriter::new(ArgCount::Range { min: 1, max: 4 });
+    g.set_arg_names(vec![0, 1, 2, 3]);
+    g.set_default(
+        1,
+        Expression::BinaryOpEval(
+            TestBinaryOperator::Add,
+            [Expression::stack(0), number(1)].into(),
+        ),
+    )
+    .unwrap();
+    g.set_default(3, Expression::stack(2)).unwrap();
+    g.evaluate_expression(Expression::Initialize(
+        TestInitializer::List,
+        (0..4).map(Expression::stack).collect(),
+    ));
+    let g = engine.register_function(g, 0).unwrap();
+
+    let Ok(TestValueWrapper(TestValue::List(values))) =
+        engine.call_named(&g, vec![value(1)], vec![(3, value(9))])
+    else {
+        panic!("expected a list");
+    };
+    assert_eq!(values[..2], [value(1), value(2)]);
+    // Skipped arguments without defaults are uninitialized, like omitted ones
+    assert!(values[2].is_uninitialized());
+    assert_eq!(values[3], value(9));
+
+    // Defaults see arguments given by name
+    assert_eq!(
+        engine.call_named(&g, vec![], vec![(2, value(7)), (0, value(3))]),
+        Ok(list(&[3, 4, 7, 7]))
+    );
+}
@@ -0,0 +1,157 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::{Expression, VariableType},
+    function::{ArgCount, FunctionWriter},
+    value::Value,
+};
+
+use super::{
+    number,
+    type_system::{
+        TestBinaryOperator, TestInitializer, TestTypeSystem, TestValue, TestValueWrapper,
+    },
+    value,
+};
+
+fn lazy(body: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::Lazy(body.into())
+}
+
+fn force(thunk: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::Force(thunk.into())
+}
+
+/// A thunk which counts how many times it is evaluated in `global`, then yields 10
+fn counting_thunk(global: usize) -> Expression<TestTypeSystem> {
+    lazy(Expression::Sequence(vec![
+        Expression::CompoundAssign {
+            target: VariableType::Global(global),
+            op: TestBinaryOperator::Add,
+            value: number(1).into(),
+        },
+        number(10),
+    ]))
+}
+
+#[test]
+fn test_force_evaluates_once() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let count = engine.create_global();
+    engine.set_global(count, value(0)).unwrap();
+
+    let mut forces = vec![Expression::AssignStack(0, counting_thunk(count).into())];
+    forces.extend((0..3).map(|_| force(Expression::stack(0))));
+    let results = Expression::Initialize(TestInitializer::List, forces);
+    assert_eq!(
+        engine.eval(&results, 1),
+        Ok(TestValueWrapper(TestValue::List(vec![
+            TestValueWrapper(TestValue::Null),
+            value(10),
+            value(10),
+            value(10)
+        ])))
+    );
+    assert_eq!(engine.get_global(count), Some(&value(1)));
+}
+
+#[test]
+fn test_forced_result_is_shared_between_references() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let count = engine.create_global();
+    let thunk = engine.create_global();
+    engine.set_global(count, value(0)).unwrap();
+
+    let mut force_arg = FunctionWriter::new(ArgCount::Fixed(1));
+    force_arg.evaluate_expression(force(Expression::stack(0)));
+    let force_arg = engine.register_function(force_arg, 0).unwrap();
+
+    engine
+        .evaluate(&Expression::AssignGlobal(
+            thunk,
+            counting_thunk(count).into(),
+        ))
+        .unwrap();
+    let thunk = engine.get_global(thunk).unwrap().dupe_ref();
+    let copy = thunk.dupe_ref();
+    assert!(!thunk.cast_to_thunk().unwrap().is_forced());
+
+    assert_eq!(engine.call(&force_arg, [thunk.clone()]), Ok(value(10)));
+    assert!(copy.cast_to_thunk().unwrap().is_forced());
+    assert_eq!(engine.call(&force_arg, [copy]), Ok(value(10)));
+    assert_eq!(engine.get_global(count), Some(&value(1)));
+}
+
+#[test]
+fn test_thunk_sees_frame_variables() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(1).into()),
+        Expression::AssignStack(
+            1,
+            lazy(Expression::BinaryOpEval(
+                TestBinaryOperator::Add,
+                [Expression::stack(0), number(1)].into(),
+            ))
+            .into(),
+        ),
+        Expression::AssignStack(0, number(5).into()),
+        force(Expression::stack(1)),
+    ]);
+    assert_eq!(engine.eval(&expr, 2), Ok(value(6)));
+}
+
+#[test]
+fn test_force_non_thunk() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(engine.eval(&force(number(3)), 0), Ok(value(3)));
+}
+
+#[test]
+fn test_return_from_thunk() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let expr = Expression::ReturnTarget(
+        0,
+        Expression::Sequence(vec![
+            Expression::AssignStack(0, lazy(Expression::Return(0, number(1).into())).into()),
+            force(Expression::stack(0)),
+        ])
+        .into(),
+    );
+    assert_eq!(
+        engine.eval(&expr, 1),
+        Err(FreightError::ReturnFromThunk { target: 0 })
+    );
+}
+
+#[test]
+fn test_failed_force_can_be_retried() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let thunk = engine.create_global();
+    engine
+        .evaluate(&Expression::AssignGlobal(
+            thunk,
+            lazy(Expression::global(thunk + 1)).into(),
+        ))
+        .unwrap();
+    let force_thunk = force(Expression::global(thunk));
+    assert!(matches!(
+        engine.evaluate(&force_thunk),
+        Err(FreightError::GlobalOutOfRange { .. })
+    ));
+
+    let target = engine.create_global();
+    engine.set_global(target, value(4)).unwrap();
+    assert_eq!(engine.evaluate(&force_thunk), Ok(value(4)));
+}
+
+#[test]
+fn test_recursive_force() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let thunk = engine.create_global();
+    let expr = Expression::Sequence(vec![
+        Expression::AssignGlobal(thunk, lazy(force(Expression::global(thunk))).into()),
+        force(Expression::global(thunk)),
+    ]);
+    assert_eq!(engine.evaluate(&expr), Err(FreightError::RecursiveForce));
+}
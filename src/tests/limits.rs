@@ -0,0 +1,495 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::FreightError,
+    execution_engine::{stack::StackPool, ExecutionEngine, Stack, DEFAULT_MAX_CALL_DEPTH},
+    expression::{Expression, NativeFunction},
+    function::{ArgCount, FunctionRef, FunctionType, FunctionWriter, StackLayout},
+    sync::{PoolCell, Shared},
+    value::Value,
+};
+
+use super::alloc_counter::count_allocations;
+use super::type_system::{
+    TestBinaryOperator, TestInitializer, TestTypeSystem, TestValue, TestValueWrapper,
+};
+
+fn recurse_natively(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let func = args[0].cast_to_function().unwrap().clone();
+    engine.call(&func, [args[0].clone()])
+}
+
+#[test]
+fn test_fuel_infinite_recursion() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let func_ref = func.to_ref(0);
+    let target = engine.create_return_target();
+    func.evaluate_expression(Expression::ReturnTarget(
+        target,
+        Expression::StaticFunctionCall(func_ref, vec![]).into(),
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+
+    engine.set_fuel(Some(100));
+    assert_eq!(
+        engine.call(&func, []),
+        Err(FreightError::OutOfFuel { consumed: 100 })
+    );
+    assert_eq!(engine.fuel(), Some(0));
+}
+
+#[test]
+fn test_fuel_through_native_calls() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    let target = engine.create_return_target();
+    func.evaluate_expression(Expression::ReturnTarget(
+        target,
+        Expression::NativeFunctionCall(
+            NativeFunction::new(recurse_natively),
+            vec![Expression::stack(0)],
+        )
+        .into(),
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+    let func_value: TestValueWrapper = func.clone().into();
+
+    for _ in 0..2 {
+        engine.set_fuel(Some(150));
+        assert_eq!(
+            engine.call(&func, [func_value.clone()]),
+            Err(FreightError::OutOfFuel { consumed: 150 })
+        );
+    }
+
+    engine.set_fuel(None);
+    assert_eq!(engine.fuel(), None);
+}
+
+fn infinite_recursion(engine: &mut ExecutionEngine<TestTypeSystem>) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let func_ref = func.to_ref(engine.functions.len());
+    let target = engine.create_return_target();
+    func.evaluate_expression(Expression::Return(
+        target,
+        Expression::StaticFunctionCall(func_ref, vec![]).into(),
+    ));
+    engine.register_function(func, target).unwrap()
+}
+
+#[test]
+fn test_default_call_depth_limit() {
+    // The default limit assumes a stack the size of a typical main thread
+    std::thread::Builder::new()
+        .stack_size(8 * 1024 * 1024)
+        .spawn(|| {
+            let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+            let func = infinite_recursion(&mut engine);
+            assert_eq!(
+                engine.call(&func, []),
+                Err(FreightError::StackOverflow {
+                    depth: DEFAULT_MAX_CALL_DEPTH
+                })
+            );
+            assert_eq!(engine.call_depth, 0);
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+}
+
+#[test]
+fn test_call_depth_restored_after_return() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_max_call_depth(8);
+    let recursive = infinite_recursion(&mut engine);
+    for _ in 0..3 {
+        assert_eq!(
+            engine.call(&recursive, []),
+            Err(FreightError::StackOverflow { depth: 8 })
+        );
+    }
+
+    let mut early_return = FunctionWriter::new(ArgCount::Fixed(0));
+    let target = engine.create_return_target();
+    early_return.evaluate_expression(Expression::Return(
+        target,
+        Expression::RawValue(TestValueWrapper(TestValue::Number(1))).into(),
+    ));
+    early_return.evaluate_expression(Expression::RawValue(TestValueWrapper(TestValue::Null)));
+    let early_return = engine.register_function(early_return, target).unwrap();
+    for _ in 0..16 {
+        assert_eq!(
+            engine.call(&early_return, []),
+            Ok(TestValueWrapper(TestValue::Number(1)))
+        );
+    }
+    assert_eq!(engine.call_depth, 0);
+}
+
+/// Builds a function which makes `2^depth` calls, too many to ever finish in a test
+pub(super) fn exponential_calls(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    depth: usize,
+) -> FunctionRef<TestTypeSystem> {
+    let mut leaf = FunctionWriter::new(ArgCount::Fixed(0));
+    leaf.evaluate_expression(Expression::RawValue(TestValueWrapper(TestValue::Number(1))));
+    let mut func = engine.register_function(leaf, 0).unwrap();
+    for _ in 0..depth {
+        let mut writer = FunctionWriter::new(ArgCount::Fixed(0));
+        writer.evaluate_expression(Expression::BinaryOpEval(
+            TestBinaryOperator::Add,
+            [
+                Expression::StaticFunctionCall(func.clone(), vec![]),
+                Expression::StaticFunctionCall(func, vec![]),
+            ]
+            .into(),
+        ));
+        func = engine.register_function(writer, 0).unwrap();
+    }
+    func
+}
+
+#[test]
+fn test_interrupt_from_other_thread() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = exponential_calls(&mut engine, 64);
+    let handle = engine.interrupt_handle();
+    let interrupter = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.interrupt();
+    });
+    assert_eq!(engine.call(&func, []), Err(FreightError::Interrupted));
+    interrupter.join().unwrap();
+
+    assert!(!engine.interrupt_handle().is_interrupted());
+    let small = exponential_calls(&mut engine, 3);
+    assert_eq!(
+        engine.call(&small, []),
+        Ok(TestValueWrapper(TestValue::Number(8)))
+    );
+}
+
+#[test]
+fn test_calls_reuse_pooled_frames() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut leaf = FunctionWriter::new(ArgCount::Fixed(1));
+    leaf.layout = StackLayout::no_alloc();
+    leaf.evaluate_expression(Expression::stack(0));
+    let mut func = engine.register_function(leaf, 0).unwrap();
+    for _ in 0..10 {
+        let mut writer = FunctionWriter::new(ArgCount::Fixed(1));
+        writer.layout = StackLayout::no_alloc();
+        let local = writer.create_variable();
+        writer.evaluate_expression(Expression::AssignStack(
+            local,
+            Expression::StaticFunctionCall(func.clone(), vec![Expression::stack(0)]).into(),
+        ));
+        writer.evaluate_expression(Expression::BinaryOpEval(
+            TestBinaryOperator::Add,
+            [
+                Expression::stack(local),
+                Expression::StaticFunctionCall(func, vec![Expression::stack(0)]),
+            ]
+            .into(),
+        ));
+        func = engine.register_function(writer, 0).unwrap();
+    }
+
+    let one = TestValueWrapper(TestValue::Number(1));
+    // Warm up once so lazily grown engine tables don't count
+    engine.call(&func, [one.clone()]).unwrap();
+    let (result, allocations) = count_allocations(|| engine.call(&func, [one.clone()]));
+    assert_eq!(result, Ok(TestValueWrapper(TestValue::Number(1024))));
+    assert_eq!(allocations, 0);
+    assert_eq!(engine.stack.with(|stack| stack.in_use()), 0);
+}
+
+#[test]
+// The test type system isn't thread safe, so its engine never is either
+#[cfg_attr(feature = "sync", allow(clippy::arc_with_non_send_sync))]
+fn test_exhausted_stack_pool_falls_back_to_allocation() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.stack = Shared::new(PoolCell::new(StackPool::with_capacity(4)));
+    engine.set_tail_call_elimination(false);
+    let mut func = FunctionWriter::new(ArgCount::Fixed(3));
+    let local = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(local, Expression::stack(2).into()));
+    func.evaluate_expression(Expression::stack(local));
+    let inner = engine.register_function(func, 0).unwrap();
+
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    outer.evaluate_expression(Expression::StaticFunctionCall(
+        inner,
+        vec![
+            Expression::RawValue(TestValueWrapper(TestValue::Number(1))),
+            Expression::RawValue(TestValueWrapper(TestValue::Number(2))),
+            Expression::RawValue(TestValueWrapper(TestValue::Number(3))),
+        ],
+    ));
+    let outer = engine.register_function(outer, 0).unwrap();
+    for _ in 0..2 {
+        assert_eq!(
+            engine.call(&outer, []),
+            Ok(TestValueWrapper(TestValue::Number(3)))
+        );
+        assert_eq!(engine.stack.with(|stack| stack.in_use()), 0);
+    }
+}
+
+#[test]
+#[cfg_attr(feature = "sync", allow(clippy::arc_with_non_send_sync))]
+fn test_exhausted_stack_pool_reuses_frames() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.stack = Shared::new(PoolCell::new(StackPool::with_capacity(2)));
+    engine.set_tail_call_elimination(false);
+    let mut func = FunctionWriter::new(ArgCount::Fixed(3));
+    let local = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(local, Expression::stack(2).into()));
+    func.evaluate_expression(Expression::stack(local));
+    let inner = engine.register_function(func, 0).unwrap();
+
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    outer.evaluate_expression(Expression::StaticFunctionCall(
+        inner,
+        vec![
+            Expression::RawValue(TestValueWrapper(TestValue::Number(1))),
+            Expression::RawValue(TestValueWrapper(TestValue::Number(2))),
+            Expression::RawValue(TestValueWrapper(TestValue::Number(3))),
+        ],
+    ));
+    let outer = engine.register_function(outer, 0).unwrap();
+    engine.call(&outer, []).unwrap();
+    let stats = engine.stats();
+    assert_eq!((stats.frame_pool_hits, stats.frame_pool_misses), (0, 1));
+
+    for _ in 0..3 {
+        assert_eq!(
+            engine.call(&outer, []),
+            Ok(TestValueWrapper(TestValue::Number(3)))
+        );
+    }
+    let stats = engine.stats();
+    assert_eq!((stats.frame_pool_hits, stats.frame_pool_misses), (3, 1));
+    engine.clear_stats();
+    let stats = engine.stats();
+    assert_eq!((stats.frame_pool_hits, stats.frame_pool_misses), (0, 0));
+}
+
+#[test]
+fn test_released_frames_drop_their_values() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.evaluate_expression(Expression::RawValue(TestValueWrapper(TestValue::Number(0))));
+    let func = engine.register_function(func, 0).unwrap();
+
+    let bound = func.bind(vec![]).unwrap();
+    let FunctionType::Bound { inner, .. } = &bound.function_type else {
+        panic!("Expected a bound function");
+    };
+    let arg = TestValueWrapper(TestValue::Function(bound.clone()));
+    engine.call(&func, [arg]).unwrap();
+    assert_eq!(Shared::strong_count(inner), 1);
+}
+
+fn call_arg(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let func = args[0].cast_to_function().unwrap().clone();
+    engine.call(&func, [])
+}
+
+#[test]
+fn test_cancellation_deadline() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let spin = exponential_calls(&mut engine, 64);
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(call_arg),
+        vec![Expression::RawValue(spin.into())],
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+
+    let deadline = Instant::now() + Duration::from_millis(20);
+    engine.set_cancellation(1000, Box::new(move || Instant::now() > deadline));
+    assert_eq!(engine.call(&func, []), Err(FreightError::Cancelled));
+    assert_eq!(engine.call_depth, 0);
+    assert_eq!(engine.stack.with(|stack| stack.in_use()), 0);
+
+    engine.clear_cancellation();
+    let small = exponential_calls(&mut engine, 3);
+    assert_eq!(
+        engine.call(&small, []),
+        Ok(TestValueWrapper(TestValue::Number(8)))
+    );
+}
+
+#[test]
+fn test_cancellation_poll_interval() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = exponential_calls(&mut engine, 10);
+    let polls = Arc::new(AtomicU64::new(0));
+    let counter = polls.clone();
+    engine.set_cancellation(
+        100,
+        Box::new(move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+            false
+        }),
+    );
+    engine.call(&func, []).unwrap();
+    assert_eq!(
+        polls.load(Ordering::Relaxed),
+        engine.stats().expressions_evaluated / 100
+    );
+}
+
+/// Builds `grow(list)`, which recurses forever wrapping `list` in a bigger list
+fn growing_list(engine: &mut ExecutionEngine<TestTypeSystem>) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    let func_ref = func.to_ref(engine.functions.len());
+    func.evaluate_expression(Expression::StaticFunctionCall(
+        func_ref,
+        vec![Expression::Initialize(
+            TestInitializer::List,
+            vec![
+                Expression::stack(0),
+                Expression::stack(0),
+                Expression::RawValue(TestValueWrapper(TestValue::Number(1))),
+            ],
+        )],
+    ));
+    engine.register_function(func, 0).unwrap()
+}
+
+#[test]
+fn test_memory_limit() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let grow = growing_list(&mut engine);
+    let empty = TestValueWrapper(TestValue::List(vec![]));
+    engine.set_max_call_depth(20);
+    assert_eq!(
+        engine.call(&grow, [empty.clone()]),
+        Err(FreightError::StackOverflow { depth: 20 })
+    );
+
+    engine.set_memory_limit(Some(64 * 1024));
+    let Err(FreightError::MemoryLimitExceeded { limit, used }) = engine.call(&grow, [empty]) else {
+        panic!("Expected the memory limit to be exceeded");
+    };
+    assert_eq!(limit, 64 * 1024);
+    assert!(used > limit);
+    assert_eq!(engine.memory_used(), used);
+    assert_eq!(engine.call_depth, 0);
+
+    engine.set_memory_limit(None);
+    assert_eq!(engine.memory_used(), 0);
+}
+
+#[test]
+#[cfg(feature = "variadic_functions")]
+fn test_memory_limit_variadic_args() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Variadic { min: 0, max: 0 });
+    func.evaluate_expression(Expression::stack(0));
+    let func = engine.register_function(func, 0).unwrap();
+    let args = vec![TestValueWrapper(TestValue::Number(1)); 8];
+    let list_size = TestValueWrapper(TestValue::List(args.clone())).approx_size();
+
+    engine.set_memory_limit(Some(list_size));
+    assert!(engine.call(&func, args.clone()).is_ok());
+    assert_eq!(
+        engine.call(&func, args),
+        Err(FreightError::MemoryLimitExceeded {
+            limit: list_size,
+            used: list_size * 2
+        })
+    );
+}
+
+#[test]
+fn test_deeply_nested_expression() {
+    const DEPTH: i64 = 100_000;
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut expr = Expression::RawValue(TestValueWrapper(TestValue::Number(0)));
+    for _ in 0..DEPTH {
+        expr = Expression::BinaryOpEval(
+            TestBinaryOperator::Add,
+            [
+                expr,
+                Expression::RawValue(TestValueWrapper(TestValue::Number(1))),
+            ]
+            .into(),
+        );
+    }
+    assert_eq!(
+        engine.evaluate(&expr),
+        Ok(TestValueWrapper(TestValue::Number(DEPTH)))
+    );
+
+    // Registering validates the whole body, and dropping the function drops it
+    let target = engine.create_return_target();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(expr);
+    let func = engine.register_function(func, target).unwrap();
+    assert_eq!(
+        engine.call(&func, []),
+        Ok(TestValueWrapper(TestValue::Number(DEPTH)))
+    );
+}
+
+/// Counts `stack[0]` down to zero while adding one to `stack[1]` for each step
+fn tail_recursive_count(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(ArgCount::Fixed(2));
+    func.layout = StackLayout::no_alloc();
+    let func_ref = func.to_ref(engine.functions.len());
+    let step = |slot, n| {
+        Expression::BinaryOpEval(
+            TestBinaryOperator::Add,
+            [
+                Expression::stack(slot),
+                Expression::RawValue(TestValueWrapper(TestValue::Number(n))),
+            ]
+            .into(),
+        )
+    };
+    func.evaluate_expression(Expression::Conditional {
+        condition: Expression::stack(0).into(),
+        then_branch: Expression::TailCall(func_ref, vec![step(0, -1), step(1, 1)]).into(),
+        else_branch: Some(Expression::stack(1).into()),
+    });
+    engine.register_function(func, 0).unwrap()
+}
+
+#[test]
+fn test_tail_calls_reuse_frame() {
+    const ITERATIONS: i64 = 1_000_000;
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let func = tail_recursive_count(&mut engine);
+    engine.set_max_call_depth(1);
+    let args = [TestValue::Number(ITERATIONS), TestValue::Number(0)].map(TestValueWrapper);
+    assert_eq!(
+        engine.call(&func, args.clone()),
+        Ok(TestValueWrapper(TestValue::Number(ITERATIONS)))
+    );
+    let stats = engine.stats();
+    assert_eq!(stats.tail_calls, ITERATIONS as u64);
+    assert_eq!(stats.peak_stack, 2);
+
+    let (result, allocations) = count_allocations(|| engine.call(&func, args));
+    assert_eq!(result, Ok(TestValueWrapper(TestValue::Number(ITERATIONS))));
+    assert_eq!(allocations, 0);
+}
@@ -1,12 +1,120 @@
 use crate::{
-    execution_engine::ExecutionEngine,
-    expression::Expression,
-    function::{ArgCount, FunctionWriter},
+    error::FreightError,
+    execution_engine::{ExecutionEngine, Stack},
+    expression::{Expression, NativeFunction},
+    function::{ArgCount, FunctionRef, FunctionWriter},
 };
 
 use self::type_system::{TestBinaryOperator, TestTypeSystem, TestValue, TestValueWrapper};
 
+mod alloc_counter;
+mod arena;
+mod arg_count;
+mod bind;
+mod captures;
+mod compose;
+mod constants;
+mod control_flow;
+mod defaults;
+mod describe;
+#[cfg(feature = "dyn_engine")]
+mod dyn_engine;
+mod engine;
+mod forward_decl;
+mod generators;
+mod index;
+mod infix;
+mod initializers;
+mod keyword_args;
+mod lazy;
+mod limits;
+mod natives;
+mod operators;
+mod optimize;
+mod pretty;
+mod reentrancy;
+#[cfg(feature = "serde")]
+mod serde;
+mod spans;
+mod strict;
+mod structural;
+#[cfg(feature = "sync")]
+mod sync;
+mod tail_calls;
+#[cfg(any(feature = "dyn_engine", feature = "sync"))]
+mod text_type_system;
+mod trace;
+mod type_assert;
 mod type_system;
+mod validation;
+mod variables;
+#[cfg(feature = "variadic_functions")]
+mod variadic;
+
+fn value(n: i64) -> TestValueWrapper {
+    TestValueWrapper(TestValue::Number(n))
+}
+
+fn number(n: i64) -> Expression<TestTypeSystem> {
+    Expression::RawValue(value(n))
+}
+
+fn binary(
+    op: TestBinaryOperator,
+    l: Expression<TestTypeSystem>,
+    r: Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    Expression::BinaryOpEval(op, [l, r].into())
+}
+
+fn add(l: Expression<TestTypeSystem>, r: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    binary(TestBinaryOperator::Add, l, r)
+}
+
+fn list(values: &[i64]) -> TestValueWrapper {
+    TestValueWrapper(TestValue::List(values.iter().copied().map(value).collect()))
+}
+
+fn raw_list(values: &[i64]) -> Expression<TestTypeSystem> {
+    Expression::RawValue(list(values))
+}
+
+/// Registers a function evaluating `body`
+fn register(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: ArgCount,
+    body: Expression<TestTypeSystem>,
+) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(args);
+    func.evaluate_expression(body);
+    engine.register_function(func, 0).unwrap()
+}
+
+/// Registers a function evaluating `body` under `name`
+fn register_named(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    name: &str,
+    args: ArgCount,
+    body: Expression<TestTypeSystem>,
+) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(args);
+    func.evaluate_expression(body);
+    engine.register_function_named(name, func, 0).unwrap()
+}
+
+/// Records its argument in the output of the context and returns it
+fn log(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let message = format!("{:?}", args[0].resolve());
+    engine.with_context(|ctx| ctx.output.push(message));
+    Ok(args[0].clone())
+}
+
+fn logged(expr: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::NativeFunctionCall(NativeFunction::new(log), vec![expr])
+}
 
 #[test]
 fn test_functions() {
@@ -18,7 +126,7 @@ fn test_functions() {
         TestBinaryOperator::Add,
         [Expression::stack(a), Expression::stack(b)].into(),
     ));
-    let add = engine.register_function(add, 0);
+    let add = engine.register_function(add, 0).unwrap();
     let mut main = FunctionWriter::new(ArgCount::Fixed(0));
     let x = main.create_variable();
     let y = main.create_variable();
@@ -34,7 +142,7 @@ fn test_functions() {
         add,
         vec![Expression::stack(x), Expression::stack(y)],
     ));
-    let main = engine.register_function(main, 0);
+    let main = engine.register_function(main, 0).unwrap();
     assert_eq!(
         engine.call(&main, []).unwrap(),
         TestValueWrapper(TestValue::Number(5))
@@ -0,0 +1,143 @@
+use crate::{
+    error::FreightError,
+    execution_engine::{ExecutionEngine, Stack},
+    expression::{Expression, NativeFunction},
+    function::{ArgCount, FunctionRef, FunctionWriter},
+    value::Value,
+};
+
+use super::{
+    type_system::{TestTypeSystem, TestValue, TestValueWrapper},
+    value,
+};
+
+fn function(func: FunctionRef<TestTypeSystem>) -> TestValueWrapper {
+    TestValueWrapper(TestValue::Function(func))
+}
+
+fn double(
+    _: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    match args[0].resolve() {
+        TestValue::Number(n) => Ok(value(n * 2)),
+        _ => Err(FreightError::InvalidInvocationTarget),
+    }
+}
+
+fn negate(
+    _: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    match args[0].resolve() {
+        TestValue::Number(n) => Ok(value(-n)),
+        _ => Err(FreightError::InvalidInvocationTarget),
+    }
+}
+
+/// Registers a function of two arguments calling the first with the second
+fn register_apply(engine: &mut ExecutionEngine<TestTypeSystem>) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(ArgCount::Fixed(2));
+    func.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::stack(0).into(),
+        vec![Expression::stack(1)],
+    ));
+    engine.register_function(func, 0).unwrap()
+}
+
+#[test]
+fn test_native_passed_to_script_function() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let double = engine.register_native(NativeFunction::new(double), ArgCount::Fixed(1));
+    let negate = engine.register_native(NativeFunction::new(negate), ArgCount::Fixed(1));
+    let apply = register_apply(&mut engine);
+
+    assert_eq!(
+        engine.call(&apply, [function(double.clone()), value(21)]),
+        Ok(value(42))
+    );
+    assert_eq!(
+        engine.call(&apply, [function(negate), value(21)]),
+        Ok(value(-21))
+    );
+    assert_eq!(engine.call(&double, [value(4)]), Ok(value(8)));
+    assert_eq!(engine.stats().native_calls, 3);
+}
+
+#[test]
+fn test_native_stored_in_variable() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let double = engine.register_native(NativeFunction::new(double), ArgCount::Fixed(1));
+
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let local = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(
+        local,
+        Expression::RawValue(function(double.clone())).into(),
+    ));
+    func.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::stack(local).into(),
+        vec![Expression::DynamicFunctionCall(
+            Expression::stack(local).into(),
+            vec![Expression::RawValue(value(5))],
+        )],
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+    assert_eq!(engine.call(&func, []), Ok(value(20)));
+
+    let stored = function(double.clone());
+    assert_eq!(stored.cast_to_function(), Some(&double));
+}
+
+#[test]
+fn test_native_arg_count_checked() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let double = engine.register_native(NativeFunction::new(double), ArgCount::Fixed(1));
+    let apply = register_apply(&mut engine);
+
+    let expected = Err(FreightError::IncorrectArgumentCount {
+        expected_min: 1,
+        expected_max: Some(1),
+        actual: 2,
+        function: Some(double.address()),
+        name: None,
+    });
+    assert_eq!(engine.call(&double, [value(1), value(2)]), expected);
+
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::RawValue(function(double.clone())).into(),
+        vec![],
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+    assert!(matches!(
+        engine.call(&func, []),
+        Err(FreightError::IncorrectArgumentCount { actual: 0, .. })
+    ));
+    assert_eq!(engine.stats().native_calls, 0);
+    assert_eq!(
+        engine.call(&apply, [function(double), value(3)]),
+        Ok(value(6))
+    );
+}
+
+#[test]
+fn test_registered_natives_are_distinct() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let first = engine.register_native(NativeFunction::new(double), ArgCount::Fixed(1));
+    let second = engine.register_native(NativeFunction::new(double), ArgCount::Fixed(1));
+    assert_ne!(first, second);
+    assert_eq!(first, first.clone());
+    assert_ne!(first.address(), second.address());
+}
+
+#[test]
+fn test_natives_registered_on_fork_are_distinct() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let double = engine.register_native(NativeFunction::new(double), ArgCount::Fixed(1));
+    let mut fork = engine.fork();
+    let negate = fork.register_native(NativeFunction::new(negate), ArgCount::Fixed(1));
+    assert_ne!(negate, double);
+    assert_eq!(fork.call(&double, [value(2)]), Ok(value(4)));
+    assert_eq!(fork.call(&negate, [value(2)]), Ok(value(-2)));
+}
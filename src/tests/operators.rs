@@ -0,0 +1,615 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::{Expression, ExpressionArena, VariableType},
+    function::{ArgCount, FunctionWriter, StackLayout},
+    optimize::fold_constants,
+};
+
+use super::{
+    add, list, logged, number, raw_list,
+    type_system::{
+        TestBinaryOperator, TestInitializer, TestLazyOperator, TestTernaryOperator, TestTypeSystem,
+        TestUnaryOperator, TestValue, TestValueWrapper, COERCIONS,
+    },
+};
+
+fn div(l: Expression<TestTypeSystem>, r: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::BinaryOpEval(TestBinaryOperator::Div, [l, r].into())
+}
+
+fn inc(v: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::UnaryOpEval(TestUnaryOperator::Inc, v.into())
+}
+
+fn division_by_zero() -> FreightError {
+    FreightError::OperatorFailed {
+        operator: "/".into(),
+        message: "division by zero".into(),
+    }
+}
+
+#[test]
+fn test_failed_operators_reach_the_host() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.evaluate_expression(div(number(12), Expression::stack(0)));
+    let func = engine.register_function(func, 0).unwrap();
+    assert_eq!(
+        engine.call(&func, [TestValueWrapper(TestValue::Number(4))]),
+        Ok(TestValueWrapper(TestValue::Number(3)))
+    );
+    assert_eq!(
+        engine.call(&func, [TestValueWrapper(TestValue::Number(0))]),
+        Err(division_by_zero())
+    );
+
+    assert_eq!(
+        engine.evaluate(&inc(number(i64::MAX))),
+        Err(FreightError::OperatorFailed {
+            operator: "++".into(),
+            message: "overflow".into(),
+        })
+    );
+}
+
+#[test]
+fn test_failed_compound_assignment_keeps_the_target() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(5).into()),
+        Expression::CompoundAssign {
+            target: VariableType::Stack(0),
+            op: TestBinaryOperator::Div,
+            value: number(0).into(),
+        },
+    ]);
+    assert_eq!(engine.eval(&expr, 1), Err(division_by_zero()));
+
+    // The error can be caught like any other
+    let caught = Expression::Sequence(vec![
+        Expression::TryCatch {
+            body: expr.into(),
+            error_slot: 1,
+            handler: number(0).into(),
+        },
+        Expression::stack(0),
+    ]);
+    assert_eq!(
+        engine.eval(&caught, 2),
+        Ok(TestValueWrapper(TestValue::Number(5)))
+    );
+}
+
+#[test]
+fn test_failed_applications_are_not_folded() {
+    let mut expr = Expression::Sequence(vec![inc(number(1)), inc(number(i64::MAX))]);
+    fold_constants(&mut expr);
+    let Expression::Sequence(exprs) = &expr else {
+        panic!("expected a sequence, got {expr:?}");
+    };
+    assert!(matches!(exprs[0], Expression::RawValue(_)));
+    assert!(matches!(exprs[1], Expression::UnaryOpEval(..)));
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert!(matches!(
+        engine.evaluate(&expr),
+        Err(FreightError::OperatorFailed { .. })
+    ));
+}
+
+#[test]
+fn test_operators_see_the_global_context() {
+    let add = |l, r| Expression::BinaryOpEval(TestBinaryOperator::Add, [l, r].into());
+    let expr = add(add(number(1), number(2)), inc(number(3)));
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(
+        engine.evaluate(&expr),
+        Ok(TestValueWrapper(TestValue::Number(7)))
+    );
+    assert_eq!(engine.context.binary_ops, 2);
+
+    // Compound assignments pass the context too, even while borrowing a global
+    let global = engine.create_global();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::AssignGlobal(global, expr.into()));
+    func.evaluate_expression(Expression::CompoundAssign {
+        target: VariableType::Global(global),
+        op: TestBinaryOperator::Add,
+        value: number(1).into(),
+    });
+    let func = engine.register_function(func, 0).unwrap();
+    engine.call(&func, []).unwrap();
+    assert_eq!(engine.context.binary_ops, 5);
+    assert_eq!(
+        engine.globals[global],
+        TestValueWrapper(TestValue::Number(8))
+    );
+}
+
+fn select(
+    a: Expression<TestTypeSystem>,
+    b: Expression<TestTypeSystem>,
+    c: Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    Expression::TernaryOpEval(TestTernaryOperator::Select, [a, b, c].into())
+}
+
+#[test]
+fn test_ternary_operands_evaluate_left_to_right() {
+    let expr = select(logged(number(0)), logged(number(1)), logged(number(2)));
+    assert_eq!(
+        expr.pretty(),
+        "(select call native(raw(TestValueWrapper(Number(0)))) \
+        call native(raw(TestValueWrapper(Number(1)))) \
+        call native(raw(TestValueWrapper(Number(2)))))"
+    );
+    let mut arena = ExpressionArena::new();
+    let root = arena.insert(&expr).unwrap();
+    for use_arena in [false, true] {
+        let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+        let result = match use_arena {
+            false => engine.eval(&expr, 0),
+            true => engine.eval_arena(&arena, root, 0),
+        };
+        // Every operand is evaluated, even the one which isn't selected
+        assert_eq!(result, Ok(TestValueWrapper(TestValue::Number(2))));
+        assert_eq!(
+            engine.context.output,
+            ["Number(0)", "Number(1)", "Number(2)"]
+        );
+    }
+}
+
+#[test]
+fn test_fold_ternary_constants() {
+    let mut expr = select(number(1), number(2), inc(number(3)));
+    fold_constants(&mut expr);
+    assert!(matches!(
+        expr,
+        Expression::RawValue(TestValueWrapper(TestValue::Number(2)))
+    ));
+
+    let mut expr = select(number(1), number(2), logged(number(3)));
+    fold_constants(&mut expr);
+    assert!(matches!(expr, Expression::TernaryOpEval(..)));
+}
+
+fn or_else(
+    l: Expression<TestTypeSystem>,
+    r: Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    Expression::LazyBinaryOpEval(TestLazyOperator::OrElse, [l, r].into())
+}
+
+#[test]
+fn test_lazy_operators_decide_whether_to_evaluate_the_right_operand() {
+    let cases = [
+        (
+            or_else(logged(number(1)), logged(number(2))),
+            1,
+            vec!["Number(1)"],
+        ),
+        (
+            or_else(logged(number(0)), logged(number(2))),
+            2,
+            vec!["Number(0)", "Number(2)"],
+        ),
+    ];
+    for (expr, expected, output) in cases {
+        let mut arena = ExpressionArena::new();
+        let root = arena.insert(&expr).unwrap();
+        for use_arena in [false, true] {
+            let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+            let result = match use_arena {
+                false => engine.eval(&expr, 0),
+                true => engine.eval_arena(&arena, root, 0),
+            };
+            assert_eq!(result, Ok(TestValueWrapper(TestValue::Number(expected))));
+            assert_eq!(engine.context.output, output);
+        }
+    }
+}
+
+#[test]
+fn test_lazy_operators_pass_on_errors_and_returns() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_error_traces(true);
+    let failing = or_else(number(0), div(number(1), number(0)));
+    let err = engine.evaluate(&failing).unwrap_err();
+    assert_eq!(err.root(), &division_by_zero());
+    assert!(err.to_string().contains("right operand of orelse"));
+
+    let returning = Expression::ReturnTarget(
+        0,
+        add_one(or_else(number(0), Expression::Return(0, number(5).into()))).into(),
+    );
+    assert_eq!(
+        engine.evaluate(&returning),
+        Ok(TestValueWrapper(TestValue::Number(5)))
+    );
+
+    // The right operand recurses natively, so it counts towards the call depth
+    engine.set_max_call_depth(3);
+    let nested = (0..4).fold(number(7), |expr, _| or_else(number(0), expr));
+    assert!(matches!(
+        engine.evaluate(&nested).map_err(|err| err.root().clone()),
+        Err(FreightError::StackOverflow { .. })
+    ));
+}
+
+fn add_one(expr: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::BinaryOpEval(TestBinaryOperator::Add, [expr, number(1)].into())
+}
+
+#[test]
+fn test_compound_assignment_updates_in_place() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    engine.globals[global] = TestValueWrapper::new_ref(list(&[1]).0);
+    let holder = engine.globals[global].clone();
+    let append = |values: &[i64]| Expression::CompoundAssign {
+        target: VariableType::Global(global),
+        op: TestBinaryOperator::Add,
+        value: raw_list(values).into(),
+    };
+    engine.evaluate(&append(&[2, 3])).unwrap();
+    engine.evaluate(&append(&[4])).unwrap();
+    assert_eq!(engine.context.in_place_appends, 2);
+    assert!(holder.ref_eq(&engine.globals[global]));
+    assert_eq!(holder.resolve(), list(&[1, 2, 3, 4]).0);
+
+    // Other operations still fall back to assigning the result
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(1).into()),
+        Expression::CompoundAssign {
+            target: VariableType::Stack(0),
+            op: TestBinaryOperator::Add,
+            value: number(2).into(),
+        },
+        Expression::stack(0),
+    ]);
+    assert_eq!(
+        engine.eval(&expr, 1),
+        Ok(TestValueWrapper(TestValue::Number(3)))
+    );
+    assert_eq!(engine.context.in_place_appends, 2);
+}
+
+#[test]
+fn test_operator_names_appear_in_errors() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_error_traces(true);
+    let expr = add_one(div(number(1), number(0)));
+    let err = engine.evaluate(&expr).unwrap_err();
+    assert_eq!(err.root(), &division_by_zero());
+    assert_eq!(
+        err.to_string(),
+        "Operator / failed: division by zero\n    in operator /\n    in left operand of +"
+    );
+
+    let expr = Expression::CompoundAssign {
+        target: VariableType::Stack(0),
+        op: TestBinaryOperator::Div,
+        value: number(0).into(),
+    };
+    let err = engine.eval(&expr, 1).unwrap_err();
+    assert_eq!(err.trace(), ["operator /"]);
+}
+
+#[cfg(feature = "profiling")]
+#[test]
+fn test_profile_groups_operators_by_name() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let expr = add_one(add_one(inc(select(number(1), number(2), number(3)))));
+    engine.evaluate(&expr).unwrap();
+    engine.evaluate(&or_else(number(1), number(2))).unwrap();
+    let profile = engine.profile();
+    assert_eq!(profile.operator_applications("+"), 2);
+    assert_eq!(profile.operator_applications("++"), 1);
+    assert_eq!(profile.operator_applications("select"), 1);
+    assert_eq!(profile.operator_applications("orelse"), 1);
+    assert_eq!(profile.operator_applications("/"), 0);
+    assert_eq!(profile.operators().count(), 4);
+
+    engine.reset_profile();
+    assert_eq!(engine.profile().operators().count(), 0);
+}
+
+/// Registers a function evaluating `operands[0] < operands[1] < ...`, keeping the middle
+/// operands in a by-value scratch slot
+fn chain(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    operands: Vec<Expression<TestTypeSystem>>,
+) -> Result<TestValueWrapper, FreightError> {
+    let ops = vec![TestBinaryOperator::Lt; operands.len().saturating_sub(1)];
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let scratch = func.create_variable();
+    func.layout = StackLayout::no_alloc();
+    func.evaluate_expression(Expression::chain_comparisons(operands, ops, scratch)?);
+    let func = engine.register_function(func, 0)?;
+    engine.call(&func, [])
+}
+
+#[test]
+fn test_comparison_chains_evaluate_middle_operands_once() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let operands = vec![
+        logged(number(1)),
+        logged(number(2)),
+        logged(number(3)),
+        logged(number(4)),
+    ];
+    assert_eq!(
+        chain(&mut engine, operands),
+        Ok(TestValueWrapper(TestValue::Number(1)))
+    );
+    assert_eq!(
+        engine.context.output,
+        ["Number(1)", "Number(2)", "Number(3)", "Number(4)"]
+    );
+
+    // The chain stops at the first comparison which fails
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let operands = vec![
+        logged(number(1)),
+        logged(number(3)),
+        logged(number(2)),
+        logged(number(4)),
+    ];
+    assert_eq!(
+        chain(&mut engine, operands),
+        Ok(TestValueWrapper(TestValue::Number(0)))
+    );
+    assert_eq!(
+        engine.context.output,
+        ["Number(1)", "Number(3)", "Number(2)"]
+    );
+}
+
+#[test]
+fn test_comparison_chain_lengths_are_checked() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(
+        chain(&mut engine, vec![number(1)]),
+        Ok(TestValueWrapper(TestValue::Number(1)))
+    );
+    assert_eq!(
+        Expression::<TestTypeSystem>::chain_comparisons(
+            vec![number(1), number(2)],
+            vec![TestBinaryOperator::Lt; 2],
+            0
+        )
+        .map(|_| ()),
+        Err(FreightError::ComparisonChainMismatch {
+            operands: 2,
+            operators: 2,
+        })
+    );
+    assert!(Expression::<TestTypeSystem>::chain_comparisons(vec![], vec![], 0).is_err());
+}
+
+fn bump(target: VariableType) -> Expression<TestTypeSystem> {
+    Expression::UnaryAssign {
+        target,
+        op: TestUnaryOperator::Inc,
+    }
+}
+
+/// Increments the target in place until it reaches `n`
+fn count_to(target: VariableType, n: i64) -> Expression<TestTypeSystem> {
+    Expression::While {
+        condition: Expression::BinaryOpEval(
+            TestBinaryOperator::Lt,
+            [Expression::Variable(target.clone()), number(n)].into(),
+        )
+        .into(),
+        body: bump(target).into(),
+    }
+}
+
+#[test]
+fn test_unary_assign_updates_variables_in_place() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert_eq!(bump(VariableType::Stack(0)).pretty(), "++= stack[0]");
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(0).into()),
+        count_to(VariableType::Stack(0), 10_000),
+        Expression::stack(0),
+    ]);
+    assert_eq!(
+        engine.eval(&expr, 1),
+        Ok(TestValueWrapper(TestValue::Number(10_000)))
+    );
+
+    // Assigning through a reference is seen by everything sharing it
+    let global = engine.create_global();
+    engine.globals[global] = TestValueWrapper::new_ref(TestValue::Number(0));
+    let holder = engine.globals[global].clone();
+    engine
+        .evaluate(&count_to(VariableType::Global(global), 10_000))
+        .unwrap();
+    assert!(holder.ref_eq(&engine.globals[global]));
+    assert_eq!(holder.resolve(), TestValue::Number(10_000));
+
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    let counter = outer.create_variable();
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(counter)]);
+    closure.evaluate_expression(count_to(VariableType::Captured(0), 10_000));
+    let closure = engine.register_function(closure, 0).unwrap();
+    outer.evaluate_expression(Expression::AssignStack(counter, number(0).into()));
+    outer.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::FunctionCapture(closure).into(),
+        vec![],
+    ));
+    outer.evaluate_expression(Expression::stack(counter));
+    let outer = engine.register_function(outer, 0).unwrap();
+    assert_eq!(
+        engine.call(&outer, []),
+        Ok(TestValueWrapper(TestValue::Number(10_000)))
+    );
+}
+
+#[test]
+fn test_failed_unary_assign_keeps_the_target() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(i64::MAX).into()),
+        Expression::TryCatch {
+            body: bump(VariableType::Stack(0)).into(),
+            error_slot: 1,
+            handler: number(0).into(),
+        },
+        Expression::stack(0),
+    ]);
+    assert_eq!(
+        engine.eval(&expr, 2),
+        Ok(TestValueWrapper(TestValue::Number(i64::MAX)))
+    );
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_map_binary_pairs_up_list_elements() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let add = FunctionWriter::map_binary(TestBinaryOperator::Add);
+    engine.register_function_named("vadd", add, 0).unwrap();
+    let vadd = |engine: &mut ExecutionEngine<TestTypeSystem>, a: TestValue, b: TestValue| {
+        engine.call_by_name("vadd", [TestValueWrapper(a), TestValueWrapper(b)])
+    };
+    assert_eq!(
+        vadd(&mut engine, list(&[1, 2, 3]).0, list(&[10, 20, 30]).0),
+        Ok(list(&[11, 22, 33]))
+    );
+    // Values which aren't lists are paired with every element
+    assert_eq!(
+        vadd(&mut engine, list(&[1, 2]).0, TestValue::Number(5)),
+        Ok(list(&[6, 7]))
+    );
+    assert_eq!(
+        vadd(&mut engine, TestValue::Number(5), list(&[1, 2]).0),
+        Ok(list(&[6, 7]))
+    );
+    assert_eq!(
+        vadd(&mut engine, TestValue::Number(5), TestValue::Number(1)),
+        Ok(TestValueWrapper(TestValue::Number(6)))
+    );
+    assert_eq!(
+        vadd(&mut engine, list(&[1, 2]).0, list(&[1]).0),
+        Err(FreightError::ListLengthMismatch { left: 2, right: 1 })
+    );
+    // Each element goes through the operator rather than a function call
+    assert_eq!(engine.context.binary_ops, 3 + 2 + 2 + 1);
+    assert_eq!(
+        engine.map_binary(
+            &TestBinaryOperator::Div,
+            &list(&[4, 1]),
+            &TestValueWrapper(TestValue::Number(0)),
+        ),
+        Err(division_by_zero())
+    );
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_map_binary_native_needs_an_operation() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let expr = Expression::NativeMacroCall(
+        crate::expression::NativeMacro::map_binary(),
+        vec![number(1)],
+    );
+    assert_eq!(
+        engine.evaluate(&expr),
+        Err(FreightError::NotABinaryOperation)
+    );
+}
+
+#[test]
+fn test_mixed_operands_are_coerced() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let coercions = || COERCIONS.with(|count| count.get());
+    let before = coercions();
+    assert_eq!(
+        engine.evaluate(&add(raw_list(&[1, 2]), number(3))),
+        Ok(list(&[1, 2, 3]))
+    );
+    assert_eq!(
+        engine.evaluate(&add(number(3), raw_list(&[1]))),
+        Ok(list(&[3, 1]))
+    );
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, raw_list(&[1]).into()),
+        Expression::CompoundAssign {
+            target: VariableType::Stack(0),
+            op: TestBinaryOperator::Add,
+            value: number(2).into(),
+        },
+        Expression::stack(0),
+    ]);
+    assert_eq!(engine.eval(&expr, 1), Ok(list(&[1, 2])));
+    let mut folded = add(raw_list(&[1]), number(2));
+    fold_constants(&mut folded);
+    assert_eq!(folded, raw_list(&[1, 2]));
+    assert_eq!(coercions(), before + 4);
+
+    // Operands of the same type never reach the hook
+    assert_eq!(
+        engine.evaluate(&add(number(1), number(2))),
+        Ok(TestValueWrapper(TestValue::Number(3)))
+    );
+    assert_eq!(
+        engine.evaluate(&add(raw_list(&[1]), raw_list(&[2]))),
+        Ok(list(&[1, 2]))
+    );
+    assert_eq!(coercions(), before + 4);
+}
+
+#[test]
+fn test_selected_operands_keep_their_aliasing() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    engine.globals[global] = TestValueWrapper::new_ref(list(&[1, 2]).0);
+    let holder = engine.globals[global].clone();
+
+    // Adding an empty list selects the other operand, which still refers to the global
+    for expr in [
+        add(Expression::global(global), raw_list(&[])),
+        add(raw_list(&[]), Expression::global(global)),
+    ] {
+        let result = engine.evaluate(&expr).unwrap();
+        assert!(result.ref_eq(&holder));
+        assert_eq!(result.resolve(), list(&[1, 2]).0);
+    }
+
+    // A copy of a stack slot holding a value is selected as it is
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    let slot = func.create_variable();
+    func.layout = StackLayout::no_alloc();
+    func.evaluate_expression(Expression::AssignStack(slot, raw_list(&[3]).into()));
+    func.evaluate_expression(Expression::AssignStack(
+        slot,
+        add(Expression::stack(slot), raw_list(&[])).into(),
+    ));
+    func.evaluate_expression(Expression::Initialize(
+        TestInitializer::List,
+        vec![
+            add(Expression::stack(slot), raw_list(&[])),
+            Expression::stack(slot),
+        ],
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+    let result = engine.call(&func, []).unwrap();
+    let TestValue::List(values) = &result.0 else {
+        panic!("Expected a list, got {result:?}");
+    };
+    assert!(!values[0].ref_eq(&values[1]));
+    assert_eq!(result.resolve(), TestValue::List(vec![list(&[3]); 2]));
+
+    // Other applications still produce new values
+    let result = engine
+        .evaluate(&add(Expression::global(global), raw_list(&[3])))
+        .unwrap();
+    assert!(!result.ref_eq(&holder));
+    assert_eq!(result.resolve(), list(&[1, 2, 3]).0);
+    assert_eq!(holder.resolve(), list(&[1, 2]).0);
+}
@@ -0,0 +1,346 @@
+use crate::{
+    execution_engine::ExecutionEngine,
+    expression::{Expression, VariableType},
+    function::{ArgCount, FunctionRef, FunctionWriter},
+    optimize::{fold_constants, fuse_unary, simplify},
+};
+
+use super::{
+    add, logged, number, register,
+    type_system::{
+        TestBinaryOperator, TestInitializer, TestTypeSystem, TestUnaryOperator, TestValue,
+        TestValueWrapper,
+    },
+};
+
+fn inc(v: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::UnaryOpEval(TestUnaryOperator::Inc, v.into())
+}
+
+fn node_count(expr: &Expression<TestTypeSystem>) -> usize {
+    let mut count = 0;
+    expr.walk(&mut |_| count += 1);
+    count
+}
+
+/// Fold an expression, checking that it evaluates the same before and after,
+/// and return the number of nodes after folding
+fn fold(expr: &mut Expression<TestTypeSystem>, stack_slots: usize) -> usize {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let before = engine.eval(expr, stack_slots);
+    fold_constants(expr);
+    assert_eq!(engine.eval(expr, stack_slots), before);
+    node_count(expr)
+}
+
+#[test]
+fn test_fold_nested_constants() {
+    let mut expr = add(add(number(1), number(2)), inc(number(3)));
+    assert_eq!(node_count(&expr), 6);
+    assert_eq!(fold(&mut expr, 0), 1);
+    assert!(matches!(
+        expr,
+        Expression::RawValue(TestValueWrapper(TestValue::Number(7)))
+    ));
+}
+
+#[test]
+fn test_fold_partially_constant() {
+    let mut expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(4).into()),
+        add(Expression::stack(0), add(number(1), number(2))),
+    ]);
+    assert_eq!(node_count(&expr), 8);
+    assert_eq!(fold(&mut expr, 1), 6);
+}
+
+#[test]
+fn test_fold_inside_initializers_and_sequences() {
+    let mut expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, add(number(1), number(2)).into()),
+        Expression::Initialize(
+            TestInitializer::List,
+            vec![inc(number(1)), add(Expression::stack(0), inc(number(0)))],
+        ),
+    ]);
+    assert_eq!(node_count(&expr), 12);
+    assert_eq!(fold(&mut expr, 1), 8);
+}
+
+#[test]
+fn test_impure_operators_are_not_folded() {
+    let mut expr = Expression::BinaryOpEval(
+        TestBinaryOperator::Lt,
+        [add(number(1), number(2)), number(5)].into(),
+    );
+    assert_eq!(node_count(&expr), 5);
+    assert_eq!(fold(&mut expr, 0), 3);
+}
+
+#[test]
+fn test_walk_in_evaluation_order() {
+    let expr = Expression::Sequence(vec![
+        add(number(1), inc(number(2))),
+        Expression::Conditional {
+            condition: number(3).into(),
+            then_branch: number(4).into(),
+            else_branch: Some(number(5).into()),
+        },
+    ]);
+    let mut visited = vec![];
+    expr.walk(&mut |expr| {
+        if let Expression::RawValue(TestValueWrapper(TestValue::Number(n))) = expr {
+            visited.push(*n);
+        }
+    });
+    assert_eq!(visited, [1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_map_offsets_stack_addresses() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(5).into()),
+        Expression::AssignStack(1, inc(Expression::stack(0)).into()),
+        add(Expression::stack(0), Expression::stack(1)),
+    ]);
+    assert_eq!(
+        engine.eval(&expr, 2),
+        Ok(TestValueWrapper(TestValue::Number(11)))
+    );
+
+    let mut offset = |mut expr: Expression<TestTypeSystem>| {
+        match &mut expr {
+            Expression::Variable(VariableType::Stack(slot)) | Expression::AssignStack(slot, _) => {
+                *slot += 3
+            }
+            _ => {}
+        }
+        expr
+    };
+    let expr = expr.map(&mut offset);
+    assert_eq!(expr.max_stack_slot(), Some(4));
+    assert_eq!(
+        engine.eval(&expr, 5),
+        Ok(TestValueWrapper(TestValue::Number(11)))
+    );
+    assert!(engine.eval(&expr, 2).is_err());
+}
+
+/// Simplify an expression, checking that it produces the same value, output and globals
+/// before and after, and return the number of nodes removed
+fn simplify_checked(expr: &mut Expression<TestTypeSystem>, stack_slots: usize) -> usize {
+    let run = |expr: &Expression<TestTypeSystem>| {
+        let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+        let global = engine.create_global();
+        engine.globals[global] = TestValueWrapper(TestValue::Number(10));
+        let result = engine.eval(expr, stack_slots);
+        (result, engine.context.output, engine.globals)
+    };
+    let before = run(expr);
+    let removed = simplify(expr);
+    assert_eq!(run(expr), before);
+    removed
+}
+
+#[test]
+fn test_simplify_removes_discarded_pure_expressions() {
+    let mut expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(1).into()),
+        add(Expression::stack(0), number(2)),
+        logged(Expression::stack(0)),
+        inc(Expression::global(0)),
+        Expression::AssignGlobal(0, add(Expression::stack(0), number(5)).into()),
+        Expression::Conditional {
+            condition: Expression::stack(0).into(),
+            then_branch: number(1).into(),
+            else_branch: None,
+        },
+        Expression::global(0),
+    ]);
+    assert_eq!(simplify_checked(&mut expr, 1), 8);
+    let Expression::Sequence(exprs) = &expr else {
+        panic!("expected a sequence, got {expr:?}");
+    };
+    assert_eq!(exprs.len(), 4);
+}
+
+#[test]
+fn test_simplify_keeps_effects_in_discarded_expressions() {
+    let mut expr = Expression::Sequence(vec![
+        add(number(1), logged(number(2))),
+        Expression::BinaryOpEval(TestBinaryOperator::Lt, [number(0), number(1)].into()),
+        Expression::AssignStack(0, Expression::stack(1).into()),
+        number(3),
+    ]);
+    assert_eq!(simplify_checked(&mut expr, 2), 0);
+}
+
+#[test]
+fn test_simplify_flattens_sequences_and_self_assignments() {
+    let mut expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(4).into()),
+        Expression::Sequence(vec![
+            Expression::AssignStack(0, Expression::stack(0).into()),
+            logged(Expression::stack(0)),
+        ]),
+        Expression::Sequence(vec![
+            Expression::stack(0),
+            Expression::Sequence(vec![inc(Expression::stack(0))]),
+        ]),
+    ]);
+    assert_eq!(simplify_checked(&mut expr, 1), 6);
+    assert_eq!(node_count(&expr), 7);
+
+    // An empty sequence at the end still decides the result
+    let mut expr = Expression::Sequence(vec![number(1), Expression::Sequence(vec![])]);
+    assert_eq!(simplify_checked(&mut expr, 0), 2);
+    assert!(matches!(&expr, Expression::Sequence(exprs) if exprs.is_empty()));
+}
+
+fn unary(op: TestUnaryOperator, v: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::UnaryOpEval(op, v.into())
+}
+
+/// Fuse an expression's unary operators, checking that it evaluates the same before and
+/// after for a few numbers in stack slot 0, and return the number of nodes removed
+fn fuse_checked(expr: &mut Expression<TestTypeSystem>) -> usize {
+    let run = |expr: &mut Expression<TestTypeSystem>| {
+        let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+        [-3, 0, 7, i64::MIN].map(|n| {
+            let body = std::mem::replace(expr, number(0));
+            let mut program =
+                Expression::Sequence(vec![Expression::AssignStack(0, number(n).into()), body]);
+            let result = engine.eval(&program, 1);
+            let Expression::Sequence(exprs) = &mut program else {
+                unreachable!()
+            };
+            *expr = exprs.pop().unwrap();
+            result
+        })
+    };
+    let before = run(expr);
+    let removed = fuse_unary(expr);
+    assert_eq!(run(expr), before);
+    removed
+}
+
+#[test]
+fn test_fuse_cancelling_unary_operators() {
+    use TestUnaryOperator::*;
+    let mut expr = unary(Neg, unary(Neg, Expression::stack(0)));
+    assert_eq!(fuse_checked(&mut expr), 2);
+    assert_eq!(expr, Expression::stack(0));
+
+    // Fused operators fuse again with what's under them
+    let mut expr = unary(
+        Neg,
+        unary(Neg, unary(Neg, unary(Neg, Expression::stack(0)))),
+    );
+    assert_eq!(fuse_checked(&mut expr), 4);
+    assert_eq!(expr, Expression::stack(0));
+    let mut expr = unary(
+        Abs,
+        unary(Neg, unary(Abs, unary(Neg, Expression::stack(0)))),
+    );
+    assert_eq!(fuse_checked(&mut expr), 3);
+    assert_eq!(expr, unary(Abs, Expression::stack(0)));
+
+    // Inside other expressions
+    let mut expr = add(unary(Neg, unary(Neg, Expression::stack(0))), number(1));
+    assert_eq!(fuse_checked(&mut expr), 2);
+    assert_eq!(expr, add(Expression::stack(0), number(1)));
+}
+
+#[test]
+fn test_unfused_unary_operators_are_kept() {
+    use TestUnaryOperator::*;
+    for mut expr in [
+        unary(Inc, unary(Inc, Expression::stack(0))),
+        unary(Neg, unary(Abs, Expression::stack(0))),
+        unary(Neg, unary(Inc, unary(Neg, Expression::stack(0)))),
+    ] {
+        let unchanged = expr.pretty();
+        assert_eq!(fuse_checked(&mut expr), 0);
+        assert_eq!(expr.pretty(), unchanged);
+    }
+}
+
+/// Registers small functions and a main function calling them, returning the result of
+/// calling main, how many function calls that took and the body of main
+fn run_with_inlining(threshold: Option<usize>) -> (TestValueWrapper, u64, String) {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_inline_threshold(threshold);
+    let get = register(&mut engine, ArgCount::Fixed(1), Expression::stack(0));
+    let sum = register(
+        &mut engine,
+        ArgCount::Fixed(2),
+        add(Expression::stack(0), inc(Expression::stack(1))),
+    );
+    // Reads its argument twice, so inlining would evaluate it twice
+    let double = register(
+        &mut engine,
+        ArgCount::Fixed(1),
+        add(Expression::stack(0), Expression::stack(0)),
+    );
+    let returns = register(
+        &mut engine,
+        ArgCount::Fixed(1),
+        Expression::Return(0, Expression::stack(0).into()),
+    );
+    let call = |func: &FunctionRef<TestTypeSystem>, args| {
+        Expression::StaticFunctionCall(func.clone(), args)
+    };
+    let main = register(
+        &mut engine,
+        ArgCount::Fixed(0),
+        add(
+            call(&sum, vec![call(&get, vec![number(1)]), number(2)]),
+            add(
+                call(&double, vec![number(3)]),
+                call(&returns, vec![number(4)]),
+            ),
+        ),
+    );
+    let body = engine.get_function(main.location).expressions[0].pretty();
+    let result = engine.call(&main, []).unwrap();
+    (result, engine.stats().function_calls, body)
+}
+
+#[test]
+fn test_inline_trivial_calls() {
+    let (expected, calls, _) = run_with_inlining(None);
+    assert_eq!(expected, TestValueWrapper(TestValue::Number(14)));
+    assert_eq!(calls, 5);
+
+    let (result, calls, body) = run_with_inlining(Some(8));
+    assert_eq!(result, expected);
+    assert_eq!(calls, 3);
+    assert!(body.starts_with("(+ (+ raw(TestValueWrapper(Number(1))) (++ raw"));
+
+    // Bodies over the threshold are still called
+    let (result, calls, _) = run_with_inlining(Some(1));
+    assert_eq!(result, expected);
+    assert_eq!(calls, 4);
+}
+
+#[test]
+fn test_inline_skips_unregistered_functions() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_inline_threshold(Some(8));
+    // A call to the function being registered can't be inlined into itself
+    let location = engine.functions.len();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    let this = func.to_ref(location);
+    func.evaluate_expression(Expression::Conditional {
+        condition: Expression::stack(0).into(),
+        then_branch: number(1).into(),
+        else_branch: Some(Expression::StaticFunctionCall(this, vec![number(1)]).into()),
+    });
+    let func = engine.register_function(func, 0).unwrap();
+    assert_eq!(
+        engine.call(&func, [TestValueWrapper(TestValue::Number(0))]),
+        Ok(TestValueWrapper(TestValue::Number(1)))
+    );
+    assert_eq!(engine.stats().function_calls, 2);
+}
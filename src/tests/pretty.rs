@@ -0,0 +1,119 @@
+use crate::{
+    execution_engine::ExecutionEngine,
+    expression::{Expression, NativeFunction, VariableType},
+    function::{ArgCount, FunctionWriter},
+};
+
+use super::{
+    number,
+    type_system::{
+        TestBinaryOperator, TestInitializer, TestTypeSystem, TestUnaryOperator, TestValue,
+        TestValueWrapper,
+    },
+};
+
+#[test]
+fn test_pretty_expression() {
+    let expr = Expression::<TestTypeSystem>::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [
+            number(1),
+            Expression::UnaryOpEval(TestUnaryOperator::Inc, Expression::global(2).into()),
+        ]
+        .into(),
+    );
+    assert_eq!(
+        expr.pretty(),
+        "(+ raw(TestValueWrapper(Number(1))) (++ global[2]))"
+    );
+}
+
+#[test]
+fn test_pretty_function_body() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let helper = engine
+        .register_function(FunctionWriter::new(ArgCount::Fixed(2)), 0)
+        .unwrap();
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(0)]);
+    closure.evaluate_expression(Expression::captured(0));
+    let closure = engine.register_function(closure, 0).unwrap();
+
+    let body = Expression::<TestTypeSystem>::ReturnTarget(
+        1,
+        Expression::Sequence(vec![
+            Expression::AssignStack(
+                1,
+                Expression::StaticFunctionCall(
+                    helper,
+                    vec![Expression::stack(0), Expression::global(3)],
+                )
+                .into(),
+            ),
+            Expression::AssignCaptured(0, Expression::FunctionCapture(closure).into()),
+            Expression::Conditional {
+                condition: Expression::And([Expression::stack(1), number(0)].into()).into(),
+                then_branch: Expression::Return(1, Expression::stack(1).into()).into(),
+                else_branch: Some(
+                    Expression::CompoundAssign {
+                        target: VariableType::Global(0),
+                        op: TestBinaryOperator::Add,
+                        value: number(1).into(),
+                    }
+                    .into(),
+                ),
+            },
+            Expression::For {
+                binding: 2,
+                iterable: Expression::Initialize(TestInitializer::List, vec![number(1)]).into(),
+                body: Expression::TryCatch {
+                    body: Expression::DynamicFunctionCall(Expression::stack(2).into(), vec![])
+                        .into(),
+                    error_slot: 3,
+                    handler: Expression::NativeFunctionCall(
+                        NativeFunction::new(|_, _| Ok(Default::default())),
+                        vec![Expression::stack(3)],
+                    )
+                    .into(),
+                }
+                .into(),
+            },
+            Expression::While {
+                condition: Expression::Or([number(0), Expression::captured(1)].into()).into(),
+                body: Expression::Sequence(vec![]).into(),
+            },
+            Expression::Match {
+                scrutinee: Expression::stack(1).into(),
+                arms: vec![(TestValueWrapper(TestValue::Number(1)), number(10))],
+                default: Some(
+                    Expression::AssignDynamic([Expression::stack(0), number(2)].into()).into(),
+                ),
+            },
+        ])
+        .into(),
+    );
+    assert_eq!(
+        body.pretty(),
+        r#"target #1 {
+    stack[1] = call #0(stack[0], global[3])
+    captured[0] = capture #1[stack[0]]
+    if (and stack[1] raw(TestValueWrapper(Number(0)))) {
+        return #1 stack[1]
+    } else {
+        global[0] += raw(TestValueWrapper(Number(1)))
+    }
+    for stack[2] in init List(raw(TestValueWrapper(Number(1)))) {
+        try {
+            call stack[2]()
+        } catch stack[3] {
+            call native(stack[3])
+        }
+    }
+    while (or raw(TestValueWrapper(Number(0))) captured[1]) {}
+    match stack[1] {
+        TestValueWrapper(Number(1)) => raw(TestValueWrapper(Number(10)))
+        _ => *stack[0] = raw(TestValueWrapper(Number(2)))
+    }
+}"#
+    );
+}
@@ -0,0 +1,170 @@
+use crate::{
+    error::FreightError,
+    execution_engine::{ExecutionEngine, Stack},
+    expression::{Expression, NativeFunction},
+    function::{ArgCount, FunctionRef, FunctionWriter},
+    value::Value,
+};
+
+use super::{
+    type_system::{TestBinaryOperator, TestTypeSystem, TestValueWrapper},
+    value,
+};
+
+/// Calls the function in its first argument with the rest of its arguments
+fn call_script(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let func = args[0].cast_to_function().unwrap().clone();
+    engine.call(&func, args[1..].to_vec())
+}
+
+/// Stores its argument in global 0 and returns the previous value
+fn swap_global(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let previous = engine.get_global(0).unwrap().resolve();
+    engine.set_global(0, TestValueWrapper(args[0].resolve()))?;
+    Ok(TestValueWrapper(previous))
+}
+
+fn fail(
+    _: &mut ExecutionEngine<TestTypeSystem>,
+    _: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    Err(FreightError::Interrupted)
+}
+
+/// Builds `main(x)`, which keeps `x + 1` in a local, passes `x` through a native to `script`,
+/// and returns the native's result plus the local
+fn main_through_native(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    script: FunctionRef<TestTypeSystem>,
+) -> FunctionRef<TestTypeSystem> {
+    let mut main = FunctionWriter::new(ArgCount::Fixed(1));
+    let local = main.create_variable();
+    main.evaluate_expression(Expression::AssignStack(
+        local,
+        Expression::BinaryOpEval(
+            TestBinaryOperator::Add,
+            [Expression::stack(0), Expression::RawValue(value(1))].into(),
+        )
+        .into(),
+    ));
+    main.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [
+            Expression::NativeFunctionCall(
+                NativeFunction::new(call_script),
+                vec![Expression::RawValue(script.into()), Expression::stack(0)],
+            ),
+            Expression::stack(local),
+        ]
+        .into(),
+    ));
+    engine.register_function(main, 0).unwrap()
+}
+
+/// Builds `script(x)`, which returns early with the result of passing `x` to `native`
+fn script_calling_native(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    native: NativeFunction<TestTypeSystem>,
+) -> FunctionRef<TestTypeSystem> {
+    let target = engine.create_return_target();
+    let mut script = FunctionWriter::new(ArgCount::Fixed(1));
+    script.evaluate_expression(Expression::Return(
+        target,
+        Expression::NativeFunctionCall(native, vec![Expression::stack(0)]).into(),
+    ));
+    script.evaluate_expression(Expression::RawValue(value(-1)));
+    engine.register_function(script, target).unwrap()
+}
+
+#[test]
+fn test_native_calls_script_calls_native() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let global = engine.create_global();
+    engine.set_global(global, value(100)).unwrap();
+    let script = script_calling_native(&mut engine, NativeFunction::new(swap_global));
+    let main = main_through_native(&mut engine, script);
+
+    assert_eq!(engine.call(&main, [value(5)]), Ok(value(106)));
+    assert_eq!(engine.get_global(global), Some(&value(5)));
+    assert_eq!(engine.call(&main, [value(7)]), Ok(value(13)));
+    assert_eq!(engine.get_global(global), Some(&value(7)));
+    assert_eq!(engine.call_depth, 0);
+    assert_eq!(engine.stack.with(|stack| stack.in_use()), 0);
+}
+
+#[test]
+fn test_error_propagates_through_nested_calls() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let script = script_calling_native(&mut engine, NativeFunction::new(fail));
+    let main = main_through_native(&mut engine, script);
+
+    assert_eq!(
+        engine.call(&main, [value(1)]),
+        Err(FreightError::Interrupted)
+    );
+    assert_eq!(engine.call_depth, 0);
+    assert_eq!(engine.stack.with(|stack| stack.in_use()), 0);
+}
+
+#[test]
+fn test_return_cannot_cross_native_boundary() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let outer_target = engine.create_return_target();
+    let script_target = engine.create_return_target();
+    let mut script = FunctionWriter::new(ArgCount::Fixed(1));
+    script.evaluate_expression(Expression::Return(
+        outer_target,
+        Expression::stack(0).into(),
+    ));
+    let script = engine.register_function(script, script_target).unwrap();
+
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    main.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(call_script),
+        vec![
+            Expression::RawValue(script.into()),
+            Expression::RawValue(value(3)),
+        ],
+    ));
+    let main = engine.register_function(main, outer_target).unwrap();
+
+    assert_eq!(
+        engine.call(&main, []),
+        Err(FreightError::UnhandledReturnTarget {
+            target: outer_target
+        })
+    );
+    assert_eq!(engine.last_return_value(), &value(3));
+}
+
+/// Registers a new function and immediately calls it
+fn register_and_call(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::stack(0), Expression::stack(0)].into(),
+    ));
+    let func = engine.register_function(func, 0)?;
+    engine.call(&func, [args[0].clone()])
+}
+
+#[test]
+fn test_register_and_call_from_nested_native() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let script = script_calling_native(&mut engine, NativeFunction::new(register_and_call));
+    let main = main_through_native(&mut engine, script);
+
+    for i in 0..20 {
+        assert_eq!(engine.call(&main, [value(i)]), Ok(value(3 * i + 1)));
+    }
+    assert_eq!(engine.functions.len(), 22);
+}
@@ -0,0 +1,326 @@
+use crate::{
+    error::{AddressKind, FreightError},
+    execution_engine::{export::ExportedFunctions, ExecutionEngine},
+    expression::{Expression, NativeFunction, VariableType},
+    function::{ArgCount, FunctionRef, FunctionWriter},
+    operators::{BinaryOperator, UnaryOperator},
+    TypeSystem,
+};
+
+use super::{
+    number,
+    type_system::{
+        TestBinaryOperator, TestInitializer, TestTypeSystem, TestUnaryOperator, TestValue,
+        TestValueWrapper,
+    },
+    value,
+};
+
+fn round_trip(expr: &Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    let json = serde_json::to_string(expr).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+#[test]
+fn test_expression_round_trip() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // Sum the numbers below 5, then wrap the total in a list
+    let expr = Expression::Sequence(vec![
+        Expression::AssignStack(0, number(0).into()),
+        Expression::For {
+            binding: 1,
+            iterable: number(5).into(),
+            body: Expression::CompoundAssign {
+                target: VariableType::Stack(0),
+                op: TestBinaryOperator::Add,
+                value: Expression::stack(1).into(),
+            }
+            .into(),
+        },
+        Expression::Initialize(
+            TestInitializer::List,
+            vec![Expression::UnaryOpEval(
+                TestUnaryOperator::Inc,
+                Expression::stack(0).into(),
+            )],
+        ),
+    ]);
+    let copy = round_trip(&expr);
+    assert_eq!(copy.pretty(), expr.pretty());
+    assert_eq!(engine.eval(&copy, 2), engine.eval(&expr, 2));
+    assert_eq!(
+        engine.eval(&copy, 2),
+        Ok(TestValueWrapper(TestValue::List(vec![TestValueWrapper(
+            TestValue::Number(11)
+        )])))
+    );
+}
+
+#[test]
+fn test_function_ref_round_trip() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut adder = FunctionWriter::new_capturing(ArgCount::Fixed(1), vec![VariableType::Stack(0)]);
+    adder.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [Expression::captured(0), Expression::stack(0)].into(),
+    ));
+    let adder = engine.register_function(adder, 0).unwrap();
+
+    let mut make_adder = FunctionWriter::new(ArgCount::Fixed(1));
+    make_adder.evaluate_expression(Expression::FunctionCapture(adder));
+    let make_adder = engine.register_function(make_adder, 0).unwrap();
+
+    let add_two = engine
+        .call(&make_adder, [TestValueWrapper(TestValue::Number(2))])
+        .unwrap();
+    let json = serde_json::to_string(&add_two).unwrap();
+    let add_two: TestValueWrapper = serde_json::from_str(&json).unwrap();
+    let add_two = add_two.0;
+    let TestValue::Function(add_two) = add_two else {
+        panic!("Expected a function, got {add_two:?}");
+    };
+    assert_eq!(
+        engine.call(&add_two, [TestValueWrapper(TestValue::Number(3))]),
+        Ok(TestValueWrapper(TestValue::Number(5)))
+    );
+}
+
+/// Registers `square(x) = x * x` and a closure maker `adder(x) = y => x + y + 1`, where the
+/// constant is pooled if the engine pools constants
+fn register_library(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+) -> (FunctionRef<TestTypeSystem>, FunctionRef<TestTypeSystem>) {
+    let mut square = FunctionWriter::new(ArgCount::Fixed(1));
+    square.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Mul,
+        [Expression::stack(0), Expression::stack(0)].into(),
+    ));
+    let square = engine.register_function_named("square", square, 0).unwrap();
+
+    let mut add = FunctionWriter::new_capturing(ArgCount::Fixed(1), vec![VariableType::Stack(0)]);
+    add.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [
+            Expression::BinaryOpEval(
+                TestBinaryOperator::Add,
+                [Expression::captured(0), Expression::stack(0)].into(),
+            ),
+            number(1),
+        ]
+        .into(),
+    ));
+    let add = engine.register_function(add, 0).unwrap();
+    let mut adder = FunctionWriter::new(ArgCount::Fixed(1));
+    adder.evaluate_expression(Expression::FunctionCapture(add));
+    let adder = engine.register_function(adder, 0).unwrap();
+    (square, adder)
+}
+
+#[test]
+fn test_function_table_round_trip_relocates() {
+    let mut source = ExecutionEngine::<TestTypeSystem>::new_default();
+    source.set_constant_pooling(true);
+    let (square, adder) = register_library(&mut source);
+    // `main() = adder(square(3))(sum_with_four(1))`, where `sum_with_four` is
+    // `sum(a, b) = square(a) + b` with 4 bound as its first argument
+    let mut sum = FunctionWriter::new(ArgCount::Fixed(2));
+    sum.evaluate_expression(Expression::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [
+            Expression::StaticFunctionCall(square.clone(), vec![Expression::stack(0)]),
+            Expression::stack(1),
+        ]
+        .into(),
+    ));
+    let sum = source.register_function_named("sum", sum, 0).unwrap();
+    let sum_with_four = sum.bind(vec![value(4)]).unwrap();
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    main.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::StaticFunctionCall(
+            adder,
+            vec![Expression::StaticFunctionCall(square, vec![number(3)])],
+        )
+        .into(),
+        vec![Expression::StaticFunctionCall(
+            sum_with_four,
+            vec![number(1)],
+        )],
+    ));
+    let main = source.register_function(main, 0).unwrap();
+    source.set_entry_point(main);
+    let expected = source.run().unwrap();
+    assert_eq!(expected, value(9 + (16 + 1) + 1));
+
+    let json = serde_json::to_string(&source.export_functions().unwrap()).unwrap();
+    let exported: ExportedFunctions<TestTypeSystem> = serde_json::from_str(&json).unwrap();
+    assert_eq!(exported.len(), 5);
+
+    // The importing engine already has its own functions and constants
+    let mut target = ExecutionEngine::<TestTypeSystem>::new_default();
+    target.set_constant_pooling(true);
+    let mut five = FunctionWriter::new(ArgCount::Fixed(0));
+    five.evaluate_expression(number(5));
+    let five = target.register_function(five, 0).unwrap();
+    let mut six = FunctionWriter::new(ArgCount::Fixed(0));
+    six.evaluate_expression(number(6));
+    let six = target.register_function(six, 0).unwrap();
+
+    let relocation = target.import_functions(exported).unwrap();
+    assert_eq!(relocation.location(0), Some(2));
+    assert_eq!(relocation.location(4), Some(6));
+    assert_eq!(relocation.location(5), None);
+    assert_eq!(target.run(), Ok(expected));
+    assert_eq!(target.call_by_name("square", [value(7)]), Ok(value(49)));
+    assert_eq!(
+        target.call_by_name("sum", [value(2), value(3)]),
+        Ok(value(7))
+    );
+    assert_eq!(target.call(&five, []), Ok(value(5)));
+    assert_eq!(target.call(&six, []), Ok(value(6)));
+    assert_eq!(target.validate(), Ok(()));
+}
+
+#[test]
+fn test_captured_function_refs_are_not_exported() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let (_, adder) = register_library(&mut engine);
+    let add_two = engine.call(&adder, [value(2)]).unwrap();
+    let TestValue::Function(add_two) = add_two.0 else {
+        panic!("Expected a function, got {add_two:?}");
+    };
+    let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+    main.evaluate_expression(Expression::StaticFunctionCall(
+        add_two.clone(),
+        vec![number(1)],
+    ));
+    engine.register_function(main, 0).unwrap();
+    assert_eq!(
+        engine.export_functions().err(),
+        Some(FreightError::NotExportable {
+            function: add_two.to_string(),
+            message: "it holds captured values".into(),
+        })
+    );
+}
+
+#[test]
+fn test_functions_held_by_values_are_not_exported() {
+    for pooling in [false, true] {
+        let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+        engine.set_constant_pooling(pooling);
+        let (square, _) = register_library(&mut engine);
+        let mut main = FunctionWriter::new(ArgCount::Fixed(0));
+        main.evaluate_expression(Expression::DynamicFunctionCall(
+            Expression::RawValue(TestValueWrapper(TestValue::Function(square.clone()))).into(),
+            vec![number(3)],
+        ));
+        engine.register_function(main, 0).unwrap();
+        assert_eq!(
+            engine.export_functions().err(),
+            Some(FreightError::NotExportable {
+                function: square.to_string(),
+                message: "it's held by a value".into(),
+            })
+        );
+    }
+}
+
+#[test]
+fn test_failed_import_adds_nothing() {
+    let mut source = ExecutionEngine::<TestTypeSystem>::new_default();
+    register_library(&mut source);
+    let json = serde_json::to_string(&source.export_functions().unwrap()).unwrap();
+
+    let mut target = ExecutionEngine::<TestTypeSystem>::new_default();
+    register_library(&mut target);
+    let exported: ExportedFunctions<TestTypeSystem> = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        target.import_functions(exported).err(),
+        Some(FreightError::DuplicateFunctionName {
+            name: "square".into()
+        })
+    );
+    assert_eq!(target.functions.len(), 3);
+}
+
+#[test]
+fn test_tampered_import_is_validated() {
+    let mut source = ExecutionEngine::<TestTypeSystem>::new_default();
+    register_library(&mut source);
+    let json = serde_json::to_string(&source.export_functions().unwrap()).unwrap();
+    // `square` comes first and reads stack slot 0, which no longer exists
+    let json = json.replacen("\"stack_size\":1", "\"stack_size\":0", 1);
+
+    let mut target = ExecutionEngine::<TestTypeSystem>::new_default();
+    let exported: ExportedFunctions<TestTypeSystem> = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        target.import_functions(exported).err(),
+        Some(FreightError::InvalidAddress {
+            function: 0,
+            kind: AddressKind::Stack,
+            address: 0,
+        })
+    );
+    assert!(target.functions.is_empty());
+    assert!(target.function_names.is_empty());
+}
+
+#[test]
+fn test_native_functions_are_not_serialized() {
+    let expr = Expression::<TestTypeSystem>::NativeFunctionCall(
+        NativeFunction::new(|_, _| Ok(TestValueWrapper(TestValue::Null))),
+        vec![],
+    );
+    let err = serde_json::to_string(&expr).unwrap_err();
+    assert!(err.to_string().contains("native functions"));
+}
+
+#[test]
+fn test_operators_round_trip_by_id() {
+    let binary = [
+        TestBinaryOperator::Add,
+        TestBinaryOperator::Lt,
+        TestBinaryOperator::Div,
+    ];
+    for op in binary {
+        assert_eq!(
+            TestTypeSystem::binary_op_from_id(op.op_id()),
+            Some(op.clone())
+        );
+        let expr = Expression::BinaryOpEval(op.clone(), [number(1), number(2)].into());
+        let json = serde_json::to_value(&expr).unwrap();
+        assert_eq!(json["BinaryOpEval"][0], op.op_id());
+        assert_eq!(round_trip(&expr), expr);
+
+        let expr = Expression::CompoundAssign {
+            target: VariableType::Stack(0),
+            op,
+            value: number(1).into(),
+        };
+        assert_eq!(round_trip(&expr), expr);
+    }
+    let op = TestUnaryOperator::Inc;
+    assert_eq!(
+        TestTypeSystem::unary_op_from_id(op.op_id()),
+        Some(op.clone())
+    );
+    let expr = Expression::UnaryOpEval(op.clone(), number(1).into());
+    assert_eq!(round_trip(&expr), expr);
+    let expr = Expression::UnaryAssign {
+        target: VariableType::Global(0),
+        op,
+    };
+    assert_eq!(round_trip(&expr), expr);
+}
+
+#[test]
+fn test_unknown_operator_ids_are_rejected() {
+    let expr = Expression::<TestTypeSystem>::BinaryOpEval(
+        TestBinaryOperator::Add,
+        [number(1), number(2)].into(),
+    );
+    let mut json = serde_json::to_value(&expr).unwrap();
+    json["BinaryOpEval"][0] = 99.into();
+    let err = serde_json::from_value::<Expression<TestTypeSystem>>(json).unwrap_err();
+    assert!(err.to_string().contains("Unknown binary operator id 99"));
+}
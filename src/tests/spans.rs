@@ -0,0 +1,120 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::{DebugInfo, Expression},
+    function::{ArgCount, FunctionWriter},
+};
+
+use super::{
+    add, number,
+    type_system::{TestTypeSystem, TestValue, TestValueWrapper},
+};
+
+fn span(start: usize, end: usize) -> DebugInfo {
+    DebugInfo {
+        file: 1,
+        start,
+        end,
+    }
+}
+
+fn spanned(
+    start: usize,
+    end: usize,
+    expr: Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    Expression::Spanned(span(start, end), expr.into())
+}
+
+/// Reads a global which doesn't exist
+fn failing() -> Expression<TestTypeSystem> {
+    Expression::global(0)
+}
+
+#[test]
+fn test_error_has_innermost_span() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let expr = spanned(
+        0,
+        40,
+        Expression::Sequence(vec![
+            spanned(0, 5, number(1)),
+            spanned(
+                6,
+                40,
+                add(
+                    spanned(6, 7, number(2)),
+                    spanned(10, 20, add(number(3), failing())),
+                ),
+            ),
+        ]),
+    );
+    let err = engine.evaluate(&expr).unwrap_err();
+    assert_eq!(err.location(), Some(&span(10, 20)));
+    assert_eq!(
+        err.root(),
+        &FreightError::GlobalOutOfRange {
+            address: 0,
+            num_globals: 0
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "Global 0 is out of range, only 0 globals exist at file 1 bytes 10..20"
+    );
+}
+
+#[test]
+fn test_unspanned_errors_are_unchanged() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let err = engine.evaluate(&add(number(1), failing())).unwrap_err();
+    assert_eq!(err.location(), None);
+    assert_eq!(
+        err,
+        FreightError::GlobalOutOfRange {
+            address: 0,
+            num_globals: 0
+        }
+    );
+}
+
+#[test]
+fn test_span_across_calls() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut unspanned = FunctionWriter::new(ArgCount::Fixed(0));
+    unspanned.evaluate_expression(failing());
+    let unspanned = engine.register_function(unspanned, 0).unwrap();
+    let mut spanned_body = FunctionWriter::new(ArgCount::Fixed(0));
+    spanned_body.evaluate_expression(spanned(100, 110, failing()));
+    let spanned_body = engine.register_function(spanned_body, 0).unwrap();
+
+    // The call site is the best location for errors in functions without spans
+    let call = |func| spanned(0, 10, Expression::StaticFunctionCall(func, vec![]));
+    let err = engine.evaluate(&call(unspanned)).unwrap_err();
+    assert_eq!(err.location(), Some(&span(0, 10)));
+    let err = engine.evaluate(&call(spanned_body)).unwrap_err();
+    assert_eq!(err.location(), Some(&span(100, 110)));
+}
+
+#[test]
+fn test_spans_keep_control_flow() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let caught = Expression::TryCatch {
+        body: spanned(0, 10, failing()).into(),
+        error_slot: 0,
+        handler: number(1).into(),
+    };
+    assert_eq!(
+        engine.eval(&caught, 1),
+        Ok(TestValueWrapper(TestValue::Number(1)))
+    );
+
+    let returned = Expression::ReturnTarget(
+        0,
+        spanned(0, 10, Expression::Return(0, number(2).into())).into(),
+    );
+    assert_eq!(
+        engine.evaluate(&returned),
+        Ok(TestValueWrapper(TestValue::Number(2)))
+    );
+}
@@ -0,0 +1,135 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::{Expression, VariableType},
+    function::{ArgCount, FunctionRef, FunctionWriter, StackLayout},
+};
+
+use super::type_system::{TestTypeSystem, TestValue, TestValueWrapper};
+
+fn null() -> Expression<TestTypeSystem> {
+    Expression::RawValue(TestValueWrapper(TestValue::Null))
+}
+
+fn engine(strict: bool) -> ExecutionEngine<TestTypeSystem> {
+    let mut engine = ExecutionEngine::new_default();
+    engine.set_strict_reads(strict);
+    engine
+}
+
+fn uninitialized(variable: VariableType) -> Result<TestValueWrapper, FreightError> {
+    Err(FreightError::UninitializedRead { variable })
+}
+
+#[test]
+fn test_stack_reads() {
+    let read = Expression::stack(0);
+    let assigned = Expression::Sequence(vec![
+        Expression::AssignStack(0, null().into()),
+        Expression::stack(0),
+    ]);
+    assert_eq!(
+        engine(false).eval(&read, 1),
+        Ok(TestValueWrapper(TestValue::Null))
+    );
+    assert_eq!(
+        engine(true).eval(&read, 1),
+        uninitialized(VariableType::Stack(0))
+    );
+    assert_eq!(
+        engine(true).eval(&assigned, 1),
+        Ok(TestValueWrapper(TestValue::Null))
+    );
+}
+
+#[test]
+fn test_unpassed_arguments() {
+    for layout in [StackLayout::all_alloc(), StackLayout::no_alloc()] {
+        for strict in [false, true] {
+            let mut engine = engine(strict);
+            let mut func = FunctionWriter::new(ArgCount::Range { min: 0, max: 1 });
+            func.layout = layout.clone();
+            func.evaluate_expression(Expression::stack(0));
+            let func = engine.register_function(func, 0).unwrap();
+            let expected = match strict {
+                true => uninitialized(VariableType::Stack(0)),
+                false => Ok(TestValueWrapper(TestValue::Null)),
+            };
+            assert_eq!(engine.call(&func, []), expected);
+            assert_eq!(
+                engine.call(&func, [TestValueWrapper(TestValue::Null)]),
+                Ok(TestValueWrapper(TestValue::Null))
+            );
+        }
+    }
+}
+
+#[test]
+fn test_global_reads() {
+    for strict in [false, true] {
+        let mut engine = engine(strict);
+        let global = engine.create_global();
+        let read = Expression::global(global);
+        let expected = match strict {
+            true => uninitialized(VariableType::Global(global)),
+            false => Ok(TestValueWrapper(TestValue::Null)),
+        };
+        assert_eq!(engine.evaluate(&read), expected);
+
+        engine
+            .evaluate(&Expression::AssignGlobal(global, null().into()))
+            .unwrap();
+        assert_eq!(
+            engine.evaluate(&read),
+            Ok(TestValueWrapper(TestValue::Null))
+        );
+    }
+}
+
+/// Builds a function which captures its only local before assigning `initial` to it,
+/// then returns the result of calling the closure, which reads the captured local
+fn capture_unassigned(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    initial: Option<Expression<TestTypeSystem>>,
+) -> FunctionRef<TestTypeSystem> {
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(0)]);
+    closure.evaluate_expression(Expression::captured(0));
+    let closure = engine.register_function(closure, 0).unwrap();
+
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(0));
+    let local = outer.create_variable();
+    let closure_slot = outer.create_variable();
+    outer.evaluate_expression(Expression::AssignStack(
+        closure_slot,
+        Expression::FunctionCapture(closure).into(),
+    ));
+    if let Some(initial) = initial {
+        outer.evaluate_expression(Expression::AssignStack(local, initial.into()));
+    }
+    outer.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::stack(closure_slot).into(),
+        vec![],
+    ));
+    engine.register_function(outer, 0).unwrap()
+}
+
+#[test]
+fn test_captured_reads() {
+    for strict in [false, true] {
+        let mut engine = engine(strict);
+        let unassigned = capture_unassigned(&mut engine, None);
+        let expected = match strict {
+            true => uninitialized(VariableType::Captured(0)),
+            false => Ok(TestValueWrapper(TestValue::Null)),
+        };
+        assert_eq!(engine.call(&unassigned, []), expected);
+
+        // The capture aliases the local, so assigning it after capturing still counts
+        let assigned = capture_unassigned(&mut engine, Some(null()));
+        assert_eq!(
+            engine.call(&assigned, []),
+            Ok(TestValueWrapper(TestValue::Null))
+        );
+    }
+}
@@ -0,0 +1,162 @@
+use std::{
+    collections::HashSet,
+    hash::{BuildHasher, RandomState},
+};
+
+use crate::{
+    error::FreightError,
+    execution_engine::{ExecutionEngine, Stack},
+    expression::{Expression, NativeFunction, VariableType},
+    function::{ArgCount, FunctionWriter},
+};
+
+use super::{
+    number,
+    type_system::{
+        TestBinaryOperator, TestInitializer, TestTypeSystem, TestUnaryOperator, TestValue,
+        TestValueWrapper,
+    },
+};
+
+fn hash(state: &RandomState, expr: &Expression<TestTypeSystem>) -> u64 {
+    state.hash_one(expr)
+}
+
+/// A tree with `depth` levels of sequences, conditionals and operators over `leaf(i)`
+fn tree(
+    depth: i64,
+    leaf: &impl Fn(i64) -> Expression<TestTypeSystem>,
+) -> Expression<TestTypeSystem> {
+    let mut expr = leaf(0);
+    for i in 1..depth {
+        expr = Expression::Sequence(vec![
+            Expression::AssignStack(0, leaf(i).into()),
+            Expression::Conditional {
+                condition: Expression::stack(0).into(),
+                then_branch: Expression::BinaryOpEval(
+                    TestBinaryOperator::Add,
+                    [
+                        expr,
+                        Expression::UnaryOpEval(TestUnaryOperator::Inc, leaf(i).into()),
+                    ]
+                    .into(),
+                )
+                .into(),
+                else_branch: Some(
+                    Expression::Initialize(TestInitializer::List, vec![leaf(i), leaf(-i)]).into(),
+                ),
+            },
+        ]);
+    }
+    expr
+}
+
+#[test]
+fn test_equal_trees_hash_equally() {
+    let state = RandomState::new();
+    let a = tree(10_000, &number);
+    let b = tree(10_000, &number);
+    assert_eq!(a, b);
+    assert_eq!(hash(&state, &a), hash(&state, &b));
+}
+
+#[test]
+fn test_single_leaf_differences() {
+    let state = RandomState::new();
+    let a = tree(1_000, &number);
+    let changed = tree(1_000, &|i| number(if i == 500 { 0 } else { i }));
+    assert_ne!(a, changed);
+    assert_ne!(hash(&state, &a), hash(&state, &changed));
+
+    let leaves: [Expression<TestTypeSystem>; 6] = [
+        Expression::stack(0),
+        Expression::global(0),
+        Expression::stack(1),
+        Expression::AssignStack(0, number(1).into()),
+        Expression::AssignGlobal(0, number(1).into()),
+        Expression::UnaryOpEval(TestUnaryOperator::Inc, number(1).into()),
+    ];
+    for (i, a) in leaves.iter().enumerate() {
+        for (j, b) in leaves.iter().enumerate() {
+            assert_eq!(a == b, i == j, "{a:?} and {b:?}");
+        }
+    }
+}
+
+#[test]
+fn test_shape_differences() {
+    let conditional = |else_branch: Option<i64>| Expression::<TestTypeSystem>::Conditional {
+        condition: number(1).into(),
+        then_branch: number(2).into(),
+        else_branch: else_branch.map(|n| number(n).into()),
+    };
+    assert_ne!(conditional(None), conditional(Some(3)));
+    assert_eq!(conditional(Some(3)), conditional(Some(3)));
+
+    // The same leaves in a different arrangement
+    let a = Expression::Sequence(vec![Expression::Sequence(vec![number(1)]), number(2)]);
+    let b = Expression::Sequence(vec![Expression::Sequence(vec![number(1), number(2)])]);
+    let state = RandomState::new();
+    assert_ne!(a, b);
+    assert_ne!(hash(&state, &a), hash(&state, &b));
+
+    let destructure = |targets| Expression::<TestTypeSystem>::DestructureAssign {
+        targets,
+        value: number(0).into(),
+    };
+    assert_ne!(
+        destructure(vec![VariableType::Stack(0)]),
+        destructure(vec![VariableType::Stack(0), VariableType::Stack(1)])
+    );
+}
+
+fn first(
+    _: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    Ok(args[0].clone())
+}
+
+fn last(
+    _: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    Ok(args[args.len() - 1].clone())
+}
+
+#[test]
+fn test_functions_compare_by_identity() {
+    let native = |func| Expression::NativeFunctionCall(NativeFunction::new(func), vec![number(1)]);
+    assert_eq!(native(first), native(first));
+    assert_ne!(native(first), native(last));
+
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let [a, b] = [(); 2].map(|_| {
+        let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+        func.evaluate_expression(number(1));
+        engine.register_function(func, 0).unwrap()
+    });
+    let call = |func| Expression::StaticFunctionCall(func, vec![]);
+    assert_eq!(call(a.clone()), call(a.clone()));
+    // Functions with identical bodies are still different functions
+    assert_ne!(call(a), call(b));
+}
+
+#[test]
+// Test values can hold references, but nothing assigns through them while they're in the set
+#[allow(clippy::mutable_key_type)]
+fn test_dedup_with_hash_set() {
+    let mut seen = HashSet::new();
+    for i in 0..10 {
+        seen.insert(tree(50, &|n| number(n * (i % 3))));
+    }
+    assert_eq!(seen.len(), 3);
+}
+
+#[test]
+fn test_values_hash_like_they_compare() {
+    let state = RandomState::new();
+    let reference = Expression::RawValue(TestValueWrapper::new_ref(TestValue::Number(1)));
+    assert_eq!(reference, number(1));
+    assert_eq!(hash(&state, &reference), hash(&state, &number(1)));
+}
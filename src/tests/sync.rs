@@ -0,0 +1,40 @@
+use crate::{
+    execution_engine::ExecutionEngine,
+    expression::{Expression, VariableType},
+    function::{ArgCount, FunctionWriter},
+};
+
+use super::text_type_system::{TextOperator, TextTypeSystem, TextValue};
+
+fn assert_send<T: Send>() {}
+
+#[test]
+fn test_engine_is_send() {
+    assert_send::<ExecutionEngine<TextTypeSystem>>();
+    assert_send::<TextValue>();
+}
+
+#[test]
+fn test_move_engine_to_thread() {
+    let mut engine = ExecutionEngine::<TextTypeSystem>::new_default();
+    let mut greet = FunctionWriter::new_capturing(ArgCount::Fixed(1), vec![VariableType::Stack(0)]);
+    greet.evaluate_expression(Expression::BinaryOpEval(
+        TextOperator::Concat,
+        [Expression::captured(0), Expression::stack(0)].into(),
+    ));
+    let greet = engine.register_function(greet, 0).unwrap();
+    let mut main = FunctionWriter::new(ArgCount::Fixed(1));
+    main.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::FunctionCapture(greet).into(),
+        vec![Expression::RawValue(TextValue::Text("world".into()))],
+    ));
+    let main = engine.register_function(main, 0).unwrap();
+    engine.set_entry_point(main);
+
+    let hello = TextValue::Text("hello ".into());
+    let first = engine.run_with_args(vec![hello.clone()]).unwrap();
+    let second = std::thread::spawn(move || engine.run_with_args(vec![hello]))
+        .join()
+        .unwrap();
+    assert_eq!(second, Ok(first));
+}
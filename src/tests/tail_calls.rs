@@ -0,0 +1,172 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::Expression,
+    function::{ArgCount, FunctionRef, FunctionWriter},
+};
+
+use super::{
+    binary, number,
+    type_system::{TestBinaryOperator, TestTypeSystem},
+    value,
+};
+
+/// Registers `sum(n, acc)`, returning `acc` once `n` reaches zero and otherwise calling
+/// itself with `n - 1` and `acc + n` last in its body
+fn register_sum(engine: &mut ExecutionEngine<TestTypeSystem>) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(ArgCount::Fixed(2));
+    let this = func.to_ref(engine.functions.len());
+    let target = engine.create_return_target();
+    func.evaluate_expression(Expression::Conditional {
+        condition: binary(TestBinaryOperator::Lt, Expression::stack(0), number(1)).into(),
+        then_branch: Expression::Return(target, Expression::stack(1).into()).into(),
+        else_branch: None,
+    });
+    func.evaluate_expression(Expression::StaticFunctionCall(
+        this,
+        vec![
+            binary(TestBinaryOperator::Sub, Expression::stack(0), number(1)),
+            binary(
+                TestBinaryOperator::Add,
+                Expression::stack(1),
+                Expression::stack(0),
+            ),
+        ],
+    ));
+    engine.register_function(func, target).unwrap()
+}
+
+#[test]
+fn test_deep_recursion_in_tail_position() {
+    const DEPTH: i64 = 500_000;
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_max_call_depth(DEPTH as usize + 1);
+    let func = register_sum(&mut engine);
+    assert!(engine.get_function(func.location).eliminated_tail_calls);
+
+    assert_eq!(
+        engine.call(&func, [value(DEPTH), value(0)]),
+        Ok(value(DEPTH * (DEPTH + 1) / 2))
+    );
+    assert_eq!(engine.stats().tail_calls, 0);
+
+    // Calls made this way are still limited by the call depth
+    engine.set_max_call_depth(100);
+    assert_eq!(
+        engine.call(&func, [value(100), value(0)]),
+        Err(FreightError::StackOverflow { depth: 100 })
+    );
+    assert_eq!(engine.call_depth, 0);
+    assert_eq!(engine.call(&func, [value(99), value(0)]), Ok(value(4950)));
+}
+
+#[test]
+fn test_tail_position_calls() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let sum = register_sum(&mut engine);
+    let call = || Expression::StaticFunctionCall(sum.clone(), vec![number(1), number(2)]);
+    let is_tail_call = |expr: &Expression<TestTypeSystem>| matches!(expr, Expression::TailCall(..));
+
+    let target = engine.create_return_target();
+    let inner_target = engine.create_return_target();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::Return(target, call().into()));
+    func.evaluate_expression(Expression::Sequence(vec![call(), call()]));
+    func.evaluate_expression(Expression::ReturnTarget(
+        inner_target,
+        Expression::Sequence(vec![
+            Expression::Return(inner_target, call().into()),
+            Expression::Conditional {
+                condition: call().into(),
+                then_branch: call().into(),
+                else_branch: Some(binary(TestBinaryOperator::Add, call(), number(1)).into()),
+            },
+        ])
+        .into(),
+    ));
+    let func = engine.register_function(func, target).unwrap();
+
+    let func = engine.get_function(func.location);
+    let [Expression::Return(_, returned), Expression::Sequence(items), Expression::ReturnTarget(_, block)] =
+        func.expressions.as_slice()
+    else {
+        panic!("The shape of the body is kept");
+    };
+    assert!(is_tail_call(returned));
+    // A sequence which isn't last in the body isn't in tail position
+    assert!(!is_tail_call(&items[0]) && !is_tail_call(&items[1]));
+    let Expression::Sequence(items) = &**block else {
+        panic!("The shape of the body is kept");
+    };
+    let [Expression::Return(_, returned), Expression::Conditional {
+        condition,
+        then_branch,
+        else_branch: Some(else_branch),
+    }] = items.as_slice()
+    else {
+        panic!("The shape of the body is kept");
+    };
+    assert!(is_tail_call(returned));
+    assert!(!is_tail_call(condition) && is_tail_call(then_branch));
+    assert!(!is_tail_call(else_branch));
+
+    engine.set_tail_call_elimination(false);
+    let unchanged = register_sum(&mut engine);
+    assert!(
+        !engine
+            .get_function(unchanged.location)
+            .eliminated_tail_calls
+    );
+    assert_eq!(engine.call(&unchanged, [value(3), value(0)]), Ok(value(6)));
+}
+
+#[cfg(feature = "variadic_functions")]
+#[test]
+fn test_variadic_arguments_are_packed_for_each_tail_call() {
+    use super::type_system::{TestValue, TestValueWrapper};
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // Counts its first argument down, passing the previous count as the only extra one
+    let mut func = FunctionWriter::new(ArgCount::at_least(1));
+    let this = func.to_ref(engine.functions.len());
+    let target = engine.create_return_target();
+    func.evaluate_expression(Expression::Conditional {
+        condition: binary(TestBinaryOperator::Lt, Expression::stack(0), number(1)).into(),
+        then_branch: Expression::Return(target, Expression::stack(1).into()).into(),
+        else_branch: None,
+    });
+    func.evaluate_expression(Expression::StaticFunctionCall(
+        this,
+        vec![
+            binary(TestBinaryOperator::Sub, Expression::stack(0), number(1)),
+            Expression::stack(0),
+        ],
+    ));
+    let func = engine.register_function(func, target).unwrap();
+    assert_eq!(
+        engine.call(&func, [value(100), value(5), value(6)]),
+        Ok(TestValueWrapper(TestValue::List(vec![value(1)])))
+    );
+}
+
+#[test]
+fn test_eliminated_tail_calls_keep_traces() {
+    let traces = |eliminate: bool| {
+        let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+        engine.set_tail_call_elimination(eliminate);
+        engine.set_error_traces(true);
+        let mut failing = FunctionWriter::new(ArgCount::Fixed(0));
+        failing.evaluate_expression(Expression::DynamicFunctionCall(number(1).into(), vec![]));
+        let mut func = engine
+            .register_function_named("failing", failing, 0)
+            .unwrap();
+        for name in ["middle", "outer"] {
+            let mut caller = FunctionWriter::new(ArgCount::Fixed(0));
+            caller.evaluate_expression(Expression::StaticFunctionCall(func, vec![]));
+            func = engine.register_function_named(name, caller, 0).unwrap();
+        }
+        engine.call(&func, []).unwrap_err()
+    };
+    let err = traces(true);
+    assert_eq!(err, traces(false));
+    assert!(err.trace().is_empty());
+}
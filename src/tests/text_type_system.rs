@@ -0,0 +1,139 @@
+use crate::{
+    error::FreightError,
+    function::FunctionRef,
+    operators::{BinaryOperator, UnaryOperator},
+    value::Value,
+    TypeSystem,
+};
+
+/// A second language whose only values are strings. Unlike the main test type system
+/// its values are thread safe and it shares no code with it.
+#[derive(Clone, Debug)]
+pub struct TextTypeSystem;
+
+impl TypeSystem for TextTypeSystem {
+    type Value = TextValue;
+
+    type UnaryOp = TextOperator;
+
+    type BinaryOp = TextOperator;
+
+    type TernaryOp = ();
+
+    type LazyBinaryOp = ();
+
+    type TypeId = ();
+
+    type Init = ();
+
+    type FieldId = usize;
+
+    type InitBuilder = ();
+
+    type GlobalContext = ();
+
+    fn error_to_value(error: FreightError) -> TextValue {
+        TextValue::Text(error.to_string())
+    }
+
+    #[cfg(feature = "serde")]
+    fn unary_op_from_id(id: u32) -> Option<TextOperator> {
+        Self::binary_op_from_id(id)
+    }
+
+    #[cfg(feature = "serde")]
+    fn binary_op_from_id(id: u32) -> Option<TextOperator> {
+        match id {
+            0 => Some(TextOperator::Concat),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum TextValue {
+    Text(String),
+    Function(FunctionRef<TextTypeSystem>),
+    #[default]
+    Null,
+}
+
+#[derive(Clone, Debug)]
+pub enum TextOperator {
+    Concat,
+}
+
+impl Value for TextValue {
+    type TS = TextTypeSystem;
+
+    fn uninitialized_reference() -> Self {
+        TextValue::Null
+    }
+
+    fn get_type(&self) -> &() {
+        &()
+    }
+
+    fn deep_clone(&self) -> Self {
+        self.clone()
+    }
+
+    fn dupe_ref(&self) -> Self {
+        self.clone()
+    }
+
+    fn into_ref(self) -> Self {
+        self
+    }
+
+    fn cast_to_function(&self) -> Option<&FunctionRef<TextTypeSystem>> {
+        match self {
+            TextValue::Function(f) => Some(f),
+            _ => None,
+        }
+    }
+
+    fn assign(&mut self, value: TextValue) {
+        *self = value;
+    }
+
+    fn is_truthy(&self) -> bool {
+        !matches!(self, TextValue::Null) && *self != TextValue::Text(String::new())
+    }
+
+    #[cfg(feature = "variadic_functions")]
+    fn gen_list(_: Vec<Self>) -> Self {
+        TextValue::Null
+    }
+}
+
+impl From<FunctionRef<TextTypeSystem>> for TextValue {
+    fn from(value: FunctionRef<TextTypeSystem>) -> Self {
+        TextValue::Function(value)
+    }
+}
+
+impl UnaryOperator<TextValue> for TextOperator {
+    #[cfg(feature = "serde")]
+    fn op_id(&self) -> u32 {
+        0
+    }
+
+    fn apply_1(&self, val: &TextValue) -> TextValue {
+        val.clone()
+    }
+}
+
+impl BinaryOperator<TextValue> for TextOperator {
+    #[cfg(feature = "serde")]
+    fn op_id(&self) -> u32 {
+        0
+    }
+
+    fn apply_2(&self, a: &TextValue, b: &TextValue) -> TextValue {
+        match (a, b) {
+            (TextValue::Text(a), TextValue::Text(b)) => TextValue::Text(format!("{a}{b}")),
+            _ => panic!("Attempt to concatenate non-text values"),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{
+    error::{FreightError, MAX_TRACE_LEN},
+    execution_engine::ExecutionEngine,
+    expression::Expression,
+    function::{ArgCount, FunctionWriter},
+};
+
+use super::{
+    add, number,
+    type_system::{
+        TestInitializer, TestTypeSystem, TestUnaryOperator, TestValue, TestValueWrapper,
+    },
+};
+
+fn inc(v: Expression<TestTypeSystem>) -> Expression<TestTypeSystem> {
+    Expression::UnaryOpEval(TestUnaryOperator::Inc, v.into())
+}
+
+/// Reads a global which doesn't exist
+fn failing() -> Expression<TestTypeSystem> {
+    Expression::global(0)
+}
+
+const FAILURE: FreightError = FreightError::GlobalOutOfRange {
+    address: 0,
+    num_globals: 0,
+};
+
+fn traced_engine() -> ExecutionEngine<TestTypeSystem> {
+    let mut engine = ExecutionEngine::new_default();
+    engine.set_error_traces(true);
+    engine
+}
+
+#[test]
+fn test_traces_disabled_by_default() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert!(!engine.error_traces());
+    let err = engine.evaluate(&add(number(1), failing())).unwrap_err();
+    assert_eq!(err, FAILURE);
+    assert!(err.trace().is_empty());
+}
+
+#[test]
+fn test_trace_innermost_first() {
+    let mut engine = traced_engine();
+    let expr = add(
+        number(1),
+        Expression::Initialize(TestInitializer::List, vec![number(2), inc(failing())]),
+    );
+    let err = engine.evaluate(&expr).unwrap_err();
+    assert_eq!(err.root(), &FAILURE);
+    assert_eq!(
+        err.trace(),
+        [
+            "operand of ++",
+            "arg 1 of initializer List",
+            "right operand of +"
+        ]
+    );
+    assert_eq!(
+        err.to_string(),
+        "Global 0 is out of range, only 0 globals exist\
+        \n    in operand of ++\
+        \n    in arg 1 of initializer List\
+        \n    in right operand of +"
+    );
+}
+
+#[test]
+fn test_trace_through_calls() {
+    let mut engine = traced_engine();
+    let mut callee = FunctionWriter::new(ArgCount::Fixed(1));
+    callee.evaluate_expression(add(Expression::stack(0), failing()));
+    let callee = engine.register_function(callee, 0).unwrap();
+    let err = engine
+        .evaluate(&Expression::StaticFunctionCall(
+            callee.clone(),
+            vec![number(1)],
+        ))
+        .unwrap_err();
+    assert_eq!(err.trace(), ["right operand of +"]);
+
+    let err = engine
+        .evaluate(&Expression::StaticFunctionCall(
+            callee.clone(),
+            vec![inc(failing())],
+        ))
+        .unwrap_err();
+    assert_eq!(
+        err.trace(),
+        [
+            "operand of ++".to_owned(),
+            format!("arg 0 of static call to #{}", callee.location)
+        ]
+    );
+}
+
+#[test]
+fn test_trace_is_bounded() {
+    let mut engine = traced_engine();
+    let mut expr = failing();
+    for _ in 0..MAX_TRACE_LEN * 2 {
+        expr = inc(expr);
+    }
+    let err = engine.evaluate(&expr).unwrap_err();
+    assert_eq!(err.root(), &FAILURE);
+    assert_eq!(err.trace(), vec!["operand of ++"; MAX_TRACE_LEN]);
+}
+
+#[test]
+fn test_returns_are_not_traced() {
+    let mut engine = traced_engine();
+    let expr = Expression::ReturnTarget(
+        0,
+        add(number(1), inc(Expression::Return(0, number(5).into()))).into(),
+    );
+    assert_eq!(
+        engine.evaluate(&expr),
+        Ok(TestValueWrapper(TestValue::Number(5)))
+    );
+    let err = engine
+        .evaluate(&add(number(1), Expression::Return(3, number(5).into())))
+        .unwrap_err();
+    assert_eq!(err, FreightError::UnhandledReturnTarget { target: 3 });
+}
@@ -0,0 +1,57 @@
+use crate::{error::FreightError, execution_engine::ExecutionEngine, expression::Expression};
+
+use super::type_system::{TestTypeId, TestTypeSystem, TestValue, TestValueWrapper};
+
+fn assert_type(expected: TestTypeId, value: TestValueWrapper) -> Expression<TestTypeSystem> {
+    Expression::TypeAssert {
+        expected,
+        value: Expression::RawValue(value).into(),
+    }
+}
+
+fn mismatch(expected: &str, actual: &str) -> FreightError {
+    FreightError::TypeMismatch {
+        expected: expected.into(),
+        actual: actual.into(),
+    }
+}
+
+#[test]
+fn test_type_assert() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    assert!(engine.type_assertions());
+    let number = TestValueWrapper(TestValue::Number(1));
+    assert_eq!(
+        engine.evaluate(&assert_type(TestTypeId::Number, number.clone())),
+        Ok(number.clone())
+    );
+    assert_eq!(
+        engine.evaluate(&assert_type(TestTypeId::List, number)),
+        Err(mismatch("List", "Number"))
+    );
+}
+
+#[test]
+fn test_type_assert_through_references() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let list = TestValueWrapper::new_ref(TestValue::List(vec![]));
+    let checked = engine
+        .evaluate(&assert_type(TestTypeId::List, list.clone()))
+        .unwrap();
+    assert!(checked.ref_eq(&list));
+    assert_eq!(
+        engine.evaluate(&assert_type(TestTypeId::Null, list)),
+        Err(mismatch("Null", "List"))
+    );
+}
+
+#[test]
+fn test_type_assertions_disabled() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.set_type_assertions(false);
+    let number = TestValueWrapper(TestValue::Number(1));
+    assert_eq!(
+        engine.evaluate(&assert_type(TestTypeId::List, number.clone())),
+        Ok(number)
+    );
+}
@@ -1,9 +1,23 @@
 #![allow(dead_code)]
 
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    hash::{Hash, Hasher},
+    mem::discriminant,
+    rc::Rc,
+};
+
 use crate::{
-    function::FunctionRef,
-    operators::{BinaryOperator, UnaryOperator},
-    value::Value,
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    function::{ArgCount, FunctionRef},
+    operators::{
+        Assoc, BinaryOperator, FusedUnary, Initializer, LazyBinaryOperator, OpResult,
+        TernaryOperator, UnaryOperator,
+    },
+    thunk::Thunk,
+    value::{Value, ValueIter},
     TypeSystem,
 };
 
@@ -17,61 +31,253 @@ impl TypeSystem for TestTypeSystem {
 
     type BinaryOp = TestBinaryOperator;
 
+    type TernaryOp = TestTernaryOperator;
+
+    type LazyBinaryOp = TestLazyOperator;
+
     type TypeId = TestTypeId;
 
-    type Init = ();
+    type Init = TestInitializer;
+
+    type FieldId = usize;
+
+    type InitBuilder = PairsBuilder;
+
+    type GlobalContext = TestContext;
 
-    type GlobalContext = ();
+    fn error_to_value(error: FreightError) -> TestValueWrapper {
+        TestValueWrapper(TestValue::Error(error))
+    }
+
+    #[cfg(feature = "serde")]
+    fn unary_op_from_id(id: u32) -> Option<TestUnaryOperator> {
+        match id {
+            0 => Some(TestUnaryOperator::Inc),
+            1 => Some(TestUnaryOperator::Neg),
+            2 => Some(TestUnaryOperator::Abs),
+            _ => None,
+        }
+    }
+
+    /// Adding a number to a list promotes it to a list of just that number
+    fn coerce_operands(
+        op: &TestBinaryOperator,
+        l: &TestValueWrapper,
+        r: &TestValueWrapper,
+    ) -> Option<(TestValueWrapper, TestValueWrapper)> {
+        COERCIONS.with(|count| count.set(count.get() + 1));
+        let promote = |n: &TestValueWrapper| TestValueWrapper(TestValue::List(vec![n.clone()]));
+        match (op, l.get_type(), r.get_type()) {
+            (TestBinaryOperator::Add, TestTypeId::Number, TestTypeId::List) => {
+                Some((promote(l), r.clone()))
+            }
+            (TestBinaryOperator::Add, TestTypeId::List, TestTypeId::Number) => {
+                Some((l.clone(), promote(r)))
+            }
+            _ => None,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn binary_op_from_id(id: u32) -> Option<TestBinaryOperator> {
+        match id {
+            0 => Some(TestBinaryOperator::Add),
+            1 => Some(TestBinaryOperator::Lt),
+            2 => Some(TestBinaryOperator::Div),
+            3 => Some(TestBinaryOperator::Sub),
+            4 => Some(TestBinaryOperator::Mul),
+            5 => Some(TestBinaryOperator::Prepend),
+            _ => None,
+        }
+    }
+}
+
+thread_local! {
+    /// How many times operands have been passed to [TypeSystem::coerce_operands] on this
+    /// thread, so tests can run in parallel
+    pub static COERCIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TestContext {
+    pub output: Vec<String>,
+    /// How many binary operators the engine has applied
+    pub binary_ops: usize,
+    /// How many lists have been appended to in place by compound assignments
+    pub in_place_appends: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TestBinaryOperator {
     Add,
+    Lt,
+    Div,
+    Sub,
+    Mul,
+    /// Puts a value at the front of a list, grouping to the right like `1 :: 2 :: [3]`
+    Prepend,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TestUnaryOperator {
     Inc,
+    /// Negation, which cancels out when applied twice
+    Neg,
+    /// The absolute value, which doesn't need the operand negated first
+    Abs,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TestTernaryOperator {
+    /// The second operand if the first is truthy, otherwise the third
+    Select,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TestLazyOperator {
+    /// The left operand if it's truthy, otherwise the right one
+    OrElse,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TestInitializer {
+    List,
+    /// A list of an x and y coordinate, which can also be given by field ids 0 and 1
+    Point,
+    /// A list of two element lists, taking an even number of values, which builds
+    /// the pairs as the values are evaluated
+    Pairs,
+    /// Like `Pairs`, but collecting the values first and copying them into the pairs
+    BufferedPairs,
+    /// A list of the results of calling the function given first with each other value
+    Map,
+}
+
+/// A [TestInitializer::Pairs] list being built
+pub struct PairsBuilder {
+    pairs: Vec<TestValueWrapper>,
+    first: Option<TestValueWrapper>,
+    len: usize,
+}
+
+#[derive(PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TestTypeId {
     Number,
     Function,
     List,
+    Error,
+    Thunk,
     Null,
 }
 
-#[derive(Debug, Clone, Default, PartialEq)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TestValueWrapper(pub TestValue);
 
 #[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum TestValue {
     Number(i64),
     Function(FunctionRef<TestTypeSystem>),
     List(Vec<TestValueWrapper>),
+    Ref(Rc<RefCell<TestValue>>),
+    Error(FreightError),
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Thunk(Thunk<TestTypeSystem>),
     #[default]
     Null,
+    /// A slot nothing has been assigned to, which otherwise behaves like `Null`
+    Uninitialized,
+}
+
+impl TestValueWrapper {
+    pub fn new_ref(value: TestValue) -> Self {
+        TestValueWrapper(TestValue::Ref(Rc::new(RefCell::new(value))))
+    }
+
+    /// The underlying value, looking through references
+    pub fn resolve(&self) -> TestValue {
+        match &self.0 {
+            TestValue::Ref(r) => r.borrow().clone(),
+            v => v.clone(),
+        }
+    }
+
+    /// Whether two values are references to the same underlying value
+    pub fn ref_eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (TestValue::Ref(a), TestValue::Ref(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl PartialEq for TestValueWrapper {
+    fn eq(&self, other: &Self) -> bool {
+        let normalize = |value: &Self| match value.resolve() {
+            TestValue::Uninitialized => TestValue::Null,
+            value => value,
+        };
+        normalize(self) == normalize(other)
+    }
+}
+
+impl Eq for TestValueWrapper {}
+
+// Consistent with equality, which looks through references and treats uninitialized as null
+impl Hash for TestValueWrapper {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.resolve() {
+            TestValue::Number(n) => n.hash(state),
+            TestValue::List(values) => values.hash(state),
+            TestValue::Uninitialized => discriminant(&TestValue::Null).hash(state),
+            value => discriminant(&value).hash(state),
+        }
+    }
 }
 
 impl Value for TestValueWrapper {
     type TS = TestTypeSystem;
 
     fn uninitialized_reference() -> Self {
-        TestValueWrapper(TestValue::Null)
+        TestValueWrapper::new_ref(TestValue::Uninitialized)
+    }
+
+    fn uninitialized_value() -> Self {
+        TestValueWrapper(TestValue::Uninitialized)
+    }
+
+    fn is_uninitialized(&self) -> bool {
+        matches!(self.resolve(), TestValue::Uninitialized)
     }
 
     fn get_type(&self) -> &<Self::TS as TypeSystem>::TypeId {
-        match &self.0 {
+        match self.resolve() {
             TestValue::Number(_) => &TestTypeId::Number,
             TestValue::Function(_) => &TestTypeId::Function,
             TestValue::List(_) => &TestTypeId::List,
-            TestValue::Null => &TestTypeId::Null,
+            TestValue::Error(_) => &TestTypeId::Error,
+            TestValue::Thunk(_) => &TestTypeId::Thunk,
+            TestValue::Ref(_) => unreachable!("References are never nested"),
+            TestValue::Null | TestValue::Uninitialized => &TestTypeId::Null,
         }
     }
 
     fn deep_clone(&self) -> Self {
-        self.clone()
+        let value = match self.resolve() {
+            TestValue::List(values) => {
+                TestValue::List(values.iter().map(Value::deep_clone).collect())
+            }
+            v => v,
+        };
+        match &self.0 {
+            TestValue::Ref(_) => TestValueWrapper::new_ref(value),
+            _ => TestValueWrapper(value),
+        }
     }
 
     fn dupe_ref(&self) -> Self {
@@ -79,18 +285,119 @@ impl Value for TestValueWrapper {
     }
 
     fn cast_to_function(&self) -> Option<&FunctionRef<Self::TS>> {
-        match self {
-            Self(TestValue::Function(f)) => Some(f),
+        match &self.0 {
+            TestValue::Function(f) => Some(f),
+            // SAFETY: tests never reassign a slot holding a function while invoking it
+            TestValue::Ref(r) => match unsafe { &*r.as_ptr() } {
+                TestValue::Function(f) => Some(f),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    fn list_elements(&self) -> Option<Vec<Self>> {
+        match self.resolve() {
+            TestValue::List(values) => Some(values),
+            _ => None,
+        }
+    }
+
+    fn get_index(&self, index: &Self) -> Result<Self, FreightError> {
+        match (self.resolve(), index.resolve()) {
+            (TestValue::List(values), TestValue::Number(i)) => values
+                .get(i as usize)
+                .cloned()
+                .ok_or(FreightError::IndexOutOfRange {
+                    index: i.to_string(),
+                }),
+            _ => Err(FreightError::NotIndexable {
+                type_name: self.type_name(),
+            }),
+        }
+    }
+
+    fn set_index(&mut self, index: &Self, value: Self) -> Result<(), FreightError> {
+        let type_name = self.type_name();
+        let set = |list: &mut TestValue| match (list, index.resolve()) {
+            (TestValue::List(values), TestValue::Number(i)) => {
+                let element = values
+                    .get_mut(i as usize)
+                    .ok_or(FreightError::IndexOutOfRange {
+                        index: i.to_string(),
+                    })?;
+                *element = TestValueWrapper(value.resolve());
+                Ok(())
+            }
+            _ => Err(FreightError::NotIndexable { type_name }),
+        };
+        match &mut self.0 {
+            TestValue::Ref(r) => set(&mut r.borrow_mut()),
+            list => set(list),
+        }
+    }
+
+    fn from_thunk(thunk: Thunk<TestTypeSystem>) -> Option<Self> {
+        Some(TestValueWrapper(TestValue::Thunk(thunk)))
+    }
+
+    fn cast_to_thunk(&self) -> Option<&Thunk<TestTypeSystem>> {
+        match &self.0 {
+            TestValue::Thunk(thunk) => Some(thunk),
+            // SAFETY: tests never reassign a slot holding a thunk while forcing it
+            TestValue::Ref(r) => match unsafe { &*r.as_ptr() } {
+                TestValue::Thunk(thunk) => Some(thunk),
+                _ => None,
+            },
             _ => None,
         }
     }
 
     fn assign(&mut self, value: <Self::TS as TypeSystem>::Value) {
-        self.0 = value.0;
+        match &self.0 {
+            TestValue::Ref(r) => *r.borrow_mut() = value.resolve(),
+            _ => self.0 = value.resolve(),
+        }
     }
 
     fn into_ref(self) -> Self {
-        self
+        match self.0 {
+            TestValue::Ref(_) => self,
+            v => TestValueWrapper::new_ref(v),
+        }
+    }
+
+    fn constant_eq(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    fn is_null(&self) -> bool {
+        matches!(self.resolve(), TestValue::Null | TestValue::Uninitialized)
+    }
+
+    fn is_truthy(&self) -> bool {
+        !matches!(
+            self.resolve(),
+            TestValue::Null | TestValue::Uninitialized | TestValue::Number(0)
+        )
+    }
+
+    fn cast_to_iterator(&self) -> Option<ValueIter<Self>> {
+        match self.resolve() {
+            TestValue::List(values) => Some(Box::new(values.into_iter().map(Ok))),
+            TestValue::Number(n) => Some(Box::new(
+                (0..n).map(|i| Ok(TestValueWrapper(TestValue::Number(i)))),
+            )),
+            _ => None,
+        }
+    }
+
+    fn approx_size(&self) -> usize {
+        let contents = match self.resolve() {
+            TestValue::List(values) => values.iter().map(Value::approx_size).sum(),
+            _ => 0,
+        };
+        std::mem::size_of::<Self>() + contents
     }
 
     #[cfg(feature = "variadic_functions")]
@@ -105,22 +412,383 @@ impl From<FunctionRef<TestTypeSystem>> for TestValueWrapper {
     }
 }
 
+/// Argument counts as `[min, max]`, with a null `max` when there's no limit
+impl From<ArgCount> for TestValueWrapper {
+    fn from(value: ArgCount) -> Self {
+        let max = match value.max() {
+            Some(max) => TestValue::Number(max as i64),
+            None => TestValue::Null,
+        };
+        let min = TestValue::Number(value.min() as i64);
+        TestValueWrapper(TestValue::List(vec![
+            TestValueWrapper(min),
+            TestValueWrapper(max),
+        ]))
+    }
+}
+
+// Operators are named by borrowed symbols, so applying them never allocates
+
 impl UnaryOperator<TestValueWrapper> for TestUnaryOperator {
+    #[cfg(feature = "serde")]
+    fn op_id(&self) -> u32 {
+        match self {
+            Self::Inc => 0,
+            Self::Neg => 1,
+            Self::Abs => 2,
+        }
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        match self {
+            Self::Inc => "++".into(),
+            Self::Neg => "-".into(),
+            Self::Abs => "abs".into(),
+        }
+    }
+
     fn apply_1(&self, val: &TestValueWrapper) -> TestValueWrapper {
-        match (self, &val.0) {
+        match (self, &val.resolve()) {
             (Self::Inc, TestValue::Number(n)) => TestValueWrapper(TestValue::Number(n + 1)),
-            _ => panic!("Attempted to increment non-integer type"),
+            (Self::Neg, TestValue::Number(n)) => {
+                TestValueWrapper(TestValue::Number(n.wrapping_neg()))
+            }
+            (Self::Abs, TestValue::Number(n)) => {
+                TestValueWrapper(TestValue::Number(n.wrapping_abs()))
+            }
+            _ => panic!("Attempted arithmetic on non-integer type"),
+        }
+    }
+
+    fn try_apply_1(&self, val: &TestValueWrapper) -> Result<TestValueWrapper, FreightError> {
+        match (self, &val.resolve()) {
+            (Self::Inc, TestValue::Number(i64::MAX)) => Err(FreightError::OperatorFailed {
+                operator: self.name().into_owned(),
+                message: "overflow".into(),
+            }),
+            _ => Ok(self.apply_1(val)),
         }
     }
+
+    // Negation wraps, so these hold for every number. `Inc` doesn't fuse at all.
+    fn fuse(&self, inner: &TestUnaryOperator) -> Option<FusedUnary<TestTypeSystem>> {
+        match (self, inner) {
+            (Self::Neg, Self::Neg) => Some(FusedUnary::Cancel),
+            (Self::Abs, Self::Neg | Self::Abs) => Some(FusedUnary::Op(Self::Abs)),
+            _ => None,
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
 }
 
 impl BinaryOperator<TestValueWrapper> for TestBinaryOperator {
+    #[cfg(feature = "serde")]
+    fn op_id(&self) -> u32 {
+        match self {
+            Self::Add => 0,
+            Self::Lt => 1,
+            Self::Div => 2,
+            Self::Sub => 3,
+            Self::Mul => 4,
+            Self::Prepend => 5,
+        }
+    }
+
+    fn name(&self) -> Cow<'_, str> {
+        match self {
+            Self::Add => "+".into(),
+            Self::Lt => "<".into(),
+            Self::Div => "/".into(),
+            Self::Sub => "-".into(),
+            Self::Mul => "*".into(),
+            Self::Prepend => "::".into(),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Self::Lt => 0,
+            Self::Add | Self::Sub | Self::Prepend => 1,
+            Self::Mul | Self::Div => 2,
+        }
+    }
+
+    fn associativity(&self) -> Assoc {
+        match self {
+            Self::Prepend => Assoc::Right,
+            _ => Assoc::Left,
+        }
+    }
+
+    fn needs_coercion(&self, a: &TestValueWrapper, b: &TestValueWrapper) -> bool {
+        a.get_type() != b.get_type()
+    }
+
     fn apply_2(&self, a: &TestValueWrapper, b: &TestValueWrapper) -> TestValueWrapper {
-        match (self, &a.0, &b.0) {
+        match (self, &a.resolve(), &b.resolve()) {
             (Self::Add, TestValue::Number(a), TestValue::Number(b)) => {
                 TestValueWrapper(TestValue::Number(a + b))
             }
-            _ => panic!("Attempt to add non-integer type"),
+            (Self::Lt, TestValue::Number(a), TestValue::Number(b)) => {
+                TestValueWrapper(TestValue::Number((a < b) as i64))
+            }
+            (Self::Div, TestValue::Number(a), TestValue::Number(b)) => {
+                TestValueWrapper(TestValue::Number(a / b))
+            }
+            (Self::Sub, TestValue::Number(a), TestValue::Number(b)) => {
+                TestValueWrapper(TestValue::Number(a - b))
+            }
+            (Self::Mul, TestValue::Number(a), TestValue::Number(b)) => {
+                TestValueWrapper(TestValue::Number(a * b))
+            }
+            (Self::Prepend, value, TestValue::List(list)) => TestValueWrapper(TestValue::List(
+                [vec![TestValueWrapper(value.clone())], list.clone()].concat(),
+            )),
+            (Self::Add, TestValue::List(a), TestValue::List(b)) => {
+                TestValueWrapper(TestValue::List([a.clone(), b.clone()].concat()))
+            }
+            _ => panic!("Attempted arithmetic on non-integer types"),
         }
     }
+
+    fn try_apply_2(
+        &self,
+        a: &TestValueWrapper,
+        b: &TestValueWrapper,
+    ) -> Result<TestValueWrapper, FreightError> {
+        match (self, b.resolve()) {
+            (Self::Div, TestValue::Number(0)) => Err(FreightError::OperatorFailed {
+                operator: self.name().into_owned(),
+                message: "division by zero".into(),
+            }),
+            _ => Ok(self.apply_2(a, b)),
+        }
+    }
+
+    fn apply_2_ctx(
+        &self,
+        a: &TestValueWrapper,
+        b: &TestValueWrapper,
+        ctx: &mut TestContext,
+    ) -> Result<TestValueWrapper, FreightError> {
+        ctx.binary_ops += 1;
+        self.try_apply_2(a, b)
+    }
+
+    /// Adding an empty list to a list produces the other list as it is
+    fn apply_2_select(
+        &self,
+        a: &TestValueWrapper,
+        b: &TestValueWrapper,
+        ctx: &mut TestContext,
+    ) -> Result<OpResult<TestValueWrapper>, FreightError> {
+        let empty = |value: &TestValueWrapper| matches!(value.resolve(), TestValue::List(values) if values.is_empty());
+        let lists = a.get_type() == &TestTypeId::List && b.get_type() == &TestTypeId::List;
+        match self {
+            Self::Add if lists && empty(b) => {
+                ctx.binary_ops += 1;
+                Ok(OpResult::Left)
+            }
+            Self::Add if lists && empty(a) => {
+                ctx.binary_ops += 1;
+                Ok(OpResult::Right)
+            }
+            _ => self.apply_2_ctx(a, b, ctx).map(OpResult::Owned),
+        }
+    }
+
+    fn apply_2_assign(
+        &self,
+        target: &mut TestValueWrapper,
+        rhs: &TestValueWrapper,
+        ctx: &mut TestContext,
+    ) -> Result<(), FreightError> {
+        if let (Self::Add, TestValue::Ref(r), TestValue::List(extra)) =
+            (self, &target.0, rhs.resolve())
+        {
+            if let TestValue::List(values) = &mut *r.borrow_mut() {
+                ctx.in_place_appends += 1;
+                values.extend(extra);
+                return Ok(());
+            }
+        }
+        let result = self.apply_2_ctx(target, rhs, ctx)?;
+        target.assign(result);
+        Ok(())
+    }
+
+    // `Lt` is left impure so tests can check that folding skips impure operators
+    fn is_pure(&self) -> bool {
+        matches!(self, Self::Add)
+    }
+}
+
+impl TernaryOperator<TestValueWrapper> for TestTernaryOperator {
+    fn name(&self) -> Cow<'_, str> {
+        match self {
+            Self::Select => "select".into(),
+        }
+    }
+
+    fn apply_3(
+        &self,
+        a: &TestValueWrapper,
+        b: &TestValueWrapper,
+        c: &TestValueWrapper,
+    ) -> TestValueWrapper {
+        match self {
+            Self::Select if a.is_truthy() => b.clone(),
+            Self::Select => c.clone(),
+        }
+    }
+
+    fn is_pure(&self) -> bool {
+        true
+    }
+}
+
+impl LazyBinaryOperator<TestValueWrapper> for TestLazyOperator {
+    fn name(&self) -> Cow<'_, str> {
+        match self {
+            Self::OrElse => "orelse".into(),
+        }
+    }
+
+    fn apply_lazy(
+        &self,
+        left: &TestValueWrapper,
+        right: &mut dyn FnMut() -> Result<TestValueWrapper, FreightError>,
+    ) -> Result<TestValueWrapper, FreightError> {
+        match self {
+            Self::OrElse if left.is_truthy() => Ok(left.clone()),
+            Self::OrElse => right(),
+        }
+    }
+}
+
+impl Initializer<TestTypeSystem> for TestInitializer {
+    fn initialize(
+        &self,
+        values: Vec<TestValueWrapper>,
+        ctx: &mut ExecutionEngine<TestTypeSystem>,
+    ) -> TestValueWrapper {
+        match self {
+            Self::List | Self::Point => TestValueWrapper(TestValue::List(values)),
+            Self::Pairs | Self::BufferedPairs => TestValueWrapper(TestValue::List(
+                values
+                    .chunks(2)
+                    .map(|pair| TestValueWrapper(TestValue::List(pair.to_vec())))
+                    .collect(),
+            )),
+            Self::Map => self.map(values, ctx).expect("Mapping failed"),
+        }
+    }
+
+    fn try_initialize(
+        &self,
+        values: Vec<TestValueWrapper>,
+        ctx: &mut ExecutionEngine<TestTypeSystem>,
+    ) -> Result<TestValueWrapper, FreightError> {
+        let pairs = matches!(self, Self::Pairs | Self::BufferedPairs);
+        if pairs && !values.len().is_multiple_of(2) {
+            return Err(self.odd_values(values.len()));
+        }
+        if *self == Self::Map {
+            return self.map(values, ctx);
+        }
+        Ok(self.initialize(values, ctx))
+    }
+
+    fn begin(&self, capacity: usize) -> Option<PairsBuilder> {
+        (*self == Self::Pairs).then(|| PairsBuilder {
+            pairs: Vec::with_capacity(capacity / 2),
+            first: None,
+            len: 0,
+        })
+    }
+
+    fn push(
+        &self,
+        builder: &mut PairsBuilder,
+        value: TestValueWrapper,
+    ) -> Result<(), FreightError> {
+        builder.len += 1;
+        match builder.first.take() {
+            Some(first) => builder
+                .pairs
+                .push(TestValueWrapper(TestValue::List(vec![first, value]))),
+            None => builder.first = Some(value),
+        }
+        Ok(())
+    }
+
+    fn finish(
+        &self,
+        builder: PairsBuilder,
+        _: &mut ExecutionEngine<TestTypeSystem>,
+    ) -> Result<TestValueWrapper, FreightError> {
+        match builder.first {
+            Some(_) => Err(self.odd_values(builder.len)),
+            None => Ok(TestValueWrapper(TestValue::List(builder.pairs))),
+        }
+    }
+
+    fn initialize_named(
+        &self,
+        fields: Vec<(usize, TestValueWrapper)>,
+        ctx: &mut ExecutionEngine<TestTypeSystem>,
+    ) -> Result<TestValueWrapper, FreightError> {
+        if *self != Self::Point {
+            return match fields.first() {
+                Some((field, _)) => Err(FreightError::UnknownField {
+                    field: field.to_string(),
+                }),
+                None => self.try_initialize(Vec::new(), ctx),
+            };
+        }
+        let mut coordinates = [None, None];
+        for (field, value) in fields {
+            match coordinates.get_mut(field) {
+                None => Err(FreightError::UnknownField {
+                    field: field.to_string(),
+                })?,
+                Some(Some(_)) => Err(FreightError::DuplicateField {
+                    field: field.to_string(),
+                })?,
+                Some(coordinate) => *coordinate = Some(value),
+            }
+        }
+        self.try_initialize(coordinates.map(Option::unwrap_or_default).into(), ctx)
+    }
+}
+
+impl TestInitializer {
+    fn odd_values(&self, count: usize) -> FreightError {
+        FreightError::InitializerFailed {
+            initializer: format!("{self:?}"),
+            message: format!("expected an even number of values, got {count}"),
+        }
+    }
+
+    /// Calls back into the engine for each value after the function
+    fn map(
+        &self,
+        values: Vec<TestValueWrapper>,
+        ctx: &mut ExecutionEngine<TestTypeSystem>,
+    ) -> Result<TestValueWrapper, FreightError> {
+        let Some(func) = values.first().and_then(|f| f.cast_to_function()).cloned() else {
+            return Err(FreightError::InitializerFailed {
+                initializer: format!("{self:?}"),
+                message: "expected a function first".into(),
+            });
+        };
+        let mapped = values[1..]
+            .iter()
+            .map(|value| ctx.call(&func, [value.clone()]))
+            .collect::<Result<_, _>>()?;
+        Ok(TestValueWrapper(TestValue::List(mapped)))
+    }
 }
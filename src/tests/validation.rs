@@ -0,0 +1,324 @@
+use crate::{
+    error::{AddressKind, FreightError},
+    execution_engine::ExecutionEngine,
+    expression::{Expression, NativeFunction, VariableType},
+    function::{ArgCount, FunctionWriter},
+};
+
+use super::{
+    number,
+    type_system::{TestTypeSystem, TestValue, TestValueWrapper},
+};
+
+#[test]
+fn test_stack_slot_out_of_range() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    let local = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(
+        local + 1,
+        Expression::stack(local).into(),
+    ));
+    assert_eq!(
+        engine.register_function(func, 0),
+        Err(FreightError::StackSlotOutOfRange { slot: 2, size: 2 })
+    );
+}
+
+#[test]
+fn test_nested_stack_slot_out_of_range() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(2));
+    func.evaluate_expression(Expression::ReturnTarget(
+        0,
+        Expression::DynamicFunctionCall(Expression::stack(0).into(), vec![Expression::stack(5)])
+            .into(),
+    ));
+    assert_eq!(
+        engine.register_function(func, 0),
+        Err(FreightError::StackSlotOutOfRange { slot: 5, size: 2 })
+    );
+}
+
+#[test]
+fn test_captured_slot_out_of_range() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut closure = FunctionWriter::new_capturing(
+        ArgCount::Fixed(0),
+        vec![VariableType::Stack(0), VariableType::Stack(1)],
+    );
+    closure.evaluate_expression(Expression::captured(2));
+    assert_eq!(
+        engine.register_function(closure, 0),
+        Err(FreightError::CaptureSlotOutOfRange { slot: 2, size: 2 })
+    );
+
+    let mut not_capturing = FunctionWriter::new(ArgCount::Fixed(0));
+    not_capturing.evaluate_expression(Expression::captured(0));
+    assert_eq!(
+        engine.register_function(not_capturing, 0),
+        Err(FreightError::CaptureSlotOutOfRange { slot: 0, size: 0 })
+    );
+}
+
+#[test]
+fn test_capture_definition_out_of_range() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(3)]);
+    closure.evaluate_expression(Expression::captured(0));
+    let closure = engine.register_function(closure, 0).unwrap();
+
+    let mut outer = FunctionWriter::new(ArgCount::Fixed(1));
+    outer.evaluate_expression(Expression::FunctionCapture(closure));
+    assert_eq!(
+        engine.register_function(outer, 0),
+        Err(FreightError::StackSlotOutOfRange { slot: 3, size: 1 })
+    );
+}
+
+#[test]
+fn test_disable_validation() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::RawValue(TestValueWrapper(TestValue::Number(1))));
+    func.evaluate_expression(Expression::stack(4));
+    assert!(func.validate().is_err());
+    func.disable_validation();
+    assert!(engine.register_function(func, 0).is_ok());
+}
+
+#[test]
+fn test_engine_validate_globals() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine.create_global();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::AssignGlobal(1, Expression::global(0).into()));
+    engine.register_function(func, 0).unwrap();
+    assert_eq!(
+        engine.validate(),
+        Err(FreightError::InvalidAddress {
+            function: 0,
+            kind: AddressKind::Global,
+            address: 1
+        })
+    );
+
+    engine.create_global();
+    assert_eq!(engine.validate(), Ok(()));
+}
+
+#[test]
+fn test_engine_validate_function_locations() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut caller = FunctionWriter::new(ArgCount::Fixed(0));
+    let callee = FunctionWriter::<TestTypeSystem>::new(ArgCount::Fixed(0));
+    caller.evaluate_expression(Expression::StaticFunctionCall(callee.to_ref(1), vec![]));
+    engine.register_function(caller, 0).unwrap();
+    assert_eq!(
+        engine.validate(),
+        Err(FreightError::InvalidAddress {
+            function: 0,
+            kind: AddressKind::Function,
+            address: 1
+        })
+    );
+
+    engine.register_function(callee, 0).unwrap();
+    assert_eq!(engine.validate(), Ok(()));
+}
+
+#[test]
+fn test_engine_validate_unvalidated_function() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    engine
+        .register_function(FunctionWriter::new(ArgCount::Fixed(0)), 0)
+        .unwrap();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.evaluate_expression(Expression::stack(1));
+    func.disable_validation();
+    engine.register_function(func, 0).unwrap();
+    assert_eq!(
+        engine.validate(),
+        Err(FreightError::InvalidAddress {
+            function: 1,
+            kind: AddressKind::Stack,
+            address: 1
+        })
+    );
+}
+
+#[test]
+fn test_max_stack_slot() {
+    assert_eq!(number(1).max_stack_slot(), None);
+    assert_eq!(
+        Expression::<TestTypeSystem>::global(7).max_stack_slot(),
+        None
+    );
+
+    let nested = Expression::<TestTypeSystem>::NativeFunctionCall(
+        NativeFunction::new(|_, _| Ok(Default::default())),
+        vec![Expression::Sequence(vec![
+            Expression::stack(2),
+            Expression::AssignStack(5, Expression::stack(1).into()),
+        ])],
+    );
+    assert_eq!(nested.max_stack_slot(), Some(5));
+
+    let for_loop = Expression::<TestTypeSystem>::For {
+        binding: 4,
+        iterable: Expression::stack(0).into(),
+        body: Expression::TryCatch {
+            body: number(0).into(),
+            error_slot: 6,
+            handler: number(0).into(),
+        }
+        .into(),
+    };
+    assert_eq!(for_loop.max_stack_slot(), Some(6));
+}
+
+#[test]
+fn test_infer_layout() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+
+    // No stack usage
+    let mut func = FunctionWriter::<TestTypeSystem>::new(ArgCount::Fixed(0));
+    func.evaluate_expression(number(1));
+    assert_eq!(func.infer_layout(), 0);
+
+    // Unused arguments still get slots
+    let mut func = FunctionWriter::<TestTypeSystem>::new(ArgCount::Fixed(2));
+    func.evaluate_expression(Expression::stack(0));
+    assert_eq!(func.infer_layout(), 2);
+
+    // Locals past the arguments, with variables declared but never used dropped
+    let mut func = FunctionWriter::<TestTypeSystem>::new(ArgCount::Range { min: 1, max: 2 });
+    for _ in 0..5 {
+        func.create_variable();
+    }
+    func.evaluate_expression(Expression::AssignStack(3, Expression::stack(0).into()));
+    assert_eq!(func.infer_layout(), 4);
+    assert_eq!(func.create_variable(), 4);
+
+    // Slots only referenced by a closure's capture definition
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(6)]);
+    closure.evaluate_expression(Expression::captured(0));
+    let closure = engine.register_function(closure, 0).unwrap();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(1));
+    func.evaluate_expression(Expression::FunctionCapture(closure));
+    assert_eq!(func.infer_layout(), 7);
+    assert!(engine.register_function(func, 0).is_ok());
+}
+
+fn ret(target: usize) -> Expression<TestTypeSystem> {
+    Expression::Return(target, number(target as i64).into())
+}
+
+#[test]
+fn test_nested_return_targets() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let [own, outer, inner] = [(); 3].map(|_| engine.create_return_target());
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(ret(own));
+    func.evaluate_expression(Expression::ReturnTarget(
+        outer,
+        Expression::Sequence(vec![
+            ret(outer),
+            Expression::ReturnTarget(
+                inner,
+                Expression::Sequence(vec![ret(own), ret(outer), ret(inner)]).into(),
+            ),
+            ret(outer),
+        ])
+        .into(),
+    ));
+    engine.register_function(func, own).unwrap();
+    assert_eq!(engine.validate(), Ok(()));
+}
+
+#[test]
+fn test_return_to_sibling_scope() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let [own, first, second] = [(); 3].map(|_| engine.create_return_target());
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::ReturnTarget(first, ret(first).into()));
+    func.evaluate_expression(Expression::ReturnTarget(second, ret(first).into()));
+    engine.register_function(func, own).unwrap();
+    assert_eq!(
+        engine.validate(),
+        Err(FreightError::ReturnTargetOutOfScope {
+            function: 0,
+            target: first
+        })
+    );
+}
+
+#[test]
+fn test_return_to_other_function() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let [caller_target, callee_target] = [(); 2].map(|_| engine.create_return_target());
+    let mut callee = FunctionWriter::new(ArgCount::Fixed(0));
+    callee.evaluate_expression(ret(caller_target));
+    let callee = engine.register_function(callee, callee_target).unwrap();
+    let mut caller = FunctionWriter::new(ArgCount::Fixed(0));
+    caller.evaluate_expression(Expression::StaticFunctionCall(callee, vec![]));
+    engine.register_function(caller, caller_target).unwrap();
+    assert_eq!(
+        engine.validate(),
+        Err(FreightError::ReturnTargetOutOfScope {
+            function: 0,
+            target: caller_target
+        })
+    );
+}
+
+#[test]
+fn test_continue_scope() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let [own, outer, inner] = [(); 3].map(|_| engine.create_return_target());
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::Loop {
+        target: outer,
+        body: Expression::Sequence(vec![
+            Expression::Continue(outer),
+            ret(outer),
+            Expression::ReturnTarget(inner, Expression::Continue(outer).into()),
+        ])
+        .into(),
+    });
+    engine.register_function(func, own).unwrap();
+    assert_eq!(engine.validate(), Ok(()));
+
+    // Only loops can be continued
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::ReturnTarget(
+        inner,
+        Expression::Continue(inner).into(),
+    ));
+    engine.register_function(func, own).unwrap();
+    assert_eq!(
+        engine.validate(),
+        Err(FreightError::ContinueOutOfScope {
+            function: 1,
+            target: inner
+        })
+    );
+}
+
+#[test]
+fn test_return_from_lazy() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let own = engine.create_return_target();
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.evaluate_expression(Expression::Lazy(ret(own).into()));
+    engine.register_function(func, own).unwrap();
+    assert_eq!(
+        engine.validate(),
+        Err(FreightError::ReturnTargetOutOfScope {
+            function: 0,
+            target: own
+        })
+    );
+}
@@ -0,0 +1,126 @@
+use crate::{
+    error::FreightError,
+    execution_engine::ExecutionEngine,
+    expression::{Expression, VariableType},
+    function::{ArgCount, FunctionWriter},
+};
+
+use super::{
+    add, number,
+    type_system::{TestTypeSystem, TestValue, TestValueWrapper},
+};
+
+fn writer(args: ArgCount) -> FunctionWriter<TestTypeSystem> {
+    let mut func = FunctionWriter::new(args);
+    func.allocate_variables();
+    func
+}
+
+#[test]
+fn test_inferred_variable_counts() {
+    // No variables at all
+    let mut func = writer(ArgCount::Fixed(2));
+    func.evaluate_expression(add(Expression::stack(0), Expression::stack(1)));
+    assert_eq!(func.check_variables(), Ok(2));
+    assert_eq!(func.variable_count(), 0);
+
+    // Locals after the arguments
+    let mut func = writer(ArgCount::Fixed(1));
+    let a = func.create_variable();
+    let b = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(a, Expression::stack(0).into()));
+    func.evaluate_expression(Expression::AssignStack(
+        b,
+        add(Expression::stack(a), number(1)).into(),
+    ));
+    assert_eq!((a, b), (1, 2));
+    assert_eq!(func.check_variables(), Ok(3));
+    assert_eq!(func.variable_count(), 2);
+
+    // Bindings of loops and handlers, after the slot of a variadic list
+    let mut func = writer(ArgCount::new_variadic(1..=1));
+    let item = func.create_variable();
+    let error = func.create_variable();
+    func.evaluate_expression(Expression::TryCatch {
+        body: Expression::For {
+            binding: item,
+            iterable: Expression::stack(1).into(),
+            body: Expression::stack(item).into(),
+        }
+        .into(),
+        error_slot: error,
+        handler: Expression::stack(error).into(),
+    });
+    assert_eq!((item, error), (2, 3));
+    assert_eq!(func.check_variables(), Ok(4));
+
+    // Variables captured by a closure, and read by a default
+    let mut func = writer(ArgCount::new(0..=1));
+    let captured = func.create_variable();
+    let mut closure =
+        FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(captured)]);
+    closure.evaluate_expression(Expression::Variable(VariableType::Captured(0)));
+    func.evaluate_expression(Expression::FunctionCapture(closure.to_ref(0)));
+    func.set_default(0, Expression::stack(captured)).unwrap();
+    func.evaluate_expression(Expression::AssignStack(captured, number(1).into()));
+    assert_eq!(func.check_variables(), Ok(2));
+
+    // Created variables left unused still take their slots, unlike with infer_layout
+    let mut func = writer(ArgCount::Fixed(0));
+    let used = func.create_variable();
+    func.create_variable();
+    func.evaluate_expression(Expression::stack(used));
+    assert_eq!(func.check_variables(), Ok(2));
+    assert_eq!(func.infer_layout(), 1);
+}
+
+#[test]
+fn test_unallocated_variables_are_listed() {
+    let mut func = writer(ArgCount::Fixed(1));
+    let var = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(var, Expression::stack(0).into()));
+    func.evaluate_expression(Expression::AssignStack(5, Expression::stack(3).into()));
+    func.evaluate_expression(Expression::stack(5));
+    let err = func.check_variables().unwrap_err();
+    assert_eq!(
+        err,
+        FreightError::UnallocatedVariables {
+            addresses: vec![3, 5],
+            stack_size: 2,
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "Stack addresses 3, 5 are used but were never created, with 2 slots allocated"
+    );
+    assert!(func.try_build(0).is_err());
+}
+
+#[test]
+fn test_registering_checks_allocated_variables() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = writer(ArgCount::Fixed(0));
+    func.disable_validation();
+    func.evaluate_expression(Expression::stack(0));
+    assert!(matches!(
+        engine.register_function(func, 0),
+        Err(FreightError::UnallocatedVariables { .. })
+    ));
+
+    // Without allocating variables, the same body is only caught by validation
+    let mut func = FunctionWriter::new(ArgCount::Fixed(0));
+    func.disable_validation();
+    func.evaluate_expression(Expression::stack(0));
+    assert!(engine.register_function(func, 0).is_ok());
+
+    let mut func = writer(ArgCount::Fixed(1));
+    let var = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(var, Expression::stack(0).into()));
+    func.evaluate_expression(add(Expression::stack(var), number(1)));
+    let func = engine.register_function(func, 0).unwrap();
+    assert_eq!(func.stack_size(), 2);
+    assert_eq!(
+        engine.call(&func, [TestValueWrapper(TestValue::Number(2))]),
+        Ok(TestValueWrapper(TestValue::Number(3)))
+    );
+}
@@ -0,0 +1,159 @@
+use crate::{
+    error::FreightError,
+    execution_engine::{ExecutionEngine, Stack},
+    expression::{Expression, NativeFunction, VariableType},
+    function::{ArgCount, FunctionRef, FunctionWriter, StackLayout},
+};
+
+use super::{
+    list, number,
+    type_system::{TestTypeSystem, TestValue, TestValueWrapper},
+    value,
+};
+
+/// Collects its arguments into a list, looking through references
+fn collect(
+    _: &mut ExecutionEngine<TestTypeSystem>,
+    args: Stack<TestValueWrapper>,
+) -> Result<TestValueWrapper, FreightError> {
+    let values = args.iter().map(|arg| TestValueWrapper(arg.resolve()));
+    Ok(TestValueWrapper(TestValue::List(values.collect())))
+}
+
+/// Registers a function returning its first `slots` stack slots as a list
+fn register_slots(
+    engine: &mut ExecutionEngine<TestTypeSystem>,
+    arg_count: ArgCount,
+    layout: StackLayout,
+    slots: usize,
+) -> FunctionRef<TestTypeSystem> {
+    let mut func = FunctionWriter::new(arg_count);
+    func.layout = layout;
+    func.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(collect),
+        (0..slots).map(Expression::stack).collect(),
+    ));
+    engine.register_function(func, 0).unwrap()
+}
+
+/// The list of items in a packed variadic list, looking through references
+fn unpack(list: &TestValue) -> Vec<TestValue> {
+    match list {
+        TestValue::List(items) => items.iter().map(TestValueWrapper::resolve).collect(),
+        other => panic!("Expected a variadic list, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_variadic_packing_matrix() {
+    let counts = [(0, 0), (0, 2), (1, 1), (1, 3), (2, 2), (2, 4)];
+    for (min, max) in counts {
+        for layout in [StackLayout::all_alloc(), StackLayout::no_alloc()] {
+            let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+            let arg_count = ArgCount::Variadic { min, max };
+            let func = register_slots(&mut engine, arg_count, layout.clone(), max + 1);
+
+            // Under the minimum, the minimum, between it and `max`, `max` and above it
+            for passed in 0..=max + 3 {
+                let args: Vec<_> = (0..passed as i64).map(|n| value(n + 10)).collect();
+                let result = engine.call(&func, args);
+                if passed < min {
+                    assert_eq!(
+                        result,
+                        Err(FreightError::IncorrectArgumentCount {
+                            expected_min: min,
+                            expected_max: None,
+                            actual: passed,
+                            function: Some(func.address()),
+                            name: None,
+                        }),
+                        "{min}..{max} with {passed} arguments"
+                    );
+                    continue;
+                }
+                let TestValue::List(slots) = result.unwrap().0 else {
+                    panic!("Expected the stack slots as a list");
+                };
+                let slots: Vec<_> = slots.into_iter().map(|slot| slot.0).collect();
+                let (positional, packed) = slots.split_at(max);
+
+                let expected: Vec<_> = (0..max as i64)
+                    .map(|n| match n < passed as i64 {
+                        true => TestValue::Number(n + 10),
+                        false => TestValue::Uninitialized,
+                    })
+                    .collect();
+                assert_eq!(positional, expected, "{min}..{max} with {passed} arguments");
+                let rest: Vec<_> = (max as i64..passed as i64)
+                    .map(|n| TestValue::Number(n + 10))
+                    .collect();
+                assert_eq!(
+                    unpack(&packed[0]),
+                    rest,
+                    "{min}..{max} with {passed} arguments"
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_variadic_list_is_after_positional_slots_and_before_variables() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    let mut func = FunctionWriter::new(ArgCount::Variadic { min: 1, max: 2 });
+    let first = func.create_variable();
+    let second = func.create_variable();
+    assert_eq!((first, second), (3, 4));
+    assert_eq!(func.variable_count(), 2);
+    func.evaluate_expression(Expression::AssignStack(second, number(7).into()));
+    func.evaluate_expression(Expression::NativeFunctionCall(
+        NativeFunction::new(collect),
+        (0..5).map(Expression::stack).collect(),
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+
+    for _ in 0..2 {
+        let result = engine.call(&func, [value(1), value(2), value(3), value(4)]);
+        let expected = vec![
+            value(1),
+            value(2),
+            list(&[3, 4]),
+            TestValueWrapper(TestValue::Uninitialized),
+            value(7),
+        ];
+        assert_eq!(result, Ok(TestValueWrapper(TestValue::List(expected))));
+    }
+    let result = engine.call(&func, [value(1)]);
+    let expected = vec![
+        value(1),
+        TestValueWrapper(TestValue::Uninitialized),
+        list(&[]),
+        TestValueWrapper(TestValue::Uninitialized),
+        value(7),
+    ];
+    assert_eq!(result, Ok(TestValueWrapper(TestValue::List(expected))));
+}
+
+#[test]
+fn test_variadic_list_slot_follows_the_layout() {
+    let mut engine = ExecutionEngine::<TestTypeSystem>::new_default();
+    // Captures the list by reference and replaces it before reading it back through
+    // the closure
+    let mut read = FunctionWriter::new_capturing(ArgCount::Fixed(0), vec![VariableType::Stack(1)]);
+    read.evaluate_expression(Expression::Variable(VariableType::Captured(0)));
+    let read = engine.register_function(read, 0).unwrap();
+
+    let mut func = FunctionWriter::new(ArgCount::at_least(1));
+    let closure = func.create_variable();
+    func.evaluate_expression(Expression::AssignStack(
+        closure,
+        Expression::FunctionCapture(read).into(),
+    ));
+    func.evaluate_expression(Expression::AssignStack(1, number(5).into()));
+    func.evaluate_expression(Expression::DynamicFunctionCall(
+        Expression::stack(closure).into(),
+        vec![],
+    ));
+    let func = engine.register_function(func, 0).unwrap();
+    assert_eq!(engine.call(&func, [value(1), value(2)]), Ok(value(5)));
+}
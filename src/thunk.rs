@@ -0,0 +1,78 @@
+use std::fmt::Debug;
+
+use crate::{
+    expression::Expression,
+    sync::{PoolCell, Shared},
+    TypeSystem,
+};
+
+/// A lazily evaluated expression created by [Expression::Lazy]. The body is evaluated
+/// the first time the thunk is forced, and every copy of the thunk shares the result.
+pub struct Thunk<TS: TypeSystem>(Shared<PoolCell<ThunkState<TS>>>);
+
+pub(crate) enum ThunkState<TS: TypeSystem> {
+    Pending(Box<ThunkEnv<TS>>),
+    Forcing,
+    Forced(TS::Value),
+}
+
+/// The body of a thunk along with the variables it can see
+pub(crate) struct ThunkEnv<TS: TypeSystem> {
+    pub(crate) body: Shared<Expression<TS>>,
+    pub(crate) stack: Vec<TS::Value>,
+    pub(crate) captured: Vec<TS::Value>,
+}
+
+impl<TS: TypeSystem> Thunk<TS> {
+    pub(crate) fn new(env: ThunkEnv<TS>) -> Self {
+        Self(Shared::new(PoolCell::new(ThunkState::Pending(env.into()))))
+    }
+
+    /// Whether the body has been evaluated and its result cached
+    pub fn is_forced(&self) -> bool {
+        self.0.with(|state| matches!(state, ThunkState::Forced(_)))
+    }
+
+    /// Whether two thunks share the same body and result
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Shared::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Take the state out of the thunk so it can be forced, marking it as being forced
+    pub(crate) fn begin_force(&self) -> ThunkState<TS> {
+        self.0.with(|state| match state {
+            ThunkState::Forced(value) => ThunkState::Forced(value.clone()),
+            state => std::mem::replace(state, ThunkState::Forcing),
+        })
+    }
+
+    /// Store the result of forcing, or the environment again so a failed force can be retried
+    pub(crate) fn end_force(&self, state: ThunkState<TS>) {
+        // The previous state is `Forcing`, so replacing it can't drop anything re-entrant
+        self.0.with(|current| *current = state);
+    }
+}
+
+impl<TS: TypeSystem> Clone for Thunk<TS> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<TS: TypeSystem> PartialEq for Thunk<TS> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl<TS: TypeSystem> Debug for Thunk<TS> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Thunk")
+            .field(&if self.is_forced() {
+                "forced"
+            } else {
+                "pending"
+            })
+            .finish()
+    }
+}
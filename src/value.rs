@@ -1,21 +1,49 @@
-use crate::{function::FunctionRef, TypeSystem};
+use crate::{error::FreightError, function::FunctionRef, thunk::Thunk, TypeSystem};
 use std::fmt::Debug;
 
+/// The elements of an iterable value, see [Value::cast_to_iterator]
+pub type ValueIter<V> = Box<dyn Iterator<Item = Result<V, FreightError>>>;
+
 pub trait Value: Clone + Default + Debug + From<FunctionRef<Self::TS>> + PartialEq {
     type TS: TypeSystem<Value = Self>;
 
     /// Create a new uninitialized (null) reference, will be used for all new stack values
     fn uninitialized_reference() -> Self;
 
+    /// Create a new uninitialized value for stack slots which aren't allocated as
+    /// references, see [StackLayout](crate::function::StackLayout)
+    fn uninitialized_value() -> Self {
+        Default::default()
+    }
+
+    /// Whether this value was created by [Value::uninitialized_reference] or
+    /// [Value::uninitialized_value] and nothing has been assigned to it since.
+    /// Only checked when the engine is in strict mode.
+    fn is_uninitialized(&self) -> bool {
+        false
+    }
+
     /// Get the type ID of this value
     fn get_type(&self) -> &<Self::TS as TypeSystem>::TypeId;
 
+    /// The name of this value's type for error messages
+    fn type_name(&self) -> String {
+        format!("{:?}", self.get_type())
+    }
+
     /// Create a deep copy of this value
     fn deep_clone(&self) -> Self;
 
     /// Create a new reference to this value
     fn dupe_ref(&self) -> Self;
 
+    /// Copy this value so that nothing assigned through the copy is visible through this
+    /// value or the other way around, used for captures made by value. See
+    /// [CaptureLayout](crate::function::CaptureLayout).
+    fn clone_unaliased(&self) -> Self {
+        self.deep_clone()
+    }
+
     /// Convert this value into a reference, if it isn't already
     fn into_ref(self) -> Self;
 
@@ -25,6 +53,78 @@ pub trait Value: Clone + Default + Debug + From<FunctionRef<Self::TS>> + Partial
     /// Assign to this value
     fn assign(&mut self, value: <Self::TS as TypeSystem>::Value);
 
+    /// Whether two constants are interchangeable, so engines with constant pooling
+    /// can store them once. See [ExecutionEngine::set_constant_pooling](crate::execution_engine::ExecutionEngine::set_constant_pooling)
+    fn constant_eq(&self, _other: &Self) -> bool {
+        false
+    }
+
+    /// Whether this value counts as true when used as a condition, which by default is
+    /// when it's not the default value
+    fn is_truthy(&self) -> bool {
+        *self != Self::default()
+    }
+
+    /// Whether this value is empty, so [Expression::Coalesce](crate::expression::Expression::Coalesce)
+    /// evaluates its fallback instead
+    fn is_null(&self) -> bool {
+        false
+    }
+
+    /// Whether this value selects a `Match` arm with the given constant
+    fn matches_constant(&self, constant: &Self) -> bool {
+        self == constant
+    }
+
+    /// Attempt to iterate over this value for a `For` loop. Any value can be iterable,
+    /// so hosts can expose custom collections.
+    fn cast_to_iterator(&self) -> Option<ValueIter<Self>> {
+        None
+    }
+
+    /// An estimate of the memory owned by this value in bytes, charged against the
+    /// engine's memory limit when values are created by initializers or variadic calls
+    fn approx_size(&self) -> usize {
+        std::mem::size_of::<Self>()
+    }
+
+    /// The elements of this value if it's a list, so it can be spread into the
+    /// arguments of a call by [Expression::Spread](crate::expression::Expression::Spread)
+    /// or assigned to several variables by
+    /// [Expression::DestructureAssign](crate::expression::Expression::DestructureAssign)
+    fn list_elements(&self) -> Option<Vec<Self>> {
+        None
+    }
+
+    /// Read the element at an index for [Expression::Index](crate::expression::Expression::Index).
+    /// Fails with [FreightError::NotIndexable] by default.
+    fn get_index(&self, _index: &Self) -> Result<Self, FreightError> {
+        Err(FreightError::NotIndexable {
+            type_name: self.type_name(),
+        })
+    }
+
+    /// Replace the element at an index for
+    /// [Expression::IndexAssign](crate::expression::Expression::IndexAssign). The change
+    /// must be made through references, so every holder of the container sees it.
+    /// Fails with [FreightError::NotIndexable] by default.
+    fn set_index(&mut self, _index: &Self, _value: Self) -> Result<(), FreightError> {
+        Err(FreightError::NotIndexable {
+            type_name: self.type_name(),
+        })
+    }
+
+    /// Wrap a thunk created by [Expression::Lazy](crate::expression::Expression::Lazy)
+    /// in a value. Type systems without lazy values return `None`.
+    fn from_thunk(_thunk: Thunk<Self::TS>) -> Option<Self> {
+        None
+    }
+
+    /// Attempt to cast this value to a thunk so it can be forced
+    fn cast_to_thunk(&self) -> Option<&Thunk<Self::TS>> {
+        None
+    }
+
     #[cfg(feature = "variadic_functions")]
     /// Create a `Value` type list out of `Vec` of `Value`
     fn gen_list(values: Vec<Self>) -> Self;